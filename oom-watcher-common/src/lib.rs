@@ -4,34 +4,585 @@
 extern crate std;
 
 #[cfg(feature = "user")]
-use std::string::String;
+use std::{fmt, string::String, vec::Vec};
 
+/// Version of the probe's wire format: the [`WireHeader`] and the bodies it frames. Bumped
+/// whenever either changes in a way an older reader would mis-decode.
+pub const WIRE_VERSION: u16 = 1;
+
+/// [`WireHeader::kind`]s: which body follows the header.
+pub const KIND_OOM_KILL: u16 = 1; // OomKillRecord
+pub const KIND_REAP: u16 = 2; // ReapEvent
+pub const KIND_SCORE_ADJ: u16 = 3; // ScoreAdjEvent
+
+/// What starts every record on the probe's ring buffer, so the one buffer can carry every
+/// kind of event and a reader can tell a record it does not understand from one it does.
 #[repr(C)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct WireHeader {
+    pub version: u16, // WIRE_VERSION of the probe that wrote it
+    pub kind: u16,    // One of the KIND_* constants
+    pub len: u32,     // Bytes of the body that follows
+}
+
+impl WireHeader {
+    /// The header of a `kind` record whose body is a `T`.
+    pub const fn of<T>(kind: u16) -> Self {
+        Self {
+            version: WIRE_VERSION,
+            kind,
+            len: core::mem::size_of::<T>() as u32,
+        }
+    }
+}
+
+/// A record as it sits on the ring buffer: the header, then a body of the kind it names.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct WireRecord<T> {
+    pub header: WireHeader,
+    pub body: T,
+}
+
+/// One OOM kill as the probe saw it. Two tasks are involved and kept apart: the *victim*
+/// the kernel chose to kill, and the *trigger* — the task whose allocation failed and
+/// invoked the OOM killer. They are often different processes, and can be different
+/// containers (a sidecar's allocation killing the main app). Shipped in an
+/// [`OomKillRecord`].
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+#[cfg_attr(feature = "user", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "user", serde(default = "OomKillEvent::unknown"))]
 pub struct OomKillEvent {
-    pub pid: u32,
-    pub tgid: u32,
-    pub comm: [u8; 16],     // Process name (TASK_COMM_LEN)
-    pub total_vm: u64,      // Total virtual memory in KB
-    pub anon_rss: u64,      // Anonymous RSS in KB
-    pub file_rss: u64,      // File RSS in KB
-    pub shmem_rss: u64,     // Shared memory RSS in KB
-    pub uid: u32,           // User ID
-    pub pgtables: u64,      // Page table size in KB
-    pub oom_score_adj: i16, // OOM score adjustment
-}
-
-/// Kubernetes coordinates of the container a killed process belonged to.
+    // Victim: the task the kernel killed. The memory fields describe its mm.
+    pub victim_pid: u32,
+    pub victim_tgid: u32,      // 0 = unknown
+    pub victim_comm: [u8; 16], // Process name (TASK_COMM_LEN)
+    pub total_vm: u64,         // Total virtual memory in KB
+    pub anon_rss: u64,         // Anonymous RSS in KB
+    pub file_rss: u64,         // File RSS in KB
+    pub shmem_rss: u64,        // Shared memory RSS in KB
+    pub uid: u32,              // User ID
+    pub pgtables: u64,         // Page table size in KB
+    pub oom_score_adj: i16,    // OOM score adjustment
+    pub victim_cgroup_id: u64, // cgroup v2 id (0 = unknown)
+    pub victim_memcg_id: u64,  // Memory-controller cgroup id (0 = unknown)
+
+    // Victim process details, read from its task_struct in every attach mode.
+    pub victim_ppid: u32,             // Parent's tgid (real_parent)
+    pub victim_parent_comm: [u8; 16], // Parent's process name
+    pub victim_ns_pid: u32,           // pid in its own (innermost) pid namespace
+    pub threads: u32,                 // Threads in its thread group
+    pub start_time: u64,              // When it started, ns since boot (CLOCK_MONOTONIC)
+    pub age_ns: u64,                  // Time from its start to the kill
+    pub hiwater_rss: u64,             // Peak RSS in KB
+    pub swap: u64,                    // Swapped-out memory (MM_SWAPENTS) in KB
+
+    // Trigger: the task whose allocation invoked the OOM killer.
+    pub trigger_pid: u32,
+    pub trigger_tgid: u32,
+    pub trigger_comm: [u8; 16],
+    pub trigger_cgroup_id: u64, // cgroup v2 id (0 = unknown)
+    pub trigger_memcg_id: u64,  // Memory-controller cgroup id (0 = unknown)
+    pub kernel_stack_id: i32,   // Its kernel stack in the probe's STACKS map, or STACK_UNKNOWN
+    pub user_stack_id: i32,     // Its user stack in the probe's STACKS map, or STACK_UNKNOWN
+
+    // Context: the OOM killer invocation (struct oom_control) that chose the victim.
+    pub constraint: u32,   // enum oom_constraint, or CONSTRAINT_UNKNOWN
+    pub gfp_mask: u32,     // GFP flags of the failed allocation
+    pub order: i32,        // Allocation order; -1 for a sysrq-forced kill
+    pub oom_memcg_id: u64, // cgroup id of the memcg whose limit was hit (0 = global/unknown)
+
+    // Snapshot of the breached memcg's page counters at the kill; all 0 when there is no
+    // breached memcg (a global OOM) or it could not be read.
+    pub memcg_usage: u64,   // Memory charged to it, in bytes
+    pub memcg_limit: u64,   // memory.max in bytes; MEMCG_NO_LIMIT if unlimited
+    pub memcg_swap: u64,    // Swap charged to it in bytes (memory+swap on cgroup v1)
+    pub memcg_peak: u64,    // High watermark of memory usage, in bytes
+    pub memcg_failcnt: u64, // Times a charge has hit the limit
+
+    // Delivery: where the event sits in the probe's output, so userspace can tell when
+    // events were lost to a full ring buffer.
+    pub cpu: u32,      // CPU the probe ran on
+    pub sequence: u64, // Kills seen on that CPU, from 1; a gap means lost events (0 = none)
+
+    // Victim fields the probe could not read on this kernel — missing from its
+    // oom:mark_victim record, or from the BTF the task_struct reads walk: a set of
+    // FIELD_* bits. Their values above are 0 and mean unknown, not zero.
+    pub unknown_fields: u32,
+}
+
+impl OomKillEvent {
+    /// An event that knows nothing: every `FIELD_*` bit set, and the constraint and stacks
+    /// marked unknown rather than zero. What a field missing from a recording reads as.
+    pub fn unknown() -> Self {
+        Self {
+            kernel_stack_id: STACK_UNKNOWN,
+            user_stack_id: STACK_UNKNOWN,
+            constraint: CONSTRAINT_UNKNOWN,
+            unknown_fields: FIELDS_ALL,
+            ..Self::default()
+        }
+    }
+
+    /// Whether the kernel reported `field`, one of the `FIELD_*` bits.
+    pub fn has(&self, field: u32) -> bool {
+        self.unknown_fields & field == 0
+    }
+}
+
+/// Fields of `oom:mark_victim`, for [`OomKillEvent::unknown_fields`]. Older kernels carry
+/// only the pid; the rest arrived over time and may move between versions.
+pub const FIELD_VICTIM_COMM: u32 = 1 << 0;
+pub const FIELD_TOTAL_VM: u32 = 1 << 1;
+pub const FIELD_ANON_RSS: u32 = 1 << 2;
+pub const FIELD_FILE_RSS: u32 = 1 << 3;
+pub const FIELD_SHMEM_RSS: u32 = 1 << 4;
+pub const FIELD_UID: u32 = 1 << 5;
+pub const FIELD_PGTABLES: u32 = 1 << 6;
+pub const FIELD_OOM_SCORE_ADJ: u32 = 1 << 7;
+
+/// Victim process details, for [`OomKillEvent::unknown_fields`]. No tracepoint carries
+/// them: the probe reads them from the victim's `task_struct`, and in the tracepoint attach
+/// mode they are unknown if the kprobe that does so did not attach.
+pub const FIELD_PARENT: u32 = 1 << 8; // victim_ppid and victim_parent_comm
+pub const FIELD_NS_PID: u32 = 1 << 9;
+pub const FIELD_THREADS: u32 = 1 << 10;
+pub const FIELD_START_TIME: u32 = 1 << 11; // start_time and age_ns
+pub const FIELD_HIWATER_RSS: u32 = 1 << 12;
+pub const FIELD_SWAP: u32 = 1 << 13;
+/// Every `FIELD_*` bit.
+pub const FIELDS_ALL: u32 = (1 << 14) - 1;
+
+/// Bytes of [`VictimCommand::exe`].
+pub const EXE_LEN: usize = 256;
+/// Bytes of [`VictimCommand::cmdline`]: how much of a victim's argv is kept.
+pub const CMDLINE_LEN: usize = 256;
+
+/// What an OOM victim was running, captured in-kernel as it is killed: `comm` alone says
+/// `java` for every JVM, and `/proc/<pid>/cmdline` is gone by the time userspace looks.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct VictimCommand {
+    /// Path of the victim's executable, NUL-terminated, starting at `exe_start` — it is
+    /// written back to front — and relative to the root of the filesystem it is on: the
+    /// container's root, for a binary in its image.
+    pub exe: [u8; EXE_LEN],
+    pub exe_start: u32,    // Where the path starts in exe; EXE_LEN if not captured
+    pub exe_complete: u32, // Non-zero if the path reaches its root; else leading dirs are cut
+    /// The start of the victim's argv: NUL-separated arguments, `cmdline_len` bytes.
+    pub cmdline: [u8; CMDLINE_LEN],
+    pub cmdline_len: u32, // Bytes of argv captured
+    pub args_len: u32,    // Bytes of argv in all (0 = not captured); above cmdline_len if cut
+}
+
+/// The body of a [`KIND_OOM_KILL`] record: the event, and the victim's command.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct OomKillRecord {
+    pub event: OomKillEvent,
+    pub command: VictimCommand,
+}
+
+/// `enum oom_constraint`: what bounded the memory the failed allocation could use.
+pub const CONSTRAINT_NONE: u32 = 0;
+pub const CONSTRAINT_CPUSET: u32 = 1;
+pub const CONSTRAINT_MEMORY_POLICY: u32 = 2;
+pub const CONSTRAINT_MEMCG: u32 = 3;
+/// No `oom_control` was captured for the kill (the kprobe did not attach).
+pub const CONSTRAINT_UNKNOWN: u32 = u32::MAX;
+
+/// A `*_stack_id` the probe could not capture — a kernel thread's user stack, or a full
+/// stack map. Ids themselves start at 0.
+pub const STACK_UNKNOWN: i32 = -1;
+
+/// One step in an OOM victim's exit after `oom:mark_victim`: the OOM reaper being woken
+/// for it, starting, finishing or skipping its reap, or the victim task exiting. The body of
+/// a [`KIND_REAP`] record, one per step.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+#[cfg_attr(feature = "user", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReapEvent {
+    pub pid: u32,        // Victim pid, as in OomKillEvent::victim_pid
+    pub stage: u32,      // One of the REAP_* stages
+    pub elapsed_ns: u64, // Time since the victim was marked
+}
+
+/// `oom:wake_reaper`: the victim was queued for the OOM reaper.
+pub const REAP_WOKEN: u32 = 0;
+/// `oom:start_task_reaping`: the reaper began unmapping the victim's memory.
+pub const REAP_STARTED: u32 = 1;
+/// `oom:finish_task_reaping`: the reaper released the victim's memory.
+pub const REAP_FINISHED: u32 = 2;
+/// `oom:skip_task_reaping`: the reaper gave up on the victim (its mm was busy).
+pub const REAP_SKIPPED: u32 = 3;
+/// `sched:sched_process_exit`: the victim task itself exited.
+pub const REAP_EXITED: u32 = 4;
+
+/// A write to a task's `oom_score_adj`, from `oom:oom_score_adj_update`: who was
+/// re-prioritised for the OOM killer, to what, and by whom. The body of a
+/// [`KIND_SCORE_ADJ`] record.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+#[cfg_attr(feature = "user", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "user", serde(default))]
+pub struct ScoreAdjEvent {
+    pub pid: u32,              // Task whose oom_score_adj changed
+    pub comm: [u8; 16],        // Its process name
+    pub oom_score_adj: i16,    // New value, -1000..=1000
+    pub cgroup_id: u64,        // Its cgroup v2 id (0 = unknown)
+    pub memcg_id: u64,         // Its memory-controller cgroup id (0 = unknown)
+    pub writer_pid: u32,       // Task that made the change
+    pub writer_comm: [u8; 16], // Its process name
+}
+
+/// Memory pressure one cgroup has been under — the lead-up to an OOM kill — counted
+/// in-kernel from the memcg reclaim tracepoints, failed memcg charges and `memory.high`
+/// throttling. The counters are cumulative; userspace polls them and works with deltas.
 ///
-/// Resolved from a PID by reading `/proc/<pid>/cgroup` for the container id, then
-/// matching that id against the pods scheduled on this node.
+/// Kept per CPU by the probe, so the ids and last task are whichever that CPU saw.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct MemcgPressure {
+    pub cgroup_id: u64,        // cgroup v2 id (0 = unknown)
+    pub memcg_id: u64,         // Memory-controller cgroup id (0 = unknown)
+    pub reclaims: u64,         // Direct memcg reclaim passes run
+    pub reclaim_ns: u64,       // Time spent in them
+    pub reclaimed_pages: u64,  // Pages they freed
+    pub charge_failures: u64,  // Charges refused at the limit
+    pub high_throttles: u64,   // Returns to userspace throttled over memory.high
+    pub high_throttle_ns: u64, // Time spent throttled
+    pub last_pid: u32,         // Last task seen under pressure, to resolve the cgroup by
+    pub last_tgid: u32,
+}
+
+/// `memcg_limit` of a memcg with no memory limit set (`memory.max` of `max`).
+pub const MEMCG_NO_LIMIT: u64 = u64::MAX;
+
+/// Sentinel for a [`KernelLayout`] offset the running kernel's BTF did not yield.
+pub const OFFSET_UNKNOWN: u32 = u32::MAX;
+
+/// Byte offsets of the kernel struct members the probe walks, resolved by userspace from
+/// the running kernel's BTF, and of the tracepoint record fields it reads, parsed from
+/// their tracefs format files. Patched into the probe's `LAYOUT` global at load time.
+///
+/// The probe never hard-codes a kernel struct or tracepoint layout: any offset left at
+/// [`OFFSET_UNKNOWN`] makes the reads that depend on it report "unknown" instead. A
+/// tracepoint field's offset is only set when the field is present with the expected size,
+/// so the set of known offsets is the set of fields the probe may read.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct KernelLayout {
+    pub task_struct_pid: u32,           // task_struct.pid
+    pub task_struct_tgid: u32,          // task_struct.tgid
+    pub task_struct_cgroups: u32,       // task_struct.cgroups (struct css_set *)
+    pub task_struct_comm: u32,          // task_struct.comm (char[TASK_COMM_LEN])
+    pub task_struct_mm: u32,            // task_struct.mm (struct mm_struct *)
+    pub task_struct_cred: u32,          // task_struct.cred (const struct cred *)
+    pub task_struct_signal: u32,        // task_struct.signal (struct signal_struct *)
+    pub task_struct_real_parent: u32,   // task_struct.real_parent (struct task_struct *)
+    pub task_struct_start_time: u32,    // task_struct.start_time (u64, monotonic ns)
+    pub task_struct_thread_pid: u32,    // task_struct.thread_pid (struct pid *)
+    pub pid_level: u32,                 // pid.level (unsigned int)
+    pub pid_numbers: u32,               // pid.numbers[] (struct upid, one per level)
+    pub upid_nr: u32,                   // upid.nr (int)
+    pub upid_size: u32,                 // Bytes per struct upid
+    pub cred_uid: u32,                  // cred.uid (kuid_t)
+    pub signal_oom_score_adj: u32,      // signal_struct.oom_score_adj (short)
+    pub signal_nr_threads: u32,         // signal_struct.nr_threads (int)
+    pub mm_struct_total_vm: u32,        // mm_struct.total_vm (pages)
+    pub mm_struct_hiwater_rss: u32,     // mm_struct.hiwater_rss (pages)
+    pub mm_struct_exe_file: u32,        // mm_struct.exe_file (struct file *)
+    pub mm_struct_arg_start: u32,       // mm_struct.arg_start (unsigned long, user address)
+    pub mm_struct_arg_end: u32,         // mm_struct.arg_end (unsigned long, user address)
+    pub file_f_path: u32,               // file.f_path (embedded struct path)
+    pub path_dentry: u32,               // path.dentry (struct dentry *)
+    pub dentry_d_parent: u32,           // dentry.d_parent (struct dentry *)
+    pub dentry_d_name: u32,             // dentry.d_name (embedded struct qstr)
+    pub qstr_len: u32,                  // qstr.len (u32)
+    pub qstr_name: u32,                 // qstr.name (const unsigned char *)
+    pub mm_struct_pgtables: u32,        // mm_struct.pgtables_bytes (atomic_long_t)
+    pub mm_struct_rss_stat: u32,        // mm_struct.rss_stat (a counter per MM_* type)
+    pub rss_stat_stride: u32,           // Bytes between rss_stat's per-type counters
+    pub rss_stat_count: u32,            // Offset of the page count within each counter
+    pub css_set_dfl_cgrp: u32,          // css_set.dfl_cgrp (struct cgroup *)
+    pub css_set_subsys: u32,            // css_set.subsys[] (struct cgroup_subsys_state *)
+    pub css_cgroup: u32,                // cgroup_subsys_state.cgroup (struct cgroup *)
+    pub cgroup_kn: u32,                 // cgroup.kn (struct kernfs_node *)
+    pub kernfs_node_id: u32,            // kernfs_node.id (u64)
+    pub kernfs_node_parent: u32,        // kernfs_node.parent (__parent since 6.15)
+    pub memory_cgrp_id: u32,            // enum cgroup_subsys_id value of the memory controller
+    pub oom_control_memcg: u32,         // oom_control.memcg (struct mem_cgroup *)
+    pub oom_control_gfp_mask: u32,      // oom_control.gfp_mask (gfp_t)
+    pub oom_control_order: u32,         // oom_control.order (int)
+    pub oom_control_constraint: u32,    // oom_control.constraint (enum oom_constraint)
+    pub oom_control_chosen: u32,        // oom_control.chosen (struct task_struct *)
+    pub mem_cgroup_css: u32,            // mem_cgroup.css (embedded struct cgroup_subsys_state)
+    pub mem_cgroup_memory: u32,         // mem_cgroup.memory (embedded struct page_counter)
+    pub mem_cgroup_swap: u32,           // mem_cgroup.swap (struct page_counter; memsw on v1)
+    pub page_counter_usage: u32,        // page_counter.usage (atomic_long_t, pages)
+    pub page_counter_max: u32,          // page_counter.max (pages)
+    pub page_counter_watermark: u32,    // page_counter.watermark (pages)
+    pub page_counter_failcnt: u32,      // page_counter.failcnt
+    pub page_size: u32,                 // Bytes per page; from the loader, not BTF
+    pub mark_victim_pid: u32,           // oom:mark_victim pid (int)
+    pub mark_victim_comm: u32,          // oom:mark_victim comm (__data_loc char[])
+    pub mark_victim_total_vm: u32,      // oom:mark_victim total_vm (unsigned long, kB)
+    pub mark_victim_anon_rss: u32,      // oom:mark_victim anon_rss (unsigned long, kB)
+    pub mark_victim_file_rss: u32,      // oom:mark_victim file_rss (unsigned long, kB)
+    pub mark_victim_shmem_rss: u32,     // oom:mark_victim shmem_rss (unsigned long, kB)
+    pub mark_victim_uid: u32,           // oom:mark_victim uid (uid_t)
+    pub mark_victim_pgtables: u32,      // oom:mark_victim pgtables (unsigned long, kB)
+    pub mark_victim_oom_score_adj: u32, // oom:mark_victim oom_score_adj (short)
+    pub reaper_pid: u32,                // oom:wake_reaper and *_task_reaping pid (int)
+    pub process_exit_pid: u32,          // sched:sched_process_exit pid (pid_t)
+    pub score_adj_pid: u32,             // oom:oom_score_adj_update pid (pid_t)
+    pub score_adj_comm: u32,            // oom:oom_score_adj_update comm (char[TASK_COMM_LEN])
+    pub score_adj_oom_score_adj: u32,   // oom:oom_score_adj_update oom_score_adj (short)
+    pub reclaim_end_nr_reclaimed: u32,  // vmscan:mm_vmscan_memcg_reclaim_end nr_reclaimed
+}
+
+impl KernelLayout {
+    /// A layout with every offset unknown — what the probe runs with when BTF is missing.
+    pub const UNKNOWN: Self = Self {
+        task_struct_pid: OFFSET_UNKNOWN,
+        task_struct_tgid: OFFSET_UNKNOWN,
+        task_struct_cgroups: OFFSET_UNKNOWN,
+        task_struct_comm: OFFSET_UNKNOWN,
+        task_struct_mm: OFFSET_UNKNOWN,
+        task_struct_cred: OFFSET_UNKNOWN,
+        task_struct_signal: OFFSET_UNKNOWN,
+        task_struct_real_parent: OFFSET_UNKNOWN,
+        task_struct_start_time: OFFSET_UNKNOWN,
+        task_struct_thread_pid: OFFSET_UNKNOWN,
+        pid_level: OFFSET_UNKNOWN,
+        pid_numbers: OFFSET_UNKNOWN,
+        upid_nr: OFFSET_UNKNOWN,
+        upid_size: OFFSET_UNKNOWN,
+        cred_uid: OFFSET_UNKNOWN,
+        signal_oom_score_adj: OFFSET_UNKNOWN,
+        signal_nr_threads: OFFSET_UNKNOWN,
+        mm_struct_total_vm: OFFSET_UNKNOWN,
+        mm_struct_hiwater_rss: OFFSET_UNKNOWN,
+        mm_struct_exe_file: OFFSET_UNKNOWN,
+        mm_struct_arg_start: OFFSET_UNKNOWN,
+        mm_struct_arg_end: OFFSET_UNKNOWN,
+        file_f_path: OFFSET_UNKNOWN,
+        path_dentry: OFFSET_UNKNOWN,
+        dentry_d_parent: OFFSET_UNKNOWN,
+        dentry_d_name: OFFSET_UNKNOWN,
+        qstr_len: OFFSET_UNKNOWN,
+        qstr_name: OFFSET_UNKNOWN,
+        mm_struct_pgtables: OFFSET_UNKNOWN,
+        mm_struct_rss_stat: OFFSET_UNKNOWN,
+        rss_stat_stride: OFFSET_UNKNOWN,
+        rss_stat_count: OFFSET_UNKNOWN,
+        css_set_dfl_cgrp: OFFSET_UNKNOWN,
+        css_set_subsys: OFFSET_UNKNOWN,
+        css_cgroup: OFFSET_UNKNOWN,
+        cgroup_kn: OFFSET_UNKNOWN,
+        kernfs_node_id: OFFSET_UNKNOWN,
+        kernfs_node_parent: OFFSET_UNKNOWN,
+        memory_cgrp_id: OFFSET_UNKNOWN,
+        oom_control_memcg: OFFSET_UNKNOWN,
+        oom_control_gfp_mask: OFFSET_UNKNOWN,
+        oom_control_order: OFFSET_UNKNOWN,
+        oom_control_constraint: OFFSET_UNKNOWN,
+        oom_control_chosen: OFFSET_UNKNOWN,
+        mem_cgroup_css: OFFSET_UNKNOWN,
+        mem_cgroup_memory: OFFSET_UNKNOWN,
+        mem_cgroup_swap: OFFSET_UNKNOWN,
+        page_counter_usage: OFFSET_UNKNOWN,
+        page_counter_max: OFFSET_UNKNOWN,
+        page_counter_watermark: OFFSET_UNKNOWN,
+        page_counter_failcnt: OFFSET_UNKNOWN,
+        page_size: OFFSET_UNKNOWN,
+        mark_victim_pid: OFFSET_UNKNOWN,
+        mark_victim_comm: OFFSET_UNKNOWN,
+        mark_victim_total_vm: OFFSET_UNKNOWN,
+        mark_victim_anon_rss: OFFSET_UNKNOWN,
+        mark_victim_file_rss: OFFSET_UNKNOWN,
+        mark_victim_shmem_rss: OFFSET_UNKNOWN,
+        mark_victim_uid: OFFSET_UNKNOWN,
+        mark_victim_pgtables: OFFSET_UNKNOWN,
+        mark_victim_oom_score_adj: OFFSET_UNKNOWN,
+        reaper_pid: OFFSET_UNKNOWN,
+        process_exit_pid: OFFSET_UNKNOWN,
+        score_adj_pid: OFFSET_UNKNOWN,
+        score_adj_comm: OFFSET_UNKNOWN,
+        score_adj_oom_score_adj: OFFSET_UNKNOWN,
+        reclaim_end_nr_reclaimed: OFFSET_UNKNOWN,
+    };
+}
+
+/// Which kills the probe ships, patched into its `FILTERS` global at load time. The lists
+/// themselves live in the probe's `CGROUP_FILTER`, `UID_FILTER` and `COMM_FILTER` maps,
+/// each entry marked `FILTER_INCLUDE` or `FILTER_EXCLUDE`; this says, per dimension,
+/// whether an include list was configured, in which case a victim listed nowhere in it is
+/// dropped. An excluded victim is always dropped, and one whose value is unknown never is.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FilterConfig {
+    pub cgroups_included: u32, // Non-zero if CGROUP_FILTER has include entries
+    pub uids_included: u32,    // Likewise for UID_FILTER
+    pub comms_included: u32,   // Likewise for COMM_FILTER
+    pub memcg_hierarchy: u32,  // Non-zero if cgroup ids name the v1 memory hierarchy
+}
+
+impl FilterConfig {
+    /// No lists: every kill is shipped.
+    pub const NONE: Self = Self {
+        cgroups_included: 0,
+        uids_included: 0,
+        comms_included: 0,
+        memcg_hierarchy: 0,
+    };
+}
+
+/// A filter map entry's action. A cgroup entry covers its descendants, and a comm entry
+/// is a prefix; the most specific entry matching a victim decides.
+pub const FILTER_INCLUDE: u8 = 1;
+pub const FILTER_EXCLUDE: u8 = 2;
+
+/// One event from the probe, decoded from its record on the ring buffer.
+#[cfg(feature = "user")]
+// Each event is moved once, from the source to the loop; boxing kills would only add an
+// allocation per event.
+#[allow(clippy::large_enum_variant)]
+#[derive(Copy, Clone, Debug)]
+pub enum ProbeEvent {
+    /// An OOM kill.
+    Kill(OomKillEvent),
+    /// A step in a victim's exit after its kill.
+    Reap(ReapEvent),
+    /// A write to a task's `oom_score_adj`.
+    ScoreAdj(ScoreAdjEvent),
+}
+
+/// Kubernetes coordinates of the container a process — an OOM victim or trigger —
+/// belonged to.
+///
+/// Resolved from the process's cgroup (captured in-kernel, or read from
+/// `/proc/<pid>/cgroup` as a fallback) for the container id, then matching that id
+/// against the pods scheduled on this node — or, when no container status carries it yet,
+/// the pod UID on the same path against theirs.
 #[cfg(feature = "user")]
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct ContainerIdentity {
     pub namespace: String,
     pub pod_name: String,
+    /// `unknown` when only the pod is: see [`Confidence::Pod`].
     pub container_name: String,
+    /// As read off the cgroup; empty when it did not name one.
     pub container_id: String,
+    /// The runtime the container id belongs to, by the `<runtime>://` scheme of its status
+    /// (`containerd`, `cri-o`, `docker`); for a pod-level identity, by the cgroup path, or
+    /// empty when that does not say.
+    pub runtime: String,
+    /// The workload the pod belongs to, from its `ownerReferences`: the Deployment of its
+    /// ReplicaSet, the CronJob of its Job, or its StatefulSet, DaemonSet or other
+    /// controller — or `Pod` and the pod's own name for a pod with no controller.
+    pub workload_kind: String,
+    pub workload_name: String,
+    pub confidence: Confidence,
+}
+
+/// How much of a [`ContainerIdentity`] resolution could vouch for.
+#[cfg(feature = "user")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Confidence {
+    /// A container status on the pod carries the container id.
+    Container,
+    /// Only the pod is known, by the UID on the cgroup path: the container had no id in its
+    /// pod's status yet — it was starting — or the path named none.
+    Pod,
+}
+
+/// The allocation that triggered an OOM kill: its trigger's captured kernel and user
+/// stacks, symbolized, innermost frame first. Either is empty when it was not captured.
+#[cfg(feature = "user")]
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct TriggerStack {
+    pub kernel: Vec<StackFrame>,
+    pub user: Vec<StackFrame>,
+}
+
+/// [`VictimCommand`] decoded: the victim's executable and arguments, each where captured.
+#[cfg(feature = "user")]
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ProcessCommand {
+    /// Path of the executable, from the root of its filesystem; `None` if not captured.
+    /// Prefixed with `…` when its leading directories did not fit.
+    pub exe: Option<String>,
+    /// The arguments, `argv[0]` first; empty if not captured.
+    pub args: Vec<String>,
+    /// Whether the arguments were cut short, the last one possibly mid-way.
+    pub truncated: bool,
+}
+
+/// One frame of a [`TriggerStack`]: an instruction pointer and, where it could be
+/// symbolized, the function it falls in and the module or file that holds it.
+#[cfg(feature = "user")]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct StackFrame {
+    pub address: u64,
+    pub symbol: Option<String>,
+    pub offset: u64, // From the start of `symbol`
+    pub module: Option<String>,
+}
+
+#[cfg(feature = "user")]
+impl fmt::Display for StackFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.symbol {
+            Some(symbol) => write!(f, "{}+{:#x}", symbol, self.offset)?,
+            None => write!(f, "{:#x}", self.address)?,
+        }
+        match &self.module {
+            Some(module) => write!(f, " [{}]", module),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Whose memory ran out: the scope of the limit an OOM kill enforced. Derived from the
+/// kill's constraint and the memcg that hit its limit, so alerts can route node pressure
+/// to the platform team and limit breaches to the workload's owners.
+#[cfg(feature = "user")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OomScope {
+    /// The victim's own container hit its memory limit.
+    Container,
+    /// A pod-level cgroup hit its limit: the pod's containers together.
+    Pod,
+    /// Some other memory cgroup hit its limit, e.g. a QoS class, the node-allocatable
+    /// `kubepods` cgroup, or a non-Kubernetes slice.
+    Cgroup,
+    /// The node itself ran out of memory.
+    Global,
+    /// The allocation's cpuset ran out of memory on its allowed NUMA nodes.
+    Cpuset,
+    /// The allocation's mempolicy ran out of memory on its allowed NUMA nodes.
+    MemoryPolicy,
+    /// No OOM context was captured for the kill.
+    Unknown,
+}
+
+#[cfg(feature = "user")]
+impl OomScope {
+    /// The value used for the `scope` metric label.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Container => "container",
+            Self::Pod => "pod",
+            Self::Cgroup => "cgroup",
+            Self::Global => "global",
+            Self::Cpuset => "cpuset",
+            Self::MemoryPolicy => "mempolicy",
+            Self::Unknown => "unknown",
+        }
+    }
 }
 
 #[cfg(feature = "user")]
@@ -43,5 +594,16 @@ pub struct EnrichedOomEvent {
     pub pod_name: Option<String>,
     pub container_name: Option<String>,
     pub container_id: Option<String>,
+    /// The victim's workload: see [`ContainerIdentity::workload_kind`].
+    pub workload_kind: Option<String>,
+    pub workload_name: Option<String>,
+    /// Whose memory limit the kill enforced.
+    pub scope: OomScope,
+    /// The container whose allocation triggered the kill, when it could be resolved.
+    pub trigger: Option<ContainerIdentity>,
+    /// The stacks of that allocation, when the source captured them.
+    pub trigger_stack: Option<TriggerStack>,
+    /// What the victim was running, when the source captured it.
+    pub victim_command: Option<ProcessCommand>,
     pub timestamp: u64,
 }
@@ -2,75 +2,1218 @@
 #![no_main]
 
 use aya_ebpf::{
-    helpers::{bpf_get_current_pid_tgid, bpf_probe_read_kernel, bpf_probe_read_kernel_str_bytes},
-    macros::{map, tracepoint},
-    maps::ring_buf::RingBuf,
-    programs::TracePointContext,
+    bindings::{
+        BPF_F_REUSE_STACKID, BPF_F_USER_STACK, BPF_RB_AVAIL_DATA, BPF_RB_FORCE_WAKEUP,
+        BPF_RB_NO_WAKEUP,
+    },
+    helpers::{
+        bpf_get_current_comm, bpf_get_current_pid_tgid, bpf_get_current_task,
+        bpf_get_smp_processor_id, bpf_ktime_get_ns, bpf_probe_read_kernel,
+        bpf_probe_read_kernel_buf, bpf_probe_read_kernel_str_bytes, bpf_probe_read_user_buf,
+    },
+    macros::{btf_tracepoint, kprobe, kretprobe, map, tracepoint},
+    maps::{
+        lpm_trie::Key, ring_buf::RingBuf, HashMap, LpmTrie, LruHashMap, LruPerCpuHashMap,
+        PerCpuArray, StackTrace,
+    },
+    programs::{
+        tracing::StackIdContext, BtfTracePointContext, ProbeContext, RetProbeContext,
+        TracePointContext,
+    },
     EbpfContext,
 };
-use oom_watcher_common::OomKillEvent;
+use oom_watcher_common::{
+    FilterConfig, KernelLayout, MemcgPressure, OomKillEvent, OomKillRecord, ReapEvent,
+    ScoreAdjEvent, VictimCommand, WireHeader, WireRecord, CMDLINE_LEN, CONSTRAINT_UNKNOWN, EXE_LEN,
+    FIELD_ANON_RSS, FIELD_FILE_RSS, FIELD_HIWATER_RSS, FIELD_NS_PID, FIELD_OOM_SCORE_ADJ,
+    FIELD_PARENT, FIELD_PGTABLES, FIELD_SHMEM_RSS, FIELD_START_TIME, FIELD_SWAP, FIELD_THREADS,
+    FIELD_TOTAL_VM, FIELD_UID, FIELD_VICTIM_COMM, FILTER_EXCLUDE, FILTER_INCLUDE, KIND_OOM_KILL,
+    KIND_REAP, KIND_SCORE_ADJ, MEMCG_NO_LIMIT, OFFSET_UNKNOWN, REAP_EXITED, REAP_FINISHED,
+    REAP_SKIPPED, REAP_STARTED, REAP_WOKEN, STACK_UNKNOWN,
+};
+
+// Every probe's events, each a WireRecord whose header says which. Sized by the loader
+// from its configuration; this is the default.
+#[map]
+static mut EVENTS: RingBuf = RingBuf::with_byte_size(65536, 0);
+
+// The loader's include/exclude lists, FILTER_INCLUDE or FILTER_EXCLUDE per entry: cgroup
+// ids (each covering its descendants), uids, and comm prefixes. See FilterConfig.
+#[map]
+static mut CGROUP_FILTER: HashMap<u64, u8> = HashMap::with_max_entries(256, 0);
+
+#[map]
+static mut UID_FILTER: HashMap<u32, u8> = HashMap::with_max_entries(256, 0);
+
+#[map]
+static mut COMM_FILTER: LpmTrie<[u8; 16], u8> = LpmTrie::with_max_entries(256, 0);
+
+// Per CPU: the sequence number of the last kill event, and how many kill events EVENTS
+// had no room for. A CPU runs one probe at a time, so neither needs atomics; userspace
+// sums the drops and checks each CPU's sequence for gaps.
+#[map]
+static mut SEQUENCES: PerCpuArray<u64> = PerCpuArray::with_max_entries(1, 0);
+
+#[map]
+static mut LOST_EVENTS: PerCpuArray<u64> = PerCpuArray::with_max_entries(1, 0);
+
+// Victim details captured on entry to __oom_kill_process, keyed by victim pid, for the
+// mark_victim tracepoint that fires further down the same call to pick up. The command is
+// kept apart, too large to pass through the stack.
+#[map]
+static mut VICTIMS: LruHashMap<u32, VictimTask> = LruHashMap::with_max_entries(64, 0);
+
+#[map]
+static mut COMMANDS: LruHashMap<u32, VictimCommand> = LruHashMap::with_max_entries(64, 0);
+
+// Per CPU: where a victim's command is read into.
+#[map]
+static mut COMMAND_SCRATCH: PerCpuArray<VictimCommand> = PerCpuArray::with_max_entries(1, 0);
 
+// The oom_control of each OOM killer invocation in flight, keyed by the pid_tgid of the
+// task running it (the trigger), from entry to return of oom_kill_process. Every
+// mark_victim in between — one per task when a memcg is killed as a group — shares it.
 #[map]
-static mut EVENTS: RingBuf = RingBuf::with_byte_size(4096, 0);
+static mut OOM_CONTEXTS: LruHashMap<u64, OomContext> = LruHashMap::with_max_entries(64, 0);
+
+// Victims marked but not yet exited: victim pid -> bpf_ktime_get_ns() at mark_victim.
+#[map]
+static mut REAPING: LruHashMap<u32, u64> = LruHashMap::with_max_entries(1024, 0);
+
+// Pressure counters per cgroup (keyed by its v2 id, or its memcg id on v1), per CPU so the
+// hot paths never contend. Userspace sums the CPUs.
+#[map]
+static mut MEMCG_PRESSURE: LruPerCpuHashMap<u64, MemcgPressure> =
+    LruPerCpuHashMap::with_max_entries(1024, 0);
+
+// Start times of in-flight memcg reclaims and memory.high throttles, keyed by pid_tgid.
+#[map]
+static mut RECLAIM_STARTS: LruHashMap<u64, u64> = LruHashMap::with_max_entries(1024, 0);
+
+#[map]
+static mut THROTTLE_STARTS: LruHashMap<u64, u64> = LruHashMap::with_max_entries(1024, 0);
+
+// Kernel and user stacks of the allocations that triggered a kill, referenced by id from
+// the event. Ids are reused on a hash collision: userspace reads a stack as soon as its
+// event arrives, and an OOM kill is rare enough that the overwrite is unlikely to race.
+#[map]
+static mut STACKS: StackTrace = StackTrace::with_max_entries(256, 0);
+
+// enum mm counter indices into mm_struct.rss_stat, stable since they were introduced.
+const MM_FILEPAGES: u32 = 0;
+const MM_ANONPAGES: u32 = 1;
+const MM_SWAPENTS: u32 = 2;
+const MM_SHMEMPAGES: u32 = 3;
+
+// Deepest pid namespace nesting the namespaced pid is read at (MAX_PID_NS_LEVEL).
+const MAX_PID_NS_LEVEL: u32 = 32;
+
+// Deepest directory nesting walked up from an executable to its filesystem's root.
+const MAX_PATH_DEPTH: usize = 16;
+
+// Every field of ProcessDetails, for when none could be read.
+const DETAIL_FIELDS: u32 =
+    FIELD_PARENT | FIELD_NS_PID | FIELD_THREADS | FIELD_START_TIME | FIELD_HIWATER_RSS | FIELD_SWAP;
+
+// Kernel struct offsets, patched in by the loader from the running kernel's BTF.
+#[no_mangle]
+static LAYOUT: KernelLayout = KernelLayout::UNKNOWN;
+
+// Which of the filter maps hold include lists, patched in by the loader.
+#[no_mangle]
+static FILTERS: FilterConfig = FilterConfig::NONE;
+
+// Bytes that must be waiting in EVENTS before a record wakes userspace, patched in by the
+// loader; 0 wakes it for every record. Batching is for storms: userspace reads the ring
+// buffer on a deadline too, so a kill short of a batch is late, never lost.
+#[no_mangle]
+static WAKEUP_BYTES: u64 = 0;
+
+// Deepest cgroup nesting the filter walks up from a victim's cgroup. A Kubernetes
+// container sits four levels down.
+const MAX_CGROUP_DEPTH: usize = 8;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct VictimTask {
+    tgid: u32,
+    cgroup_id: u64,
+    memcg_id: u64,
+    cgroup_listing: Option<u8>,
+    details: ProcessDetails,
+}
+
+// The victim's details only its task_struct holds, for every attach mode. Fields that
+// could not be read are 0 and flagged in unknown_fields.
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct ProcessDetails {
+    ppid: u32,
+    parent_comm: [u8; 16],
+    ns_pid: u32,
+    threads: u32,
+    start_time: u64,
+    age_ns: u64,
+    hiwater_rss: u64,
+    swap: u64,
+    unknown_fields: u32,
+}
 
-// Tracepoint data structure matching the format from /sys/kernel/tracing/events/oom/mark_victim/format
 #[repr(C)]
-struct MarkVictimArgs {
-    common_type: u16,
-    common_flags: u8,
-    common_preempt_count: u8,
-    common_pid: i32,
-
-    pid: i32,           // offset:8
-    comm_data_loc: u32, // offset:12 - __data_loc for comm string
-    total_vm: u64,      // offset:16
-    anon_rss: u64,      // offset:24
-    file_rss: u64,      // offset:32
-    shmem_rss: u64,     // offset:40
-    uid: u32,           // offset:48
-    pgtables: u64,      // offset:56
-    oom_score_adj: i16, // offset:64
-}
-
-// Use the oom:mark_victim tracepoint which is available on this kernel
+#[derive(Copy, Clone)]
+struct OomContext {
+    constraint: u32,
+    gfp_mask: u32,
+    order: i32,
+    memcg_id: u64,
+    memcg: MemcgSnapshot,
+}
+
+// The breached memcg's page counters, converted to bytes. Zeros when unknown.
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct MemcgSnapshot {
+    usage: u64,
+    limit: u64,
+    swap: u64,
+    peak: u64,
+    failcnt: u64,
+}
+
+// The tracepoint only carries the victim's pid, and by the time userspace looks the victim
+// may already be reaped. __oom_kill_process(victim, message) runs just before mark_victim,
+// so read the victim's tgid, cgroups and process details from its task_struct here and hand
+// them over by pid.
+#[kprobe]
+pub fn victim_task(ctx: ProbeContext) -> u32 {
+    let task: *const u8 = match ctx.arg(0) {
+        Some(task) => task,
+        None => return 0,
+    };
+    let layout = layout();
+
+    let pid: i32 = match unsafe { read_field(task, layout.task_struct_pid) } {
+        Some(pid) => pid,
+        None => return 0,
+    };
+    let victim = VictimTask {
+        tgid: unsafe { read_field::<i32>(task, layout.task_struct_tgid) }.unwrap_or(0) as u32,
+        cgroup_id: dfl_cgroup_id(task, &layout).unwrap_or(0),
+        memcg_id: memcg_cgroup_id(task, &layout).unwrap_or(0),
+        cgroup_listing: cgroup_listing(task, &layout),
+        details: process_details(task, &layout),
+    };
+
+    unsafe {
+        let _ = (*core::ptr::addr_of_mut!(VICTIMS)).insert(pid as u32, victim, 0);
+        if let Some(command) = victim_command(task, &layout) {
+            // By reference: a copy of the command would not fit the stack.
+            #[allow(clippy::needless_borrows_for_generic_args)]
+            let _ = (*core::ptr::addr_of_mut!(COMMANDS)).insert(pid as u32, &*command, 0);
+        }
+    }
+
+    0
+}
+
+// oom_kill_process(struct oom_control *oc, const char *message): the OOM killer has chosen
+// a victim and is about to kill it. Capture why — constraint, allocation, breached memcg.
+#[kprobe]
+pub fn oom_context(ctx: ProbeContext) -> u32 {
+    let oc: *const u8 = match ctx.arg(0) {
+        Some(oc) => oc,
+        None => return 0,
+    };
+    let layout = layout();
+
+    let context = read_oom_context(oc, &layout);
+
+    unsafe {
+        let _ =
+            (*core::ptr::addr_of_mut!(OOM_CONTEXTS)).insert(bpf_get_current_pid_tgid(), context, 0);
+    }
+
+    0
+}
+
+#[inline(always)]
+fn read_oom_context(oc: *const u8, layout: &KernelLayout) -> OomContext {
+    unsafe {
+        // A NULL oc->memcg is a global OOM; the id and snapshot stay 0.
+        let memcg =
+            read_field::<*const u8>(oc, layout.oom_control_memcg).unwrap_or(core::ptr::null());
+        OomContext {
+            constraint: read_field(oc, layout.oom_control_constraint).unwrap_or(CONSTRAINT_UNKNOWN),
+            gfp_mask: read_field(oc, layout.oom_control_gfp_mask).unwrap_or(0),
+            order: read_field(oc, layout.oom_control_order).unwrap_or(0),
+            memcg_id: memcg_id(memcg, layout).unwrap_or(0),
+            memcg: memcg_snapshot(memcg, layout),
+        }
+    }
+}
+
+// Drop the context once oom_kill_process returns, so a later mark_victim outside it (a task
+// already exiting is marked directly by out_of_memory) cannot pick up a stale one.
+#[kretprobe]
+pub fn oom_context_exit(_ctx: RetProbeContext) -> u32 {
+    unsafe {
+        let _ = (*core::ptr::addr_of_mut!(OOM_CONTEXTS)).remove(bpf_get_current_pid_tgid());
+    }
+    0
+}
+
 #[tracepoint]
-pub fn mark_victim(ctx: TracePointContext) -> u32 {
-    let tgid_pid = bpf_get_current_pid_tgid() as u64;
-    let current_tgid = (tgid_pid >> 32) as u32;
-
-    // Read tracepoint arguments
-    let args: MarkVictimArgs =
-        match unsafe { bpf_probe_read_kernel(ctx.as_ptr() as *const MarkVictimArgs) } {
-            Ok(args) => args,
-            Err(_) => return 0,
+pub fn wake_reaper(ctx: TracePointContext) -> u32 {
+    reaper_stage(&ctx, REAP_WOKEN)
+}
+
+#[tracepoint]
+pub fn start_task_reaping(ctx: TracePointContext) -> u32 {
+    reaper_stage(&ctx, REAP_STARTED)
+}
+
+#[tracepoint]
+pub fn finish_task_reaping(ctx: TracePointContext) -> u32 {
+    reaper_stage(&ctx, REAP_FINISHED)
+}
+
+#[tracepoint]
+pub fn skip_task_reaping(ctx: TracePointContext) -> u32 {
+    reaper_stage(&ctx, REAP_SKIPPED)
+}
+
+// Fires for every exiting task on the node; only marked victims get past the lookup. The
+// victim's exit ends its lifecycle, so its entry is dropped here.
+#[tracepoint]
+pub fn victim_exit(ctx: TracePointContext) -> u32 {
+    let layout = layout();
+    let record = ctx.as_ptr() as *const u8;
+    let pid = match unsafe { read_field::<i32>(record, layout.process_exit_pid) } {
+        Some(pid) => pid as u32,
+        None => return 0,
+    };
+    emit_reap_stage(pid, REAP_EXITED);
+    unsafe {
+        let _ = (*core::ptr::addr_of_mut!(REAPING)).remove(pid);
+    }
+    0
+}
+
+// The oom reaper tracepoints (wake_reaper, start/finish/skip_task_reaping) all carry just
+// the victim's pid, where the loader found it in their format files.
+#[inline(always)]
+fn reaper_stage(ctx: &TracePointContext, stage: u32) -> u32 {
+    let layout = layout();
+    let record = ctx.as_ptr() as *const u8;
+    if let Some(pid) = unsafe { read_field::<i32>(record, layout.reaper_pid) } {
+        emit_reap_stage(pid as u32, stage);
+    }
+    0
+}
+
+// Report `stage` for `pid` if it is a victim still being followed.
+#[inline(always)]
+fn emit_reap_stage(pid: u32, stage: u32) {
+    let marked = match unsafe { (*core::ptr::addr_of_mut!(REAPING)).get(pid) } {
+        Some(marked) => *marked,
+        None => return,
+    };
+    let event = ReapEvent {
+        pid,
+        stage,
+        elapsed_ns: unsafe { bpf_ktime_get_ns() }.saturating_sub(marked),
+    };
+    emit(KIND_REAP, event);
+}
+
+// Fires on every write to /proc/<pid>/oom_score_adj (and the legacy oom_adj), in the
+// writer's context. The tracepoint does not carry the target task, so its cgroups are only
+// captured when the writer is the target itself — the common case of a process adjusting
+// its own score; otherwise userspace resolves the pid, which is still alive. The record is
+// read where the loader found its fields; without the pid or the score there is no event.
+#[tracepoint]
+pub fn score_adj_update(ctx: TracePointContext) -> u32 {
+    let layout = layout();
+    let record = ctx.as_ptr() as *const u8;
+    let (pid, oom_score_adj) = match unsafe {
+        (
+            read_field::<i32>(record, layout.score_adj_pid),
+            read_field::<i16>(record, layout.score_adj_oom_score_adj),
+        )
+    } {
+        (Some(pid), Some(oom_score_adj)) => (pid as u32, oom_score_adj),
+        _ => return 0,
+    };
+    let comm = unsafe { read_field::<[u8; 16]>(record, layout.score_adj_comm) }.unwrap_or([0; 16]);
+    let writer_pid = bpf_get_current_pid_tgid() as u32;
+    let current = unsafe { bpf_get_current_task() } as *const u8;
+    let (cgroup_id, memcg_id) = if pid == writer_pid {
+        (
+            dfl_cgroup_id(current, &layout).unwrap_or(0),
+            memcg_cgroup_id(current, &layout).unwrap_or(0),
+        )
+    } else {
+        (0, 0)
+    };
+
+    let event = ScoreAdjEvent {
+        pid,
+        comm,
+        oom_score_adj,
+        cgroup_id,
+        memcg_id,
+        writer_pid,
+        writer_comm: bpf_get_current_comm().unwrap_or([0; 16]),
+    };
+    emit(KIND_SCORE_ADJ, event);
+    0
+}
+
+// A task charging a memcg at its limit (or over memory.high) reclaims from it directly:
+// try_to_free_mem_cgroup_pages, bracketed by these two tracepoints, in the task's context.
+#[tracepoint]
+pub fn memcg_reclaim_begin(_ctx: TracePointContext) -> u32 {
+    unsafe {
+        let _ = (*core::ptr::addr_of_mut!(RECLAIM_STARTS)).insert(
+            bpf_get_current_pid_tgid(),
+            bpf_ktime_get_ns(),
+            0,
+        );
+    }
+    0
+}
+
+#[tracepoint]
+pub fn memcg_reclaim_end(ctx: TracePointContext) -> u32 {
+    let elapsed = match take_start(unsafe { &mut *core::ptr::addr_of_mut!(RECLAIM_STARTS) }) {
+        Some(elapsed) => elapsed,
+        None => return 0,
+    };
+    // Where the loader found it; without it the reclaim is still counted and timed.
+    let layout = layout();
+    let nr_reclaimed =
+        unsafe { read_field::<u64>(ctx.as_ptr() as *const u8, layout.reclaim_end_nr_reclaimed) }
+            .unwrap_or(0);
+    if let Some(pressure) = pressure() {
+        let pressure = unsafe { &mut *pressure };
+        pressure.reclaims += 1;
+        pressure.reclaim_ns += elapsed;
+        pressure.reclaimed_pages += nr_reclaimed;
+    }
+    0
+}
+
+// try_charge_memcg returns -ENOMEM once reclaim (and the OOM killer) could not make room.
+#[kretprobe]
+pub fn charge_failed(ctx: RetProbeContext) -> u32 {
+    if ctx.ret::<i32>() != 0 {
+        if let Some(pressure) = pressure() {
+            unsafe { (*pressure).charge_failures += 1 };
+        }
+    }
+    0
+}
+
+// mem_cgroup_handle_over_high runs on return to userspace for a task whose memcg is over
+// memory.high: it reclaims, then sleeps the task as a penalty. Time it from entry to return.
+#[kprobe]
+pub fn over_high(_ctx: ProbeContext) -> u32 {
+    unsafe {
+        let _ = (*core::ptr::addr_of_mut!(THROTTLE_STARTS)).insert(
+            bpf_get_current_pid_tgid(),
+            bpf_ktime_get_ns(),
+            0,
+        );
+    }
+    0
+}
+
+#[kretprobe]
+pub fn over_high_exit(_ctx: RetProbeContext) -> u32 {
+    let elapsed = match take_start(unsafe { &mut *core::ptr::addr_of_mut!(THROTTLE_STARTS) }) {
+        Some(elapsed) => elapsed,
+        None => return 0,
+    };
+    if let Some(pressure) = pressure() {
+        let pressure = unsafe { &mut *pressure };
+        pressure.high_throttles += 1;
+        pressure.high_throttle_ns += elapsed;
+    }
+    0
+}
+
+// Remove the current task's start time from `starts`, returning the time since it.
+#[inline(always)]
+fn take_start(starts: &mut LruHashMap<u64, u64>) -> Option<u64> {
+    let pid_tgid = bpf_get_current_pid_tgid();
+    let start = unsafe { starts.get(pid_tgid) }.copied()?;
+    let _ = starts.remove(pid_tgid);
+    Some(unsafe { bpf_ktime_get_ns() }.saturating_sub(start))
+}
+
+// The current task's cgroup's pressure counters on this CPU, created on first use.
+#[inline(always)]
+fn pressure() -> Option<*mut MemcgPressure> {
+    let layout = layout();
+    let task = unsafe { bpf_get_current_task() } as *const u8;
+    let cgroup_id = dfl_cgroup_id(task, &layout).unwrap_or(0);
+    let memcg_id = memcg_cgroup_id(task, &layout).unwrap_or(0);
+    let key = if cgroup_id != 0 { cgroup_id } else { memcg_id };
+    if key == 0 {
+        return None;
+    }
+
+    let pid_tgid = bpf_get_current_pid_tgid();
+    let map = unsafe { &mut *core::ptr::addr_of_mut!(MEMCG_PRESSURE) };
+    if map.get_ptr_mut(key).is_none() {
+        let fresh = MemcgPressure {
+            cgroup_id,
+            memcg_id,
+            ..MemcgPressure::default()
         };
+        let _ = map.insert(key, fresh, 0);
+    }
+    let pressure = map.get_ptr_mut(key)?;
+    unsafe {
+        (*pressure).last_pid = pid_tgid as u32;
+        (*pressure).last_tgid = (pid_tgid >> 32) as u32;
+    }
+    Some(pressure)
+}
+
+// Use the oom:mark_victim tracepoint which is available on this kernel. The tracepoint
+// describes the victim; the current task is the trigger, whose allocation failed.
+#[tracepoint]
+pub fn mark_victim(ctx: TracePointContext) -> u32 {
+    let layout = layout();
+
+    // The record's layout varies by kernel: read only the fields the loader found in its
+    // format file, and flag the rest as unknown.
+    let record = ctx.as_ptr() as *const u8;
+    let victim_pid: u32 = match unsafe { read_field::<i32>(record, layout.mark_victim_pid) } {
+        Some(pid) => pid as u32,
+        None => return 0,
+    };
+    let mut unknown_fields = 0;
+    let mut field = |offset: u32, flag: u32| -> Option<u32> {
+        if offset == OFFSET_UNKNOWN {
+            unknown_fields |= flag;
+            return None;
+        }
+        Some(offset)
+    };
 
-    // Extract comm string from __data_loc field
+    // comm is a __data_loc field: its low 16 bits locate the string within the record.
     let mut comm = [0u8; 16];
-    let comm_offset = (args.comm_data_loc & 0xFFFF) as usize;
-    let comm_ptr = unsafe { (ctx.as_ptr() as *const u8).add(comm_offset) };
-    let _ = unsafe { bpf_probe_read_kernel_str_bytes(comm_ptr, &mut comm) };
+    if let Some(offset) = field(layout.mark_victim_comm, FIELD_VICTIM_COMM) {
+        if let Some(data_loc) = unsafe { read_field::<u32>(record, offset) } {
+            let comm_ptr = unsafe { record.add((data_loc & 0xFFFF) as usize) };
+            let _ = unsafe { bpf_probe_read_kernel_str_bytes(comm_ptr, &mut comm) };
+        }
+    }
+    let total_vm = field(layout.mark_victim_total_vm, FIELD_TOTAL_VM);
+    let anon_rss = field(layout.mark_victim_anon_rss, FIELD_ANON_RSS);
+    let file_rss = field(layout.mark_victim_file_rss, FIELD_FILE_RSS);
+    let shmem_rss = field(layout.mark_victim_shmem_rss, FIELD_SHMEM_RSS);
+    let uid = field(layout.mark_victim_uid, FIELD_UID);
+    let pgtables = field(layout.mark_victim_pgtables, FIELD_PGTABLES);
+    let oom_score_adj = field(layout.mark_victim_oom_score_adj, FIELD_OOM_SCORE_ADJ);
+    let kb = |offset: Option<u32>| -> u64 {
+        offset
+            .and_then(|o| unsafe { read_field::<u64>(record, o) })
+            .unwrap_or(0)
+    };
 
-    let event = OomKillEvent {
-        pid: args.pid as u32,
-        tgid: current_tgid,
-        comm,
-        total_vm: args.total_vm,
-        anon_rss: args.anon_rss,
-        file_rss: args.file_rss,
-        shmem_rss: args.shmem_rss,
-        uid: args.uid,
-        pgtables: args.pgtables,
-        oom_score_adj: args.oom_score_adj,
+    // Zeros mean "unknown": the kprobe did not attach, or BTF lacked an offset.
+    let victim = unsafe {
+        let victims = &mut *core::ptr::addr_of_mut!(VICTIMS);
+        let victim = victims.get(victim_pid).copied();
+        let _ = victims.remove(victim_pid);
+        victim
+    };
+
+    let mut event = OomKillEvent {
+        victim_pid,
+        victim_tgid: victim.map_or(0, |v| v.tgid),
+        victim_comm: comm,
+        total_vm: kb(total_vm),
+        anon_rss: kb(anon_rss),
+        file_rss: kb(file_rss),
+        shmem_rss: kb(shmem_rss),
+        uid: uid
+            .and_then(|o| unsafe { read_field::<u32>(record, o) })
+            .unwrap_or(0),
+        pgtables: kb(pgtables),
+        oom_score_adj: oom_score_adj
+            .and_then(|o| unsafe { read_field::<i16>(record, o) })
+            .unwrap_or(0),
+        victim_cgroup_id: victim.map_or(0, |v| v.cgroup_id),
+        victim_memcg_id: victim.map_or(0, |v| v.memcg_id),
+        unknown_fields,
+        ..Default::default()
+    };
+    match victim {
+        Some(victim) => add_details(&mut event, &victim.details),
+        None => event.unknown_fields |= DETAIL_FIELDS,
+    }
+    let listing = victim.and_then(|v| v.cgroup_listing);
+    let commands = unsafe { &mut *core::ptr::addr_of_mut!(COMMANDS) };
+    let command = commands.get_ptr(victim_pid);
+    emit_kill(
+        &ctx,
+        event,
+        listing,
+        command,
+        current_oom_context(),
+        &layout,
+    );
+    let _ = commands.remove(victim_pid);
+
+    0
+}
+
+// The tp_btf flavour of mark_victim(struct task_struct *task, uid_t uid), preferred where
+// the kernel supports it: it hands over the victim's task_struct itself, so the victim is
+// read whole, without the record layout or the victim_task kprobe.
+#[btf_tracepoint(function = "mark_victim")]
+pub fn mark_victim_btf(ctx: BtfTracePointContext) -> i32 {
+    let layout = layout();
+    let task: *const u8 = ctx.arg(0);
+    let uid: u32 = ctx.arg(1);
+    if let Some(mut event) = victim_from_task(task, &layout) {
+        event.uid = uid;
+        event.unknown_fields &= !FIELD_UID;
+        let listing = cgroup_listing(task, &layout);
+        let command = victim_command(task, &layout);
+        emit_kill(
+            &ctx,
+            event,
+            listing,
+            command,
+            current_oom_context(),
+            &layout,
+        );
+    }
+    0
+}
+
+// Fallback for kernels without oom:mark_victim: __oom_kill_process(victim, message) is
+// called once per task killed, group kills included, by the OOM killer's task.
+#[kprobe]
+pub fn victim_kill(ctx: ProbeContext) -> u32 {
+    let task: *const u8 = match ctx.arg(0) {
+        Some(task) => task,
+        None => return 0,
+    };
+    let layout = layout();
+    if let Some(event) = victim_from_task(task, &layout) {
+        let listing = cgroup_listing(task, &layout);
+        let command = victim_command(task, &layout);
+        emit_kill(
+            &ctx,
+            event,
+            listing,
+            command,
+            current_oom_context(),
+            &layout,
+        );
+    }
+    0
+}
+
+// Last resort when __oom_kill_process is inlined too: oom_kill_process(oc, message), whose
+// oc->chosen is the victim. Only the chosen task is seen, not the rest of a group kill.
+#[kprobe]
+pub fn chosen_kill(ctx: ProbeContext) -> u32 {
+    let oc: *const u8 = match ctx.arg(0) {
+        Some(oc) => oc,
+        None => return 0,
+    };
+    let layout = layout();
+    let task: *const u8 = match unsafe { read_field(oc, layout.oom_control_chosen) } {
+        Some(task) => task,
+        None => return 0,
+    };
+    if let Some(event) = victim_from_task(task, &layout) {
+        let listing = cgroup_listing(task, &layout);
+        let context = read_oom_context(oc, &layout);
+        let command = victim_command(task, &layout);
+        emit_kill(&ctx, event, listing, command, Some(context), &layout);
+    }
+    0
+}
+
+// The victim as its task_struct describes it, with the same units as the tracepoint: the
+// mm's page counts in kB. What the layout lacks, or a task without an mm, reads unknown.
+#[inline(always)]
+fn victim_from_task(task: *const u8, layout: &KernelLayout) -> Option<OomKillEvent> {
+    let pid: i32 = unsafe { read_field(task, layout.task_struct_pid) }?;
+    let comm = unsafe { read_field::<[u8; 16]>(task, layout.task_struct_comm) };
+
+    let mm = task_mm(task, layout);
+    let total_vm = pages_kb(unsafe { read_field(mm, layout.mm_struct_total_vm) }, layout);
+    let anon_rss = rss_kb(mm, MM_ANONPAGES, layout);
+    let file_rss = rss_kb(mm, MM_FILEPAGES, layout);
+    let shmem_rss = rss_kb(mm, MM_SHMEMPAGES, layout);
+    let pgtables = unsafe { read_field::<u64>(mm, layout.mm_struct_pgtables) }.map(|b| b / 1024);
+    let uid: Option<u32> = unsafe {
+        read_field::<*const u8>(task, layout.task_struct_cred)
+            .and_then(|cred| read_field(cred, layout.cred_uid))
+    };
+    let oom_score_adj: Option<i16> = unsafe {
+        read_field::<*const u8>(task, layout.task_struct_signal)
+            .and_then(|signal| read_field(signal, layout.signal_oom_score_adj))
+    };
+
+    let mut unknown_fields = 0;
+    for (known, flag) in [
+        (comm.is_some(), FIELD_VICTIM_COMM),
+        (total_vm.is_some(), FIELD_TOTAL_VM),
+        (anon_rss.is_some(), FIELD_ANON_RSS),
+        (file_rss.is_some(), FIELD_FILE_RSS),
+        (shmem_rss.is_some(), FIELD_SHMEM_RSS),
+        (uid.is_some(), FIELD_UID),
+        (pgtables.is_some(), FIELD_PGTABLES),
+        (oom_score_adj.is_some(), FIELD_OOM_SCORE_ADJ),
+    ] {
+        if !known {
+            unknown_fields |= flag;
+        }
+    }
+
+    let mut event = OomKillEvent {
+        victim_pid: pid as u32,
+        victim_tgid: unsafe { read_field::<i32>(task, layout.task_struct_tgid) }.unwrap_or(0)
+            as u32,
+        victim_comm: comm.unwrap_or([0; 16]),
+        total_vm: total_vm.unwrap_or(0),
+        anon_rss: anon_rss.unwrap_or(0),
+        file_rss: file_rss.unwrap_or(0),
+        shmem_rss: shmem_rss.unwrap_or(0),
+        uid: uid.unwrap_or(0),
+        pgtables: pgtables.unwrap_or(0),
+        oom_score_adj: oom_score_adj.unwrap_or(0),
+        victim_cgroup_id: dfl_cgroup_id(task, layout).unwrap_or(0),
+        victim_memcg_id: memcg_cgroup_id(task, layout).unwrap_or(0),
+        unknown_fields,
+        ..Default::default()
+    };
+    add_details(&mut event, &process_details(task, layout));
+    Some(event)
+}
+
+// The task's parent, age, thread count, namespaced pid and peak and swapped memory.
+#[inline(always)]
+fn process_details(task: *const u8, layout: &KernelLayout) -> ProcessDetails {
+    let parent: Option<*const u8> = unsafe { read_field(task, layout.task_struct_real_parent) };
+    let ppid: Option<i32> = parent.and_then(|p| unsafe { read_field(p, layout.task_struct_tgid) });
+    let parent_comm: Option<[u8; 16]> =
+        parent.and_then(|p| unsafe { read_field(p, layout.task_struct_comm) });
+    let threads: Option<i32> = unsafe {
+        read_field::<*const u8>(task, layout.task_struct_signal)
+            .and_then(|signal| read_field(signal, layout.signal_nr_threads))
+    };
+    let ns_pid = unsafe { ns_pid(task, layout) };
+    let start_time: Option<u64> = unsafe { read_field(task, layout.task_struct_start_time) };
+
+    // hiwater_rss is only brought up to date as the RSS shrinks, so the peak so far is the
+    // larger of it and the current RSS.
+    let mm = task_mm(task, layout);
+    let rss = match (
+        rss_kb(mm, MM_ANONPAGES, layout),
+        rss_kb(mm, MM_FILEPAGES, layout),
+        rss_kb(mm, MM_SHMEMPAGES, layout),
+    ) {
+        (Some(anon), Some(file), Some(shmem)) => anon + file + shmem,
+        _ => 0,
     };
+    let hiwater_rss = pages_kb(
+        unsafe { read_field(mm, layout.mm_struct_hiwater_rss) },
+        layout,
+    )
+    .map(|hiwater| hiwater.max(rss));
+    let swap = rss_kb(mm, MM_SWAPENTS, layout);
+
+    let mut unknown_fields = 0;
+    for (known, flag) in [
+        (ppid.is_some() && parent_comm.is_some(), FIELD_PARENT),
+        (ns_pid.is_some(), FIELD_NS_PID),
+        (threads.is_some(), FIELD_THREADS),
+        (start_time.is_some(), FIELD_START_TIME),
+        (hiwater_rss.is_some(), FIELD_HIWATER_RSS),
+        (swap.is_some(), FIELD_SWAP),
+    ] {
+        if !known {
+            unknown_fields |= flag;
+        }
+    }
+
+    ProcessDetails {
+        ppid: ppid.unwrap_or(0) as u32,
+        parent_comm: parent_comm.unwrap_or([0; 16]),
+        ns_pid: ns_pid.unwrap_or(0),
+        threads: threads.unwrap_or(0) as u32,
+        start_time: start_time.unwrap_or(0),
+        age_ns: start_time.map_or(0, |start| {
+            unsafe { bpf_ktime_get_ns() }.saturating_sub(start)
+        }),
+        hiwater_rss: hiwater_rss.unwrap_or(0),
+        swap: swap.unwrap_or(0),
+        unknown_fields,
+    }
+}
+
+#[inline(always)]
+fn add_details(event: &mut OomKillEvent, details: &ProcessDetails) {
+    event.victim_ppid = details.ppid;
+    event.victim_parent_comm = details.parent_comm;
+    event.victim_ns_pid = details.ns_pid;
+    event.threads = details.threads;
+    event.start_time = details.start_time;
+    event.age_ns = details.age_ns;
+    event.hiwater_rss = details.hiwater_rss;
+    event.swap = details.swap;
+    event.unknown_fields |= details.unknown_fields;
+}
+
+// task->thread_pid->numbers[level].nr: the task's pid as seen in the innermost pid
+// namespace it belongs to — its container's, for a containerised process.
+#[inline(always)]
+unsafe fn ns_pid(task: *const u8, layout: &KernelLayout) -> Option<u32> {
+    if layout.pid_numbers == OFFSET_UNKNOWN || layout.upid_size == OFFSET_UNKNOWN {
+        return None;
+    }
+    let pid: *const u8 = read_field(task, layout.task_struct_thread_pid)?;
+    let level: u32 = read_field(pid, layout.pid_level)?;
+    let upid = layout.pid_numbers + level.min(MAX_PID_NS_LEVEL) * layout.upid_size;
+    read_field::<i32>(pid.add(upid as usize), layout.upid_nr).map(|nr| nr as u32)
+}
+
+// Read the task's command into this CPU's scratch slot, for emit_kill to copy out.
+#[inline(always)]
+fn victim_command(task: *const u8, layout: &KernelLayout) -> Option<*const VictimCommand> {
+    let command = unsafe { (*core::ptr::addr_of!(COMMAND_SCRATCH)).get_ptr_mut(0) }?;
+    unsafe { read_command(task, layout, &mut *command) };
+    Some(command)
+}
+
+// The task's executable path and the start of its argv.
+//
+// bpf_d_path would give the executable's full path, but the kernel only allows it on a few
+// fentry and LSM hooks, none of which the kill probes are; so the path is walked up the
+// dentries instead, to the root of the filesystem the executable is on.
+//
+// argv is user memory of the task's mm, which a probe can only read from inside that mm:
+// it is captured when the victim shares the current task's — the trigger's — mm, as when
+// a process is killed for its own allocation.
+#[inline(always)]
+unsafe fn read_command(task: *const u8, layout: &KernelLayout, command: &mut VictimCommand) {
+    command.exe_start = EXE_LEN as u32;
+    command.exe_complete = 0;
+    command.cmdline_len = 0;
+    command.args_len = 0;
+    let mm = task_mm(task, layout);
+    if mm.is_null() {
+        return;
+    }
+
+    if layout.file_f_path != OFFSET_UNKNOWN && layout.path_dentry != OFFSET_UNKNOWN {
+        let dentry: Option<*const u8> = read_field::<*const u8>(mm, layout.mm_struct_exe_file)
+            .and_then(|file| read_field(file, layout.file_f_path + layout.path_dentry));
+        if let Some(dentry) = dentry {
+            exe_path(dentry, layout, command);
+        }
+    }
+
+    if task_mm(bpf_get_current_task() as *const u8, layout) != mm {
+        return;
+    }
+    let arg_start: Option<u64> = read_field(mm, layout.mm_struct_arg_start);
+    let arg_end: Option<u64> = read_field(mm, layout.mm_struct_arg_end);
+    if let (Some(start), Some(end)) = (arg_start, arg_end) {
+        let args_len = end.saturating_sub(start);
+        let len = args_len.min(CMDLINE_LEN as u64) as usize;
+        if bpf_probe_read_user_buf(start as *const u8, &mut command.cmdline[..len]).is_ok() {
+            command.cmdline_len = len as u32;
+            command.args_len = args_len.min(u32::MAX as u64) as u32;
+        }
+    }
+}
+
+// Write the path of `dentry` into the back of command.exe, one name at a time from the
+// executable up, until the filesystem's root (which is its own parent) or the buffer's
+// start; exe_start then says where it begins.
+#[inline(always)]
+unsafe fn exe_path(mut dentry: *const u8, layout: &KernelLayout, command: &mut VictimCommand) {
+    if layout.dentry_d_parent == OFFSET_UNKNOWN
+        || layout.dentry_d_name == OFFSET_UNKNOWN
+        || layout.qstr_len == OFFSET_UNKNOWN
+        || layout.qstr_name == OFFSET_UNKNOWN
+    {
+        return;
+    }
+    let mut start = EXE_LEN - 1;
+    command.exe[start] = 0;
+    for _ in 0..MAX_PATH_DEPTH {
+        let parent: *const u8 = match read_field(dentry, layout.dentry_d_parent) {
+            Some(parent) => parent,
+            None => return,
+        };
+        if parent == dentry {
+            command.exe_complete = 1;
+            break;
+        }
+        let name: Option<*const u8> = read_field(dentry, layout.dentry_d_name + layout.qstr_name);
+        let len: Option<u32> = read_field(dentry, layout.dentry_d_name + layout.qstr_len);
+        let (name, len) = match (name, len) {
+            (Some(name), Some(len)) if (len as usize) < start => (name, len as usize),
+            _ => break,
+        };
+        start -= len;
+        if bpf_probe_read_kernel_buf(name, &mut command.exe[start..start + len]).is_err() {
+            return;
+        }
+        start -= 1;
+        command.exe[start] = b'/';
+        command.exe_start = start as u32;
+        dentry = parent;
+    }
+}
+
+// task->mm, or null for a task without one (or an unknown offset), which reads as unknown.
+#[inline(always)]
+fn task_mm(task: *const u8, layout: &KernelLayout) -> *const u8 {
+    unsafe { read_field(task, layout.task_struct_mm) }.unwrap_or(core::ptr::null())
+}
+
+// A page count in kB, the tracepoint's unit.
+#[inline(always)]
+fn pages_kb(pages: Option<u64>, layout: &KernelLayout) -> Option<u64> {
+    match layout.page_size {
+        OFFSET_UNKNOWN => None,
+        page_size => Some(pages?.saturating_mul(page_size as u64 / 1024)),
+    }
+}
+
+// mm->rss_stat[member] in kB.
+#[inline(always)]
+fn rss_kb(mm: *const u8, member: u32, layout: &KernelLayout) -> Option<u64> {
+    if mm.is_null()
+        || layout.mm_struct_rss_stat == OFFSET_UNKNOWN
+        || layout.rss_stat_stride == OFFSET_UNKNOWN
+    {
+        return None;
+    }
+    let counter = layout.mm_struct_rss_stat + member * layout.rss_stat_stride;
+    let mm_counter = unsafe { mm.add(counter as usize) };
+    // Per-CPU batching can leave the approximate total briefly negative.
+    let pages: i64 = unsafe { read_field(mm_counter, layout.rss_stat_count) }?;
+    pages_kb(Some(pages.max(0) as u64), layout)
+}
+
+// The oom_control captured by oom_context for the OOM killer invocation the current task
+// is running, if any.
+#[inline(always)]
+fn current_oom_context() -> Option<OomContext> {
+    unsafe {
+        (*core::ptr::addr_of_mut!(OOM_CONTEXTS))
+            .get(bpf_get_current_pid_tgid())
+            .copied()
+    }
+}
+
+// Complete a kill whose victim fields are filled in — the trigger is the current task,
+// whose allocation failed — and publish it with the victim's command, whichever attach
+// mode saw it, unless the filters drop it. `cgroup_listing` is the victim's from
+// cgroup_listing.
+#[inline(always)]
+fn emit_kill<C: StackIdContext>(
+    ctx: &C,
+    mut event: OomKillEvent,
+    cgroup_listing: Option<u8>,
+    command: Option<*const VictimCommand>,
+    context: Option<OomContext>,
+    layout: &KernelLayout,
+) {
+    if !admitted(&event, cgroup_listing) {
+        return;
+    }
+
+    let tgid_pid = bpf_get_current_pid_tgid();
+    let trigger = unsafe { bpf_get_current_task() } as *const u8;
+
+    event.trigger_pid = tgid_pid as u32;
+    event.trigger_tgid = (tgid_pid >> 32) as u32;
+    event.trigger_comm = bpf_get_current_comm().unwrap_or([0; 16]);
+    event.trigger_cgroup_id = dfl_cgroup_id(trigger, layout).unwrap_or(0);
+    event.trigger_memcg_id = memcg_cgroup_id(trigger, layout).unwrap_or(0);
+    event.kernel_stack_id = stack_id(ctx, BPF_F_REUSE_STACKID as u64);
+    event.user_stack_id = stack_id(ctx, (BPF_F_USER_STACK | BPF_F_REUSE_STACKID) as u64);
+    event.constraint = context.map_or(CONSTRAINT_UNKNOWN, |c| c.constraint);
+    event.gfp_mask = context.map_or(0, |c| c.gfp_mask);
+    event.order = context.map_or(0, |c| c.order);
+    event.oom_memcg_id = context.map_or(0, |c| c.memcg_id);
+    event.memcg_usage = context.map_or(0, |c| c.memcg.usage);
+    event.memcg_limit = context.map_or(0, |c| c.memcg.limit);
+    event.memcg_swap = context.map_or(0, |c| c.memcg.swap);
+    event.memcg_peak = context.map_or(0, |c| c.memcg.peak);
+    event.memcg_failcnt = context.map_or(0, |c| c.memcg.failcnt);
+    event.cpu = unsafe { bpf_get_smp_processor_id() };
+    event.sequence = bump(unsafe { &*core::ptr::addr_of!(SEQUENCES) });
 
     unsafe {
         // Access the mutable static through a raw pointer to avoid creating a
         // shared reference to it (see the `static_mut_refs` lint).
-        let _ = (*core::ptr::addr_of_mut!(EVENTS)).output::<OomKillEvent>(&event, 0);
+        match (*core::ptr::addr_of_mut!(EVENTS)).reserve::<WireRecord<OomKillRecord>>(0) {
+            Some(mut entry) => {
+                // Fill the record in place: the command is too large for the stack.
+                let record = entry.as_mut_ptr();
+                (*record).header = WireHeader::of::<OomKillRecord>(KIND_OOM_KILL);
+                (*record).body.event = event;
+                let out = &mut (*record).body.command;
+                match command {
+                    Some(command) => {
+                        let bytes = core::slice::from_raw_parts_mut(
+                            out as *mut VictimCommand as *mut u8,
+                            size_of::<VictimCommand>(),
+                        );
+                        if bpf_probe_read_kernel_buf(command as *const u8, bytes).is_err() {
+                            out.exe_start = EXE_LEN as u32;
+                            out.args_len = 0;
+                        }
+                    }
+                    None => {
+                        out.exe_start = EXE_LEN as u32;
+                        out.args_len = 0;
+                    }
+                }
+                entry.submit(wakeup_flags());
+            }
+            None => {
+                bump(&*core::ptr::addr_of!(LOST_EVENTS));
+            }
+        }
+        // Follow the victim through the reaper until it exits.
+        let _ = (*core::ptr::addr_of_mut!(REAPING)).insert(event.victim_pid, bpf_ktime_get_ns(), 0);
     }
+}
 
-    0
+// Publish `body` on EVENTS as a `kind` record. Only kills count as lost when EVENTS is
+// full: a missed reap step or score change is not a missed kill.
+#[inline(always)]
+fn emit<T: 'static>(kind: u16, body: T) {
+    unsafe {
+        if let Some(mut entry) = (*core::ptr::addr_of_mut!(EVENTS)).reserve::<WireRecord<T>>(0) {
+            entry.write(WireRecord {
+                header: WireHeader::of::<T>(kind),
+                body,
+            });
+            entry.submit(wakeup_flags());
+        }
+    }
+}
+
+// How a record's submit should wake userspace, given WAKEUP_BYTES.
+#[inline(always)]
+fn wakeup_flags() -> u64 {
+    let batch = unsafe { core::ptr::read_volatile(core::ptr::addr_of!(WAKEUP_BYTES)) };
+    if batch == 0 {
+        return 0;
+    }
+    let waiting = unsafe { (*core::ptr::addr_of!(EVENTS)).query(BPF_RB_AVAIL_DATA as u64) };
+    if waiting >= batch {
+        BPF_RB_FORCE_WAKEUP as u64
+    } else {
+        BPF_RB_NO_WAKEUP as u64
+    }
+}
+
+// Whether the filters let a kill through. In each dimension a victim whose value is
+// unknown passes; otherwise an exclude listing drops it, as does being listed nowhere when
+// that dimension has an include list.
+#[inline(always)]
+fn admitted(event: &OomKillEvent, cgroup_listing: Option<u8>) -> bool {
+    let filters = unsafe { core::ptr::read_volatile(core::ptr::addr_of!(FILTERS)) };
+    let uid_listing = event.has(FIELD_UID).then(|| unsafe {
+        (*core::ptr::addr_of!(UID_FILTER))
+            .get(event.uid)
+            .copied()
+            .unwrap_or(0)
+    });
+    let comm_listing = event.has(FIELD_VICTIM_COMM).then(|| unsafe {
+        (*core::ptr::addr_of!(COMM_FILTER))
+            .get(Key::new(128, event.victim_comm))
+            .copied()
+            .unwrap_or(0)
+    });
+    admits(cgroup_listing, filters.cgroups_included)
+        && admits(uid_listing, filters.uids_included)
+        && admits(comm_listing, filters.comms_included)
+}
+
+#[inline(always)]
+fn admits(listing: Option<u8>, included: u32) -> bool {
+    match listing {
+        None | Some(FILTER_INCLUDE) => true,
+        Some(FILTER_EXCLUDE) => false,
+        Some(_) => included == 0,
+    }
+}
+
+// The action of the nearest of the task's cgroup and its ancestors in CGROUP_FILTER, 0 if
+// none is listed, or None if the cgroup cannot be read. The hierarchy walked is the one
+// the loader resolved the listed paths in.
+#[inline(always)]
+fn cgroup_listing(task: *const u8, layout: &KernelLayout) -> Option<u8> {
+    let filters = unsafe { core::ptr::read_volatile(core::ptr::addr_of!(FILTERS)) };
+    let cgroup = if filters.memcg_hierarchy != 0 {
+        memcg_cgroup(task, layout)
+    } else {
+        dfl_cgroup(task, layout)
+    }?;
+    let mut kn: *const u8 = unsafe { read_field(cgroup, layout.cgroup_kn) }?;
+    let listed = unsafe { &*core::ptr::addr_of!(CGROUP_FILTER) };
+    for _ in 0..MAX_CGROUP_DEPTH {
+        // The root's parent is NULL, which read_field refuses.
+        let id: u64 = match unsafe { read_field(kn, layout.kernfs_node_id) } {
+            Some(id) => id,
+            None => break,
+        };
+        if let Some(action) = unsafe { listed.get(id) } {
+            return Some(*action);
+        }
+        kn = match unsafe { read_field(kn, layout.kernfs_node_parent) } {
+            Some(parent) => parent,
+            None => break,
+        };
+    }
+    Some(0)
+}
+
+// Increment this CPU's count in `counter`, returning the new value (0 if unreadable).
+#[inline(always)]
+fn bump(counter: &PerCpuArray<u64>) -> u64 {
+    match counter.get_ptr_mut(0) {
+        Some(count) => unsafe {
+            *count += 1;
+            *count
+        },
+        None => 0,
+    }
+}
+
+// The current task's stack, kernel or user per `flags`, stored in STACKS. A kernel thread
+// has no user stack.
+#[inline(always)]
+fn stack_id<C: StackIdContext>(ctx: &C, flags: u64) -> i32 {
+    let stacks = unsafe { &*core::ptr::addr_of!(STACKS) };
+    ctx.get_stackid(stacks, flags)
+        .map_or(STACK_UNKNOWN, |id| id as i32)
+}
+
+// Read LAYOUT through a volatile load so the compiler cannot fold the UNKNOWN initialiser
+// in: the real values only exist once the loader has patched the object.
+#[inline(always)]
+fn layout() -> KernelLayout {
+    unsafe { core::ptr::read_volatile(core::ptr::addr_of!(LAYOUT)) }
+}
+
+// Read a `T` at `base + offset`, or None if the offset is unknown or the read faults.
+#[inline(always)]
+unsafe fn read_field<T>(base: *const u8, offset: u32) -> Option<T> {
+    if base.is_null() || offset == OFFSET_UNKNOWN {
+        return None;
+    }
+    bpf_probe_read_kernel(base.add(offset as usize) as *const T).ok()
+}
+
+// task->cgroups->dfl_cgrp->kn->id: the task's cgroup on the unified (v2) hierarchy.
+#[inline(always)]
+fn dfl_cgroup_id(task: *const u8, layout: &KernelLayout) -> Option<u64> {
+    unsafe { cgroup_id(dfl_cgroup(task, layout)?, layout) }
+}
+
+// task->cgroups->dfl_cgrp
+#[inline(always)]
+fn dfl_cgroup(task: *const u8, layout: &KernelLayout) -> Option<*const u8> {
+    unsafe {
+        let css_set: *const u8 = read_field(task, layout.task_struct_cgroups)?;
+        read_field(css_set, layout.css_set_dfl_cgrp)
+    }
+}
+
+// task->cgroups->subsys[memory_cgrp_id]->cgroup->kn->id: the task's cgroup on the memory
+// controller's hierarchy — the v1 memory mount, or the unified hierarchy on v2.
+#[inline(always)]
+fn memcg_cgroup_id(task: *const u8, layout: &KernelLayout) -> Option<u64> {
+    unsafe { cgroup_id(memcg_cgroup(task, layout)?, layout) }
+}
+
+// task->cgroups->subsys[memory_cgrp_id]->cgroup
+#[inline(always)]
+fn memcg_cgroup(task: *const u8, layout: &KernelLayout) -> Option<*const u8> {
+    if layout.css_set_subsys == OFFSET_UNKNOWN || layout.memory_cgrp_id == OFFSET_UNKNOWN {
+        return None;
+    }
+    let subsys = layout.css_set_subsys + layout.memory_cgrp_id * size_of::<*const u8>() as u32;
+    unsafe {
+        let css_set: *const u8 = read_field(task, layout.task_struct_cgroups)?;
+        let css: *const u8 = read_field(css_set, subsys)?;
+        read_field(css, layout.css_cgroup)
+    }
+}
+
+// memcg->css.cgroup->kn->id: the cgroup id of a struct mem_cgroup.
+#[inline(always)]
+unsafe fn memcg_id(memcg: *const u8, layout: &KernelLayout) -> Option<u64> {
+    if memcg.is_null() || layout.mem_cgroup_css == OFFSET_UNKNOWN {
+        return None;
+    }
+    let css = memcg.add(layout.mem_cgroup_css as usize);
+    let cgroup: *const u8 = read_field(css, layout.css_cgroup)?;
+    cgroup_id(cgroup, layout)
+}
+
+// memcg->memory and memcg->swap, read at the kill. Page counts become bytes here so the
+// event does not depend on the page size of the node that produced it.
+#[inline(always)]
+unsafe fn memcg_snapshot(memcg: *const u8, layout: &KernelLayout) -> MemcgSnapshot {
+    if memcg.is_null() || layout.page_size == OFFSET_UNKNOWN {
+        return MemcgSnapshot::default();
+    }
+    let page_size = layout.page_size as u64;
+    let pages = |counter: u32, field: u32| -> Option<u64> {
+        if counter == OFFSET_UNKNOWN {
+            return None;
+        }
+        read_field(memcg.add(counter as usize), field)
+    };
+    let bytes = |pages: Option<u64>| pages.map_or(0, |p| p.saturating_mul(page_size));
+
+    // An unlimited counter's max is PAGE_COUNTER_MAX, LONG_MAX / PAGE_SIZE.
+    let unlimited = i64::MAX as u64 / page_size;
+    let limit = match pages(layout.mem_cgroup_memory, layout.page_counter_max) {
+        Some(max) if max >= unlimited => MEMCG_NO_LIMIT,
+        max => bytes(max),
+    };
+
+    MemcgSnapshot {
+        usage: bytes(pages(layout.mem_cgroup_memory, layout.page_counter_usage)),
+        limit,
+        swap: bytes(pages(layout.mem_cgroup_swap, layout.page_counter_usage)),
+        peak: bytes(pages(
+            layout.mem_cgroup_memory,
+            layout.page_counter_watermark,
+        )),
+        failcnt: pages(layout.mem_cgroup_memory, layout.page_counter_failcnt).unwrap_or(0),
+    }
+}
+
+// cgroup->kn->id, which is also the inode number of the cgroup's cgroupfs directory.
+#[inline(always)]
+unsafe fn cgroup_id(cgroup: *const u8, layout: &KernelLayout) -> Option<u64> {
+    let kn: *const u8 = read_field(cgroup, layout.cgroup_kn)?;
+    read_field(kn, layout.kernfs_node_id)
 }
 
 #[cfg(not(test))]
@@ -0,0 +1,484 @@
+//! Mapping the cgroup ids the probe captures back to cgroupfs paths.
+//!
+//! A cgroup's id is the inode number of its directory in cgroupfs. [`CgroupIndex::track`]
+//! walks the hierarchy once, then follows it with inotify, indexing each cgroup as it is
+//! created: a container's cgroup is known even if it is gone before anything looks it up.
+//! Removed cgroups stay indexed for [`REMOVED_GRACE`], which is the point — the victim's
+//! container id can still be read off the path after the process, and even the container,
+//! is gone. An id the index does not hold is looked for by rewalking the hierarchy.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use log::{debug, warn};
+
+#[cfg(target_os = "linux")]
+use crate::inotify::Inotify;
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// How long a removed cgroup stays indexed: as long as the pod cache keeps a deleted pod.
+const REMOVED_GRACE: Duration = Duration::from_secs(300);
+
+/// How long an id a walk did not find is answered as unknown without walking again.
+const MISS_RETRY: Duration = Duration::from_secs(60);
+
+/// Which cgroup hierarchy the index walks, and so which captured id names its entries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hierarchy {
+    /// cgroup v2: one unified hierarchy, named by the task's default cgroup id.
+    Unified,
+    /// cgroup v1: the memory controller's own hierarchy, named by the memcg id.
+    MemoryV1,
+}
+
+pub struct CgroupIndex {
+    root: PathBuf,
+    hierarchy: Hierarchy,
+    entries: Arc<Mutex<Entries>>,
+}
+
+impl CgroupIndex {
+    /// Index the host's cgroupfs, choosing the hierarchy by whether it is mounted as v2.
+    pub fn detect() -> Self {
+        let root = Path::new(CGROUP_ROOT);
+        if root.join("cgroup.controllers").exists() {
+            Self::new(root, Hierarchy::Unified)
+        } else {
+            Self::new(root.join("memory"), Hierarchy::MemoryV1)
+        }
+    }
+
+    pub fn new(root: impl Into<PathBuf>, hierarchy: Hierarchy) -> Self {
+        Self {
+            root: root.into(),
+            hierarchy,
+            entries: Arc::default(),
+        }
+    }
+
+    /// Index the hierarchy on a thread of its own and keep the index current as cgroups
+    /// are created and removed, for as long as the index lives.
+    #[cfg(target_os = "linux")]
+    pub fn track(&self) -> std::io::Result<()> {
+        let mut tracker = Tracker {
+            root: self.root.clone(),
+            inotify: Inotify::new()?,
+            dirs: HashMap::new(),
+            entries: Arc::downgrade(&self.entries),
+        };
+        std::thread::Builder::new()
+            .name("cgroup-index".into())
+            .spawn(move || tracker.run())?;
+        Ok(())
+    }
+
+    /// Of the two ids the probe captures, the one that names a directory in this
+    /// index's hierarchy.
+    pub fn select(&self, cgroup_id: Option<u64>, memcg_id: Option<u64>) -> Option<u64> {
+        match self.hierarchy {
+            Hierarchy::Unified => cgroup_id,
+            Hierarchy::MemoryV1 => memcg_id,
+        }
+    }
+
+    pub fn hierarchy(&self) -> Hierarchy {
+        self.hierarchy
+    }
+
+    /// The root of the hierarchy, which paths are relative to.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The id of the cgroup at `path`, relative to the hierarchy root as [`Self::path`]
+    /// returns it: the inverse lookup, for cgroups named in configuration.
+    pub fn id(&self, path: &str) -> std::io::Result<u64> {
+        let path = self.root.join(path.trim_start_matches('/'));
+        let meta = fs::metadata(path)?;
+        if !meta.is_dir() {
+            return Err(std::io::Error::other("not a cgroup directory"));
+        }
+        Ok(meta.ino())
+    }
+
+    /// The path of cgroup `id` relative to the hierarchy root, in the same form as the
+    /// path column of `/proc/<pid>/cgroup` (e.g. `/kubepods/burstable/pod…/<id>`).
+    ///
+    /// An id not indexed is looked for by a walk on the blocking pool; one the walk does
+    /// not find either is not looked for again for [`MISS_RETRY`].
+    pub async fn path(&self, id: u64) -> Option<String> {
+        let started = Instant::now();
+        {
+            let entries = lock(&self.entries);
+            if let Some(entry) = entries.paths.get(&id) {
+                return Some(entry.path.clone());
+            }
+            if entries
+                .missed
+                .get(&id)
+                .is_some_and(|at| started.duration_since(*at) < MISS_RETRY)
+            {
+                return None;
+            }
+        }
+
+        debug!(
+            "cgroup id {} not indexed; walking {}",
+            id,
+            self.root.display()
+        );
+        let root = self.root.clone();
+        let found = tokio::task::spawn_blocking(move || {
+            let mut found = HashMap::new();
+            walk(&root, &root, &mut found);
+            found
+        })
+        .await
+        .unwrap_or_default();
+
+        let mut entries = lock(&self.entries);
+        entries.merge(found, started, Instant::now());
+        match entries.paths.get(&id) {
+            Some(entry) => Some(entry.path.clone()),
+            None => {
+                entries.miss(id, Instant::now());
+                None
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Entries {
+    paths: HashMap<u64, Entry>,
+    /// Removed cgroups in the order they went, to drop once their grace is over.
+    removed: VecDeque<(Instant, u64)>,
+    /// Ids a walk did not find, and when.
+    missed: HashMap<u64, Instant>,
+}
+
+struct Entry {
+    path: String,
+    /// When it was indexed.
+    seen: Instant,
+    /// When it was removed, if it has been.
+    removed: Option<Instant>,
+}
+
+impl Entries {
+    fn insert(&mut self, id: u64, path: String, now: Instant) {
+        self.missed.remove(&id);
+        self.paths.insert(
+            id,
+            Entry {
+                path,
+                seen: now,
+                removed: None,
+            },
+        );
+    }
+
+    fn remove(&mut self, id: u64, now: Instant) {
+        if let Some(entry) = self.paths.get_mut(&id) {
+            if entry.removed.is_none() {
+                entry.removed = Some(now);
+                self.removed.push_back((now, id));
+            }
+        }
+        self.prune(now);
+    }
+
+    fn miss(&mut self, id: u64, now: Instant) {
+        self.missed.insert(id, now);
+        self.prune(now);
+    }
+
+    /// Take in what a walk started at `started` found: every cgroup in it, and every
+    /// cgroup indexed before the walk but not in it as removed.
+    fn merge(&mut self, found: HashMap<u64, String>, started: Instant, now: Instant) {
+        let gone: Vec<u64> = self
+            .paths
+            .iter()
+            .filter(|(id, entry)| {
+                entry.removed.is_none() && entry.seen < started && !found.contains_key(id)
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        for id in gone {
+            self.remove(id, now);
+        }
+        for (id, path) in found {
+            if self.paths.get(&id).is_none_or(|entry| entry.path != path) {
+                self.insert(id, path, now);
+            }
+        }
+    }
+
+    /// Drop the cgroups removed more than [`REMOVED_GRACE`] ago, and forget misses old
+    /// enough to retry.
+    fn prune(&mut self, now: Instant) {
+        while let Some(&(at, id)) = self.removed.front() {
+            if now.duration_since(at) < REMOVED_GRACE {
+                break;
+            }
+            self.removed.pop_front();
+            // Unless its inode was reused for a new cgroup since.
+            if self
+                .paths
+                .get(&id)
+                .is_some_and(|entry| entry.removed == Some(at))
+            {
+                self.paths.remove(&id);
+            }
+        }
+        self.missed
+            .retain(|_, at| now.duration_since(*at) < MISS_RETRY);
+    }
+}
+
+fn lock(entries: &Mutex<Entries>) -> MutexGuard<'_, Entries> {
+    entries.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The path of `dir` relative to `root`, as the index holds it.
+fn relative(root: &Path, dir: &Path) -> Option<String> {
+    let relative = dir.strip_prefix(root).ok()?;
+    Some(format!("/{}", relative.display()))
+}
+
+fn walk(root: &Path, dir: &Path, paths: &mut HashMap<u64, String>) {
+    let (Ok(meta), Some(path)) = (fs::metadata(dir), relative(root, dir)) else {
+        return;
+    };
+    paths.insert(meta.ino(), path);
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+            walk(root, &entry.path(), paths);
+        }
+    }
+}
+
+/// The thread behind [`CgroupIndex::track`].
+#[cfg(target_os = "linux")]
+struct Tracker {
+    root: PathBuf,
+    inotify: Inotify,
+    /// Watched cgroup directories, by watch, with their ids.
+    dirs: HashMap<i32, (PathBuf, u64)>,
+    entries: std::sync::Weak<Mutex<Entries>>,
+}
+
+#[cfg(target_os = "linux")]
+impl Tracker {
+    fn run(&mut self) {
+        let Some(entries) = self.entries.upgrade() else {
+            return;
+        };
+        let root = self.root.clone();
+        self.add(&entries, &root);
+        debug!(
+            "Indexed {} cgroups under {}",
+            lock(&entries).paths.len(),
+            root.display()
+        );
+        drop(entries);
+
+        loop {
+            let events = match self.inotify.read() {
+                Ok(events) => events,
+                Err(e) => {
+                    warn!("Stopped following cgroups under {}: {}", root.display(), e);
+                    return;
+                }
+            };
+            let Some(entries) = self.entries.upgrade() else {
+                return;
+            };
+            for event in events {
+                if event.mask & libc::IN_IGNORED != 0 {
+                    // The directory is gone with its cgroup.
+                    if let Some((_, id)) = self.dirs.remove(&event.wd) {
+                        lock(&entries).remove(id, Instant::now());
+                    }
+                } else if event.mask & libc::IN_CREATE != 0 {
+                    if let Some((dir, _)) = self.dirs.get(&event.wd) {
+                        let dir = dir.join(&event.name);
+                        self.add(&entries, &dir);
+                    }
+                } else if event.mask & libc::IN_Q_OVERFLOW != 0 {
+                    debug!("cgroup changes dropped; the cgroups missed are found by walking");
+                }
+            }
+        }
+    }
+
+    /// Index cgroup `dir` and those below it. The directory is watched before it is
+    /// listed, so a child created in between is seen one way or the other.
+    fn add(&mut self, entries: &Mutex<Entries>, dir: &Path) {
+        let (Ok(meta), Some(path)) = (fs::metadata(dir), relative(&self.root, dir)) else {
+            return;
+        };
+        let id = meta.ino();
+        lock(entries).insert(id, path, Instant::now());
+        match self.inotify.watch(dir, libc::IN_CREATE | libc::IN_ONLYDIR) {
+            Ok(wd) => {
+                self.dirs.insert(wd, (dir.to_path_buf(), id));
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                lock(entries).remove(id, Instant::now());
+                return;
+            }
+            Err(e) => debug!("Not following {} for new cgroups: {}", dir.display(), e),
+        }
+        let Ok(children) = fs::read_dir(dir) else {
+            return;
+        };
+        for child in children.flatten() {
+            if child.file_type().is_ok_and(|kind| kind.is_dir()) {
+                self.add(entries, &child.path());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(name: &str) -> PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("oom-watcher-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("kubepods/burstable/pod1234/abc123")).unwrap();
+        root
+    }
+
+    /// Wait for the tracker to index cgroup `id`, without a lookup that could walk.
+    async fn indexed(index: &CgroupIndex, id: u64) -> bool {
+        for _ in 0..100 {
+            if lock(&index.entries).paths.contains_key(&id) {
+                return true;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        false
+    }
+
+    #[tokio::test]
+    async fn maps_a_directory_inode_to_its_relative_path() {
+        let root = tree("index");
+        let ino = fs::metadata(root.join("kubepods/burstable/pod1234/abc123"))
+            .unwrap()
+            .ino();
+
+        let index = CgroupIndex::new(&root, Hierarchy::Unified);
+        assert_eq!(
+            index.path(ino).await.as_deref(),
+            Some("/kubepods/burstable/pod1234/abc123")
+        );
+        assert_eq!(index.path(u64::MAX).await, None);
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn keeps_paths_of_removed_cgroups() {
+        let root = tree("removed");
+        let leaf = root.join("kubepods/burstable/pod1234/abc123");
+        let ino = fs::metadata(&leaf).unwrap().ino();
+
+        let index = CgroupIndex::new(&root, Hierarchy::Unified);
+        assert!(index.path(ino).await.is_some());
+        fs::remove_dir(&leaf).unwrap();
+        assert_eq!(
+            index.path(ino).await.as_deref(),
+            Some("/kubepods/burstable/pod1234/abc123")
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn resolves_a_cgroup_removed_before_it_was_looked_up() {
+        let root = tree("tracked");
+        let pod = root.join("kubepods/burstable/pod1234");
+        let index = CgroupIndex::new(&root, Hierarchy::Unified);
+        index.track().unwrap();
+        assert!(indexed(&index, fs::metadata(&pod).unwrap().ino()).await);
+
+        let leaf = pod.join("def456");
+        fs::create_dir(&leaf).unwrap();
+        let ino = fs::metadata(&leaf).unwrap().ino();
+        assert!(indexed(&index, ino).await);
+        fs::remove_dir(&leaf).unwrap();
+
+        assert_eq!(
+            index.path(ino).await.as_deref(),
+            Some("/kubepods/burstable/pod1234/def456")
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn does_not_rewalk_for_an_id_it_just_missed() {
+        let root = tree("missed");
+        let index = CgroupIndex::new(&root, Hierarchy::Unified);
+        assert_eq!(index.path(u64::MAX).await, None);
+
+        // Were it walked again, the cgroup created since would be indexed.
+        let late = root.join("kubepods/late");
+        fs::create_dir(&late).unwrap();
+        assert_eq!(index.path(u64::MAX).await, None);
+        let ino = fs::metadata(&late).unwrap().ino();
+        assert!(!lock(&index.entries).paths.contains_key(&ino));
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn drops_removed_cgroups_after_their_grace() {
+        let start = Instant::now();
+        let mut entries = Entries::default();
+        entries.insert(1, "/a".into(), start);
+        entries.insert(2, "/b".into(), start);
+        entries.remove(1, start);
+        entries.remove(2, start);
+        // Inode 2 reused for a new cgroup.
+        entries.insert(2, "/c".into(), start + Duration::from_secs(1));
+        entries.miss(3, start);
+
+        entries.prune(start + REMOVED_GRACE - Duration::from_secs(1));
+        assert!(entries.paths.contains_key(&1));
+        entries.prune(start + REMOVED_GRACE);
+        assert!(!entries.paths.contains_key(&1));
+        assert_eq!(entries.paths[&2].path, "/c");
+        assert!(entries.missed.is_empty());
+    }
+
+    #[test]
+    fn looks_up_the_id_of_a_configured_path() {
+        let root = tree("id");
+        let ino = fs::metadata(root.join("kubepods/burstable")).unwrap().ino();
+
+        let index = CgroupIndex::new(&root, Hierarchy::Unified);
+        assert_eq!(index.id("/kubepods/burstable").unwrap(), ino);
+        assert_eq!(index.id("kubepods/burstable").unwrap(), ino);
+        assert!(index.id("/kubepods/guaranteed").is_err());
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn selects_the_id_for_its_hierarchy() {
+        let v2 = CgroupIndex::new("/", Hierarchy::Unified);
+        let v1 = CgroupIndex::new("/", Hierarchy::MemoryV1);
+        assert_eq!(v2.select(Some(1), Some(2)), Some(1));
+        assert_eq!(v1.select(Some(1), Some(2)), Some(2));
+    }
+}
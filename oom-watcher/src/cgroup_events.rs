@@ -0,0 +1,485 @@
+//! The cgroupfs OOM source: kills counted off each memory cgroup's own OOM counters, for
+//! clusters that forbid privileged eBPF DaemonSets.
+//!
+//! Every memcg counts the kills of its tasks — `oom_kill` in `memory.events` on cgroup v2,
+//! in `memory.oom_control` on v1 — and the kernel signals a change: inotify `IN_MODIFY` on
+//! `memory.events`, or on v1 an eventfd registered through `cgroup.event_control`.
+//! [`CgroupEventsSource`] watches every cgroup under the kubepods hierarchy, new ones
+//! included, and turns each increment into an [`OomKillEvent`] for that cgroup. The cgroup
+//! is all it knows — not the victim's pid, comm or memory, which are marked unknown — but
+//! it is what resolution needs, so the watch loop, enrichment and metrics run unchanged.
+//!
+//! On v2 this needs only read access to cgroupfs. v1's registration writes
+//! `cgroup.event_control`, so there the mount must be writable.
+
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io, mem,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::fs::MetadataExt,
+    },
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{debug, error, info};
+use oom_watcher_common::{OomKillEvent, ProbeEvent, CONSTRAINT_MEMCG};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::{
+    cgroup::{CgroupIndex, Hierarchy},
+    inotify::Inotify,
+    watch::OomEventSource,
+};
+
+/// Where the kubelet puts pod cgroups under the hierarchy root: the systemd cgroup
+/// driver's slice, then the cgroupfs driver's directory.
+const KUBEPODS: [&str; 2] = ["kubepods.slice", "kubepods"];
+
+/// v1 signals an OOM as the OOM killer is invoked, before it kills: counters are read
+/// this long after a signal, by when its kills are counted.
+const V1_SETTLE: Duration = Duration::from_millis(100);
+
+/// A memcg's OOM counters.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OomCounts {
+    /// `oom`: times its limit was hit and the OOM killer invoked for it (v2 only).
+    pub ooms: u64,
+    /// `oom_kill`: tasks in it the OOM killer killed.
+    pub kills: u64,
+}
+
+impl OomCounts {
+    /// Parse `memory.events` (v2) or `memory.oom_control` (v1), both `<key> <value>`
+    /// lines. `None` without an `oom_kill` line: v1 before 4.13, or a file being written.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut ooms = 0;
+        let mut kills = None;
+        for (key, value) in text.lines().filter_map(|line| line.split_once(' ')) {
+            match key {
+                "oom" => ooms = value.trim().parse().unwrap_or(0),
+                "oom_kill" => kills = value.trim().parse().ok(),
+                _ => {}
+            }
+        }
+        Some(Self {
+            ooms,
+            kills: kills?,
+        })
+    }
+}
+
+/// The kills cgroup `id` counted going from `before` to `after`: one event per `oom_kill`
+/// increment, with the cgroup as the memcg breached if its `oom` rose too. (Otherwise an
+/// ancestor's limit was hit, or the node's, and which is not known.)
+pub fn kills(
+    id: u64,
+    hierarchy: Hierarchy,
+    before: OomCounts,
+    after: OomCounts,
+) -> Vec<OomKillEvent> {
+    let breached = after.ooms > before.ooms;
+    let mut event = OomKillEvent {
+        victim_cgroup_id: if hierarchy == Hierarchy::Unified {
+            id
+        } else {
+            0
+        },
+        victim_memcg_id: id,
+        // A counter says nothing of the task it counts.
+        ..OomKillEvent::unknown()
+    };
+    if breached {
+        (event.constraint, event.oom_memcg_id) = (CONSTRAINT_MEMCG, id);
+    }
+    vec![event; after.kills.saturating_sub(before.kills) as usize]
+}
+
+/// The cgroupfs adapter for [`OomEventSource`]: kills counted by a watcher thread — inotify
+/// and eventfds only read blocking here — and handed over a channel.
+pub struct CgroupEventsSource {
+    kills: UnboundedReceiver<OomKillEvent>,
+    hierarchy: Hierarchy,
+}
+
+impl CgroupEventsSource {
+    /// Watch the kubepods hierarchy of the host's cgroupfs.
+    pub fn open() -> io::Result<Self> {
+        let index = CgroupIndex::detect();
+        let root = KUBEPODS
+            .iter()
+            .map(|name| index.root().join(name))
+            .find(|dir| dir.is_dir())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no kubepods cgroup under {}", index.root().display()),
+                )
+            })?;
+        Self::watch(&root, index.hierarchy())
+    }
+
+    /// Watch `root` and every cgroup below it, counting from their current counts.
+    pub fn watch(root: &Path, hierarchy: Hierarchy) -> io::Result<Self> {
+        let (sender, kills) = mpsc::unbounded_channel();
+        let mut watcher = Watcher::new(hierarchy, sender)?;
+        watcher.add(root);
+        info!(
+            "Watching {} {} files under {} for OOM kills",
+            watcher.cgroups.len(),
+            counts_file(hierarchy),
+            root.display()
+        );
+        thread::Builder::new()
+            .name("cgroup-events".into())
+            .spawn(move || watcher.run())?;
+        Ok(Self { kills, hierarchy })
+    }
+
+    /// The file whose counters are watched, for `oom_probe_attach_info`.
+    pub fn target(&self) -> &'static str {
+        counts_file(self.hierarchy)
+    }
+}
+
+impl OomEventSource for CgroupEventsSource {
+    async fn next(&mut self) -> Option<ProbeEvent> {
+        self.kills.recv().await.map(ProbeEvent::Kill)
+    }
+}
+
+fn counts_file(hierarchy: Hierarchy) -> &'static str {
+    match hierarchy {
+        Hierarchy::Unified => "memory.events",
+        Hierarchy::MemoryV1 => "memory.oom_control",
+    }
+}
+
+/// One watched cgroup.
+struct Cgroup {
+    dir: PathBuf,
+    id: u64,
+    /// The file its counts are read from.
+    counts_path: PathBuf,
+    counts: OomCounts,
+    /// Whether its counts include its descendants' (all but `memory.events.local`), so are
+    /// left to them when it has any.
+    hierarchical: bool,
+    /// v1: the eventfd signalled on OOM, and the `memory.oom_control` it is registered on,
+    /// held open for as long as the registration.
+    signal: Option<(OwnedFd, File)>,
+}
+
+struct Watcher {
+    hierarchy: Hierarchy,
+    inotify: Inotify,
+    /// Directory watches, for cgroups created below them.
+    dirs: HashMap<i32, PathBuf>,
+    /// Watched cgroups, by their counts file's watch (v2) or their eventfd (v1).
+    cgroups: HashMap<i32, Cgroup>,
+    /// v1: cgroups signalled, and when their counts are to be read.
+    settling: Option<(Instant, Vec<RawFd>)>,
+    kills: UnboundedSender<OomKillEvent>,
+}
+
+impl Watcher {
+    fn new(hierarchy: Hierarchy, kills: UnboundedSender<OomKillEvent>) -> io::Result<Self> {
+        Ok(Self {
+            hierarchy,
+            inotify: Inotify::new()?,
+            dirs: HashMap::new(),
+            cgroups: HashMap::new(),
+            settling: None,
+            kills,
+        })
+    }
+
+    /// Watch cgroup `dir` and those below it. The directory is watched before it is
+    /// listed, so a child created in between is seen one way or the other.
+    fn add(&mut self, dir: &Path) {
+        match self.inotify.watch(dir, libc::IN_CREATE | libc::IN_ONLYDIR) {
+            Ok(wd) => {
+                self.dirs.insert(wd, dir.to_path_buf());
+            }
+            Err(e) => {
+                debug!("Not watching {} for new cgroups: {}", dir.display(), e);
+                return;
+            }
+        }
+        if let Err(e) = self.register(dir) {
+            debug!("Not watching {} for OOM kills: {}", dir.display(), e);
+        }
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+                self.add(&entry.path());
+            }
+        }
+    }
+
+    /// Start counting the kills in cgroup `dir`.
+    fn register(&mut self, dir: &Path) -> io::Result<()> {
+        let id = fs::metadata(dir)?.ino();
+        let (key, counts_path, counts, hierarchical, signal) = match self.hierarchy {
+            Hierarchy::Unified => {
+                let local = dir.join("memory.events.local");
+                let (path, hierarchical) = if local.exists() {
+                    (local, false)
+                } else {
+                    (dir.join("memory.events"), true)
+                };
+                let counts = read_counts(&path)?;
+                let wd = self.inotify.watch(&path, libc::IN_MODIFY)?;
+                (wd, path, counts, hierarchical, None)
+            }
+            Hierarchy::MemoryV1 => {
+                let path = dir.join("memory.oom_control");
+                let control = File::open(&path)?;
+                let counts = read_counts(&path)?;
+                // SAFETY: plain syscall; the fd it returns is owned from here on.
+                let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+                if fd < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                let eventfd = unsafe { OwnedFd::from_raw_fd(fd) };
+                fs::write(
+                    dir.join("cgroup.event_control"),
+                    format!("{} {}", eventfd.as_raw_fd(), control.as_raw_fd()),
+                )?;
+                (fd, path, counts, true, Some((eventfd, control)))
+            }
+        };
+        self.cgroups.insert(
+            key,
+            Cgroup {
+                dir: dir.to_path_buf(),
+                id,
+                counts_path,
+                counts,
+                hierarchical,
+                signal,
+            },
+        );
+        Ok(())
+    }
+
+    /// Wait on inotify and the eventfds until the source is dropped.
+    fn run(mut self) {
+        while !self.kills.is_closed() {
+            let inotify = self.inotify.as_raw_fd();
+            let mut fds: Vec<libc::pollfd> = std::iter::once(inotify)
+                .chain(
+                    self.cgroups
+                        .iter()
+                        .filter(|(_, c)| c.signal.is_some())
+                        .map(|(fd, _)| *fd),
+                )
+                .map(|fd| libc::pollfd {
+                    fd,
+                    events: libc::POLLIN,
+                    revents: 0,
+                })
+                .collect();
+            let timeout = self.settling.as_ref().map_or(-1, |(deadline, _)| {
+                deadline
+                    .saturating_duration_since(Instant::now())
+                    .as_millis() as i32
+            });
+            // SAFETY: fds is a valid array of its length for the duration of the call.
+            let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+            if ready < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                error!("Failed to wait on cgroup OOM counters: {}", e);
+                return;
+            }
+
+            for fd in fds.iter().filter(|fd| fd.revents != 0).map(|fd| fd.fd) {
+                if fd == inotify {
+                    if let Err(e) = self.read_inotify() {
+                        error!("Failed to read cgroup OOM counter changes: {}", e);
+                        return;
+                    }
+                } else {
+                    self.signalled(fd);
+                }
+            }
+            if let Some((_, keys)) = self
+                .settling
+                .take_if(|(deadline, _)| Instant::now() >= *deadline)
+            {
+                for key in keys {
+                    self.changed(key);
+                }
+            }
+        }
+    }
+
+    fn read_inotify(&mut self) -> io::Result<()> {
+        for event in self.inotify.read()? {
+            if event.mask & libc::IN_IGNORED != 0 {
+                // The watched file or directory is gone with its cgroup.
+                self.dirs.remove(&event.wd);
+                self.cgroups.remove(&event.wd);
+            } else if event.mask & libc::IN_MODIFY != 0 {
+                self.changed(event.wd);
+            } else if event.mask & libc::IN_CREATE != 0 {
+                if let Some(dir) = self.dirs.get(&event.wd) {
+                    let dir = dir.join(&event.name);
+                    self.add(&dir);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// v1: cgroup `fd` was signalled; read its counts once the kills are done.
+    fn signalled(&mut self, fd: RawFd) {
+        let mut count = [0u8; 8];
+        // SAFETY: count is writable for its whole length. An eventfd reads as a u64.
+        unsafe { libc::read(fd, count.as_mut_ptr() as *mut libc::c_void, count.len()) };
+        let (_, keys) = self
+            .settling
+            .get_or_insert_with(|| (Instant::now() + V1_SETTLE, Vec::new()));
+        if !keys.contains(&fd) {
+            keys.push(fd);
+        }
+    }
+
+    /// Re-read the counts of cgroup `key`, sending a kill for each one it counted.
+    fn changed(&mut self, key: i32) {
+        let Some(cgroup) = self.cgroups.get_mut(&key) else {
+            return;
+        };
+        let counts = match read_counts(&cgroup.counts_path) {
+            Ok(counts) => counts,
+            // Mid-write; the write's own notification reads it again.
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => return,
+            Err(_) => {
+                // Removed; v1 signals the eventfd as it goes.
+                self.cgroups.remove(&key);
+                return;
+            }
+        };
+        let before = mem::replace(&mut cgroup.counts, counts);
+        if cgroup.hierarchical && has_children(&cgroup.dir) {
+            return;
+        }
+        for event in kills(cgroup.id, self.hierarchy, before, counts) {
+            debug!("OOM kill counted in {}", cgroup.dir.display());
+            if self.kills.send(event).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn read_counts(path: &Path) -> io::Result<OomCounts> {
+    OomCounts::parse(&fs::read_to_string(path)?)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no oom_kill counter"))
+}
+
+fn has_children(dir: &Path) -> bool {
+    fs::read_dir(dir).is_ok_and(|entries| {
+        entries
+            .flatten()
+            .any(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, os::unix::fs::FileExt};
+
+    use oom_watcher_common::{CONSTRAINT_UNKNOWN, FIELD_ANON_RSS, FIELD_VICTIM_COMM};
+
+    use super::*;
+
+    #[test]
+    fn reads_both_hierarchies_counters() {
+        let v2 = "low 0\nhigh 12\nmax 40\noom 3\noom_kill 2\noom_group_kill 0\n";
+        assert_eq!(OomCounts::parse(v2), Some(OomCounts { ooms: 3, kills: 2 }));
+
+        let v1 = "oom_kill_disable 0\nunder_oom 0\noom_kill 5\n";
+        assert_eq!(OomCounts::parse(v1), Some(OomCounts { ooms: 0, kills: 5 }));
+        // Before 4.13, v1 counts no kills.
+        assert_eq!(OomCounts::parse("oom_kill_disable 0\nunder_oom 1\n"), None);
+    }
+
+    #[test]
+    fn sends_a_kill_per_increment() {
+        let before = OomCounts { ooms: 1, kills: 1 };
+
+        let own = kills(
+            42,
+            Hierarchy::Unified,
+            before,
+            OomCounts { ooms: 2, kills: 3 },
+        );
+        assert_eq!(own.len(), 2);
+        assert_eq!((own[0].victim_cgroup_id, own[0].victim_memcg_id), (42, 42));
+        assert_eq!(
+            (own[0].constraint, own[0].oom_memcg_id),
+            (CONSTRAINT_MEMCG, 42)
+        );
+        assert!(!own[0].has(FIELD_VICTIM_COMM) && !own[0].has(FIELD_ANON_RSS));
+
+        let ancestors = kills(
+            42,
+            Hierarchy::MemoryV1,
+            before,
+            OomCounts { ooms: 1, kills: 2 },
+        );
+        assert_eq!(ancestors[0].victim_cgroup_id, 0);
+        assert_eq!(
+            (ancestors[0].constraint, ancestors[0].oom_memcg_id),
+            (CONSTRAINT_UNKNOWN, 0)
+        );
+
+        assert!(kills(42, Hierarchy::Unified, before, before).is_empty());
+    }
+
+    #[tokio::test]
+    async fn counts_the_kills_of_a_watched_cgroup() {
+        let root = std::env::temp_dir().join(format!("oom-watcher-events-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let leaf = root.join("pod1234/abc123");
+        fs::create_dir_all(&leaf).unwrap();
+        // A parent's counts include its children's; only the leaf's are reported.
+        fs::write(root.join("pod1234/memory.events"), "oom 0\noom_kill 0\n").unwrap();
+        fs::write(leaf.join("memory.events"), "oom 0\noom_kill 0\n").unwrap();
+
+        let mut source = CgroupEventsSource::watch(&root, Hierarchy::Unified).unwrap();
+        for path in [
+            root.join("pod1234/memory.events"),
+            leaf.join("memory.events"),
+        ] {
+            // Rewritten in place, as cgroupfs does, not truncated first.
+            let file = fs::OpenOptions::new().write(true).open(path).unwrap();
+            file.write_all_at(b"oom 1\noom_kill 1\n", 0).unwrap();
+            (&file).flush().unwrap();
+        }
+
+        let event = tokio::time::timeout(Duration::from_secs(5), source.next())
+            .await
+            .expect("no kill counted");
+        let Some(ProbeEvent::Kill(event)) = event else {
+            panic!("not a kill");
+        };
+        assert_eq!(event.victim_cgroup_id, fs::metadata(&leaf).unwrap().ino());
+        assert_eq!(event.constraint, CONSTRAINT_MEMCG);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), source.next())
+                .await
+                .is_err()
+        );
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,279 @@
+//! Reading a container's identity off its cgroup path.
+//!
+//! Runtimes and cgroup drivers name a container's cgroup differently: `cri-containerd-<id>`,
+//! `crio-<id>`, `docker-<id>` and `libpod-<id>` scopes under the systemd driver, a bare
+//! `<id>` directory under cgroupfs, nested below `kubelet.slice` in kind or `user@.service`
+//! when rootless. [`parse`] walks the path one component at a time and keeps what it
+//! recognises — the runtime, the pod's QoS class and UID, the container id — instead of
+//! matching each layout with a pattern of its own. The fixtures under
+//! `fixtures/cgroup` are the `/proc/<pid>/cgroup` files it is tested against; their README
+//! says which are captures.
+
+/// The container runtime whose naming a cgroup follows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Runtime {
+    Containerd,
+    CriO,
+    Docker,
+    Podman,
+}
+
+impl Runtime {
+    /// The scheme Kubernetes prefixes this runtime's container ids with.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Containerd => "containerd",
+            Self::CriO => "cri-o",
+            Self::Docker => "docker",
+            Self::Podman => "podman",
+        }
+    }
+}
+
+/// The QoS class whose kubepods slice a pod's cgroup sits in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Qos {
+    Guaranteed,
+    Burstable,
+    BestEffort,
+}
+
+/// What a cgroup path says about the container in it. Every part is optional: a pod-level
+/// cgroup has no container id, a cgroupfs-driver path does not say which runtime made it,
+/// and a container outside Kubernetes has no pod.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ContainerCgroup {
+    pub runtime: Option<Runtime>,
+    pub qos: Option<Qos>,
+    /// The pod's UID, dashed as the API server writes it.
+    pub pod_uid: Option<String>,
+    pub container_id: Option<String>,
+}
+
+/// Parse cgroup text: a cgroupfs path, or the whole of a `/proc/<pid>/cgroup` file, of
+/// whose lines the one that says the most about a container is kept. `None` if no line
+/// names a pod or a container.
+pub fn parse(cgroup: &str) -> Option<ContainerCgroup> {
+    cgroup
+        .lines()
+        .filter_map(|line| parse_path(path_of(line)))
+        .rev()
+        .max_by_key(|parsed| (parsed.container_id.is_some(), parsed.pod_uid.is_some()))
+}
+
+/// The path of a `/proc/<pid>/cgroup` line (`<id>:<controllers>:<path>`), or the line
+/// itself when it already is one.
+fn path_of(line: &str) -> &str {
+    if line.starts_with('/') {
+        return line;
+    }
+    line.splitn(3, ':').nth(2).unwrap_or(line)
+}
+
+fn parse_path(path: &str) -> Option<ContainerCgroup> {
+    let mut parsed = ContainerCgroup::default();
+    let mut kubepods = false;
+    // The runtime a component names when the id follows as a component of its own:
+    // `/docker/<id>`, or `…slice:cri-containerd:<id>` from a systemd driver's unit name.
+    let mut named = None;
+    // `:` separates the parts of a unit name a systemd-driver runtime could not nest.
+    for component in path.split(['/', ':']) {
+        let unit = component
+            .strip_suffix(".scope")
+            .or_else(|| component.strip_suffix(".slice"))
+            .unwrap_or(component);
+
+        if let Some((runtime, id)) = scoped_container(unit) {
+            (parsed.runtime, parsed.container_id) = (Some(runtime), Some(id.to_string()));
+        } else if is_container_id(unit) {
+            (parsed.runtime, parsed.container_id) = (named, Some(unit.to_string()));
+        } else if unit.contains("kubepods") {
+            // The systemd driver: kubepods[-<qos>][-pod<uid>], maybe kubelet- prefixed.
+            kubepods = true;
+            parsed.qos = parsed.qos.or(qos_of(unit.split('-')));
+            if let Some((_, uid)) = unit.rsplit_once("-pod") {
+                parsed.pod_uid = pod_uid(uid).or(parsed.pod_uid);
+            }
+        } else if kubepods {
+            // The cgroupfs driver: kubepods/[<qos>/]pod<uid>.
+            parsed.qos = parsed.qos.or(qos_of([unit]));
+            if let Some(uid) = unit.strip_prefix("pod") {
+                parsed.pod_uid = pod_uid(uid).or(parsed.pod_uid);
+            }
+        }
+        named = match unit {
+            "cri-containerd" => Some(Runtime::Containerd),
+            "crio" => Some(Runtime::CriO),
+            "docker" => Some(Runtime::Docker),
+            "libpod" => Some(Runtime::Podman),
+            _ => None,
+        };
+    }
+
+    // Guaranteed pods sit straight under kubepods, in no QoS slice of their own.
+    if parsed.pod_uid.is_some() && parsed.qos.is_none() {
+        parsed.qos = Some(Qos::Guaranteed);
+    }
+    (parsed.pod_uid.is_some() || parsed.container_id.is_some()).then_some(parsed)
+}
+
+/// A runtime's `<prefix>-<id>` scope. `crio-conmon-<id>` and `libpod-conmon-<id>` are the
+/// runtime's monitor process, not the container, and do not match.
+fn scoped_container(unit: &str) -> Option<(Runtime, &str)> {
+    [
+        ("cri-containerd-", Runtime::Containerd),
+        ("crio-", Runtime::CriO),
+        ("docker-", Runtime::Docker),
+        ("libpod-", Runtime::Podman),
+    ]
+    .into_iter()
+    .find_map(|(prefix, runtime)| {
+        let id = unit.strip_prefix(prefix)?;
+        is_container_id(id).then_some((runtime, id))
+    })
+}
+
+fn qos_of<'a>(parts: impl IntoIterator<Item = &'a str>) -> Option<Qos> {
+    parts.into_iter().find_map(|part| match part {
+        "burstable" => Some(Qos::Burstable),
+        "besteffort" => Some(Qos::BestEffort),
+        _ => None,
+    })
+}
+
+/// A pod UID as a cgroup name spells it: dashed under cgroupfs, underscored under systemd,
+/// or the 32 hex digits of a static pod's config hash.
+fn pod_uid(uid: &str) -> Option<String> {
+    let valid = uid.len() >= 32
+        && uid
+            .bytes()
+            .all(|b| b.is_ascii_hexdigit() || b == b'-' || b == b'_');
+    valid.then(|| uid.replace('_', "-"))
+}
+
+/// Container ids are 64 lowercase hex digits under every runtime.
+fn is_container_id(s: &str) -> bool {
+    s.len() == 64 && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UID: &str = "8f3c1a2e-5b7d-4c9e-a1f0-123456789abc";
+
+    /// A fixture's name and content.
+    macro_rules! fixture {
+        ($name:literal) => {
+            (
+                $name,
+                include_str!(concat!("../fixtures/cgroup/", $name, ".cgroup")),
+            )
+        };
+    }
+
+    /// The runtime, QoS class, and the leading digits of the pod UID and container id a
+    /// parse found, `-` for each it did not.
+    fn describe(parsed: Option<ContainerCgroup>) -> String {
+        let parsed = parsed.unwrap_or_default();
+        let prefix = |id: Option<String>| id.map_or("-".to_string(), |id| id[..8].to_string());
+        [
+            parsed
+                .runtime
+                .map_or("-".to_string(), |r| r.as_str().to_string()),
+            parsed
+                .qos
+                .map_or("-".to_string(), |q| format!("{:?}", q).to_lowercase()),
+            prefix(parsed.pod_uid),
+            prefix(parsed.container_id),
+        ]
+        .join(" ")
+    }
+
+    #[test]
+    fn parses_every_runtime_and_driver_in_the_corpus() {
+        let corpus = [
+            // Captures.
+            (fixture!("docker-cgroupfs-v1"), "docker - - 9d5b23ed"),
+            (
+                fixture!("cgroupfs-besteffort-v1"),
+                "- besteffort 3d274242 3e74d3fd",
+            ),
+            (
+                fixture!("cgroupfs-burstable-v1"),
+                "- burstable fd52ef25 8c046cb0",
+            ),
+            (
+                fixture!("systemd-guaranteed-v1"),
+                "- guaranteed 97f1ae73 3291bfdd",
+            ),
+            (
+                fixture!("dockershim-systemd-v1"),
+                "docker burstable 2d3da189 7b8952da",
+            ),
+            (fixture!("uuid-container-v1"), "- besteffort e9b90526 -"),
+            (fixture!("podman-v2"), "podman - - 93afc7bc"),
+            (fixture!("host-v1"), "- - - -"),
+            (fixture!("namespaced-v2"), "- - - -"),
+            // Constructed, pending captures.
+            (
+                fixture!("containerd-systemd-v2"),
+                "containerd burstable 8f3c1a2e ca978112",
+            ),
+            (
+                fixture!("containerd-systemd-guaranteed-v2"),
+                "containerd guaranteed 8f3c1a2e 3e23e816",
+            ),
+            (
+                fixture!("containerd-systemd-unnested-v2"),
+                "containerd burstable 8f3c1a2e 3f79bb7b",
+            ),
+            (
+                fixture!("containerd-cgroupfs-v2"),
+                "- besteffort 8f3c1a2e 18ac3e73",
+            ),
+            (
+                fixture!("crio-systemd-v2"),
+                "cri-o besteffort 8f3c1a2e 252f10c8",
+            ),
+            (
+                fixture!("crio-systemd-v1"),
+                "cri-o burstable 8f3c1a2e cd0aa985",
+            ),
+            (fixture!("crio-conmon-v2"), "- burstable 8f3c1a2e -"),
+            (fixture!("docker-systemd-v2"), "docker - - 8254c329"),
+            (
+                fixture!("kind-containerd-v2"),
+                "containerd besteffort 8f3c1a2e acac86c0",
+            ),
+            (
+                fixture!("rootless-containerd-v2"),
+                "containerd burstable 8f3c1a2e 62c66a7a",
+            ),
+            (fixture!("podman-rootless-v2"), "podman - - 65c74c15"),
+        ];
+
+        for ((name, content), expected) in corpus {
+            assert_eq!(describe(parse(content)), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn parses_a_bare_cgroupfs_path() {
+        let id = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+        let parsed = parse(&format!("/kubepods/pod{}/{}", UID, id)).unwrap();
+        assert_eq!(parsed.qos, Some(Qos::Guaranteed));
+        assert_eq!(parsed.pod_uid.as_deref(), Some(UID));
+        assert_eq!(parsed.container_id.as_deref(), Some(id));
+
+        // An id is still read beside a pod directory whose name is no UID.
+        let parsed = parse(&format!("/kubepods/burstable/pod1234/{}", id)).unwrap();
+        assert_eq!(
+            (parsed.pod_uid, parsed.container_id.as_deref()),
+            (None, Some(id))
+        );
+
+        // A QoS slice, with no pod below it, names nothing.
+        assert_eq!(parse("/kubepods.slice/kubepods-burstable.slice"), None);
+    }
+}
@@ -0,0 +1,129 @@
+//! Decoding of the victim's command — its executable path and argv — as the probe captured
+//! it in-kernel, while the victim still had both.
+//!
+//! The probe can only read argv from within the victim's own address space, so it misses
+//! it when one process's allocation kills another. [`proc_args`] is the fallback: the
+//! victim may not have been reaped yet when userspace sees the kill.
+
+use std::fs;
+
+use oom_watcher_common::{ProcessCommand, VictimCommand, EXE_LEN};
+
+/// The command `command` describes, or `None` if the probe captured neither part of it.
+pub fn decode(command: &VictimCommand) -> Option<ProcessCommand> {
+    let start = command.exe_start as usize;
+    let exe = (start < EXE_LEN).then(|| {
+        let path = &command.exe[start..];
+        let end = path.iter().position(|&b| b == 0).unwrap_or(path.len());
+        let path = String::from_utf8_lossy(&path[..end]);
+        if command.exe_complete != 0 {
+            path.into_owned()
+        } else {
+            format!("…{}", path)
+        }
+    });
+
+    let (args, truncated) = if command.args_len == 0 {
+        (Vec::new(), false)
+    } else {
+        let len = (command.cmdline_len as usize).min(command.cmdline.len());
+        (
+            split_args(&command.cmdline[..len]),
+            command.args_len > command.cmdline_len,
+        )
+    };
+
+    if exe.is_none() && args.is_empty() {
+        return None;
+    }
+    Some(ProcessCommand {
+        exe,
+        args,
+        truncated,
+    })
+}
+
+/// The arguments of `pid` from `/proc/<pid>/cmdline`, if it is still there to read.
+pub fn proc_args(pid: u32) -> Vec<String> {
+    fs::read(format!("/proc/{}/cmdline", pid))
+        .map(|cmdline| split_args(&cmdline))
+        .unwrap_or_default()
+}
+
+/// NUL-separated arguments, as in argv's memory and `/proc/<pid>/cmdline`.
+fn split_args(cmdline: &[u8]) -> Vec<String> {
+    let cmdline = cmdline.strip_suffix(&[0]).unwrap_or(cmdline);
+    if cmdline.is_empty() {
+        return Vec::new();
+    }
+    cmdline
+        .split(|&b| b == 0)
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use oom_watcher_common::CMDLINE_LEN;
+
+    use super::*;
+
+    fn command(exe: &str, complete: bool, argv: &[u8], args_len: u32) -> VictimCommand {
+        let mut command = VictimCommand {
+            exe: [0; EXE_LEN],
+            exe_start: EXE_LEN as u32,
+            exe_complete: complete as u32,
+            cmdline: [0; CMDLINE_LEN],
+            cmdline_len: argv.len() as u32,
+            args_len,
+        };
+        if !exe.is_empty() {
+            // Written back to front, NUL-terminated, as the probe does.
+            let start = EXE_LEN - 1 - exe.len();
+            command.exe[start..EXE_LEN - 1].copy_from_slice(exe.as_bytes());
+            command.exe_start = start as u32;
+        }
+        command.cmdline[..argv.len()].copy_from_slice(argv);
+        command
+    }
+
+    #[test]
+    fn decodes_the_exe_and_arguments() {
+        let argv = b"java\0-Xmx2g\0-jar\0app.jar\0";
+        let decoded = decode(&command("/usr/bin/java", true, argv, argv.len() as u32)).unwrap();
+        assert_eq!(decoded.exe.as_deref(), Some("/usr/bin/java"));
+        assert_eq!(decoded.args, vec!["java", "-Xmx2g", "-jar", "app.jar"]);
+        assert!(!decoded.truncated);
+    }
+
+    #[test]
+    fn marks_what_was_cut_short() {
+        let decoded = decode(&command(
+            "/lib/app/bin/server",
+            false,
+            b"server\0--con",
+            4000,
+        ))
+        .unwrap();
+        assert_eq!(decoded.exe.as_deref(), Some("…/lib/app/bin/server"));
+        assert_eq!(decoded.args, vec!["server", "--con"]);
+        assert!(decoded.truncated);
+    }
+
+    #[test]
+    fn keeps_the_exe_without_arguments() {
+        let decoded = decode(&command("/bin/sh", true, b"", 0)).unwrap();
+        assert_eq!(decoded.exe.as_deref(), Some("/bin/sh"));
+        assert!(decoded.args.is_empty());
+    }
+
+    #[test]
+    fn nothing_captured_is_none() {
+        assert_eq!(decode(&command("", false, b"", 0)), None);
+    }
+
+    #[test]
+    fn reads_this_process_from_proc() {
+        assert!(!proc_args(std::process::id()).is_empty());
+    }
+}
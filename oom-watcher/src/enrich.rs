@@ -1,26 +1,50 @@
-use oom_watcher_common::{ContainerIdentity, EnrichedOomEvent, OomKillEvent};
+use oom_watcher_common::{
+    ContainerIdentity, EnrichedOomEvent, OomKillEvent, OomScope, ProcessCommand, TriggerStack,
+};
 
-/// Build an [`EnrichedOomEvent`] from a raw OOM kill event and an optional resolved
-/// container identity. This is the sole construction site for an enriched event.
+/// What is known about a kill beyond its victim, each part only when it was captured.
+#[derive(Debug, Default)]
+pub struct KillDetails {
+    /// The resolved container of the task whose allocation triggered the kill.
+    pub trigger: Option<ContainerIdentity>,
+    /// That allocation's kernel and user stacks.
+    pub trigger_stack: Option<TriggerStack>,
+    /// The victim's command line.
+    pub victim_command: Option<ProcessCommand>,
+}
+
+/// Build an [`EnrichedOomEvent`] from a raw OOM kill event, the optional resolved
+/// container identity of its victim, and the [`KillDetails`] captured alongside. This is
+/// the sole construction site for an enriched event.
 ///
 /// It encodes one rule: `node_name` is known iff a Kubernetes client exists (the
-/// caller passes `Some`), independent of whether the container identity could be
+/// caller passes `Some`), independent of whether either container identity could be
 /// resolved. A failed resolution clears the container fields but never the node.
 pub fn enrich(
     raw_event: OomKillEvent,
     node_name: Option<&str>,
     identity: Option<ContainerIdentity>,
+    scope: OomScope,
+    details: KillDetails,
     timestamp: u64,
 ) -> EnrichedOomEvent {
-    let (namespace, pod_name, container_name, container_id) = match identity {
-        Some(id) => (
-            Some(id.namespace),
-            Some(id.pod_name),
-            Some(id.container_name),
-            Some(id.container_id),
-        ),
-        None => (None, None, None, None),
-    };
+    let KillDetails {
+        trigger,
+        trigger_stack,
+        victim_command,
+    } = details;
+    let (namespace, pod_name, container_name, container_id, workload_kind, workload_name) =
+        match identity {
+            Some(id) => (
+                Some(id.namespace),
+                Some(id.pod_name),
+                Some(id.container_name),
+                Some(id.container_id),
+                Some(id.workload_kind),
+                Some(id.workload_name),
+            ),
+            None => (None, None, None, None, None, None),
+        };
 
     EnrichedOomEvent {
         raw_event,
@@ -29,6 +53,12 @@ pub fn enrich(
         pod_name,
         container_name,
         container_id,
+        workload_kind,
+        workload_name,
+        scope,
+        trigger,
+        trigger_stack,
+        victim_command,
         timestamp,
     }
 }
@@ -36,34 +66,43 @@ pub fn enrich(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::resolve::identity;
 
     fn raw() -> OomKillEvent {
         OomKillEvent {
-            pid: 1234,
-            tgid: 1200,
-            comm: *b"python\0\0\0\0\0\0\0\0\0\0",
+            victim_pid: 1234,
+            victim_tgid: 1234,
+            victim_comm: *b"python\0\0\0\0\0\0\0\0\0\0",
             total_vm: 100,
             anon_rss: 50,
             file_rss: 20,
             shmem_rss: 5,
             uid: 1000,
             pgtables: 8,
-            oom_score_adj: 0,
+            trigger_pid: 1200,
+            trigger_tgid: 1200,
+            ..OomKillEvent::default()
         }
     }
 
-    fn identity() -> ContainerIdentity {
+    fn sidecar() -> ContainerIdentity {
         ContainerIdentity {
-            namespace: "prod".into(),
-            pod_name: "api-7d9".into(),
-            container_name: "api".into(),
-            container_id: "abc123".into(),
+            container_name: "envoy".into(),
+            container_id: "def456".into(),
+            ..identity()
         }
     }
 
     #[test]
     fn fills_all_fields_when_identity_resolved_on_node() {
-        let e = enrich(raw(), Some("node-1"), Some(identity()), 42);
+        let e = enrich(
+            raw(),
+            Some("node-1"),
+            Some(identity()),
+            OomScope::Container,
+            KillDetails::default(),
+            42,
+        );
         assert_eq!(e.node_name.as_deref(), Some("node-1"));
         assert_eq!(e.namespace.as_deref(), Some("prod"));
         assert_eq!(e.pod_name.as_deref(), Some("api-7d9"));
@@ -75,7 +114,14 @@ mod tests {
     fn keeps_node_when_identity_unresolved() {
         // The load-bearing invariant: a failed resolution must not erase the node we
         // already know we are running on.
-        let e = enrich(raw(), Some("node-1"), None, 42);
+        let e = enrich(
+            raw(),
+            Some("node-1"),
+            None,
+            OomScope::Container,
+            KillDetails::default(),
+            42,
+        );
         assert_eq!(e.node_name.as_deref(), Some("node-1"));
         assert_eq!(e.namespace, None);
         assert_eq!(e.pod_name, None);
@@ -85,7 +131,14 @@ mod tests {
 
     #[test]
     fn all_none_in_standalone_mode() {
-        let e = enrich(raw(), None, None, 42);
+        let e = enrich(
+            raw(),
+            None,
+            None,
+            OomScope::Container,
+            KillDetails::default(),
+            42,
+        );
         assert_eq!(e.node_name, None);
         assert_eq!(e.namespace, None);
         assert_eq!(e.pod_name, None);
@@ -94,10 +147,52 @@ mod tests {
     }
 
     #[test]
-    fn passes_raw_event_and_timestamp_through() {
-        let e = enrich(raw(), None, None, 99);
+    fn passes_raw_event_scope_and_timestamp_through() {
+        let e = enrich(
+            raw(),
+            None,
+            None,
+            OomScope::Global,
+            KillDetails::default(),
+            99,
+        );
         assert_eq!(e.timestamp, 99);
-        assert_eq!(e.raw_event.pid, 1234);
+        assert_eq!(e.scope, OomScope::Global);
+        assert_eq!(e.raw_event.victim_pid, 1234);
         assert_eq!(e.raw_event.total_vm, 100);
     }
+
+    #[test]
+    fn carries_the_trigger_alongside_the_victim() {
+        let e = enrich(
+            raw(),
+            Some("node-1"),
+            Some(identity()),
+            OomScope::Container,
+            KillDetails {
+                trigger: Some(sidecar()),
+                ..KillDetails::default()
+            },
+            42,
+        );
+        assert_eq!(e.container_name.as_deref(), Some("api"));
+        assert_eq!(e.trigger, Some(sidecar()));
+    }
+
+    #[test]
+    fn trigger_does_not_stand_in_for_an_unresolved_victim() {
+        let e = enrich(
+            raw(),
+            Some("node-1"),
+            None,
+            OomScope::Container,
+            KillDetails {
+                trigger: Some(sidecar()),
+                ..KillDetails::default()
+            },
+            42,
+        );
+        assert_eq!(e.container_name, None);
+        assert_eq!(e.trigger, Some(sidecar()));
+    }
 }
@@ -0,0 +1,95 @@
+//! The little of inotify the cgroupfs watchers need: watches added by path, and the events
+//! read off them.
+
+use std::{
+    ffi::{CString, OsStr, OsString},
+    io, mem,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::ffi::OsStrExt,
+    },
+    path::Path,
+};
+
+/// One event: the watch it came from, what happened, and the name of the child it
+/// happened to (empty when it happened to the watched path itself).
+pub struct Event {
+    pub wd: i32,
+    pub mask: u32,
+    pub name: OsString,
+}
+
+pub struct Inotify {
+    fd: OwnedFd,
+}
+
+impl Inotify {
+    pub fn new() -> io::Result<Self> {
+        // SAFETY: plain syscall; the fd it returns is owned from here on.
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        })
+    }
+
+    /// Watch `path` for the events in `mask`, returning the watch descriptor its events
+    /// carry.
+    pub fn watch(&self, path: &Path, mask: u32) -> io::Result<i32> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        // SAFETY: path is NUL-terminated and outlives the call.
+        let wd = unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), path.as_ptr(), mask) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(wd)
+    }
+
+    /// The events pending, blocking until there are some. Empty if interrupted.
+    pub fn read(&self) -> io::Result<Vec<Event>> {
+        let mut buf = [0u8; 4096];
+        // SAFETY: buf is writable for its whole length.
+        let len = unsafe {
+            libc::read(
+                self.fd.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+            )
+        };
+        if len < 0 {
+            let e = io::Error::last_os_error();
+            return match e.kind() {
+                io::ErrorKind::Interrupted => Ok(Vec::new()),
+                _ => Err(e),
+            };
+        }
+
+        let header = mem::size_of::<libc::inotify_event>();
+        let mut events = Vec::new();
+        let mut offset = 0;
+        while offset + header <= len as usize {
+            // SAFETY: a whole header lies within what was read; read without assuming
+            // alignment.
+            let event = unsafe {
+                (buf.as_ptr().add(offset) as *const libc::inotify_event).read_unaligned()
+            };
+            let name = &buf[offset + header..][..event.len as usize];
+            let name = name.split(|&byte| byte == 0).next().unwrap_or_default();
+            offset += header + event.len as usize;
+            events.push(Event {
+                wd: event.wd,
+                mask: event.mask,
+                name: OsStr::from_bytes(name).to_os_string(),
+            });
+        }
+        Ok(events)
+    }
+}
+
+impl AsRawFd for Inotify {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
@@ -0,0 +1,408 @@
+//! The kernel log fallback: OOM kills read from `/dev/kmsg`, for hosts where the eBPF
+//! probe cannot load — a build without the `ebpf` feature, or a kernel that refuses the
+//! program.
+//!
+//! The OOM killer reports each kill in a burst of log lines: who invoked it and for what
+//! allocation, a stack dump, the `oom-kill:` summary of its constraint and cgroups, then
+//! `Killed process` with the victim's memory. [`KmsgParser`] folds a burst into an
+//! [`OomKillEvent`], so the watch loop runs unchanged. The log carries less than the probe
+//! reads — no process details, command or stacks, and the victim's cgroup only as a path —
+//! so [`KmsgSource`] looks the paths up in cgroupfs while the cgroups still exist, and the
+//! fields the log lacks are marked unknown.
+
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    sync::LazyLock,
+    thread,
+};
+
+use log::{debug, error, info, warn};
+use oom_watcher_common::{
+    OomKillEvent, ProbeEvent, CONSTRAINT_CPUSET, CONSTRAINT_MEMCG, CONSTRAINT_MEMORY_POLICY,
+    CONSTRAINT_NONE, CONSTRAINT_UNKNOWN, FIELD_HIWATER_RSS, FIELD_NS_PID, FIELD_OOM_SCORE_ADJ,
+    FIELD_PARENT, FIELD_PGTABLES, FIELD_START_TIME, FIELD_SWAP, FIELD_THREADS, FIELD_UID,
+    STACK_UNKNOWN,
+};
+use regex::Regex;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::{
+    cgroup::{CgroupIndex, Hierarchy},
+    watch::OomEventSource,
+};
+
+const KMSG: &str = "/dev/kmsg";
+
+/// Bytes read per record; the kernel caps a record's text at 1 KiB, plus its prefix and
+/// dictionary.
+const RECORD_LEN: usize = 8192;
+
+/// `<trigger> invoked oom-killer: gfp_mask=0xcc0(GFP_KERNEL), order=0, oom_score_adj=0`
+static INVOKED: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(.+) invoked oom-killer: gfp_mask=0x([0-9a-f]+).*?, order=(-?\d+)")
+        .expect("valid regex")
+});
+
+/// The stack dump's header: `CPU: 3 PID: 1234 Comm: java Not tainted …` (with `UID: 0`
+/// after the CPU on recent kernels).
+static DUMP: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\bPID: (\d+) Comm: ").expect("valid regex"));
+
+/// `[Memory cgroup out of memory|Out of memory]: Killed process 1234 (java) total-vm:…kB,
+/// anon-rss:…kB, file-rss:…kB, shmem-rss:…kB, UID:1000 pgtables:…kB oom_score_adj:0`,
+/// the part from `UID` on missing before 5.2.
+static KILLED: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        r"Killed process (\d+) \((.*)\) total-vm:(\d+)kB, anon-rss:(\d+)kB, ",
+        r"file-rss:(\d+)kB, shmem-rss:(\d+)kB",
+        r"(?:, UID:(\d+) pgtables:(\d+)kB oom_score_adj:(-?\d+))?",
+    ))
+    .expect("valid regex")
+});
+
+/// Fields the kernel log never carries: the probe reads them from the victim's
+/// `task_struct`.
+const PROCESS_FIELDS: u32 =
+    FIELD_PARENT | FIELD_NS_PID | FIELD_THREADS | FIELD_START_TIME | FIELD_HIWATER_RSS | FIELD_SWAP;
+
+/// Fields the `Killed process` line only carries since 5.2.
+const KILLED_FIELDS_5_2: u32 = FIELD_UID | FIELD_PGTABLES | FIELD_OOM_SCORE_ADJ;
+
+/// One kill read from the kernel log, with the cgroups it names as paths.
+#[derive(Clone, Debug)]
+pub struct KmsgKill {
+    pub event: OomKillEvent,
+    /// `task_memcg`: the victim's memory cgroup.
+    pub task_memcg: Option<String>,
+    /// `oom_memcg`: the memory cgroup whose limit was hit; `None` for a global OOM.
+    pub oom_memcg: Option<String>,
+}
+
+/// Folds the kernel log's lines, in order, into the kills they report.
+#[derive(Default)]
+pub struct KmsgParser {
+    // The OOM killer invocation in progress; one can kill several tasks (memory.oom.group).
+    invocation: Option<Invocation>,
+    summary: Option<Summary>,
+}
+
+/// From `invoked oom-killer` and the stack dump after it: the trigger and its allocation.
+struct Invocation {
+    comm: [u8; 16],
+    pid: Option<u32>,
+    gfp_mask: u32,
+    order: i32,
+}
+
+/// From the `oom-kill:` line.
+#[derive(Default)]
+struct Summary {
+    constraint: Option<u32>,
+    oom_memcg: Option<String>,
+    task_memcg: Option<String>,
+    pid: Option<u32>,
+}
+
+impl KmsgParser {
+    /// Feed one `/dev/kmsg` record; the kill it completes, if any.
+    pub fn record(&mut self, record: &[u8]) -> Option<KmsgKill> {
+        let record = String::from_utf8_lossy(record);
+        // `<prio>,<seq>,<usec>,<flags>;<text>`, then dictionary lines indented by a space.
+        let text = record.split_once(';').map_or(&*record, |(_, text)| text);
+        self.line(text.lines().next().unwrap_or(""))
+    }
+
+    /// Feed one line of log text; the kill it completes, if any.
+    pub fn line(&mut self, line: &str) -> Option<KmsgKill> {
+        if let Some(caps) = INVOKED.captures(line) {
+            self.invocation = Some(Invocation {
+                comm: comm(&caps[1]),
+                pid: None,
+                gfp_mask: u32::from_str_radix(&caps[2], 16).unwrap_or(0),
+                order: caps[3].parse().unwrap_or(0),
+            });
+            self.summary = None;
+        } else if let Some(caps) = DUMP.captures(line) {
+            if let Some(invocation) = self.invocation.as_mut().filter(|i| i.pid.is_none()) {
+                invocation.pid = caps[1].parse().ok();
+            }
+        } else if let Some(fields) = line.strip_prefix("oom-kill:") {
+            self.summary = Some(summary(fields));
+        } else if let Some(caps) = KILLED.captures(line) {
+            return Some(self.kill(&caps));
+        }
+        None
+    }
+
+    fn kill(&self, caps: &regex::Captures) -> KmsgKill {
+        let pid = number(caps, 1).unwrap_or(0);
+        let mut event = OomKillEvent {
+            victim_pid: pid,
+            victim_comm: comm(&caps[2]),
+            total_vm: number(caps, 3).unwrap_or(0),
+            anon_rss: number(caps, 4).unwrap_or(0),
+            file_rss: number(caps, 5).unwrap_or(0),
+            shmem_rss: number(caps, 6).unwrap_or(0),
+            kernel_stack_id: STACK_UNKNOWN,
+            user_stack_id: STACK_UNKNOWN,
+            constraint: CONSTRAINT_UNKNOWN,
+            unknown_fields: PROCESS_FIELDS,
+            ..OomKillEvent::default()
+        };
+        match (number(caps, 7), number(caps, 8), number(caps, 9)) {
+            (Some(uid), Some(pgtables), Some(adj)) => {
+                event.uid = uid;
+                event.pgtables = pgtables;
+                event.oom_score_adj = adj;
+            }
+            _ => event.unknown_fields |= KILLED_FIELDS_5_2,
+        }
+        if let Some(invocation) = &self.invocation {
+            event.trigger_comm = invocation.comm;
+            event.trigger_pid = invocation.pid.unwrap_or(0);
+            event.gfp_mask = invocation.gfp_mask;
+            event.order = invocation.order;
+        }
+
+        let (mut task_memcg, mut oom_memcg) = (None, None);
+        if let Some(summary) = &self.summary {
+            event.constraint = summary.constraint.unwrap_or(CONSTRAINT_UNKNOWN);
+            oom_memcg = summary.oom_memcg.clone();
+            // The summary names the chosen victim; the rest of a group kill share its
+            // constraint and memcg, not necessarily its cgroup.
+            if summary.pid == Some(pid) {
+                task_memcg = summary.task_memcg.clone();
+            }
+        }
+        KmsgKill {
+            event,
+            task_memcg,
+            oom_memcg,
+        }
+    }
+}
+
+/// Capture group `i` of `caps` as a number, if it matched.
+fn number<T: std::str::FromStr>(caps: &regex::Captures, i: usize) -> Option<T> {
+    caps.get(i).and_then(|m| m.as_str().parse().ok())
+}
+
+/// Parse the `oom-kill:` line's comma-separated fields:
+/// `constraint=CONSTRAINT_MEMCG,nodemask=(null),cpuset=/,mems_allowed=0,oom_memcg=/…,
+/// task_memcg=/…,task=java,pid=1234,uid=1000` — `global_oom` in place of `oom_memcg` when
+/// the node ran out. A list in `mems_allowed` splits into pieces without `=`, ignored.
+fn summary(fields: &str) -> Summary {
+    let mut summary = Summary::default();
+    for (key, value) in fields.split(',').filter_map(|field| field.split_once('=')) {
+        match key {
+            "constraint" => summary.constraint = Some(constraint(value)),
+            "oom_memcg" => summary.oom_memcg = Some(value.to_string()),
+            "task_memcg" => summary.task_memcg = Some(value.to_string()),
+            "pid" => summary.pid = value.parse().ok(),
+            _ => {}
+        }
+    }
+    summary
+}
+
+fn constraint(name: &str) -> u32 {
+    match name {
+        "CONSTRAINT_NONE" => CONSTRAINT_NONE,
+        "CONSTRAINT_CPUSET" => CONSTRAINT_CPUSET,
+        "CONSTRAINT_MEMORY_POLICY" => CONSTRAINT_MEMORY_POLICY,
+        "CONSTRAINT_MEMCG" => CONSTRAINT_MEMCG,
+        _ => CONSTRAINT_UNKNOWN,
+    }
+}
+
+/// A process name as a kernel `comm`: at most 15 bytes, NUL-padded.
+fn comm(name: &str) -> [u8; 16] {
+    let mut comm = [0u8; 16];
+    let len = name.len().min(15);
+    comm[..len].copy_from_slice(&name.as_bytes()[..len]);
+    comm
+}
+
+/// The fallback adapter for [`OomEventSource`]: kills parsed from `/dev/kmsg` by a reader
+/// thread — the device only reads blocking — and handed over a channel.
+pub struct KmsgSource {
+    kills: UnboundedReceiver<KmsgKill>,
+    cgroups: CgroupIndex,
+}
+
+impl KmsgSource {
+    /// Open `/dev/kmsg` at its end, so only kills from now on are reported, and start
+    /// reading it. Needs `CAP_SYSLOG` where `kernel.dmesg_restrict` is set.
+    pub fn open() -> io::Result<Self> {
+        let mut file = File::open(KMSG)?;
+        file.seek(SeekFrom::End(0))?;
+        let (sender, kills) = mpsc::unbounded_channel();
+        thread::Builder::new()
+            .name("kmsg".into())
+            .spawn(move || read_kmsg(file, sender))?;
+        info!("Reading OOM kills from {}", KMSG);
+        Ok(Self {
+            kills,
+            cgroups: CgroupIndex::detect(),
+        })
+    }
+
+    /// The id of the cgroup at `path`, while it still exists.
+    fn cgroup_id(&self, path: &str) -> Option<u64> {
+        self.cgroups
+            .id(path)
+            .map_err(|e| debug!("No cgroup directory for {}: {}", path, e))
+            .ok()
+    }
+}
+
+impl OomEventSource for KmsgSource {
+    async fn next(&mut self) -> Option<ProbeEvent> {
+        let kill = self.kills.recv().await?;
+        let mut event = kill.event;
+        if let Some(id) = kill.task_memcg.as_deref().and_then(|p| self.cgroup_id(p)) {
+            event.victim_memcg_id = id;
+            if self.cgroups.hierarchy() == Hierarchy::Unified {
+                event.victim_cgroup_id = id;
+            }
+        }
+        if let Some(id) = kill.oom_memcg.as_deref().and_then(|p| self.cgroup_id(p)) {
+            event.oom_memcg_id = id;
+        }
+        Some(ProbeEvent::Kill(event))
+    }
+}
+
+/// Read `kmsg` a record at a time until the source is dropped, sending every kill.
+fn read_kmsg(mut kmsg: File, kills: UnboundedSender<KmsgKill>) {
+    let mut parser = KmsgParser::default();
+    let mut record = vec![0u8; RECORD_LEN];
+    loop {
+        match kmsg.read(&mut record) {
+            Ok(0) => return,
+            Ok(len) => {
+                if let Some(kill) = parser.record(&record[..len]) {
+                    if kills.send(kill).is_err() {
+                        return;
+                    }
+                }
+            }
+            // The ring wrapped past unread records; reading resumes at the oldest left.
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                warn!("Kernel log records were overwritten before being read; kills in them are missed")
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => {
+                error!("Failed to read {}: {}", KMSG, e);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use oom_watcher_common::FIELD_VICTIM_COMM;
+
+    use super::*;
+
+    fn feed(parser: &mut KmsgParser, lines: &[&str]) -> Vec<KmsgKill> {
+        lines.iter().filter_map(|line| parser.line(line)).collect()
+    }
+
+    #[test]
+    fn folds_a_memcg_oom_into_one_kill() {
+        let kills = feed(
+            &mut KmsgParser::default(),
+            &[
+                "envoy invoked oom-killer: gfp_mask=0xcc0(GFP_KERNEL), order=0, oom_score_adj=-997",
+                "CPU: 3 UID: 0 PID: 4242 Comm: envoy Not tainted 6.8.0-1015-aws #16-Ubuntu",
+                "Call Trace:",
+                "memory: usage 524288kB, limit 524288kB, failcnt 112",
+                "oom-kill:constraint=CONSTRAINT_MEMCG,nodemask=(null),cpuset=cri-containerd-abc.scope,mems_allowed=0-1,oom_memcg=/kubepods.slice/kubepods-burstable.slice/kubepods-burstable-pod1234.slice,task_memcg=/kubepods.slice/kubepods-burstable.slice/kubepods-burstable-pod1234.slice/cri-containerd-abc.scope,task=java,pid=1234,uid=1000",
+                "Memory cgroup out of memory: Killed process 1234 (java) total-vm:4194304kB, anon-rss:520000kB, file-rss:1024kB, shmem-rss:0kB, UID:1000 pgtables:2048kB oom_score_adj:-997",
+            ],
+        );
+
+        assert_eq!(kills.len(), 1);
+        let kill = &kills[0];
+        let e = &kill.event;
+        assert_eq!((e.victim_pid, &e.victim_comm[..5]), (1234, &b"java\0"[..]));
+        assert_eq!(
+            (e.total_vm, e.anon_rss, e.file_rss),
+            (4194304, 520000, 1024)
+        );
+        assert_eq!((e.uid, e.pgtables, e.oom_score_adj), (1000, 2048, -997));
+        assert_eq!(
+            (e.trigger_pid, &e.trigger_comm[..6]),
+            (4242, &b"envoy\0"[..])
+        );
+        assert_eq!((e.gfp_mask, e.order), (0xcc0, 0));
+        assert_eq!(e.constraint, CONSTRAINT_MEMCG);
+        assert_eq!(
+            kill.oom_memcg.as_deref(),
+            Some("/kubepods.slice/kubepods-burstable.slice/kubepods-burstable-pod1234.slice")
+        );
+        assert!(kill
+            .task_memcg
+            .as_deref()
+            .is_some_and(|path| path.ends_with("/cri-containerd-abc.scope")));
+        assert!(e.has(FIELD_VICTIM_COMM) && e.has(FIELD_UID));
+        assert!(!e.has(FIELD_PARENT));
+        assert_eq!(e.kernel_stack_id, STACK_UNKNOWN);
+    }
+
+    #[test]
+    fn reads_an_older_global_oom() {
+        let kills = feed(
+            &mut KmsgParser::default(),
+            &[
+                "stress invoked oom-killer: gfp_mask=0x6200ca(GFP_HIGHUSER_MOVABLE), nodemask=(null), order=0, oom_score_adj=0",
+                "CPU: 0 PID: 77 Comm: stress Not tainted 4.19.0 #1",
+                "oom-kill:constraint=CONSTRAINT_NONE,nodemask=(null),cpuset=/,mems_allowed=0,global_oom,task_memcg=/user.slice,task=stress,pid=78,uid=0",
+                "Out of memory: Kill process 78 (stress) score 912 or sacrifice child",
+                "Killed process 78 (stress) total-vm:2100000kB, anon-rss:1900000kB, file-rss:4kB, shmem-rss:0kB",
+            ],
+        );
+
+        let kill = &kills[0];
+        assert_eq!(kill.event.constraint, CONSTRAINT_NONE);
+        assert_eq!(kill.event.gfp_mask, 0x6200ca);
+        assert_eq!(kill.oom_memcg, None);
+        assert_eq!(kill.task_memcg.as_deref(), Some("/user.slice"));
+        // Before 5.2 the kill line stops at shmem-rss.
+        assert!(!kill.event.has(FIELD_UID) && !kill.event.has(FIELD_OOM_SCORE_ADJ));
+    }
+
+    #[test]
+    fn a_group_kill_shares_the_invocation_not_the_victims_cgroup() {
+        let kills = feed(
+            &mut KmsgParser::default(),
+            &[
+                "java invoked oom-killer: gfp_mask=0xcc0(GFP_KERNEL), order=0, oom_score_adj=0",
+                "oom-kill:constraint=CONSTRAINT_MEMCG,nodemask=(null),cpuset=/,mems_allowed=0,oom_memcg=/pod,task_memcg=/pod/app,task=java,pid=10,uid=0",
+                "Memory cgroup out of memory: Killed process 10 (java) total-vm:1kB, anon-rss:1kB, file-rss:0kB, shmem-rss:0kB, UID:0 pgtables:4kB oom_score_adj:0",
+                "Tasks in /pod are going to be killed due to memory.oom.group set",
+                "Memory cgroup out of memory: Killed process 11 (sidecar) total-vm:1kB, anon-rss:1kB, file-rss:0kB, shmem-rss:0kB, UID:0 pgtables:4kB oom_score_adj:0",
+            ],
+        );
+
+        assert_eq!(kills.len(), 2);
+        assert_eq!(kills[1].event.constraint, CONSTRAINT_MEMCG);
+        assert_eq!(kills[1].oom_memcg.as_deref(), Some("/pod"));
+        assert_eq!(kills[1].task_memcg, None);
+    }
+
+    #[test]
+    fn strips_the_record_prefix_and_dictionary() {
+        let mut parser = KmsgParser::default();
+        let record = b"3,1024,884211334,-;Out of memory: Killed process 9 (a) total-vm:1kB, anon-rss:1kB, file-rss:0kB, shmem-rss:0kB, UID:0 pgtables:4kB oom_score_adj:0\n SUBSYSTEM=memory\n";
+
+        let kill = parser.record(record).unwrap();
+        assert_eq!(kill.event.victim_pid, 9);
+        assert!(parser
+            .record(b"6,1025,884211400,-;eth0: link up\n")
+            .is_none());
+    }
+}
@@ -1,17 +1,33 @@
-use std::fs;
+use std::{fs, sync::Arc};
 
 use anyhow::{anyhow, Result};
-use k8s_openapi::api::core::v1::Pod;
-use kube::{api::ListParams, Api, Client, Config};
+use k8s_openapi::{
+    api::core::v1::{Event, EventSource, ObjectReference},
+    apimachinery::pkg::apis::meta::v1::{ObjectMeta, Time},
+    jiff::Timestamp,
+};
+use kube::{api::PostParams, Api, Client, Config};
 use log::{debug, warn};
-use oom_watcher_common::ContainerIdentity;
-use regex::Regex;
+use oom_watcher_common::{Confidence, ContainerIdentity};
 
-use crate::resolve::{ContainerResolver, ResolutionOutcome};
+use crate::{
+    cgroup::CgroupIndex,
+    cgroup_path::{self, ContainerCgroup, Runtime},
+    pod_cache::PodCache,
+    pressure::WarningSink,
+    resolve::{ContainerResolver, ProcessRef, ResolutionOutcome, RuntimeMismatch},
+    workload::{Owner, Workloads},
+};
+
+/// The `reason` of the warning events posted for containers approaching OOM.
+const APPROACHING_OOM_REASON: &str = "ApproachingOOM";
 
 pub struct KubernetesClient {
-    pods_api: Api<Pod>,
+    client: Client,
+    pods: Arc<PodCache>,
+    workloads: Workloads<Client>,
     node_name: String,
+    cgroups: CgroupIndex,
 }
 
 impl KubernetesClient {
@@ -20,7 +36,6 @@ impl KubernetesClient {
             .map_err(|e| anyhow!("Failed to create in-cluster config: {}", e))?;
 
         let client = Client::try_from(config)?;
-        let pods_api: Api<Pod> = Api::all(client);
 
         // Require NODE_NAME rather than defaulting to "unknown": a wrong node scopes
         // the spec.nodeName field selector to a node with no pods, so every lookup
@@ -29,117 +44,189 @@ impl KubernetesClient {
             anyhow!("NODE_NAME is unset; the DaemonSet must expose it via the downward API")
         })?;
 
+        // Watched in the background from here on; lookups wait for its first list.
+        let pods = Arc::new(PodCache::from_env());
+        let (watched, api, node) = (pods.clone(), Api::all(client.clone()), node_name.clone());
+        tokio::spawn(async move { watched.run(api, &node).await });
+
+        // Indexed and followed in the background too, so a container's cgroup is known
+        // even if it is removed before the kill is resolved.
+        let cgroups = CgroupIndex::detect();
+        #[cfg(target_os = "linux")]
+        if let Err(e) = cgroups.track() {
+            warn!(
+                "Not following {} for new cgroups; each is looked up by walking it: {}",
+                cgroups.root().display(),
+                e
+            );
+        }
+
         Ok(Self {
-            pods_api,
+            workloads: Workloads::new(client.clone()),
+            client,
+            pods,
             node_name,
+            cgroups,
         })
     }
 
-    pub async fn get_container_info(&self, pid: u32) -> Result<Option<ContainerIdentity>> {
-        let container_id = self.get_container_id_from_pid(pid)?;
+    pub async fn get_container_info(
+        &self,
+        process: &ProcessRef,
+    ) -> Result<Option<ContainerIdentity>> {
+        // Prefer the cgroup the probe captured in-kernel: it does not depend on the victim
+        // still existing. /proc is the fallback when the probe could not capture one, or
+        // captured only a pod-level cgroup.
+        let cgroup = match self.get_container_cgroup_from_cgroup_id(process).await {
+            Some(cgroup) if cgroup.container_id.is_some() => Some(cgroup),
+            captured => match self.get_container_cgroup_from_pid(process.pid) {
+                Some(read) if read.container_id.is_some() || captured.is_none() => Some(read),
+                _ => captured,
+            },
+        };
+        let Some(cgroup) = cgroup else {
+            return Ok(None);
+        };
 
-        if let Some(container_id) = container_id {
-            return self.get_pod_info_from_container_id(&container_id).await;
-        }
+        debug!(
+            "PID {} is in {} container {} of pod {} ({:?})",
+            process.pid,
+            cgroup.runtime.map_or("unknown", Runtime::as_str),
+            cgroup.container_id.as_deref().unwrap_or("unknown"),
+            cgroup.pod_uid.as_deref().unwrap_or("unknown"),
+            cgroup.qos
+        );
+        self.get_pod_info(&cgroup).await
+    }
 
-        Ok(None)
+    async fn get_container_cgroup_from_cgroup_id(
+        &self,
+        process: &ProcessRef,
+    ) -> Option<ContainerCgroup> {
+        let id = self.cgroups.select(process.cgroup_id, process.memcg_id)?;
+        let Some(path) = self.cgroups.path(id).await else {
+            debug!("No cgroup directory for id {} (PID {})", id, process.pid);
+            return None;
+        };
+
+        let cgroup = cgroup_path::parse(&path);
+        if cgroup.is_none() {
+            debug!(
+                "Could not extract container ID from cgroup {} for PID {}",
+                path, process.pid
+            );
+        }
+        cgroup
     }
 
-    fn get_container_id_from_pid(&self, pid: u32) -> Result<Option<String>> {
+    fn get_container_cgroup_from_pid(&self, pid: u32) -> Option<ContainerCgroup> {
         let cgroup_path = format!("/proc/{}/cgroup", pid);
         let content = match fs::read_to_string(&cgroup_path) {
             Ok(content) => content,
             Err(_) => {
                 debug!("Could not read cgroup file for PID {}", pid);
-                return Ok(None);
+                return None;
             }
         };
 
-        // Extract container ID from cgroup path
-        // Formats can vary: docker, containerd, cri-o
-        let patterns = [
-            r"/docker/([a-f0-9]{64})",                  // Docker
-            r"/kubepods/[^/]*/pod[^/]*/([a-f0-9]{64})", // Containerd/CRI-O
-            r"cri-containerd-([a-f0-9]{64})",           // CRI-containerd
-        ];
-
-        for pattern in &patterns {
-            let re = Regex::new(pattern)?;
-            if let Some(captures) = re.captures(&content) {
-                if let Some(container_id) = captures.get(1) {
-                    return Ok(Some(container_id.as_str().to_string()));
-                }
-            }
+        let cgroup = cgroup_path::parse(&content);
+        if cgroup.is_none() {
+            debug!(
+                "Could not extract container ID from cgroup for PID {}: {}",
+                pid, content
+            );
         }
-
-        debug!(
-            "Could not extract container ID from cgroup for PID {}: {}",
-            pid, content
-        );
-        Ok(None)
+        cgroup
     }
 
-    async fn get_pod_info_from_container_id(
-        &self,
-        container_id: &str,
-    ) -> Result<Option<ContainerIdentity>> {
-        // Scope the query to this node so we don't list every pod in the
-        // cluster on each OOM event; the kubelet supports the spec.nodeName
-        // field selector for pods.
-        let params = ListParams::default().fields(&format!("spec.nodeName={}", self.node_name));
-        let pods = self.pods_api.list(&params).await?;
-
-        for pod in pods.items {
-            if let Some(status) = &pod.status {
-                if let Some(container_statuses) = &status.container_statuses {
-                    for container_status in container_statuses {
-                        if let Some(container_id_full) = &container_status.container_id {
-                            // Container ID format: docker://abc123... or containerd://abc123...
-                            if container_id_full.ends_with(container_id)
-                                || container_id_full.contains(container_id)
-                            {
-                                let namespace = pod
-                                    .metadata
-                                    .namespace
-                                    .clone()
-                                    .unwrap_or_else(|| "default".to_string());
-                                let pod_name = pod
-                                    .metadata
-                                    .name
-                                    .clone()
-                                    .unwrap_or_else(|| "unknown".to_string());
-                                let container_name = container_status.name.clone();
-
-                                return Ok(Some(ContainerIdentity {
-                                    namespace,
-                                    pod_name,
-                                    container_name,
-                                    container_id: container_id.to_string(),
-                                }));
-                            }
-                        }
-                    }
-                }
-            }
+    async fn get_pod_info(&self, cgroup: &ContainerCgroup) -> Result<Option<ContainerIdentity>> {
+        let mut identity = self.pods.resolve(cgroup).await?;
+        if let Some(identity) = &mut identity {
+            let owner = Owner {
+                kind: std::mem::take(&mut identity.workload_kind),
+                name: std::mem::take(&mut identity.workload_name),
+            };
+            let workload = self.workloads.resolve(&identity.namespace, owner).await;
+            (identity.workload_kind, identity.workload_name) = (workload.kind, workload.name);
         }
-
-        warn!("Could not find pod info for container ID: {}", container_id);
-        Ok(None)
+        match &identity {
+            None => warn!(
+                "Could not find pod info for container ID {} or pod UID {}",
+                cgroup.container_id.as_deref().unwrap_or("unknown"),
+                cgroup.pod_uid.as_deref().unwrap_or("unknown")
+            ),
+            Some(identity) if identity.confidence == Confidence::Pod => debug!(
+                "Container {} not in the status of pod {}/{} yet; resolved to the pod only",
+                cgroup.container_id.as_deref().unwrap_or("unknown"),
+                identity.namespace,
+                identity.pod_name
+            ),
+            Some(_) => {}
+        }
+        Ok(identity)
     }
 }
 
 /// The in-cluster adapter for the Resolution seam. Maps `get_container_info`'s
-/// `Result<Option<_>>` onto the three [`ResolutionOutcome`] variants.
+/// `Result<Option<_>>` onto the [`ResolutionOutcome`] variants, a [`RuntimeMismatch`] error
+/// onto its own.
 impl ContainerResolver for KubernetesClient {
     fn node_name(&self) -> &str {
         &self.node_name
     }
 
-    async fn resolve(&self, pid: u32) -> ResolutionOutcome {
-        match self.get_container_info(pid).await {
+    async fn cgroup_path(&self, cgroup_id: u64) -> Option<String> {
+        self.cgroups.path(cgroup_id).await
+    }
+
+    async fn resolve(&self, process: &ProcessRef) -> ResolutionOutcome {
+        match self.get_container_info(process).await {
             Ok(Some(identity)) => ResolutionOutcome::Found(identity),
             Ok(None) => ResolutionOutcome::NotFound,
-            Err(e) => ResolutionOutcome::Failed(e),
+            Err(e) => match e.downcast::<RuntimeMismatch>() {
+                Ok(mismatch) => ResolutionOutcome::RuntimeMismatch(mismatch),
+                Err(e) => ResolutionOutcome::Failed(e),
+            },
         }
     }
 }
+
+/// Posts warnings as `Warning` events on the container's pod, where `kubectl describe pod`
+/// and event exporters pick them up.
+impl WarningSink for KubernetesClient {
+    async fn warn(&self, identity: &ContainerIdentity, message: &str) -> Result<()> {
+        let now = Time(Timestamp::now());
+        let event = Event {
+            metadata: ObjectMeta {
+                generate_name: Some(format!("{}.", identity.pod_name)),
+                namespace: Some(identity.namespace.clone()),
+                ..ObjectMeta::default()
+            },
+            involved_object: ObjectReference {
+                api_version: Some("v1".to_string()),
+                kind: Some("Pod".to_string()),
+                namespace: Some(identity.namespace.clone()),
+                name: Some(identity.pod_name.clone()),
+                field_path: self.pods.field_path(identity),
+                ..ObjectReference::default()
+            },
+            reason: Some(APPROACHING_OOM_REASON.to_string()),
+            message: Some(message.to_string()),
+            type_: Some("Warning".to_string()),
+            source: Some(EventSource {
+                component: Some("oom-watcher".to_string()),
+                host: Some(self.node_name.clone()),
+            }),
+            reporting_component: Some("oom-watcher".to_string()),
+            reporting_instance: Some(self.node_name.clone()),
+            first_timestamp: Some(now.clone()),
+            last_timestamp: Some(now),
+            count: Some(1),
+            ..Event::default()
+        };
+        Api::<Event>::namespaced(self.client.clone(), &identity.namespace)
+            .create(&PostParams::default(), &event)
+            .await?;
+        Ok(())
+    }
+}
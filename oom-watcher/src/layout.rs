@@ -0,0 +1,705 @@
+//! Kernel layout: the struct member offsets the probe walks, resolved from the running
+//! kernel's BTF.
+//!
+//! The probe object is built once and loaded on every kernel in the fleet, so it cannot
+//! bake in where `task_struct.cgroups` or `kernfs_node.id` live. [`load`] reads them from
+//! `/sys/kernel/btf/vmlinux` at load time and the loader patches the result into the
+//! probe's `LAYOUT` global. [`Btf`] is the minimal reader this needs — just enough of the
+//! format to find a struct member's offset and an enumerator's value.
+
+use std::fs;
+
+use anyhow::{anyhow, bail, Context, Result};
+use oom_watcher_common::{KernelLayout, OFFSET_UNKNOWN};
+
+/// Where the kernel exposes its own BTF (`CONFIG_DEBUG_INFO_BTF`).
+const VMLINUX_BTF: &str = "/sys/kernel/btf/vmlinux";
+
+/// Read the running kernel's BTF and resolve the probe's [`KernelLayout`] from it.
+pub fn load() -> Result<KernelLayout> {
+    let data = fs::read(VMLINUX_BTF).with_context(|| format!("reading {}", VMLINUX_BTF))?;
+    Ok(resolve(&Btf::parse(&data)?))
+}
+
+/// Resolve every offset the probe uses. Members this kernel does not have are left at
+/// [`OFFSET_UNKNOWN`] rather than failing the whole layout, so one renamed field only
+/// loses the data that depends on it.
+pub fn resolve(btf: &Btf) -> KernelLayout {
+    let offset = |ty: &str, member: &str| btf.member_offset(ty, member).unwrap_or(OFFSET_UNKNOWN);
+
+    // mm_struct.rss_stat is one counter per MM_* type. Before 6.2 it was a struct
+    // mm_rss_stat of atomic_long_t count[]; since, an array of struct percpu_counter, whose
+    // count is the same approximate total the tracepoint reports.
+    let (rss_stat_stride, rss_stat_count) = match btf.member_offset("mm_rss_stat", "count") {
+        Some(count) => (size_of::<std::ffi::c_long>() as u32, count),
+        None => match (
+            btf.type_size("percpu_counter"),
+            btf.member_offset("percpu_counter", "count"),
+        ) {
+            (Some(size), Some(count)) => (size, count),
+            _ => (OFFSET_UNKNOWN, OFFSET_UNKNOWN),
+        },
+    };
+
+    KernelLayout {
+        task_struct_pid: offset("task_struct", "pid"),
+        task_struct_tgid: offset("task_struct", "tgid"),
+        task_struct_cgroups: offset("task_struct", "cgroups"),
+        task_struct_comm: offset("task_struct", "comm"),
+        task_struct_mm: offset("task_struct", "mm"),
+        task_struct_cred: offset("task_struct", "cred"),
+        task_struct_signal: offset("task_struct", "signal"),
+        task_struct_real_parent: offset("task_struct", "real_parent"),
+        task_struct_start_time: offset("task_struct", "start_time"),
+        task_struct_thread_pid: offset("task_struct", "thread_pid"),
+        pid_level: offset("pid", "level"),
+        pid_numbers: offset("pid", "numbers"),
+        upid_nr: offset("upid", "nr"),
+        upid_size: btf.type_size("upid").unwrap_or(OFFSET_UNKNOWN),
+        cred_uid: offset("cred", "uid"),
+        signal_oom_score_adj: offset("signal_struct", "oom_score_adj"),
+        signal_nr_threads: offset("signal_struct", "nr_threads"),
+        mm_struct_total_vm: offset("mm_struct", "total_vm"),
+        mm_struct_hiwater_rss: offset("mm_struct", "hiwater_rss"),
+        mm_struct_exe_file: offset("mm_struct", "exe_file"),
+        mm_struct_arg_start: offset("mm_struct", "arg_start"),
+        mm_struct_arg_end: offset("mm_struct", "arg_end"),
+        file_f_path: offset("file", "f_path"),
+        path_dentry: offset("path", "dentry"),
+        dentry_d_parent: offset("dentry", "d_parent"),
+        dentry_d_name: offset("dentry", "d_name"),
+        qstr_len: offset("qstr", "len"),
+        qstr_name: offset("qstr", "name"),
+        mm_struct_pgtables: offset("mm_struct", "pgtables_bytes"),
+        mm_struct_rss_stat: offset("mm_struct", "rss_stat"),
+        rss_stat_stride,
+        rss_stat_count,
+        css_set_dfl_cgrp: offset("css_set", "dfl_cgrp"),
+        css_set_subsys: offset("css_set", "subsys"),
+        css_cgroup: offset("cgroup_subsys_state", "cgroup"),
+        cgroup_kn: offset("cgroup", "kn"),
+        kernfs_node_id: offset("kernfs_node", "id"),
+        kernfs_node_parent: btf
+            .member_offset("kernfs_node", "parent")
+            .or_else(|| btf.member_offset("kernfs_node", "__parent"))
+            .unwrap_or(OFFSET_UNKNOWN),
+        memory_cgrp_id: btf
+            .enumerator("cgroup_subsys_id", "memory_cgrp_id")
+            .and_then(|value| u32::try_from(value).ok())
+            .unwrap_or(OFFSET_UNKNOWN),
+        oom_control_memcg: offset("oom_control", "memcg"),
+        oom_control_gfp_mask: offset("oom_control", "gfp_mask"),
+        oom_control_order: offset("oom_control", "order"),
+        oom_control_constraint: offset("oom_control", "constraint"),
+        oom_control_chosen: offset("oom_control", "chosen"),
+        mem_cgroup_css: offset("mem_cgroup", "css"),
+        mem_cgroup_memory: offset("mem_cgroup", "memory"),
+        mem_cgroup_swap: offset("mem_cgroup", "swap"),
+        page_counter_usage: offset("page_counter", "usage"),
+        page_counter_max: offset("page_counter", "max"),
+        page_counter_watermark: offset("page_counter", "watermark"),
+        page_counter_failcnt: offset("page_counter", "failcnt"),
+        // Not in BTF: the loader fills these in from the running system.
+        ..KernelLayout::UNKNOWN
+    }
+}
+
+const BTF_MAGIC: u16 = 0xeb9f;
+const HEADER_LEN: usize = 24;
+
+const KIND_INT: u32 = 1;
+const KIND_ARRAY: u32 = 3;
+const KIND_STRUCT: u32 = 4;
+const KIND_UNION: u32 = 5;
+const KIND_ENUM: u32 = 6;
+const KIND_FUNC_PROTO: u32 = 13;
+const KIND_VAR: u32 = 14;
+const KIND_DATASEC: u32 = 15;
+const KIND_DECL_TAG: u32 = 17;
+const KIND_ENUM64: u32 = 19;
+
+/// The parts of a BTF blob the layout needs: composite types with their members, and
+/// enums with their values. Every other kind is kept only as a placeholder so type ids
+/// (which are positional) stay correct.
+pub struct Btf {
+    types: Vec<BtfType>,
+    strings: Vec<u8>,
+}
+
+enum BtfType {
+    Composite {
+        name: u32,
+        size: u32,
+        members: Vec<Member>,
+    },
+    Enum {
+        name: u32,
+        values: Vec<(u32, i64)>,
+    },
+    Other,
+}
+
+struct Member {
+    name: u32,
+    type_id: u32,
+    /// Offset from the start of the enclosing type, in bits.
+    bit_offset: u32,
+}
+
+impl Btf {
+    /// Parse a native-endian BTF blob, as found in `/sys/kernel/btf/vmlinux`.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut header = Reader::new(data, 0);
+        if header.u16()? != BTF_MAGIC {
+            bail!("not a native-endian BTF blob");
+        }
+        header.skip(2)?; // version, flags
+        let hdr_len = header.u32()? as usize;
+        let type_off = header.u32()? as usize;
+        let type_len = header.u32()? as usize;
+        let str_off = header.u32()? as usize;
+        let str_len = header.u32()? as usize;
+        if hdr_len < HEADER_LEN {
+            bail!("BTF header too short: {} bytes", hdr_len);
+        }
+
+        let strings = data
+            .get(hdr_len + str_off..hdr_len + str_off + str_len)
+            .ok_or_else(|| anyhow!("BTF string section out of bounds"))?
+            .to_vec();
+        let type_section = data
+            .get(hdr_len + type_off..hdr_len + type_off + type_len)
+            .ok_or_else(|| anyhow!("BTF type section out of bounds"))?;
+
+        // Type id 0 is the implicit `void`; ids are positional from 1.
+        let mut types = vec![BtfType::Other];
+        let mut r = Reader::new(type_section, 0);
+        while !r.is_empty() {
+            types.push(parse_type(&mut r)?);
+        }
+
+        Ok(Self { types, strings })
+    }
+
+    /// Byte offset of `member` within the struct or union named `ty`, looking through
+    /// anonymous nested structs and unions.
+    pub fn member_offset(&self, ty: &str, member: &str) -> Option<u32> {
+        self.types.iter().find_map(|t| match t {
+            BtfType::Composite { name, members, .. } if self.name(*name) == Some(ty) => {
+                self.find_member(members, member).map(|bits| bits / 8)
+            }
+            _ => None,
+        })
+    }
+
+    /// Size in bytes of the struct or union named `ty`.
+    pub fn type_size(&self, ty: &str) -> Option<u32> {
+        self.types.iter().find_map(|t| match t {
+            BtfType::Composite { name, size, .. } if self.name(*name) == Some(ty) => Some(*size),
+            _ => None,
+        })
+    }
+
+    /// Value of the enumerator `value` of the enum named `ty`.
+    pub fn enumerator(&self, ty: &str, value: &str) -> Option<i64> {
+        self.types.iter().find_map(|t| match t {
+            BtfType::Enum { name, values } if self.name(*name) == Some(ty) => values
+                .iter()
+                .find(|(n, _)| self.name(*n) == Some(value))
+                .map(|(_, v)| *v),
+            _ => None,
+        })
+    }
+
+    fn find_member(&self, members: &[Member], wanted: &str) -> Option<u32> {
+        members.iter().find_map(|m| match self.name(m.name) {
+            Some(name) if name == wanted => Some(m.bit_offset),
+            Some("") => match self.types.get(m.type_id as usize) {
+                Some(BtfType::Composite { members, .. }) => self
+                    .find_member(members, wanted)
+                    .map(|inner| m.bit_offset + inner),
+                _ => None,
+            },
+            _ => None,
+        })
+    }
+
+    fn name(&self, offset: u32) -> Option<&str> {
+        let tail = self.strings.get(offset as usize..)?;
+        let end = tail.iter().position(|&b| b == 0)?;
+        std::str::from_utf8(&tail[..end]).ok()
+    }
+}
+
+fn parse_type(r: &mut Reader) -> Result<BtfType> {
+    let name = r.u32()?;
+    let info = r.u32()?;
+    let size = r.u32()?; // or a type id, for the kinds that reference one
+    let vlen = (info & 0xffff) as usize;
+    let kind = (info >> 24) & 0x1f;
+    let kind_flag = info >> 31 == 1;
+
+    Ok(match kind {
+        KIND_STRUCT | KIND_UNION => {
+            let mut members = Vec::with_capacity(vlen);
+            for _ in 0..vlen {
+                let name = r.u32()?;
+                let type_id = r.u32()?;
+                let offset = r.u32()?;
+                // With kind_flag set the top byte holds a bitfield size, not offset bits.
+                let bit_offset = if kind_flag {
+                    offset & 0x00ff_ffff
+                } else {
+                    offset
+                };
+                members.push(Member {
+                    name,
+                    type_id,
+                    bit_offset,
+                });
+            }
+            BtfType::Composite {
+                name,
+                size,
+                members,
+            }
+        }
+        KIND_ENUM => {
+            let mut values = Vec::with_capacity(vlen);
+            for _ in 0..vlen {
+                let value_name = r.u32()?;
+                values.push((value_name, i64::from(r.u32()? as i32)));
+            }
+            BtfType::Enum { name, values }
+        }
+        KIND_ENUM64 => {
+            let mut values = Vec::with_capacity(vlen);
+            for _ in 0..vlen {
+                let value_name = r.u32()?;
+                let lo = u64::from(r.u32()?);
+                let hi = u64::from(r.u32()?);
+                values.push((value_name, (hi << 32 | lo) as i64));
+            }
+            BtfType::Enum { name, values }
+        }
+        KIND_INT | KIND_VAR | KIND_DECL_TAG => {
+            r.skip(4)?;
+            BtfType::Other
+        }
+        KIND_ARRAY => {
+            r.skip(12)?;
+            BtfType::Other
+        }
+        KIND_FUNC_PROTO => {
+            r.skip(8 * vlen)?;
+            BtfType::Other
+        }
+        KIND_DATASEC => {
+            r.skip(12 * vlen)?;
+            BtfType::Other
+        }
+        // PTR, FWD, TYPEDEF, VOLATILE, CONST, RESTRICT, FUNC, FLOAT, TYPE_TAG: no trailer.
+        2 | 7..=12 | 16 | 18 => BtfType::Other,
+        other => bail!("unknown BTF kind {}", other),
+    })
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Self { data, pos }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + n)
+            .ok_or_else(|| anyhow!("truncated BTF at byte {}", self.pos))?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, n: usize) -> Result<()> {
+        self.take(n).map(|_| ())
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_ne_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_ne_bytes(self.take(4)?.try_into()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds small native-endian BTF blobs, so the reader is tested against the wire
+    /// format rather than against itself.
+    #[derive(Default)]
+    struct BtfBuilder {
+        types: Vec<u8>,
+        strings: Vec<u8>,
+        next_id: u32,
+    }
+
+    impl BtfBuilder {
+        fn new() -> Self {
+            Self {
+                strings: vec![0],
+                next_id: 1,
+                ..Self::default()
+            }
+        }
+
+        fn string(&mut self, s: &str) -> u32 {
+            let offset = self.strings.len() as u32;
+            self.strings.extend_from_slice(s.as_bytes());
+            self.strings.push(0);
+            offset
+        }
+
+        fn header(&mut self, name: &str, kind: u32, vlen: usize, size: u32) -> u32 {
+            let name = self.string(name);
+            for word in [name, kind << 24 | vlen as u32, size] {
+                self.types.extend_from_slice(&word.to_ne_bytes());
+            }
+            self.next_id += 1;
+            self.next_id - 1
+        }
+
+        fn int(&mut self, name: &str, size: u32) -> u32 {
+            let id = self.header(name, KIND_INT, 0, size);
+            self.types.extend_from_slice(&(size * 8).to_ne_bytes());
+            id
+        }
+
+        fn ptr(&mut self) -> u32 {
+            self.header("", 2, 0, 0)
+        }
+
+        /// A struct whose members are `(name, type id, byte offset)`.
+        fn structure(&mut self, name: &str, members: &[(&str, u32, u32)]) -> u32 {
+            self.sized_structure(name, 0, members)
+        }
+
+        fn sized_structure(&mut self, name: &str, size: u32, members: &[(&str, u32, u32)]) -> u32 {
+            let id = self.header(name, KIND_STRUCT, members.len(), size);
+            for (member, type_id, offset) in members {
+                let member = self.string(member);
+                for word in [member, *type_id, offset * 8] {
+                    self.types.extend_from_slice(&word.to_ne_bytes());
+                }
+            }
+            id
+        }
+
+        fn enumeration(&mut self, name: &str, values: &[(&str, i32)]) -> u32 {
+            let id = self.header(name, KIND_ENUM, values.len(), 4);
+            for (value, v) in values {
+                let value = self.string(value);
+                self.types.extend_from_slice(&value.to_ne_bytes());
+                self.types.extend_from_slice(&v.to_ne_bytes());
+            }
+            id
+        }
+
+        fn build(&self) -> Vec<u8> {
+            let mut out = Vec::new();
+            out.extend_from_slice(&BTF_MAGIC.to_ne_bytes());
+            out.extend_from_slice(&[1, 0]);
+            for word in [
+                HEADER_LEN as u32,
+                0,
+                self.types.len() as u32,
+                self.types.len() as u32,
+                self.strings.len() as u32,
+            ] {
+                out.extend_from_slice(&word.to_ne_bytes());
+            }
+            out.extend_from_slice(&self.types);
+            out.extend_from_slice(&self.strings);
+            out
+        }
+    }
+
+    /// Just the cgroup walk of a kernel: task_struct → css_set → cgroup → kernfs_node.
+    fn cgroup_walk() -> BtfBuilder {
+        let mut b = BtfBuilder::new();
+        let int = b.int("int", 4);
+        let u64_ = b.int("u64", 8);
+        let ptr = b.ptr();
+        b.structure(
+            "task_struct",
+            &[
+                ("state", int, 0),
+                ("pid", int, 1520),
+                ("tgid", int, 1524),
+                ("cgroups", ptr, 2912),
+            ],
+        );
+        b.structure("css_set", &[("subsys", ptr, 0), ("dfl_cgrp", ptr, 112)]);
+        b.structure("cgroup_subsys_state", &[("cgroup", ptr, 0)]);
+        b.structure("cgroup", &[("self", int, 0), ("kn", ptr, 256)]);
+        b.structure(
+            "kernfs_node",
+            &[("count", int, 0), ("parent", ptr, 8), ("id", u64_, 88)],
+        );
+        b.enumeration(
+            "cgroup_subsys_id",
+            &[("cpuset_cgrp_id", 0), ("memory_cgrp_id", 4)],
+        );
+        b
+    }
+
+    #[test]
+    fn resolves_the_cgroup_walk() {
+        let btf = Btf::parse(&cgroup_walk().build()).unwrap();
+        let layout = resolve(&btf);
+        assert_eq!(layout.task_struct_pid, 1520);
+        assert_eq!(layout.task_struct_tgid, 1524);
+        assert_eq!(layout.task_struct_cgroups, 2912);
+        assert_eq!(layout.css_set_dfl_cgrp, 112);
+        assert_eq!(layout.css_set_subsys, 0);
+        assert_eq!(layout.css_cgroup, 0);
+        assert_eq!(layout.cgroup_kn, 256);
+        assert_eq!(layout.kernfs_node_id, 88);
+        assert_eq!(layout.kernfs_node_parent, 8);
+        assert_eq!(layout.memory_cgrp_id, 4);
+    }
+
+    #[test]
+    fn resolves_the_oom_control() {
+        let mut b = cgroup_walk();
+        let int = b.int("int", 4);
+        let ptr = b.ptr();
+        b.structure(
+            "oom_control",
+            &[
+                ("zonelist", ptr, 0),
+                ("memcg", ptr, 16),
+                ("gfp_mask", int, 24),
+                ("order", int, 28),
+                ("constraint", int, 64),
+            ],
+        );
+        b.structure("mem_cgroup", &[("css", int, 0)]);
+        let layout = resolve(&Btf::parse(&b.build()).unwrap());
+        assert_eq!(layout.oom_control_memcg, 16);
+        assert_eq!(layout.oom_control_gfp_mask, 24);
+        assert_eq!(layout.oom_control_order, 28);
+        assert_eq!(layout.oom_control_constraint, 64);
+        assert_eq!(layout.mem_cgroup_css, 0);
+    }
+
+    #[test]
+    fn resolves_the_memcg_page_counters() {
+        let mut b = cgroup_walk();
+        let int = b.int("int", 4);
+        let long = b.int("long", 8);
+        let counter = b.structure(
+            "page_counter",
+            &[
+                ("usage", long, 0),
+                ("emin", long, 8),
+                ("watermark", long, 64),
+                ("failcnt", long, 72),
+                ("max", long, 160),
+            ],
+        );
+        // swap shares an anonymous union with v1's memsw.
+        let swap = b.structure("", &[("swap", counter, 0), ("memsw", counter, 0)]);
+        b.structure(
+            "mem_cgroup",
+            &[("css", int, 0), ("memory", counter, 200), ("", swap, 456)],
+        );
+        let layout = resolve(&Btf::parse(&b.build()).unwrap());
+        assert_eq!(layout.mem_cgroup_memory, 200);
+        assert_eq!(layout.mem_cgroup_swap, 456);
+        assert_eq!(layout.page_counter_usage, 0);
+        assert_eq!(layout.page_counter_watermark, 64);
+        assert_eq!(layout.page_counter_failcnt, 72);
+        assert_eq!(layout.page_counter_max, 160);
+        assert_eq!(layout.page_size, OFFSET_UNKNOWN);
+    }
+
+    /// The victim's task, cred, signal, pid and mm, as the probe reads them from its
+    /// task_struct.
+    fn victim_task(b: &mut BtfBuilder) {
+        let int = b.int("int", 4);
+        let short = b.int("short", 2);
+        let long = b.int("long", 8);
+        let ptr = b.ptr();
+        b.structure(
+            "task_struct",
+            &[
+                ("mm", ptr, 1152),
+                ("real_parent", ptr, 1536),
+                ("thread_pid", ptr, 1624),
+                ("start_time", long, 1848),
+                ("cred", ptr, 2680),
+                ("comm", int, 2704),
+                ("signal", ptr, 2800),
+            ],
+        );
+        b.structure("cred", &[("usage", int, 0), ("uid", int, 8)]);
+        b.structure(
+            "signal_struct",
+            &[("nr_threads", int, 16), ("oom_score_adj", short, 1082)],
+        );
+        b.sized_structure("upid", 16, &[("nr", int, 0), ("ns", ptr, 8)]);
+        b.structure(
+            "pid",
+            &[("count", int, 0), ("level", int, 4), ("numbers", int, 96)],
+        );
+        b.structure(
+            "mm_struct",
+            &[
+                ("pgtables_bytes", long, 96),
+                ("hiwater_rss", long, 184),
+                ("total_vm", long, 208),
+                ("rss_stat", int, 720),
+            ],
+        );
+        b.structure("oom_control", &[("chosen", ptr, 40)]);
+    }
+
+    #[test]
+    fn resolves_the_victim_task() {
+        let mut b = BtfBuilder::new();
+        victim_task(&mut b);
+        let long = b.int("long", 8);
+        b.sized_structure(
+            "percpu_counter",
+            40,
+            &[("lock", long, 0), ("count", long, 8)],
+        );
+        let layout = resolve(&Btf::parse(&b.build()).unwrap());
+        assert_eq!(layout.task_struct_mm, 1152);
+        assert_eq!(layout.task_struct_cred, 2680);
+        assert_eq!(layout.task_struct_comm, 2704);
+        assert_eq!(layout.task_struct_signal, 2800);
+        assert_eq!(layout.cred_uid, 8);
+        assert_eq!(layout.signal_oom_score_adj, 1082);
+        assert_eq!(layout.mm_struct_total_vm, 208);
+        assert_eq!(layout.mm_struct_pgtables, 96);
+        assert_eq!(layout.mm_struct_rss_stat, 720);
+        assert_eq!(layout.rss_stat_stride, 40);
+        assert_eq!(layout.rss_stat_count, 8);
+        assert_eq!(layout.oom_control_chosen, 40);
+    }
+
+    #[test]
+    fn resolves_the_process_details() {
+        let mut b = BtfBuilder::new();
+        victim_task(&mut b);
+        let layout = resolve(&Btf::parse(&b.build()).unwrap());
+        assert_eq!(layout.task_struct_real_parent, 1536);
+        assert_eq!(layout.task_struct_thread_pid, 1624);
+        assert_eq!(layout.task_struct_start_time, 1848);
+        assert_eq!(layout.signal_nr_threads, 16);
+        assert_eq!(layout.pid_level, 4);
+        assert_eq!(layout.pid_numbers, 96);
+        assert_eq!(layout.upid_nr, 0);
+        assert_eq!(layout.upid_size, 16);
+        assert_eq!(layout.mm_struct_hiwater_rss, 184);
+    }
+
+    #[test]
+    fn resolves_the_exe_and_argv() {
+        let mut b = BtfBuilder::new();
+        let int = b.int("int", 4);
+        let long = b.int("long", 8);
+        let ptr = b.ptr();
+        b.structure(
+            "mm_struct",
+            &[
+                ("arg_start", long, 296),
+                ("arg_end", long, 304),
+                ("exe_file", ptr, 928),
+            ],
+        );
+        b.structure("file", &[("f_mode", int, 0), ("f_path", int, 64)]);
+        b.structure("path", &[("mnt", ptr, 0), ("dentry", ptr, 8)]);
+        // qstr's len shares an anonymous union with hash_len, in an anonymous struct.
+        let hash_len = b.structure("", &[("hash", int, 0), ("len", int, 4)]);
+        let union = b.structure("", &[("", hash_len, 0), ("hash_len", long, 0)]);
+        b.structure("qstr", &[("", union, 0), ("name", ptr, 8)]);
+        b.structure(
+            "dentry",
+            &[
+                ("d_flags", int, 0),
+                ("d_parent", ptr, 24),
+                ("d_name", int, 32),
+            ],
+        );
+        let layout = resolve(&Btf::parse(&b.build()).unwrap());
+        assert_eq!(layout.mm_struct_arg_start, 296);
+        assert_eq!(layout.mm_struct_arg_end, 304);
+        assert_eq!(layout.mm_struct_exe_file, 928);
+        assert_eq!(layout.file_f_path, 64);
+        assert_eq!(layout.path_dentry, 8);
+        assert_eq!(layout.dentry_d_parent, 24);
+        assert_eq!(layout.dentry_d_name, 32);
+        assert_eq!(layout.qstr_len, 4);
+        assert_eq!(layout.qstr_name, 8);
+    }
+
+    #[test]
+    fn resolves_the_pre_percpu_rss_stat() {
+        let mut b = BtfBuilder::new();
+        victim_task(&mut b);
+        let long = b.int("long", 8);
+        b.structure("mm_rss_stat", &[("count", long, 0)]);
+        let layout = resolve(&Btf::parse(&b.build()).unwrap());
+        assert_eq!(layout.rss_stat_stride, 8);
+        assert_eq!(layout.rss_stat_count, 0);
+    }
+
+    #[test]
+    fn resolves_the_renamed_kernfs_parent() {
+        let mut b = BtfBuilder::new();
+        let ptr = b.ptr();
+        b.structure("kernfs_node", &[("__parent", ptr, 16)]);
+        let layout = resolve(&Btf::parse(&b.build()).unwrap());
+        assert_eq!(layout.kernfs_node_parent, 16);
+    }
+
+    #[test]
+    fn missing_members_stay_unknown() {
+        let mut b = BtfBuilder::new();
+        let int = b.int("int", 4);
+        b.structure("task_struct", &[("pid", int, 8)]);
+        let layout = resolve(&Btf::parse(&b.build()).unwrap());
+        assert_eq!(layout.task_struct_pid, 8);
+        assert_eq!(layout.task_struct_cgroups, OFFSET_UNKNOWN);
+        assert_eq!(layout.memory_cgrp_id, OFFSET_UNKNOWN);
+        assert_eq!(layout.rss_stat_stride, OFFSET_UNKNOWN);
+    }
+
+    #[test]
+    fn looks_through_anonymous_members() {
+        let mut b = BtfBuilder::new();
+        let int = b.int("int", 4);
+        let inner = b.structure("", &[("pid", int, 16)]);
+        b.structure("task_struct", &[("state", int, 0), ("", inner, 64)]);
+        let btf = Btf::parse(&b.build()).unwrap();
+        assert_eq!(btf.member_offset("task_struct", "pid"), Some(80));
+    }
+
+    #[test]
+    fn rejects_foreign_blobs() {
+        assert!(Btf::parse(b"\x7fELF not btf at all, just bytes").is_err());
+        assert!(Btf::parse(&[]).is_err());
+    }
+}
@@ -0,0 +1,95 @@
+//! Lost-event accounting for the kill ring buffer.
+//!
+//! The probe drops an OOM kill event when `EVENTS` has no room for it, which an OOM storm
+//! can cause. Two signals say so: the probe's own per-CPU count of failed outputs, and gaps
+//! in each CPU's event sequence numbers as they arrive. [`LossTracker`] combines them —
+//! they should agree, but either can lag (a gap shows only once a later event from that
+//! CPU arrives) — and hands out the newly lost count for `oom_events_lost_total`.
+
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub struct LossTracker {
+    /// The last sequence number delivered from each CPU.
+    last: HashMap<u32, u64>,
+    /// Events missing from the sequences so far.
+    gaps: u64,
+    /// The probe's count of failed outputs, as last read.
+    dropped: u64,
+    /// The total already handed out by [`LossTracker::take_lost`].
+    reported: u64,
+}
+
+impl LossTracker {
+    /// Note the delivery of event `sequence` from `cpu`, returning how many of that CPU's
+    /// events went missing just before it. Sequence 0 is an unsequenced event.
+    pub fn observe(&mut self, cpu: u32, sequence: u64) -> u64 {
+        if sequence == 0 {
+            return 0;
+        }
+        // Sequences start at 1 when the probe is loaded, so from a CPU's first event on
+        // every number is accounted for.
+        let last = self.last.entry(cpu).or_insert(0);
+        let missing = sequence.saturating_sub(*last + 1);
+        *last = (*last).max(sequence);
+        self.gaps += missing;
+        missing
+    }
+
+    /// Update the probe's count of failed outputs, summed over CPUs.
+    pub fn set_dropped(&mut self, dropped: u64) {
+        self.dropped = self.dropped.max(dropped);
+    }
+
+    /// Events lost since the last call, by whichever signal has seen more of them.
+    pub fn take_lost(&mut self) -> u64 {
+        let total = self.gaps.max(self.dropped);
+        let lost = total - self.reported;
+        self.reported = total;
+        lost
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_gaps_per_cpu() {
+        let mut loss = LossTracker::default();
+        assert_eq!(loss.observe(0, 1), 0);
+        assert_eq!(loss.observe(1, 1), 0);
+        assert_eq!(loss.observe(0, 4), 2);
+        assert_eq!(loss.observe(1, 2), 0);
+        assert_eq!(loss.take_lost(), 2);
+        assert_eq!(loss.take_lost(), 0);
+    }
+
+    #[test]
+    fn counts_events_lost_before_a_cpus_first_delivery() {
+        let mut loss = LossTracker::default();
+        assert_eq!(loss.observe(3, 3), 2);
+    }
+
+    #[test]
+    fn ignores_unsequenced_events() {
+        let mut loss = LossTracker::default();
+        assert_eq!(loss.observe(0, 0), 0);
+        assert_eq!(loss.observe(0, 0), 0);
+        assert_eq!(loss.take_lost(), 0);
+    }
+
+    #[test]
+    fn does_not_count_drops_twice() {
+        let mut loss = LossTracker::default();
+        // The probe counts two drops before the gap shows.
+        loss.set_dropped(2);
+        assert_eq!(loss.take_lost(), 2);
+        loss.observe(0, 1);
+        loss.observe(0, 4);
+        assert_eq!(loss.take_lost(), 0);
+        // A third drop, not yet followed by an event.
+        loss.set_dropped(3);
+        assert_eq!(loss.take_lost(), 1);
+    }
+}
@@ -1,10 +1,39 @@
+mod cgroup;
+#[cfg(target_os = "linux")]
+mod cgroup_events;
+mod cgroup_path;
+#[cfg(feature = "ebpf")]
+mod command;
 mod enrich;
 mod http;
+#[cfg(target_os = "linux")]
+mod inotify;
+mod kmsg;
 mod kubernetes;
+#[cfg(feature = "ebpf")]
+mod layout;
+#[cfg(feature = "ebpf")]
+mod loss;
+mod merged;
 mod metrics;
+mod pod_cache;
+mod pressure;
+#[cfg(feature = "ebpf")]
+mod probe;
+mod reaper;
+mod recording;
 mod resolve;
+mod scope;
+mod score_adj;
 mod source;
+#[cfg(feature = "ebpf")]
+mod stack;
+#[cfg(feature = "ebpf")]
+mod tracefs;
 mod watch;
+#[cfg(feature = "ebpf")]
+mod wire;
+mod workload;
 
 use std::{
     sync::Arc,
@@ -16,11 +45,9 @@ use axum::serve;
 use kubernetes::KubernetesClient;
 use log::{error, info, warn};
 use metrics::MetricsCollector;
+use recording::RecordingSource;
 use resolve::ContainerResolver;
-#[cfg(not(feature = "ebpf"))]
-use source::ParkSource;
-#[cfg(feature = "ebpf")]
-use source::RingBufSource;
+use source::{ParkSource, SelectedSource, SourceMode};
 use tokio::{signal, task};
 
 #[tokio::main]
@@ -31,13 +58,14 @@ async fn main() -> anyhow::Result<()> {
 
     // Resolver for the watch loop: Some iff in-cluster. A failure drops us to standalone
     // mode (no node, no container identity) rather than aborting startup.
+    // Shared with the pressure loop, which resolves through the same client.
     let k8s_client = match KubernetesClient::new().await {
         Ok(client) => {
             info!(
                 "Successfully connected to Kubernetes API on node: {}",
                 client.node_name()
             );
-            Some(client)
+            Some(Arc::new(client))
         }
         Err(e) => {
             warn!(
@@ -69,12 +97,32 @@ async fn main() -> anyhow::Result<()> {
         }
     });
 
-    // Event source: the eBPF probe in-cluster, a parking source for non-eBPF builds. All
+    // Event source: the eBPF probe where it loads, else the kernel log, else the cgroup
+    // OOM counters, else a parking source — or the one OOM_EVENT_SOURCE names. All
     // aya/ring-buffer handling lives behind the OomEventSource seam.
+    let mode = SourceMode::from_env();
+    let replaying = matches!(mode, SourceMode::Replay { .. });
+    #[cfg_attr(not(feature = "ebpf"), allow(unused_mut))]
+    let mut source = SelectedSource::open(mode)?;
+    for (mode, target) in source.attach_modes() {
+        let node = k8s_client.as_ref().map_or("unknown", |c| c.node_name());
+        metrics_collector.record_attach_mode(node, mode, target);
+    }
+    // Memcg pressure is only counted by the probe.
+    let pressure_config = pressure::PressureConfig::from_env();
     #[cfg(feature = "ebpf")]
-    let source = RingBufSource::new()?;
+    let pressure = match source.probe_mut() {
+        Some(probe) => Some(probe.pressure_source(pressure_config.interval)?),
+        None => None,
+    };
     #[cfg(not(feature = "ebpf"))]
-    let source = ParkSource;
+    let pressure: Option<ParkSource> = None;
+    // A replay resolves as the recording did, in place of the cluster it was made on.
+    let replay_resolver = source.replay_resolver();
+    // RECORD_FILE: tee everything the watch loop receives to a recording.
+    let record_file = std::env::var_os("RECORD_FILE").map(std::path::PathBuf::from);
+    let node = k8s_client.as_ref().map(|c| c.node_name());
+    let source = RecordingSource::new(source, record_file.as_deref(), node)?;
 
     info!("🔍 OOM Watcher is now active and monitoring for OOM events...");
     info!(
@@ -84,10 +132,51 @@ async fn main() -> anyhow::Result<()> {
     info!("⏹️  Press Ctrl-C to stop monitoring");
 
     // The watch loop owns the source and resolver and borrows the recorder for the life of
-    // the task. It loops forever in production; the select! below supervises and aborts it.
+    // the task; kills, reap steps and oom_score_adj changes all come through it. It loops
+    // forever in production; the select! below supervises and aborts it.
+    let pressure_resolver = k8s_client.clone();
     let recorder = metrics_collector.clone();
     let mut event_processor = task::spawn(async move {
-        watch::run(source, k8s_client, recorder.as_ref(), wall_clock_secs).await;
+        match replay_resolver {
+            Some(resolver) => {
+                watch::run(source, Some(resolver), recorder.as_ref(), wall_clock_secs).await
+            }
+            None => watch::run(source, k8s_client, recorder.as_ref(), wall_clock_secs).await,
+        }
+        // A replay ends with its recording; keep serving the metrics it produced.
+        if replaying {
+            info!("Replay finished; serving its metrics until stopped");
+            std::future::pending::<()>().await;
+        }
+    });
+
+    // The pressure loop warns about containers approaching OOM; supervised the same way.
+    // The resolver doubles as the sink for its warning events.
+    let recorder = metrics_collector.clone();
+    let mut pressure_processor = task::spawn(async move {
+        let sink = pressure_resolver.clone();
+        match pressure {
+            Some(pressure) => {
+                pressure::run(
+                    pressure,
+                    pressure_resolver,
+                    sink,
+                    recorder.as_ref(),
+                    &pressure_config,
+                )
+                .await
+            }
+            None => {
+                pressure::run(
+                    ParkSource,
+                    pressure_resolver,
+                    sink,
+                    recorder.as_ref(),
+                    &pressure_config,
+                )
+                .await
+            }
+        }
     });
 
     // Run until shutdown is requested or a worker task exits unexpectedly. If a worker
@@ -103,6 +192,10 @@ async fn main() -> anyhow::Result<()> {
             error!("Event processor task exited unexpectedly: {:?}", res);
             Err(anyhow!("event processor task exited"))
         }
+        res = &mut pressure_processor => {
+            error!("Pressure processor task exited unexpectedly: {:?}", res);
+            Err(anyhow!("pressure processor task exited"))
+        }
         res = &mut metrics_server => {
             error!("Metrics server task exited unexpectedly: {:?}", res);
             Err(anyhow!("metrics server task exited"))
@@ -110,6 +203,7 @@ async fn main() -> anyhow::Result<()> {
     };
 
     event_processor.abort();
+    pressure_processor.abort();
     metrics_server.abort();
 
     outcome
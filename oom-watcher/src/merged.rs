@@ -0,0 +1,421 @@
+//! Several OOM event sources run as one, for belt-and-braces coverage: the probe, the
+//! kernel log and the cgroup counters each miss kills the others see.
+//!
+//! [`MergedSource`] reads every backend at once. A kill is held for a window, during which
+//! the same kill from another backend — agreeing on the victim's pid, start time and
+//! cgroup, as far as both know them — is folded into it, and the richest record of it
+//! kept. Each backend counts once per kill, so a group kill the cgroup counters see only
+//! as two kills in one cgroup stays two. Which backends saw each kill is handed to the
+//! watch loop, for the per-backend counts that show where one is missing kills. Reap
+//! steps and score changes pass straight through.
+
+use std::{
+    collections::VecDeque,
+    future::{poll_fn, Future},
+    task::Poll,
+    time::Duration,
+};
+
+use oom_watcher_common::{
+    OomKillEvent, ProbeEvent, ProcessCommand, TriggerStack, CONSTRAINT_UNKNOWN, FIELDS_ALL,
+    FIELD_START_TIME,
+};
+use tokio::time::{self, Instant};
+
+use crate::watch::OomEventSource;
+
+/// Backends `S`, named for the counts of what they saw, merged into one source.
+pub struct MergedSource<S> {
+    backends: Vec<Backend<S>>,
+    window: Duration,
+    /// Kills held for their window, oldest first.
+    held: VecDeque<Held>,
+    /// The stack and command of the kill last yielded, for the loop to ask after.
+    stack: Option<TriggerStack>,
+    command: Option<ProcessCommand>,
+    /// Backends that saw the kill last yielded, until the loop asks.
+    seen: Vec<&'static str>,
+    /// Where among the live backends the next read starts, so a busy one cannot starve
+    /// the rest.
+    first: usize,
+}
+
+struct Backend<S> {
+    name: &'static str,
+    source: S,
+    ended: bool,
+}
+
+/// A kill held for its window, as the richest backend to see it so far reported it.
+struct Held {
+    event: OomKillEvent,
+    stack: Option<TriggerStack>,
+    command: Option<ProcessCommand>,
+    richness: u32,
+    /// Indices of the backends that saw it.
+    seen_by: Vec<usize>,
+    deadline: Instant,
+}
+
+impl<S: OomEventSource> MergedSource<S> {
+    /// Merge `backends`, folding together the copies of a kill that arrive within
+    /// `window` of its first.
+    pub fn new(backends: Vec<(&'static str, S)>, window: Duration) -> Self {
+        Self {
+            backends: backends
+                .into_iter()
+                .map(|(name, source)| Backend {
+                    name,
+                    source,
+                    ended: false,
+                })
+                .collect(),
+            window,
+            held: VecDeque::new(),
+            stack: None,
+            command: None,
+            seen: Vec::new(),
+            first: 0,
+        }
+    }
+
+    /// The backends, e.g. to find the one that also feeds the pressure loop.
+    pub fn backends_mut(&mut self) -> impl Iterator<Item = &mut S> {
+        self.backends.iter_mut().map(|backend| &mut backend.source)
+    }
+
+    /// The next event from any live backend, with the backend's index; `None` for an
+    /// event when that backend has ended. Each call polls the backends from the one after
+    /// the last call's first, round-robin: in an OOM storm the first backend is always
+    /// ready, and would otherwise hold the others' copies of a kill past its window.
+    ///
+    /// The other backends' `next` is dropped unfinished, so each must lose nothing by
+    /// that — true of the ring buffer, whose records are only read once readable, and of
+    /// the channels the kernel log and cgroup sources read from.
+    async fn next_of_any(&mut self) -> (usize, Option<ProbeEvent>) {
+        let first = self.first;
+        self.first = self.first.wrapping_add(1);
+        let mut nexts: Vec<_> = self
+            .backends
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, backend)| !backend.ended)
+            .map(|(i, backend)| (i, Box::pin(backend.source.next())))
+            .collect();
+        if !nexts.is_empty() {
+            let len = nexts.len();
+            nexts.rotate_left(first % len);
+        }
+        poll_fn(|cx| {
+            for (i, next) in nexts.iter_mut() {
+                if let Poll::Ready(event) = next.as_mut().poll(cx) {
+                    return Poll::Ready((*i, event));
+                }
+            }
+            Poll::Pending
+        })
+        .await
+    }
+
+    /// Fold a kill from backend `i` into the held kill it is a copy of, or hold it anew.
+    async fn add(&mut self, i: usize, event: OomKillEvent) {
+        let source = &mut self.backends[i].source;
+        let stack = source.trigger_stack(&event).await;
+        let command = source.victim_command(&event);
+        let richness = richness(&event) + stack.is_some() as u32 + command.is_some() as u32;
+
+        let copy_of = self
+            .held
+            .iter_mut()
+            .find(|held| !held.seen_by.contains(&i) && same_kill(&held.event, &event));
+        match copy_of {
+            Some(held) => {
+                held.seen_by.push(i);
+                if richness > held.richness {
+                    held.event = event;
+                    held.stack = stack;
+                    held.command = command;
+                    held.richness = richness;
+                }
+            }
+            None => self.held.push_back(Held {
+                event,
+                stack,
+                command,
+                richness,
+                seen_by: vec![i],
+                deadline: Instant::now() + self.window,
+            }),
+        }
+    }
+
+    /// Yield held kill `index`.
+    fn release(&mut self, index: usize) -> Option<ProbeEvent> {
+        let held = self.held.remove(index)?;
+        self.stack = held.stack;
+        self.command = held.command;
+        self.seen = held
+            .seen_by
+            .iter()
+            .map(|&i| self.backends[i].name)
+            .collect();
+        Some(ProbeEvent::Kill(held.event))
+    }
+}
+
+impl<S: OomEventSource> OomEventSource for MergedSource<S> {
+    async fn next(&mut self) -> Option<ProbeEvent> {
+        loop {
+            // A kill is released once its window is up, or every live backend has seen it.
+            let now = Instant::now();
+            let backends = &self.backends;
+            let due = self.held.iter().position(|held| {
+                held.deadline <= now
+                    || (0..backends.len()).all(|i| backends[i].ended || held.seen_by.contains(&i))
+            });
+            if let Some(index) = due {
+                return self.release(index);
+            }
+            if self.backends.iter().all(|backend| backend.ended) {
+                return None;
+            }
+
+            let received = match self.held.iter().map(|held| held.deadline).min() {
+                Some(deadline) => time::timeout_at(deadline, self.next_of_any()).await.ok(),
+                None => Some(self.next_of_any().await),
+            };
+            match received {
+                // A window is up.
+                None => {}
+                Some((i, None)) => self.backends[i].ended = true,
+                Some((i, Some(ProbeEvent::Kill(event)))) => self.add(i, event).await,
+                Some((_, Some(event))) => return Some(event),
+            }
+        }
+    }
+
+    async fn trigger_stack(&mut self, _event: &OomKillEvent) -> Option<TriggerStack> {
+        self.stack.take()
+    }
+
+    fn victim_command(&mut self, _event: &OomKillEvent) -> Option<ProcessCommand> {
+        self.command.take()
+    }
+
+    fn lost_events(&mut self) -> u64 {
+        self.backends_mut().map(|source| source.lost_events()).sum()
+    }
+
+    fn skipped_records(&mut self) -> Vec<(&'static str, u64)> {
+        self.backends_mut()
+            .flat_map(|source| source.skipped_records())
+            .collect()
+    }
+
+    fn kills_seen(&mut self) -> Vec<(&'static str, u64)> {
+        self.seen.drain(..).map(|name| (name, 1)).collect()
+    }
+}
+
+/// Whether `a` and `b` can be the same kill: nothing either knows of the victim — pid,
+/// start time, cgroup — contradicts the other, and they share at least its pid or cgroup.
+fn same_kill(a: &OomKillEvent, b: &OomKillEvent) -> bool {
+    let agree = |x: u64, y: u64| x == 0 || y == 0 || x == y;
+    let both = |x: u64, y: u64| x != 0 && y != 0;
+    let start_times_agree =
+        !a.has(FIELD_START_TIME) || !b.has(FIELD_START_TIME) || a.start_time == b.start_time;
+
+    agree(a.victim_pid.into(), b.victim_pid.into())
+        && start_times_agree
+        && agree(a.victim_cgroup_id, b.victim_cgroup_id)
+        && agree(a.victim_memcg_id, b.victim_memcg_id)
+        && (both(a.victim_pid.into(), b.victim_pid.into())
+            || both(a.victim_cgroup_id, b.victim_cgroup_id)
+            || both(a.victim_memcg_id, b.victim_memcg_id))
+}
+
+/// How much of a kill a record knows: its victim fields, and whether it has the victim's
+/// pid and cgroup, the trigger, and the constraint.
+fn richness(event: &OomKillEvent) -> u32 {
+    (FIELDS_ALL & !event.unknown_fields).count_ones()
+        + (event.victim_pid != 0) as u32
+        + (event.victim_cgroup_id != 0 || event.victim_memcg_id != 0) as u32
+        + (event.trigger_pid != 0) as u32
+        + (event.constraint != CONSTRAINT_UNKNOWN) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use oom_watcher_common::{ReapEvent, FIELD_ANON_RSS, STACK_UNKNOWN};
+
+    use super::*;
+
+    /// Yields its events, then ends or, if `parks`, waits forever.
+    struct Backend(VecDeque<ProbeEvent>, bool);
+
+    impl OomEventSource for Backend {
+        async fn next(&mut self) -> Option<ProbeEvent> {
+            match self.0.pop_front() {
+                Some(event) => Some(event),
+                None if self.1 => std::future::pending().await,
+                None => None,
+            }
+        }
+
+        fn lost_events(&mut self) -> u64 {
+            1
+        }
+    }
+
+    fn backend(events: impl IntoIterator<Item = OomKillEvent>) -> Backend {
+        Backend(events.into_iter().map(ProbeEvent::Kill).collect(), false)
+    }
+
+    /// A kill as the probe reports it.
+    fn probe(pid: u32, cgroup_id: u64) -> OomKillEvent {
+        OomKillEvent {
+            victim_pid: pid,
+            victim_cgroup_id: cgroup_id,
+            anon_rss: 1024,
+            start_time: 5,
+            kernel_stack_id: STACK_UNKNOWN,
+            ..OomKillEvent::default()
+        }
+    }
+
+    /// A kill as the cgroup counters report it: the cgroup alone.
+    fn counted(cgroup_id: u64) -> OomKillEvent {
+        OomKillEvent {
+            victim_cgroup_id: cgroup_id,
+            constraint: CONSTRAINT_UNKNOWN,
+            unknown_fields: FIELDS_ALL,
+            ..OomKillEvent::default()
+        }
+    }
+
+    async fn drain(merged: &mut MergedSource<Backend>) -> Vec<(OomKillEvent, Vec<&'static str>)> {
+        let mut kills = Vec::new();
+        while let Some(event) = merged.next().await {
+            if let ProbeEvent::Kill(event) = event {
+                kills.push((
+                    event,
+                    merged
+                        .kills_seen()
+                        .into_iter()
+                        .map(|(name, _)| name)
+                        .collect(),
+                ));
+            }
+        }
+        kills
+    }
+
+    #[tokio::test]
+    async fn keeps_the_richest_copy_of_a_kill() {
+        let mut merged = MergedSource::new(
+            vec![
+                ("cgroup", backend([counted(42)])),
+                ("ebpf", backend([probe(1234, 42)])),
+            ],
+            Duration::from_secs(60),
+        );
+
+        let kills = drain(&mut merged).await;
+        assert_eq!(kills.len(), 1);
+        let (event, seen) = &kills[0];
+        assert_eq!((event.victim_pid, event.anon_rss), (1234, 1024));
+        assert!(event.has(FIELD_ANON_RSS));
+        assert_eq!(seen, &["cgroup", "ebpf"]);
+        assert_eq!(merged.lost_events(), 2);
+    }
+
+    #[tokio::test]
+    async fn counts_each_backend_once_per_kill() {
+        // A group kill: two victims in one cgroup, which the counters cannot tell apart.
+        let mut merged = MergedSource::new(
+            vec![
+                ("ebpf", backend([probe(10, 42), probe(11, 42)])),
+                ("cgroup", backend([counted(42), counted(42)])),
+            ],
+            Duration::from_secs(60),
+        );
+
+        let kills = drain(&mut merged).await;
+        let pids: Vec<_> = kills.iter().map(|(event, _)| event.victim_pid).collect();
+        assert_eq!(pids, vec![10, 11]);
+        assert!(kills.iter().all(|(_, seen)| seen.len() == 2));
+    }
+
+    #[tokio::test]
+    async fn keeps_different_victims_apart() {
+        let mut restarted = probe(1234, 42);
+        restarted.start_time = 6;
+        let mut merged = MergedSource::new(
+            vec![
+                ("ebpf", backend([probe(1234, 42)])),
+                ("kmsg", backend([restarted, probe(99, 7)])),
+            ],
+            Duration::from_secs(60),
+        );
+
+        assert_eq!(drain(&mut merged).await.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn releases_a_kill_when_its_window_is_up() {
+        let reap = ProbeEvent::Reap(ReapEvent::default());
+        let mut merged = MergedSource::new(
+            vec![
+                (
+                    "ebpf",
+                    Backend([ProbeEvent::Kill(probe(1, 42)), reap].into(), true),
+                ),
+                ("kmsg", Backend(VecDeque::new(), true)),
+            ],
+            Duration::from_millis(50),
+        );
+
+        // The reap passes straight through; the kill waits out its window for kmsg.
+        assert!(matches!(merged.next().await, Some(ProbeEvent::Reap(_))));
+        let started = Instant::now();
+        assert!(matches!(merged.next().await, Some(ProbeEvent::Kill(_))));
+        assert!(started.elapsed() >= Duration::from_millis(40));
+        assert_eq!(merged.kills_seen(), vec![("ebpf", 1)]);
+    }
+
+    #[tokio::test]
+    async fn reads_the_backends_in_turn() {
+        // The probe has a storm queued; the kernel log's one event still comes through.
+        let storm = (0..10).map(|pid| {
+            ProbeEvent::Reap(ReapEvent {
+                pid,
+                ..ReapEvent::default()
+            })
+        });
+        let mut merged = MergedSource::new(
+            vec![
+                ("ebpf", Backend(storm.collect(), true)),
+                (
+                    "kmsg",
+                    Backend(
+                        [ProbeEvent::Reap(ReapEvent {
+                            pid: 99,
+                            ..ReapEvent::default()
+                        })]
+                        .into(),
+                        true,
+                    ),
+                ),
+            ],
+            Duration::from_secs(60),
+        );
+
+        let mut pids = Vec::new();
+        for _ in 0..3 {
+            if let Some(ProbeEvent::Reap(reap)) = merged.next().await {
+                pids.push(reap.pid);
+            }
+        }
+        assert_eq!(pids, vec![0, 99, 1]);
+    }
+}
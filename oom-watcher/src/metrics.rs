@@ -1,7 +1,11 @@
-use oom_watcher_common::EnrichedOomEvent;
-use prometheus::{CounterVec, GaugeVec, Registry, TextEncoder};
+use oom_watcher_common::{
+    Confidence, ContainerIdentity, EnrichedOomEvent, MemcgPressure, ReapEvent, ScoreAdjEvent,
+    FIELD_ANON_RSS, FIELD_FILE_RSS, FIELD_HIWATER_RSS, FIELD_SHMEM_RSS, FIELD_START_TIME,
+    FIELD_SWAP, FIELD_TOTAL_VM, MEMCG_NO_LIMIT,
+};
+use prometheus::{CounterVec, GaugeVec, HistogramOpts, HistogramVec, Registry, TextEncoder};
 
-use crate::resolve::ResolutionOutcome;
+use crate::{reaper, resolve::ResolutionOutcome, watch::comm_str};
 
 /// The recording seam: how the watch loop reports what it observed, decoupled from
 /// Prometheus. The loop depends on this trait, never on the metrics backend.
@@ -13,6 +17,35 @@ pub trait MetricsRecorder {
 
     /// Record an enriched OOM event: kill counts, memory gauges, and timestamp.
     fn record_oom_event(&self, event: &EnrichedOomEvent);
+
+    /// Record one step of a victim's exit after the kill: the reaper's progress, or the
+    /// victim exiting.
+    fn record_reap(&self, node: &str, event: &ReapEvent);
+
+    /// Count OOM kill events the source lost before they could be recorded.
+    fn record_lost_events(&self, node: &str, count: u64);
+
+    /// Count probe records the source could not decode, keyed by reason.
+    fn record_skipped_records(&self, node: &str, reason: &str, count: u64);
+
+    /// Count OOM kills a backend of a merged source saw, keyed by backend.
+    fn record_kills_seen(&self, node: &str, source: &str, count: u64);
+
+    /// Record a write to a process's `oom_score_adj`, with the container it resolved to.
+    fn record_score_adj(
+        &self,
+        node: &str,
+        identity: Option<&ContainerIdentity>,
+        event: &ScoreAdjEvent,
+    );
+
+    /// Record one poll interval's worth of memory pressure on a container's cgroup.
+    fn record_pressure(
+        &self,
+        node: &str,
+        identity: Option<&ContainerIdentity>,
+        delta: &MemcgPressure,
+    );
 }
 
 /// The Prometheus adapter for the [`MetricsRecorder`] seam. Owns the registry and the
@@ -23,9 +56,25 @@ pub struct MetricsCollector {
     registry: Registry,
     oom_kills_total: CounterVec,
     oom_kills_per_node_total: CounterVec,
+    oom_workload_kills_total: CounterVec,
+    oom_kill_triggers_total: CounterVec,
     oom_memory_usage_bytes: GaugeVec,
+    oom_memcg_limit_bytes: GaugeVec,
+    oom_memcg_headroom_ratio: GaugeVec,
+    oom_victim_age_seconds: GaugeVec,
     oom_last_timestamp: GaugeVec,
     oom_resolution_failures_total: CounterVec,
+    oom_events_lost_total: CounterVec,
+    oom_records_skipped_total: CounterVec,
+    oom_kills_seen_total: CounterVec,
+    oom_victim_reap_seconds: HistogramVec,
+    oom_score_adj: GaugeVec,
+    oom_score_adj_changes_total: CounterVec,
+    oom_memcg_reclaims_total: CounterVec,
+    oom_memcg_reclaim_seconds_total: CounterVec,
+    oom_memcg_charge_failures_total: CounterVec,
+    oom_memcg_high_throttled_seconds_total: CounterVec,
+    oom_probe_attach_info: GaugeVec,
 }
 
 impl MetricsCollector {
@@ -34,7 +83,7 @@ impl MetricsCollector {
 
         let oom_kills_total = CounterVec::new(
             prometheus::Opts::new("oom_kills_total", "Total number of OOM kills observed"),
-            &["node", "namespace", "pod", "container"],
+            &["node", "namespace", "pod", "container", "scope"],
         )
         .expect("Failed to create oom_kills_total metric");
 
@@ -47,6 +96,39 @@ impl MetricsCollector {
         )
         .expect("Failed to create oom_kills_per_node_total metric");
 
+        let oom_workload_kills_total = CounterVec::new(
+            prometheus::Opts::new(
+                "oom_workload_kills_total",
+                "OOM kills by the workload that owns the victim's pod; survives pod name changes",
+            ),
+            &[
+                "node",
+                "namespace",
+                "workload_kind",
+                "workload",
+                "container",
+                "scope",
+            ],
+        )
+        .expect("Failed to create oom_workload_kills_total metric");
+
+        let oom_kill_triggers_total = CounterVec::new(
+            prometheus::Opts::new(
+                "oom_kill_triggers_total",
+                "OOM kills by the container whose allocation triggered them",
+            ),
+            &[
+                "node",
+                "namespace",
+                "pod",
+                "container",
+                "trigger_namespace",
+                "trigger_pod",
+                "trigger_container",
+            ],
+        )
+        .expect("Failed to create oom_kill_triggers_total metric");
+
         let oom_memory_usage_bytes = GaugeVec::new(
             prometheus::Opts::new(
                 "oom_memory_usage_bytes",
@@ -56,6 +138,33 @@ impl MetricsCollector {
         )
         .expect("Failed to create oom_memory_usage_bytes metric");
 
+        let oom_memcg_limit_bytes = GaugeVec::new(
+            prometheus::Opts::new(
+                "oom_memcg_limit_bytes",
+                "Memory limit of the memcg that hit it, at the time of OOM kill",
+            ),
+            &["node", "namespace", "pod", "container", "scope"],
+        )
+        .expect("Failed to create oom_memcg_limit_bytes metric");
+
+        let oom_memcg_headroom_ratio = GaugeVec::new(
+            prometheus::Opts::new(
+                "oom_memcg_headroom_ratio",
+                "Unused fraction of the breached memcg's limit at the time of OOM kill",
+            ),
+            &["node", "namespace", "pod", "container", "scope"],
+        )
+        .expect("Failed to create oom_memcg_headroom_ratio metric");
+
+        let oom_victim_age_seconds = GaugeVec::new(
+            prometheus::Opts::new(
+                "oom_victim_age_seconds",
+                "How long the killed process had been running at the time of OOM kill",
+            ),
+            &["node", "namespace", "pod", "container"],
+        )
+        .expect("Failed to create oom_victim_age_seconds metric");
+
         let oom_last_timestamp = GaugeVec::new(
             prometheus::Opts::new("oom_last_timestamp", "Timestamp of the last OOM kill event"),
             &["node", "namespace", "pod", "container"],
@@ -71,32 +180,192 @@ impl MetricsCollector {
         )
         .expect("Failed to create oom_resolution_failures_total metric");
 
+        let oom_events_lost_total = CounterVec::new(
+            prometheus::Opts::new(
+                "oom_events_lost_total",
+                "OOM kill events the probe dropped before they reached userspace",
+            ),
+            &["node"],
+        )
+        .expect("Failed to create oom_events_lost_total metric");
+
+        let oom_records_skipped_total = CounterVec::new(
+            prometheus::Opts::new(
+                "oom_records_skipped_total",
+                "Probe records skipped as undecodable (unknown version or kind, or cut short), by reason",
+            ),
+            &["node", "reason"],
+        )
+        .expect("Failed to create oom_records_skipped_total metric");
+
+        let oom_kills_seen_total = CounterVec::new(
+            prometheus::Opts::new(
+                "oom_kills_seen_total",
+                "OOM kills each backend of a merged event source saw; one below oom_kills_total missed some",
+            ),
+            &["node", "source"],
+        )
+        .expect("Failed to create oom_kills_seen_total metric");
+
+        let oom_victim_reap_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "oom_victim_reap_seconds",
+                "Time from an OOM kill to each step of the victim's exit, by stage",
+            )
+            .buckets(vec![
+                0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+            ]),
+            &["node", "stage"],
+        )
+        .expect("Failed to create oom_victim_reap_seconds metric");
+
+        let oom_score_adj = GaugeVec::new(
+            prometheus::Opts::new(
+                "oom_score_adj",
+                "Last oom_score_adj written for a process, by container and process name",
+            ),
+            &["node", "namespace", "pod", "container", "comm"],
+        )
+        .expect("Failed to create oom_score_adj metric");
+
+        let oom_score_adj_changes_total = CounterVec::new(
+            prometheus::Opts::new(
+                "oom_score_adj_changes_total",
+                "Writes to oom_score_adj observed, by container",
+            ),
+            &["node", "namespace", "pod", "container"],
+        )
+        .expect("Failed to create oom_score_adj_changes_total metric");
+
+        let pressure_counter = |name: &str, help: &str| {
+            CounterVec::new(
+                prometheus::Opts::new(name, help),
+                &["node", "namespace", "pod", "container"],
+            )
+            .unwrap_or_else(|e| panic!("Failed to create {} metric: {}", name, e))
+        };
+        let oom_memcg_reclaims_total = pressure_counter(
+            "oom_memcg_reclaims_total",
+            "Direct reclaim passes run against a container's memcg",
+        );
+        let oom_memcg_reclaim_seconds_total = pressure_counter(
+            "oom_memcg_reclaim_seconds_total",
+            "Time spent in direct reclaim against a container's memcg",
+        );
+        let oom_memcg_charge_failures_total = pressure_counter(
+            "oom_memcg_charge_failures_total",
+            "Memory charges a container's memcg refused at its limit",
+        );
+        let oom_memcg_high_throttled_seconds_total = pressure_counter(
+            "oom_memcg_high_throttled_seconds_total",
+            "Time a container's tasks spent throttled over memory.high",
+        );
+
+        let oom_probe_attach_info = GaugeVec::new(
+            prometheus::Opts::new(
+                "oom_probe_attach_info",
+                "How the OOM kill probe attached on this node: always 1, the mode and target",
+            ),
+            &["node", "mode", "target"],
+        )
+        .expect("Failed to create oom_probe_attach_info metric");
+
         registry
             .register(Box::new(oom_kills_total.clone()))
             .expect("Failed to register oom_kills_total");
         registry
             .register(Box::new(oom_kills_per_node_total.clone()))
             .expect("Failed to register oom_kills_per_node_total");
+        registry
+            .register(Box::new(oom_workload_kills_total.clone()))
+            .expect("Failed to register oom_workload_kills_total");
+        registry
+            .register(Box::new(oom_kill_triggers_total.clone()))
+            .expect("Failed to register oom_kill_triggers_total");
         registry
             .register(Box::new(oom_memory_usage_bytes.clone()))
             .expect("Failed to register oom_memory_usage_bytes");
+        registry
+            .register(Box::new(oom_memcg_limit_bytes.clone()))
+            .expect("Failed to register oom_memcg_limit_bytes");
+        registry
+            .register(Box::new(oom_memcg_headroom_ratio.clone()))
+            .expect("Failed to register oom_memcg_headroom_ratio");
+        registry
+            .register(Box::new(oom_victim_age_seconds.clone()))
+            .expect("Failed to register oom_victim_age_seconds");
         registry
             .register(Box::new(oom_last_timestamp.clone()))
             .expect("Failed to register oom_last_timestamp");
         registry
             .register(Box::new(oom_resolution_failures_total.clone()))
             .expect("Failed to register oom_resolution_failures_total");
+        registry
+            .register(Box::new(oom_events_lost_total.clone()))
+            .expect("Failed to register oom_events_lost_total");
+        registry
+            .register(Box::new(oom_records_skipped_total.clone()))
+            .expect("Failed to register oom_records_skipped_total");
+        registry
+            .register(Box::new(oom_kills_seen_total.clone()))
+            .expect("Failed to register oom_kills_seen_total");
+        registry
+            .register(Box::new(oom_victim_reap_seconds.clone()))
+            .expect("Failed to register oom_victim_reap_seconds");
+        registry
+            .register(Box::new(oom_score_adj.clone()))
+            .expect("Failed to register oom_score_adj");
+        registry
+            .register(Box::new(oom_score_adj_changes_total.clone()))
+            .expect("Failed to register oom_score_adj_changes_total");
+        registry
+            .register(Box::new(oom_probe_attach_info.clone()))
+            .expect("Failed to register oom_probe_attach_info");
+        for counter in [
+            &oom_memcg_reclaims_total,
+            &oom_memcg_reclaim_seconds_total,
+            &oom_memcg_charge_failures_total,
+            &oom_memcg_high_throttled_seconds_total,
+        ] {
+            registry
+                .register(Box::new(counter.clone()))
+                .expect("Failed to register memcg pressure metric");
+        }
 
         Self {
             registry,
             oom_kills_total,
             oom_kills_per_node_total,
+            oom_workload_kills_total,
+            oom_kill_triggers_total,
             oom_memory_usage_bytes,
+            oom_memcg_limit_bytes,
+            oom_memcg_headroom_ratio,
+            oom_victim_age_seconds,
             oom_last_timestamp,
             oom_resolution_failures_total,
+            oom_events_lost_total,
+            oom_records_skipped_total,
+            oom_kills_seen_total,
+            oom_victim_reap_seconds,
+            oom_score_adj,
+            oom_score_adj_changes_total,
+            oom_memcg_reclaims_total,
+            oom_memcg_reclaim_seconds_total,
+            oom_memcg_charge_failures_total,
+            oom_memcg_high_throttled_seconds_total,
+            oom_probe_attach_info,
         }
     }
 
+    /// Publish which attach mode the kill probe settled on, and on which tracepoint or
+    /// function. Set once at startup, so it is not part of the recording seam.
+    pub fn record_attach_mode(&self, node: &str, mode: &str, target: &str) {
+        self.oom_probe_attach_info
+            .with_label_values(&[node, mode, target])
+            .set(1.0);
+    }
+
     /// Render the registry in the Prometheus text exposition format.
     pub fn get_metrics(&self) -> String {
         let encoder = TextEncoder::new();
@@ -112,9 +381,13 @@ impl MetricsRecorder for MetricsCollector {
     /// failure rate is `failures / kills` in PromQL.
     fn record_resolution_outcome(&self, node: &str, outcome: &ResolutionOutcome) {
         let reason = match outcome {
+            ResolutionOutcome::Found(identity) if identity.confidence == Confidence::Pod => {
+                "pod_only"
+            }
             ResolutionOutcome::Found(_) => return,
             ResolutionOutcome::NotFound => "not_found",
             ResolutionOutcome::Failed(_) => "error",
+            ResolutionOutcome::RuntimeMismatch(_) => "runtime_mismatch",
         };
         self.oom_resolution_failures_total
             .with_label_values(&[node, reason])
@@ -127,9 +400,9 @@ impl MetricsRecorder for MetricsCollector {
         let pod = event.pod_name.as_deref().unwrap_or("unknown");
         let container = event.container_name.as_deref().unwrap_or("unknown");
 
-        // Increment total OOM kills
+        // Increment total OOM kills, labelled with whose limit the kill enforced
         self.oom_kills_total
-            .with_label_values(&[node, namespace, pod, container])
+            .with_label_values(&[node, namespace, pod, container, event.scope.as_str()])
             .inc();
 
         // Increment per-node OOM kills
@@ -137,35 +410,249 @@ impl MetricsRecorder for MetricsCollector {
             .with_label_values(&[node])
             .inc();
 
-        // Record memory usage at time of OOM
-        let labels = &[node, namespace, pod, container];
+        // And per workload, when the victim's pod is known
+        if let (Some(kind), Some(workload)) = (&event.workload_kind, &event.workload_name) {
+            self.oom_workload_kills_total
+                .with_label_values(&[
+                    node,
+                    namespace,
+                    kind,
+                    workload,
+                    container,
+                    event.scope.as_str(),
+                ])
+                .inc();
+        }
+
+        // Attribute the kill to the container whose allocation triggered it
+        let trigger = event.trigger.as_ref();
+        self.oom_kill_triggers_total
+            .with_label_values(&[
+                node,
+                namespace,
+                pod,
+                container,
+                trigger.map_or("unknown", |t| t.namespace.as_str()),
+                trigger.map_or("unknown", |t| t.pod_name.as_str()),
+                trigger.map_or("unknown", |t| t.container_name.as_str()),
+            ])
+            .inc();
 
-        self.oom_memory_usage_bytes
-            .with_label_values(&[labels[0], labels[1], labels[2], labels[3], "total_vm"])
-            .set((event.raw_event.total_vm * 1024) as f64); // Convert KB to bytes
+        // Record memory usage at time of OOM, for the fields this kernel reports
+        let labels = &[node, namespace, pod, container];
+        let raw = &event.raw_event;
+        for (memory_type, field, kb) in [
+            ("total_vm", FIELD_TOTAL_VM, raw.total_vm),
+            ("anon_rss", FIELD_ANON_RSS, raw.anon_rss),
+            ("file_rss", FIELD_FILE_RSS, raw.file_rss),
+            ("shmem_rss", FIELD_SHMEM_RSS, raw.shmem_rss),
+            ("hiwater_rss", FIELD_HIWATER_RSS, raw.hiwater_rss),
+            ("swap", FIELD_SWAP, raw.swap),
+        ] {
+            if raw.has(field) {
+                self.oom_memory_usage_bytes
+                    .with_label_values(&[labels[0], labels[1], labels[2], labels[3], memory_type])
+                    .set((kb * 1024) as f64); // Convert KB to bytes
+            }
+        }
 
-        self.oom_memory_usage_bytes
-            .with_label_values(&[labels[0], labels[1], labels[2], labels[3], "anon_rss"])
-            .set((event.raw_event.anon_rss * 1024) as f64);
+        // Record the breached memcg's counters, when the kill had one and it was read
+        if raw.memcg_limit != 0 || raw.memcg_usage != 0 {
+            for (memory_type, bytes) in [
+                ("memcg_usage", raw.memcg_usage),
+                ("memcg_swap", raw.memcg_swap),
+                ("memcg_peak", raw.memcg_peak),
+            ] {
+                self.oom_memory_usage_bytes
+                    .with_label_values(&[labels[0], labels[1], labels[2], labels[3], memory_type])
+                    .set(bytes as f64);
+            }
 
-        self.oom_memory_usage_bytes
-            .with_label_values(&[labels[0], labels[1], labels[2], labels[3], "file_rss"])
-            .set((event.raw_event.file_rss * 1024) as f64);
+            // An unlimited memcg has neither a limit nor headroom to report
+            if raw.memcg_limit != MEMCG_NO_LIMIT && raw.memcg_limit != 0 {
+                let scoped = &[node, namespace, pod, container, event.scope.as_str()];
+                let limit = raw.memcg_limit as f64;
+                self.oom_memcg_limit_bytes
+                    .with_label_values(scoped)
+                    .set(limit);
+                self.oom_memcg_headroom_ratio
+                    .with_label_values(scoped)
+                    .set(raw.memcg_limit.saturating_sub(raw.memcg_usage) as f64 / limit);
+            }
+        }
 
-        self.oom_memory_usage_bytes
-            .with_label_values(&[labels[0], labels[1], labels[2], labels[3], "shmem_rss"])
-            .set((event.raw_event.shmem_rss * 1024) as f64);
+        // Record how long the victim had lived: a fresh process or a slow leak
+        if raw.has(FIELD_START_TIME) {
+            self.oom_victim_age_seconds
+                .with_label_values(labels)
+                .set(raw.age_ns as f64 / 1e9);
+        }
 
         // Record timestamp
         self.oom_last_timestamp
             .with_label_values(&[node, namespace, pod, container])
             .set(event.timestamp as f64);
     }
+
+    fn record_reap(&self, node: &str, event: &ReapEvent) {
+        self.oom_victim_reap_seconds
+            .with_label_values(&[node, reaper::stage_str(event.stage)])
+            .observe(event.elapsed_ns as f64 / 1e9);
+    }
+
+    fn record_lost_events(&self, node: &str, count: u64) {
+        self.oom_events_lost_total
+            .with_label_values(&[node])
+            .inc_by(count as f64);
+    }
+
+    fn record_skipped_records(&self, node: &str, reason: &str, count: u64) {
+        self.oom_records_skipped_total
+            .with_label_values(&[node, reason])
+            .inc_by(count as f64);
+    }
+
+    fn record_kills_seen(&self, node: &str, source: &str, count: u64) {
+        self.oom_kills_seen_total
+            .with_label_values(&[node, source])
+            .inc_by(count as f64);
+    }
+
+    fn record_score_adj(
+        &self,
+        node: &str,
+        identity: Option<&ContainerIdentity>,
+        event: &ScoreAdjEvent,
+    ) {
+        let namespace = identity.map_or("unknown", |i| i.namespace.as_str());
+        let pod = identity.map_or("unknown", |i| i.pod_name.as_str());
+        let container = identity.map_or("unknown", |i| i.container_name.as_str());
+
+        self.oom_score_adj
+            .with_label_values(&[node, namespace, pod, container, comm_str(&event.comm)])
+            .set(event.oom_score_adj as f64);
+        self.oom_score_adj_changes_total
+            .with_label_values(&[node, namespace, pod, container])
+            .inc();
+    }
+
+    fn record_pressure(
+        &self,
+        node: &str,
+        identity: Option<&ContainerIdentity>,
+        delta: &MemcgPressure,
+    ) {
+        let labels = &[
+            node,
+            identity.map_or("unknown", |i| i.namespace.as_str()),
+            identity.map_or("unknown", |i| i.pod_name.as_str()),
+            identity.map_or("unknown", |i| i.container_name.as_str()),
+        ];
+        self.oom_memcg_reclaims_total
+            .with_label_values(labels)
+            .inc_by(delta.reclaims as f64);
+        self.oom_memcg_reclaim_seconds_total
+            .with_label_values(labels)
+            .inc_by(delta.reclaim_ns as f64 / 1e9);
+        self.oom_memcg_charge_failures_total
+            .with_label_values(labels)
+            .inc_by(delta.charge_failures as f64);
+        self.oom_memcg_high_throttled_seconds_total
+            .with_label_values(labels)
+            .inc_by(delta.high_throttle_ns as f64 / 1e9);
+    }
+}
+
+/// Recording spy — the second adapter for [`MetricsRecorder`]. Captures every call so
+/// tests assert what was reported, with no Prometheus involved. Compiled only under test.
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct SpyRecorder {
+    pub(crate) outcomes: std::cell::RefCell<Vec<(String, &'static str)>>,
+    pub(crate) events: std::cell::RefCell<Vec<EnrichedOomEvent>>,
+    pub(crate) lost: std::cell::RefCell<Vec<(String, u64)>>,
+    pub(crate) skipped: std::cell::RefCell<Vec<(String, String, u64)>>,
+    pub(crate) seen: std::cell::RefCell<Vec<(String, String, u64)>>,
+    /// (node, pid, stage).
+    pub(crate) reaps: std::cell::RefCell<Vec<(String, u32, u32)>>,
+    /// (node, container, new value).
+    pub(crate) score_adjs: std::cell::RefCell<Vec<(String, Option<String>, i16)>>,
+    /// (container, reclaims, charge failures).
+    pub(crate) pressure: std::cell::RefCell<Vec<(Option<String>, u64, u64)>>,
+}
+
+#[cfg(test)]
+impl MetricsRecorder for SpyRecorder {
+    fn record_resolution_outcome(&self, node: &str, outcome: &ResolutionOutcome) {
+        let reason = match outcome {
+            ResolutionOutcome::Found(_) => "found",
+            ResolutionOutcome::NotFound => "not_found",
+            ResolutionOutcome::Failed(_) => "error",
+            ResolutionOutcome::RuntimeMismatch(_) => "runtime_mismatch",
+        };
+        self.outcomes.borrow_mut().push((node.to_string(), reason));
+    }
+
+    fn record_oom_event(&self, event: &EnrichedOomEvent) {
+        self.events.borrow_mut().push(event.clone());
+    }
+
+    fn record_reap(&self, node: &str, event: &ReapEvent) {
+        self.reaps
+            .borrow_mut()
+            .push((node.to_string(), event.pid, event.stage));
+    }
+
+    fn record_lost_events(&self, node: &str, count: u64) {
+        self.lost.borrow_mut().push((node.to_string(), count));
+    }
+
+    fn record_skipped_records(&self, node: &str, reason: &str, count: u64) {
+        self.skipped
+            .borrow_mut()
+            .push((node.to_string(), reason.to_string(), count));
+    }
+
+    fn record_kills_seen(&self, node: &str, source: &str, count: u64) {
+        self.seen
+            .borrow_mut()
+            .push((node.to_string(), source.to_string(), count));
+    }
+
+    fn record_score_adj(
+        &self,
+        node: &str,
+        identity: Option<&ContainerIdentity>,
+        event: &ScoreAdjEvent,
+    ) {
+        self.score_adjs.borrow_mut().push((
+            node.to_string(),
+            identity.map(|i| i.container_name.clone()),
+            event.oom_score_adj,
+        ));
+    }
+
+    fn record_pressure(
+        &self,
+        _: &str,
+        identity: Option<&ContainerIdentity>,
+        delta: &MemcgPressure,
+    ) {
+        self.pressure.borrow_mut().push((
+            identity.map(|i| i.container_name.clone()),
+            delta.reclaims,
+            delta.charge_failures,
+        ));
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        enrich::{enrich, KillDetails},
+        resolve::identity,
+    };
 
     #[test]
     fn counts_failures_by_reason_and_ignores_found() {
@@ -176,20 +663,344 @@ mod tests {
         collector
             .record_resolution_outcome("node-1", &ResolutionOutcome::Failed(anyhow::anyhow!("x")));
         // Found must not touch the failures counter.
+        collector.record_resolution_outcome("node-1", &ResolutionOutcome::Found(identity()));
+
+        let out = collector.get_metrics();
+        assert!(
+            out.contains("oom_resolution_failures_total{node=\"node-1\",reason=\"not_found\"} 2")
+        );
+        assert!(out.contains("oom_resolution_failures_total{node=\"node-1\",reason=\"error\"} 1"));
+        assert!(!out.contains("pod_only"));
+    }
+
+    #[test]
+    fn counts_runtime_mismatches() {
+        let collector = MetricsCollector::new();
+
         collector.record_resolution_outcome(
             "node-1",
-            &ResolutionOutcome::Found(oom_watcher_common::ContainerIdentity {
-                namespace: "p".into(),
-                pod_name: "po".into(),
-                container_name: "c".into(),
+            &ResolutionOutcome::RuntimeMismatch(crate::resolve::RuntimeMismatch {
                 container_id: "id".into(),
+                cgroup: "cri-o",
+                status: "containerd".into(),
+            }),
+        );
+
+        let out = collector.get_metrics();
+        assert!(out.contains(
+            "oom_resolution_failures_total{node=\"node-1\",reason=\"runtime_mismatch\"} 1"
+        ));
+    }
+
+    #[test]
+    fn counts_pod_only_resolutions() {
+        let collector = MetricsCollector::new();
+
+        collector.record_resolution_outcome(
+            "node-1",
+            &ResolutionOutcome::Found(ContainerIdentity {
+                container_name: "unknown".into(),
+                container_id: String::new(),
+                confidence: Confidence::Pod,
+                ..identity()
             }),
         );
 
         let out = collector.get_metrics();
         assert!(
-            out.contains("oom_resolution_failures_total{node=\"node-1\",reason=\"not_found\"} 2")
+            out.contains("oom_resolution_failures_total{node=\"node-1\",reason=\"pod_only\"} 1")
         );
-        assert!(out.contains("oom_resolution_failures_total{node=\"node-1\",reason=\"error\"} 1"));
+    }
+
+    #[test]
+    fn labels_kills_with_scope_and_triggering_container() {
+        let collector = MetricsCollector::new();
+        let in_container = |container: &str| ContainerIdentity {
+            container_name: container.into(),
+            ..identity()
+        };
+        let event = enrich(
+            oom_watcher_common::OomKillEvent::default(),
+            Some("node-1"),
+            Some(in_container("api")),
+            oom_watcher_common::OomScope::Pod,
+            KillDetails {
+                trigger: Some(in_container("envoy")),
+                ..KillDetails::default()
+            },
+            0,
+        );
+
+        collector.record_oom_event(&event);
+
+        let out = collector.get_metrics();
+        assert!(out.contains(
+            "oom_kills_total{container=\"api\",namespace=\"prod\",node=\"node-1\",pod=\"api-7d9\",scope=\"pod\"} 1"
+        ));
+        assert!(out.contains(
+            "oom_kill_triggers_total{container=\"api\",namespace=\"prod\",node=\"node-1\",pod=\"api-7d9\",trigger_container=\"envoy\",trigger_namespace=\"prod\",trigger_pod=\"api-7d9\"} 1"
+        ));
+        assert!(out.contains(
+            "oom_workload_kills_total{container=\"api\",namespace=\"prod\",node=\"node-1\",scope=\"pod\",workload=\"api\",workload_kind=\"Deployment\"} 1"
+        ));
+    }
+
+    #[test]
+    fn counts_workload_kills_across_pods_and_only_when_resolved() {
+        let collector = MetricsCollector::new();
+        let in_pod = |pod: &str| ContainerIdentity {
+            pod_name: pod.into(),
+            ..identity()
+        };
+        for victim in [Some(in_pod("api-7d9")), Some(in_pod("api-8e1")), None] {
+            collector.record_oom_event(&enrich(
+                oom_watcher_common::OomKillEvent::default(),
+                Some("node-1"),
+                victim,
+                oom_watcher_common::OomScope::Container,
+                KillDetails::default(),
+                0,
+            ));
+        }
+
+        let out = collector.get_metrics();
+        assert!(out.contains(
+            "oom_workload_kills_total{container=\"api\",namespace=\"prod\",node=\"node-1\",scope=\"container\",workload=\"api\",workload_kind=\"Deployment\"} 2"
+        ));
+        assert!(!out.contains("workload=\"unknown\""));
+    }
+
+    #[test]
+    fn records_the_breached_memcg_limit_and_headroom() {
+        let collector = MetricsCollector::new();
+        let raw = oom_watcher_common::OomKillEvent {
+            memcg_usage: 768 << 20,
+            memcg_limit: 1 << 30,
+            memcg_peak: 1 << 30,
+            ..Default::default()
+        };
+        let event = enrich(
+            raw,
+            Some("node-1"),
+            None,
+            oom_watcher_common::OomScope::Container,
+            KillDetails::default(),
+            0,
+        );
+
+        collector.record_oom_event(&event);
+
+        let out = collector.get_metrics();
+        let labels = "container=\"unknown\",namespace=\"unknown\",node=\"node-1\",pod=\"unknown\"";
+        assert!(out.contains(&format!(
+            "oom_memcg_limit_bytes{{{},scope=\"container\"}} 1073741824",
+            labels
+        )));
+        assert!(out.contains(&format!(
+            "oom_memcg_headroom_ratio{{{},scope=\"container\"}} 0.25",
+            labels
+        )));
+        assert!(out.contains(
+            "oom_memory_usage_bytes{container=\"unknown\",memory_type=\"memcg_usage\",namespace=\"unknown\",node=\"node-1\",pod=\"unknown\"} 805306368"
+        ));
+    }
+
+    #[test]
+    fn skips_limit_and_headroom_without_a_limit() {
+        let collector = MetricsCollector::new();
+        let unlimited = oom_watcher_common::OomKillEvent {
+            memcg_usage: 1 << 30,
+            memcg_limit: MEMCG_NO_LIMIT,
+            ..Default::default()
+        };
+        for raw in [oom_watcher_common::OomKillEvent::default(), unlimited] {
+            let scope = oom_watcher_common::OomScope::Cgroup;
+            collector.record_oom_event(&enrich(raw, None, None, scope, KillDetails::default(), 0));
+        }
+
+        let out = collector.get_metrics();
+        assert!(!out.contains("oom_memcg_limit_bytes{"));
+        assert!(!out.contains("oom_memcg_headroom_ratio{"));
+        assert!(out.contains("memory_type=\"memcg_usage\",namespace=\"unknown\",node=\"unknown\",pod=\"unknown\"} 1073741824"));
+    }
+
+    #[test]
+    fn skips_memory_fields_the_kernel_did_not_report() {
+        let collector = MetricsCollector::new();
+        let raw = oom_watcher_common::OomKillEvent {
+            total_vm: 2048,
+            unknown_fields: FIELD_ANON_RSS | FIELD_FILE_RSS | FIELD_SHMEM_RSS,
+            ..Default::default()
+        };
+        let scope = oom_watcher_common::OomScope::Unknown;
+        collector.record_oom_event(&enrich(raw, None, None, scope, KillDetails::default(), 0));
+
+        let out = collector.get_metrics();
+        assert!(out.contains("memory_type=\"total_vm\",namespace=\"unknown\",node=\"unknown\",pod=\"unknown\"} 2097152"));
+        assert!(!out.contains("memory_type=\"anon_rss\""));
+        assert!(!out.contains("memory_type=\"shmem_rss\""));
+    }
+
+    #[test]
+    fn records_the_victims_peak_swap_and_age() {
+        let collector = MetricsCollector::new();
+        let raw = oom_watcher_common::OomKillEvent {
+            hiwater_rss: 4096,
+            swap: 1024,
+            age_ns: 90_500_000_000,
+            ..Default::default()
+        };
+        let scope = oom_watcher_common::OomScope::Unknown;
+        collector.record_oom_event(&enrich(raw, None, None, scope, KillDetails::default(), 0));
+        let no_age = oom_watcher_common::OomKillEvent {
+            unknown_fields: FIELD_START_TIME,
+            ..Default::default()
+        };
+        collector.record_oom_event(&enrich(
+            no_age,
+            Some("node-1"),
+            None,
+            scope,
+            KillDetails::default(),
+            0,
+        ));
+
+        let out = collector.get_metrics();
+        let labels = "namespace=\"unknown\",node=\"unknown\",pod=\"unknown\"";
+        assert!(out.contains(&format!(
+            "oom_memory_usage_bytes{{container=\"unknown\",memory_type=\"hiwater_rss\",{}}} 4194304",
+            labels
+        )));
+        assert!(out.contains(&format!(
+            "oom_memory_usage_bytes{{container=\"unknown\",memory_type=\"swap\",{}}} 1048576",
+            labels
+        )));
+        assert!(out.contains(&format!(
+            "oom_victim_age_seconds{{container=\"unknown\",{}}} 90.5",
+            labels
+        )));
+        assert!(!out.contains(
+            "oom_victim_age_seconds{container=\"unknown\",namespace=\"unknown\",node=\"node-1\""
+        ));
+    }
+
+    #[test]
+    fn observes_reap_latency_by_stage() {
+        let collector = MetricsCollector::new();
+        let reap = |stage, elapsed_ns| ReapEvent {
+            pid: 1234,
+            stage,
+            elapsed_ns,
+        };
+
+        collector.record_reap(
+            "node-1",
+            &reap(oom_watcher_common::REAP_FINISHED, 20_000_000),
+        );
+        collector.record_reap(
+            "node-1",
+            &reap(oom_watcher_common::REAP_SKIPPED, 3_000_000_000),
+        );
+
+        let out = collector.get_metrics();
+        assert!(out.contains(
+            "oom_victim_reap_seconds_bucket{node=\"node-1\",stage=\"reaped\",le=\"0.05\"} 1"
+        ));
+        assert!(out.contains(
+            "oom_victim_reap_seconds_bucket{node=\"node-1\",stage=\"skipped\",le=\"2.5\"} 0"
+        ));
+        assert!(out.contains("oom_victim_reap_seconds_count{node=\"node-1\",stage=\"skipped\"} 1"));
+    }
+
+    #[test]
+    fn tracks_oom_score_adj_per_container() {
+        let collector = MetricsCollector::new();
+        let identity = identity();
+        let change = |oom_score_adj| ScoreAdjEvent {
+            pid: 10,
+            comm: *b"java\0\0\0\0\0\0\0\0\0\0\0\0",
+            oom_score_adj,
+            ..ScoreAdjEvent::default()
+        };
+
+        collector.record_score_adj("node-1", Some(&identity), &change(500));
+        collector.record_score_adj("node-1", Some(&identity), &change(-999));
+
+        let out = collector.get_metrics();
+        assert!(out.contains(
+            "oom_score_adj{comm=\"java\",container=\"api\",namespace=\"prod\",node=\"node-1\",pod=\"api-7d9\"} -999"
+        ));
+        assert!(out.contains(
+            "oom_score_adj_changes_total{container=\"api\",namespace=\"prod\",node=\"node-1\",pod=\"api-7d9\"} 2"
+        ));
+    }
+
+    #[test]
+    fn counts_lost_events_per_node() {
+        let collector = MetricsCollector::new();
+        collector.record_lost_events("node-1", 3);
+        collector.record_lost_events("node-1", 2);
+
+        let out = collector.get_metrics();
+        assert!(out.contains("oom_events_lost_total{node=\"node-1\"} 5"));
+    }
+
+    #[test]
+    fn counts_skipped_records_by_reason() {
+        let collector = MetricsCollector::new();
+        collector.record_skipped_records("node-1", "kind", 2);
+        collector.record_skipped_records("node-1", "version", 1);
+
+        let out = collector.get_metrics();
+        assert!(out.contains("oom_records_skipped_total{node=\"node-1\",reason=\"kind\"} 2"));
+        assert!(out.contains("oom_records_skipped_total{node=\"node-1\",reason=\"version\"} 1"));
+    }
+
+    #[test]
+    fn counts_kills_seen_by_backend() {
+        let collector = MetricsCollector::new();
+        collector.record_kills_seen("node-1", "ebpf", 1);
+        collector.record_kills_seen("node-1", "ebpf", 1);
+        collector.record_kills_seen("node-1", "cgroup", 1);
+
+        let out = collector.get_metrics();
+        assert!(out.contains("oom_kills_seen_total{node=\"node-1\",source=\"ebpf\"} 2"));
+        assert!(out.contains("oom_kills_seen_total{node=\"node-1\",source=\"cgroup\"} 1"));
+    }
+
+    #[test]
+    fn exposes_the_attach_mode_as_info() {
+        let collector = MetricsCollector::new();
+        collector.record_attach_mode("node-1", "kprobe", "__oom_kill_process");
+
+        let out = collector.get_metrics();
+        assert!(out.contains(
+            "oom_probe_attach_info{mode=\"kprobe\",node=\"node-1\",target=\"__oom_kill_process\"} 1"
+        ));
+    }
+
+    #[test]
+    fn accumulates_memcg_pressure_per_container() {
+        let collector = MetricsCollector::new();
+        let identity = identity();
+        let delta = MemcgPressure {
+            reclaims: 30,
+            reclaim_ns: 1_500_000_000,
+            charge_failures: 1,
+            ..MemcgPressure::default()
+        };
+
+        collector.record_pressure("node-1", Some(&identity), &delta);
+        collector.record_pressure("node-1", Some(&identity), &delta);
+
+        let out = collector.get_metrics();
+        let labels = "container=\"api\",namespace=\"prod\",node=\"node-1\",pod=\"api-7d9\"";
+        assert!(out.contains(&format!("oom_memcg_reclaims_total{{{}}} 60", labels)));
+        assert!(out.contains(&format!("oom_memcg_reclaim_seconds_total{{{}}} 3", labels)));
+        assert!(out.contains(&format!("oom_memcg_charge_failures_total{{{}}} 2", labels)));
+        assert!(out.contains(&format!(
+            "oom_memcg_high_throttled_seconds_total{{{}}} 0",
+            labels
+        )));
     }
 }
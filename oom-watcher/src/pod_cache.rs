@@ -0,0 +1,586 @@
+//! This node's pods, kept in memory by a watch rather than listed per lookup.
+//!
+//! [`PodCache`] runs a kube-runtime watcher on the pods scheduled to this node and indexes
+//! them by UID, and their containers — app, init and ephemeral — by id: the current ones
+//! and, so a container that was OOM-killed and restarted still resolves, the ones they
+//! replaced. A container too new to have an id in its pod's status yet still resolves to
+//! its pod, by the UID on its cgroup path, marked [`Confidence::Pod`]. Resolution then
+//! answers from memory: an OOM storm costs no API calls, and a slow API server delays
+//! only the cache, not each kill. A deleted pod is kept for a grace period, so kills
+//! resolved late — after the kubelet has already torn the pod down — still find it.
+
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use futures::StreamExt;
+use k8s_openapi::api::core::v1::{ContainerStatus, Pod};
+use kube::{
+    runtime::{watcher, WatchStreamExt},
+    Api,
+};
+use log::{debug, warn};
+use oom_watcher_common::{Confidence, ContainerIdentity};
+use tokio::sync::watch;
+
+use crate::{
+    cgroup_path::{ContainerCgroup, Runtime},
+    resolve::RuntimeMismatch,
+    workload,
+};
+
+/// How long a deleted pod stays resolvable, unless `POD_CACHE_GRACE_SECS` says otherwise.
+const DEFAULT_GRACE: Duration = Duration::from_secs(300);
+
+/// How long a lookup waits for the first list of pods before giving up on it.
+const SYNC_WAIT: Duration = Duration::from_secs(10);
+
+pub struct PodCache {
+    index: Mutex<Index>,
+    synced: watch::Sender<bool>,
+}
+
+impl PodCache {
+    /// A cache that keeps deleted pods for `POD_CACHE_GRACE_SECS` (default 300).
+    pub fn from_env() -> Self {
+        let grace = env::var("POD_CACHE_GRACE_SECS")
+            .ok()
+            .and_then(|secs| secs.trim().parse().ok())
+            .map_or(DEFAULT_GRACE, Duration::from_secs);
+        Self::new(grace)
+    }
+
+    pub fn new(grace: Duration) -> Self {
+        Self {
+            index: Mutex::new(Index::new(grace)),
+            synced: watch::Sender::new(false),
+        }
+    }
+
+    /// Watch the pods on `node_name` and keep the index current. Runs until the task is
+    /// dropped; the watcher relists with backoff whenever the watch breaks.
+    pub async fn run(&self, pods: Api<Pod>, node_name: &str) {
+        let config = watcher::Config::default().fields(&format!("spec.nodeName={}", node_name));
+        let mut events = watcher(pods, config).default_backoff().boxed();
+        while let Some(event) = events.next().await {
+            match event {
+                Ok(event) => self.apply(event, Instant::now()),
+                Err(e) => warn!("Pod watch failed, retrying: {}", e),
+            }
+        }
+    }
+
+    fn apply(&self, event: watcher::Event<Pod>, now: Instant) {
+        let mut index = self.index.lock().unwrap_or_else(|e| e.into_inner());
+        match event {
+            watcher::Event::Apply(pod) => index.apply(pod),
+            watcher::Event::Delete(pod) => index.delete(&pod, now),
+            watcher::Event::Init => index.init(),
+            watcher::Event::InitApply(pod) => index.init_apply(pod),
+            watcher::Event::InitDone => {
+                index.init_done(now);
+                debug!("Pod cache synced: {} pods", index.pods.len());
+                self.synced.send_replace(true);
+            }
+        }
+        index.expire(now);
+    }
+
+    /// The container a cgroup names, in a pod on this node or one deleted within the grace
+    /// period: by its container id, or else only its pod, by UID. Waits for the first list
+    /// of pods if it has not landed yet, and fails if it does not land in time — or with a
+    /// [`RuntimeMismatch`] if the status carrying the id names another runtime than the
+    /// cgroup does.
+    pub async fn resolve(&self, cgroup: &ContainerCgroup) -> Result<Option<ContainerIdentity>> {
+        let mut synced = self.synced.subscribe();
+        tokio::time::timeout(SYNC_WAIT, synced.wait_for(|synced| *synced))
+            .await
+            .map_err(|_| anyhow!("Pod cache has not synced with the API server"))?
+            .map_err(|_| anyhow!("Pod cache stopped"))?;
+        let index = self.index.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let container_id = cgroup.container_id.as_deref();
+        let runtime = cgroup.runtime.map(Runtime::as_str);
+        if let Some(identity) = container_id.and_then(|id| index.container(id, now)) {
+            return match runtime {
+                Some(runtime) if runtime != identity.runtime => Err(RuntimeMismatch {
+                    container_id: identity.container_id,
+                    cgroup: runtime,
+                    status: identity.runtime,
+                }
+                .into()),
+                _ => Ok(Some(identity)),
+            };
+        }
+        let runtime = runtime.unwrap_or_default();
+        Ok(cgroup
+            .pod_uid
+            .as_deref()
+            .and_then(|uid| index.pod(uid, container_id, runtime, now)))
+    }
+
+    /// Where in its pod's spec `identity`'s container is declared, as an event's
+    /// `fieldPath`: `spec.containers{<name>}` for an app container. `None` for an init or
+    /// ephemeral container, declared elsewhere, and for an identity that only knows its pod.
+    pub fn field_path(&self, identity: &ContainerIdentity) -> Option<String> {
+        if identity.confidence != Confidence::Container {
+            return None;
+        }
+        let index = self.index.lock().unwrap_or_else(|e| e.into_inner());
+        index.field_path(&identity.container_id, Instant::now())
+    }
+}
+
+/// The pods themselves, by UID, and the containers in them, by bare id.
+struct Index {
+    grace: Duration,
+    pods: HashMap<String, Cached>,
+    /// Container id → the UID of its pod. Ids are never reused, so entries are only
+    /// dropped with their pod.
+    containers: HashMap<String, String>,
+    /// The UIDs listed since the watch (re)started, while it relists.
+    listing: Option<HashSet<String>>,
+}
+
+struct Cached {
+    pod: Pod,
+    /// When the pod was deleted, or dropped out of a relist.
+    deleted: Option<Instant>,
+}
+
+impl Index {
+    fn new(grace: Duration) -> Self {
+        Self {
+            grace,
+            pods: HashMap::new(),
+            containers: HashMap::new(),
+            listing: None,
+        }
+    }
+
+    fn apply(&mut self, pod: Pod) {
+        let Some(uid) = pod.metadata.uid.clone() else {
+            return;
+        };
+        for (_, id) in container_ids(&pod) {
+            self.containers.insert(id.to_string(), uid.clone());
+        }
+        self.pods.insert(uid, Cached { pod, deleted: None });
+    }
+
+    fn delete(&mut self, pod: &Pod, now: Instant) {
+        let cached = pod
+            .metadata
+            .uid
+            .as_ref()
+            .and_then(|uid| self.pods.get_mut(uid));
+        if let Some(cached) = cached {
+            cached.deleted.get_or_insert(now);
+        }
+    }
+
+    fn init(&mut self) {
+        self.listing = Some(HashSet::new());
+    }
+
+    fn init_apply(&mut self, pod: Pod) {
+        if let (Some(listing), Some(uid)) = (&mut self.listing, &pod.metadata.uid) {
+            listing.insert(uid.clone());
+        }
+        self.apply(pod);
+    }
+
+    /// A relist is complete: pods it did not return were deleted while the watch was down.
+    fn init_done(&mut self, now: Instant) {
+        let Some(listing) = self.listing.take() else {
+            return;
+        };
+        for (uid, cached) in &mut self.pods {
+            if !listing.contains(uid) {
+                cached.deleted.get_or_insert(now);
+            }
+        }
+    }
+
+    /// Forget pods deleted longer than the grace period ago, with their containers.
+    fn expire(&mut self, now: Instant) {
+        let grace = self.grace;
+        self.pods
+            .retain(|_, cached| cached.deleted.is_none_or(|at| now - at < grace));
+        let pods = &self.pods;
+        self.containers.retain(|_, uid| pods.contains_key(uid));
+    }
+
+    fn container(&self, container_id: &str, now: Instant) -> Option<ContainerIdentity> {
+        let pod = self.live(self.containers.get(container_id)?, now)?;
+        let (status, runtime) = statuses(pod).find_map(|status| {
+            let (runtime, _) = container_ids_of(status).find(|(_, id)| *id == container_id)?;
+            Some((status, runtime))
+        })?;
+        Some(identity(
+            pod,
+            &status.name,
+            container_id,
+            runtime,
+            Confidence::Container,
+        ))
+    }
+
+    /// Only the pod with `uid`, for a container whose id (if the cgroup named one) none of
+    /// its statuses carries yet.
+    fn pod(
+        &self,
+        uid: &str,
+        container_id: Option<&str>,
+        runtime: &str,
+        now: Instant,
+    ) -> Option<ContainerIdentity> {
+        let pod = self.live(uid, now)?;
+        let container_id = container_id.unwrap_or_default();
+        Some(identity(
+            pod,
+            "unknown",
+            container_id,
+            runtime,
+            Confidence::Pod,
+        ))
+    }
+
+    /// `spec.containers{<name>}`, if `container_id` is one of its pod's app containers.
+    fn field_path(&self, container_id: &str, now: Instant) -> Option<String> {
+        let pod = self.live(self.containers.get(container_id)?, now)?;
+        let status = pod
+            .status
+            .as_ref()?
+            .container_statuses
+            .iter()
+            .flatten()
+            .find(|status| container_ids_of(status).any(|(_, id)| id == container_id))?;
+        Some(format!("spec.containers{{{}}}", status.name))
+    }
+
+    /// The pod with `uid`, unless it was deleted longer than the grace period ago.
+    fn live(&self, uid: &str, now: Instant) -> Option<&Pod> {
+        let cached = self.pods.get(uid)?;
+        let expired = cached.deleted.is_some_and(|at| now - at >= self.grace);
+        (!expired).then_some(&cached.pod)
+    }
+}
+
+/// The identity of a container in `pod`, its workload the pod's own controller — which
+/// [`Workloads`](workload::Workloads) walks further up — or the pod itself.
+fn identity(
+    pod: &Pod,
+    container_name: &str,
+    container_id: &str,
+    runtime: &str,
+    confidence: Confidence,
+) -> ContainerIdentity {
+    let pod_name = pod
+        .metadata
+        .name
+        .clone()
+        .unwrap_or_else(|| "unknown".to_string());
+    let owner = workload::controller(&pod.metadata).unwrap_or_else(|| workload::Owner {
+        kind: "Pod".to_string(),
+        name: pod_name.clone(),
+    });
+    ContainerIdentity {
+        namespace: pod
+            .metadata
+            .namespace
+            .clone()
+            .unwrap_or_else(|| "default".to_string()),
+        pod_name,
+        container_name: container_name.to_string(),
+        container_id: container_id.to_string(),
+        runtime: runtime.to_string(),
+        workload_kind: owner.kind,
+        workload_name: owner.name,
+        confidence,
+    }
+}
+
+/// The statuses of every container in the pod: app, init and ephemeral.
+fn statuses(pod: &Pod) -> impl Iterator<Item = &ContainerStatus> {
+    pod.status.iter().flat_map(|status| {
+        [
+            &status.container_statuses,
+            &status.init_container_statuses,
+            &status.ephemeral_container_statuses,
+        ]
+        .into_iter()
+        .flatten()
+        .flatten()
+    })
+}
+
+/// The runtime and bare id of every container the pod's statuses name.
+fn container_ids(pod: &Pod) -> impl Iterator<Item = (&str, &str)> {
+    statuses(pod).flat_map(container_ids_of)
+}
+
+/// A container's current id and the one it last restarted from, split into the runtime
+/// and the bare id of their `<runtime>://<id>` form. Ids not of that form are skipped:
+/// they can only ever be matched loosely.
+fn container_ids_of(status: &ContainerStatus) -> impl Iterator<Item = (&str, &str)> {
+    let last = status
+        .last_state
+        .as_ref()
+        .and_then(|state| state.terminated.as_ref())
+        .and_then(|terminated| terminated.container_id.as_deref());
+    status
+        .container_id
+        .as_deref()
+        .into_iter()
+        .chain(last)
+        .filter_map(|id| id.split_once("://"))
+        .filter(|(runtime, id)| !runtime.is_empty() && !id.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const GRACE: Duration = Duration::from_secs(60);
+
+    fn pod(uid: &str, name: &str, id: &str, last_id: Option<&str>) -> Pod {
+        let last_state = last_id.map_or(json!({}), |last| {
+            json!({ "terminated": { "exitCode": 137, "containerID": format!("containerd://{}", last) } })
+        });
+        serde_json::from_value(json!({
+            "metadata": { "uid": uid, "name": name, "namespace": "shop" },
+            "status": {
+                "containerStatuses": [{
+                    "name": "app",
+                    "containerID": format!("containerd://{}", id),
+                    "lastState": last_state,
+                    "image": "app:1",
+                    "imageID": "",
+                    "ready": true,
+                    "restartCount": 1,
+                }]
+            }
+        }))
+        .unwrap()
+    }
+
+    fn pod_name(index: &Index, id: &str, now: Instant) -> Option<String> {
+        index.container(id, now).map(|identity| identity.pod_name)
+    }
+
+    #[test]
+    fn resolves_current_and_restarted_containers() {
+        let now = Instant::now();
+        let mut index = Index::new(GRACE);
+        index.apply(pod("u1", "cart", "new", Some("old")));
+
+        let identity = index.container("old", now).unwrap();
+        assert_eq!(
+            (identity.namespace.as_str(), identity.pod_name.as_str()),
+            ("shop", "cart")
+        );
+        assert_eq!(identity.container_name, "app");
+        assert_eq!(identity.container_id, "old");
+        assert_eq!(pod_name(&index, "new", now).as_deref(), Some("cart"));
+        assert_eq!(pod_name(&index, "other", now), None);
+        // No controller: the pod is its own workload.
+        assert_eq!(
+            (
+                identity.workload_kind.as_str(),
+                identity.workload_name.as_str()
+            ),
+            ("Pod", "cart")
+        );
+    }
+
+    #[test]
+    fn takes_the_workload_from_the_pods_controller() {
+        let now = Instant::now();
+        let mut index = Index::new(GRACE);
+        let mut cart = pod("u1", "cart-7d9f-x2k", "c1", None);
+        cart.metadata.owner_references = serde_json::from_value(json!([
+            { "apiVersion": "v1", "kind": "Node", "name": "node-1", "uid": "n1" },
+            {
+                "apiVersion": "apps/v1",
+                "kind": "ReplicaSet",
+                "name": "cart-7d9f",
+                "uid": "r1",
+                "controller": true,
+            },
+        ]))
+        .unwrap();
+        index.apply(cart);
+
+        let identity = index.container("c1", now).unwrap();
+        assert_eq!(
+            (
+                identity.workload_kind.as_str(),
+                identity.workload_name.as_str()
+            ),
+            ("ReplicaSet", "cart-7d9f")
+        );
+    }
+
+    #[test]
+    fn resolves_init_and_ephemeral_containers() {
+        let now = Instant::now();
+        let mut index = Index::new(GRACE);
+        let status = |name: &str, id: &str| {
+            json!({
+                "name": name,
+                "containerID": format!("cri-o://{}", id),
+                "image": "tools:1",
+                "imageID": "",
+                "ready": false,
+                "restartCount": 0,
+            })
+        };
+        index.apply(
+            serde_json::from_value(json!({
+                "metadata": { "uid": "u1", "name": "cart", "namespace": "shop" },
+                "status": {
+                    "initContainerStatuses": [status("migrate", "i1")],
+                    "ephemeralContainerStatuses": [status("debugger", "e1")],
+                }
+            }))
+            .unwrap(),
+        );
+
+        let name = |id| {
+            index
+                .container(id, now)
+                .map(|identity| identity.container_name)
+        };
+        assert_eq!(name("i1").as_deref(), Some("migrate"));
+        assert_eq!(name("e1").as_deref(), Some("debugger"));
+        // Declared outside spec.containers, so neither has a field path there.
+        assert_eq!(index.field_path("i1", now), None);
+        assert_eq!(index.field_path("e1", now), None);
+    }
+
+    #[test]
+    fn names_the_field_path_of_app_containers() {
+        let now = Instant::now();
+        let mut index = Index::new(GRACE);
+        index.apply(pod("u1", "cart", "new", Some("old")));
+
+        assert_eq!(
+            index.field_path("new", now).as_deref(),
+            Some("spec.containers{app}")
+        );
+        assert_eq!(
+            index.field_path("old", now).as_deref(),
+            Some("spec.containers{app}")
+        );
+        assert_eq!(index.field_path("other", now), None);
+    }
+
+    #[test]
+    fn falls_back_to_the_pod_by_uid() {
+        let now = Instant::now();
+        let mut index = Index::new(GRACE);
+        index.apply(pod("u1", "cart", "c1", None));
+
+        // Starting: the cgroup names a container no status carries yet.
+        let identity = index.pod("u1", Some("c2"), "cri-o", now).unwrap();
+        assert_eq!(identity.pod_name, "cart");
+        assert_eq!(identity.container_name, "unknown");
+        assert_eq!(identity.container_id, "c2");
+        assert_eq!(identity.runtime, "cri-o");
+        assert_eq!(identity.confidence, Confidence::Pod);
+
+        assert_eq!(
+            index.container("c1", now).unwrap().confidence,
+            Confidence::Container
+        );
+        assert_eq!(index.pod("u2", None, "", now), None);
+    }
+
+    #[test]
+    fn matches_container_ids_exactly() {
+        let now = Instant::now();
+        let mut index = Index::new(GRACE);
+        index.apply(pod("u1", "cart", "c1234", None));
+
+        assert_eq!(index.container("c1234", now).unwrap().runtime, "containerd");
+        assert_eq!(index.container("c12", now), None);
+        assert_eq!(index.container("containerd://c1234", now), None);
+    }
+
+    #[tokio::test]
+    async fn refuses_a_container_id_under_another_runtime() {
+        let cache = PodCache::new(GRACE);
+        cache.apply(
+            watcher::Event::Apply(pod("u1", "cart", "c1", None)),
+            Instant::now(),
+        );
+        cache.apply(watcher::Event::InitDone, Instant::now());
+        let cgroup = |runtime| ContainerCgroup {
+            runtime,
+            container_id: Some("c1".to_string()),
+            ..ContainerCgroup::default()
+        };
+
+        let found = cache.resolve(&cgroup(Some(Runtime::Containerd))).await;
+        assert_eq!(found.unwrap().unwrap().pod_name, "cart");
+        // The cgroupfs driver does not name the runtime, so there is nothing to check.
+        assert!(cache.resolve(&cgroup(None)).await.unwrap().is_some());
+
+        let mismatch = cache
+            .resolve(&cgroup(Some(Runtime::CriO)))
+            .await
+            .unwrap_err();
+        let mismatch = mismatch.downcast::<RuntimeMismatch>().unwrap();
+        assert_eq!(
+            (mismatch.cgroup, mismatch.status.as_str()),
+            ("cri-o", "containerd")
+        );
+    }
+
+    #[test]
+    fn keeps_deleted_pods_for_the_grace_period() {
+        let now = Instant::now();
+        let mut index = Index::new(GRACE);
+        let cart = pod("u1", "cart", "c1", None);
+        index.apply(cart.clone());
+        index.delete(&cart, now);
+
+        index.expire(now + GRACE / 2);
+        assert_eq!(
+            pod_name(&index, "c1", now + GRACE / 2).as_deref(),
+            Some("cart")
+        );
+        assert_eq!(pod_name(&index, "c1", now + GRACE), None);
+
+        index.expire(now + GRACE);
+        assert!(index.pods.is_empty() && index.containers.is_empty());
+    }
+
+    #[test]
+    fn a_relist_marks_vanished_pods_deleted() {
+        let now = Instant::now();
+        let mut index = Index::new(GRACE);
+        index.apply(pod("u1", "cart", "c1", None));
+        index.apply(pod("u2", "shelf", "c2", None));
+
+        index.init();
+        index.init_apply(pod("u2", "shelf", "c2", None));
+        index.init_done(now);
+
+        assert!(index.pods["u1"].deleted.is_some());
+        assert!(index.pods["u2"].deleted.is_none());
+        index.expire(now + GRACE);
+        assert_eq!(pod_name(&index, "c1", now + GRACE), None);
+        assert_eq!(
+            pod_name(&index, "c2", now + GRACE).as_deref(),
+            Some("shelf")
+        );
+    }
+}
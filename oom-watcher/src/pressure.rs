@@ -0,0 +1,420 @@
+//! The pressure loop: warning about containers on their way to an OOM kill.
+//!
+//! A memcg OOM is rarely sudden. Before the kill, tasks charging the memcg reclaim from it
+//! directly, more often and for longer, and a memcg over `memory.high` gets its tasks
+//! throttled; the last step is a charge the kernel refuses. The probe counts all three per
+//! cgroup ([`MemcgPressure`]). [`run`] polls those counters, turns them into per-interval
+//! deltas, resolves each cgroup under pressure to its container, records the deltas, and —
+//! past the [`PressureConfig`] thresholds — warns, optionally as a Kubernetes event on the
+//! pod so teams are paged before the kill rather than after it.
+
+use std::{collections::HashMap, env, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use log::warn;
+use oom_watcher_common::{ContainerIdentity, MemcgPressure};
+
+use crate::{
+    metrics::MetricsRecorder,
+    resolve::{ContainerResolver, ProcessRef},
+};
+
+/// How long to stay quiet about a container after warning about it.
+const WARNING_COOLDOWN: Duration = Duration::from_secs(300);
+
+/// The seam for where pressure counters reach userspace: each `next` yields a cumulative
+/// snapshot, one entry per cgroup, once per poll interval. `None` means the stream has
+/// ended.
+// Static dispatch only — the loop is generic over a concrete source, never `dyn`.
+#[allow(async_fn_in_trait)]
+pub trait PressureSource {
+    async fn next(&mut self) -> Option<Vec<MemcgPressure>>;
+}
+
+/// The seam for warning events. `KubernetesClient` posts them as `Warning` events on the
+/// container's pod; tests use a fake.
+#[allow(async_fn_in_trait)]
+pub trait WarningSink {
+    async fn warn(&self, identity: &ContainerIdentity, message: &str) -> Result<()>;
+}
+
+/// One client shared by several loops, as for [`ContainerResolver`].
+impl<T: WarningSink> WarningSink for Arc<T> {
+    async fn warn(&self, identity: &ContainerIdentity, message: &str) -> Result<()> {
+        (**self).warn(identity, message).await
+    }
+}
+
+/// Poll interval and warning thresholds, from the environment.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PressureConfig {
+    /// `PRESSURE_INTERVAL_SECS`: how often the counters are polled (default 10).
+    pub interval: Duration,
+    /// `PRESSURE_RECLAIM_RATE`: memcg reclaims per second that count as approaching OOM
+    /// (default 10).
+    pub reclaim_rate: f64,
+    /// `PRESSURE_THROTTLE_RATIO`: fraction of an interval spent throttled over
+    /// `memory.high` that counts as approaching OOM (default 0.1).
+    pub throttle_ratio: f64,
+    /// `PRESSURE_WARNINGS`: whether to post warnings as Kubernetes events (default false).
+    /// Warnings are logged either way.
+    pub events: bool,
+}
+
+impl Default for PressureConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(10),
+            reclaim_rate: 10.0,
+            throttle_ratio: 0.1,
+            events: false,
+        }
+    }
+}
+
+impl PressureConfig {
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            interval: env_parse("PRESSURE_INTERVAL_SECS")
+                .filter(|secs| *secs > 0)
+                .map_or(defaults.interval, Duration::from_secs),
+            reclaim_rate: env_parse("PRESSURE_RECLAIM_RATE").unwrap_or(defaults.reclaim_rate),
+            throttle_ratio: env_parse("PRESSURE_THROTTLE_RATIO").unwrap_or(defaults.throttle_ratio),
+            events: env_parse("PRESSURE_WARNINGS").unwrap_or(defaults.events),
+        }
+    }
+}
+
+fn env_parse<T: std::str::FromStr>(name: &str) -> Option<T> {
+    env::var(name).ok()?.parse().ok()
+}
+
+/// Run the pressure loop: diff each snapshot from `source` against the last, resolve and
+/// record every cgroup whose counters moved, and warn about those past the thresholds,
+/// until the source ends. `sink` is only used when `config.events` is set.
+pub async fn run<S, R>(
+    mut source: S,
+    resolver: Option<impl ContainerResolver>,
+    sink: Option<impl WarningSink>,
+    recorder: &R,
+    config: &PressureConfig,
+) where
+    S: PressureSource,
+    R: MetricsRecorder,
+{
+    let mut previous: HashMap<u64, MemcgPressure> = HashMap::new();
+    // A cgroup's container never changes, so a found identity is kept while it is tracked.
+    let mut identities: HashMap<u64, ContainerIdentity> = HashMap::new();
+    let mut last_warned: HashMap<u64, u64> = HashMap::new();
+    let cooldown = WARNING_COOLDOWN.as_secs() / config.interval.as_secs().max(1);
+    let mut tick: u64 = 0;
+
+    while let Some(snapshot) = source.next().await {
+        tick += 1;
+        let mut current = HashMap::with_capacity(snapshot.len());
+        for cumulative in snapshot {
+            let key = key(&cumulative);
+            let delta = delta(previous.get(&key), &cumulative);
+            current.insert(key, cumulative);
+            if counters(&delta) == [0; 6] {
+                continue;
+            }
+
+            let identity = match (identities.get(&key), &resolver) {
+                (Some(identity), _) => Some(identity.clone()),
+                (None, Some(client)) => {
+                    let identity = resolve(client, &delta).await;
+                    if let Some(identity) = &identity {
+                        identities.insert(key, identity.clone());
+                    }
+                    identity
+                }
+                (None, None) => None,
+            };
+            let node = resolver.as_ref().map_or("unknown", |r| r.node_name());
+            recorder.record_pressure(node, identity.as_ref(), &delta);
+
+            let Some(reason) = assess(&delta, config) else {
+                continue;
+            };
+            if last_warned
+                .get(&key)
+                .is_some_and(|warned| tick - warned < cooldown)
+            {
+                continue;
+            }
+            last_warned.insert(key, tick);
+            warn_approaching(&delta, identity.as_ref(), &reason, &sink, config).await;
+        }
+
+        // Forget cgroups the probe no longer tracks (removed, or evicted from its map).
+        identities.retain(|key, _| current.contains_key(key));
+        last_warned.retain(|key, _| current.contains_key(key));
+        previous = current;
+    }
+}
+
+/// The key the probe files a cgroup's counters under: its v2 id, or its memcg id on v1.
+fn key(counters: &MemcgPressure) -> u64 {
+    if counters.cgroup_id != 0 {
+        counters.cgroup_id
+    } else {
+        counters.memcg_id
+    }
+}
+
+/// The cumulative counters of `p`, in a fixed order.
+fn counters(p: &MemcgPressure) -> [u64; 6] {
+    [
+        p.reclaims,
+        p.reclaim_ns,
+        p.reclaimed_pages,
+        p.charge_failures,
+        p.high_throttles,
+        p.high_throttle_ns,
+    ]
+}
+
+/// What changed since `previous`; the ids and last task are the current ones. A counter
+/// that went backwards means the probe dropped and recreated the entry, so the current
+/// counters are all new.
+fn delta(previous: Option<&MemcgPressure>, current: &MemcgPressure) -> MemcgPressure {
+    let Some(previous) = previous.filter(|previous| {
+        counters(current)
+            .iter()
+            .zip(counters(previous))
+            .all(|(cur, prev)| *cur >= prev)
+    }) else {
+        return *current;
+    };
+    MemcgPressure {
+        reclaims: current.reclaims - previous.reclaims,
+        reclaim_ns: current.reclaim_ns - previous.reclaim_ns,
+        reclaimed_pages: current.reclaimed_pages - previous.reclaimed_pages,
+        charge_failures: current.charge_failures - previous.charge_failures,
+        high_throttles: current.high_throttles - previous.high_throttles,
+        high_throttle_ns: current.high_throttle_ns - previous.high_throttle_ns,
+        ..*current
+    }
+}
+
+/// Why an interval's `delta` counts as approaching OOM, if it does.
+fn assess(delta: &MemcgPressure, config: &PressureConfig) -> Option<String> {
+    let interval = config.interval.as_secs_f64();
+    let mut reasons = Vec::new();
+    if delta.charge_failures > 0 {
+        reasons.push(format!(
+            "{} charges failed at the memory limit",
+            delta.charge_failures
+        ));
+    }
+    let reclaim_rate = delta.reclaims as f64 / interval;
+    if delta.reclaims > 0 && reclaim_rate >= config.reclaim_rate {
+        reasons.push(format!(
+            "reclaiming {:.1} times/s at {:.1}ms each",
+            reclaim_rate,
+            delta.reclaim_ns as f64 / delta.reclaims as f64 / 1e6
+        ));
+    }
+    let throttled = delta.high_throttle_ns as f64 / 1e9 / interval;
+    if delta.high_throttles > 0 && throttled >= config.throttle_ratio {
+        reasons.push(format!(
+            "throttled over memory.high for {:.0}% of the last {:?}",
+            throttled * 100.0,
+            config.interval
+        ));
+    }
+    (!reasons.is_empty()).then(|| reasons.join("; "))
+}
+
+async fn resolve(
+    client: &impl ContainerResolver,
+    delta: &MemcgPressure,
+) -> Option<ContainerIdentity> {
+    let process = ProcessRef::under_pressure(delta);
+    client
+        .resolve(&process)
+        .await
+        .found_or_log(format_args!("cgroup {} under memory pressure", key(delta)))
+}
+
+async fn warn_approaching(
+    delta: &MemcgPressure,
+    identity: Option<&ContainerIdentity>,
+    reason: &str,
+    sink: &Option<impl WarningSink>,
+    config: &PressureConfig,
+) {
+    let Some(identity) = identity else {
+        warn!("cgroup {} is approaching OOM: {}", key(delta), reason);
+        return;
+    };
+    warn!(
+        "Container {}/{}/{} is approaching OOM: {}",
+        identity.namespace, identity.pod_name, identity.container_name, reason
+    );
+    if let Some(sink) = sink.as_ref().filter(|_| config.events) {
+        let message = format!(
+            "Container {} is approaching OOM: {}",
+            identity.container_name, reason
+        );
+        if let Err(e) = sink.warn(identity, &message).await {
+            warn!(
+                "Failed to post warning event for {}/{}: {}",
+                identity.namespace, identity.pod_name, e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::VecDeque};
+
+    use super::*;
+    use crate::{
+        metrics::SpyRecorder,
+        resolve::{identity, Behavior, FakeResolver},
+    };
+
+    struct VecSource(VecDeque<Vec<MemcgPressure>>);
+
+    impl PressureSource for VecSource {
+        async fn next(&mut self) -> Option<Vec<MemcgPressure>> {
+            self.0.pop_front()
+        }
+    }
+
+    #[derive(Default)]
+    struct SpySink {
+        warnings: RefCell<Vec<(String, String)>>,
+    }
+
+    impl WarningSink for &SpySink {
+        async fn warn(&self, identity: &ContainerIdentity, message: &str) -> Result<()> {
+            self.warnings
+                .borrow_mut()
+                .push((identity.pod_name.clone(), message.to_string()));
+            Ok(())
+        }
+    }
+
+    fn counters(reclaims: u64, charge_failures: u64) -> MemcgPressure {
+        MemcgPressure {
+            cgroup_id: 42,
+            reclaims,
+            reclaim_ns: reclaims * 2_000_000,
+            charge_failures,
+            last_pid: 10,
+            last_tgid: 10,
+            ..MemcgPressure::default()
+        }
+    }
+
+    fn resolver() -> Option<FakeResolver> {
+        Some(FakeResolver {
+            node: "node-1".into(),
+            behavior: Behavior::PerPid(vec![(10, identity())]),
+        })
+    }
+
+    fn config(events: bool) -> PressureConfig {
+        PressureConfig {
+            interval: Duration::from_secs(10),
+            events,
+            ..PressureConfig::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn records_deltas_between_snapshots() {
+        let spy = SpyRecorder::default();
+        let snapshots = [
+            vec![counters(5, 0)],
+            vec![counters(5, 0)], // unchanged: nothing to record
+            vec![counters(12, 1)],
+            vec![counters(3, 0)], // went backwards: the entry was recreated
+        ];
+
+        run(
+            VecSource(snapshots.map(Vec::from).into()),
+            resolver(),
+            None::<&SpySink>,
+            &spy,
+            &config(false),
+        )
+        .await;
+
+        let api = Some("api".to_string());
+        assert_eq!(
+            *spy.pressure.borrow(),
+            vec![(api.clone(), 5, 0), (api.clone(), 7, 1), (api, 3, 0)]
+        );
+    }
+
+    #[tokio::test]
+    async fn warns_past_the_thresholds_once_per_cooldown() {
+        let spy = SpyRecorder::default();
+        let sink = SpySink::default();
+        let snapshots = [
+            vec![counters(50, 0)],  // 5 reclaims/s: under the threshold
+            vec![counters(200, 0)], // 15/s: warn
+            vec![counters(400, 2)], // still bad, but within the cooldown
+        ];
+
+        run(
+            VecSource(snapshots.into()),
+            resolver(),
+            Some(&sink),
+            &spy,
+            &config(true),
+        )
+        .await;
+
+        let warnings = sink.warnings.borrow();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].0, "api-7d9");
+        assert_eq!(
+            warnings[0].1,
+            "Container api is approaching OOM: reclaiming 15.0 times/s at 2.0ms each"
+        );
+    }
+
+    #[tokio::test]
+    async fn posts_no_events_unless_enabled() {
+        let spy = SpyRecorder::default();
+        let sink = SpySink::default();
+
+        run(
+            VecSource([vec![counters(0, 3)]].into()),
+            resolver(),
+            Some(&sink),
+            &spy,
+            &config(false),
+        )
+        .await;
+
+        assert!(sink.warnings.borrow().is_empty());
+        assert_eq!(spy.pressure.borrow().len(), 1);
+    }
+
+    #[test]
+    fn assesses_each_signal() {
+        let config = config(false);
+        assert_eq!(assess(&counters(10, 0), &config), None);
+        assert_eq!(
+            assess(&counters(0, 2), &config).as_deref(),
+            Some("2 charges failed at the memory limit")
+        );
+        let throttled = MemcgPressure {
+            high_throttles: 40,
+            high_throttle_ns: 2_500_000_000,
+            ..MemcgPressure::default()
+        };
+        assert_eq!(
+            assess(&throttled, &config).as_deref(),
+            Some("throttled over memory.high for 25% of the last 10s")
+        );
+    }
+}
@@ -0,0 +1,274 @@
+//! Load-time configuration of the probe: how large its kill ring buffer is, how eagerly it
+//! wakes userspace, and which kills it ships at all.
+//!
+//! All three are settled as the probe loads — the ring buffer sized by the loader, the
+//! wakeup threshold and filters written into the probe's globals and filter maps — so one
+//! eBPF object serves every configuration.
+//!
+//! Filtering in-kernel keeps excluded kills off the ring buffer altogether: a noisy batch
+//! namespace cannot crowd out the kills that matter, and watchers with disjoint scopes can
+//! share a node. See [`FilterConfig`] for how the lists combine.
+
+use std::{env, time::Duration};
+
+use log::warn;
+use oom_watcher_common::{FilterConfig, FILTER_EXCLUDE, FILTER_INCLUDE};
+
+use crate::cgroup::Hierarchy;
+
+/// Bytes of a comm, NUL included (`TASK_COMM_LEN`).
+const COMM_LEN: usize = 16;
+
+/// Ring buffer size and victim filters, from the environment.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProbeConfig {
+    /// `RING_BUFFER_BYTES`: size of the kill ring buffer (default 65536, room for about
+    /// sixty kills). Rounded up to a power of two of at least a page, as the kernel
+    /// requires.
+    pub ring_buffer_bytes: u32,
+    /// `RING_BUFFER_BATCH_BYTES`: with a storm of kills, wake userspace only once this
+    /// much is waiting in the ring buffer, not for every kill (default 0: every kill).
+    /// Capped at half the ring buffer, so a batch never waits on a full one.
+    pub batch_bytes: u32,
+    /// `RING_BUFFER_BATCH_DELAY_MS`: when batching, the longest a kill waits for its batch
+    /// to fill before userspace reads it anyway (default 1000).
+    pub batch_delay: Duration,
+    /// `FILTER_INCLUDE_CGROUPS`/`FILTER_EXCLUDE_CGROUPS`: cgroup paths relative to the
+    /// hierarchy root (e.g. `/kubepods.slice/kubepods-besteffort.slice`), each covering
+    /// the cgroups below it.
+    pub cgroups: Filter<String>,
+    /// `FILTER_INCLUDE_UIDS`/`FILTER_EXCLUDE_UIDS`: victim uids.
+    pub uids: Filter<u32>,
+    /// `FILTER_INCLUDE_COMMS`/`FILTER_EXCLUDE_COMMS`: prefixes of the victim's comm.
+    pub comms: Filter<String>,
+}
+
+/// One dimension's include and exclude lists. Both empty filters nothing.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Filter<T> {
+    pub include: Vec<T>,
+    pub exclude: Vec<T>,
+}
+
+impl<T> Default for Filter<T> {
+    fn default() -> Self {
+        Self {
+            include: Vec::new(),
+            exclude: Vec::new(),
+        }
+    }
+}
+
+impl<T> Filter<T> {
+    /// Every listed value with its `FILTER_*` action, as the probe's map holds it.
+    pub fn entries(&self) -> impl Iterator<Item = (&T, u8)> {
+        let include = self.include.iter().map(|value| (value, FILTER_INCLUDE));
+        let exclude = self.exclude.iter().map(|value| (value, FILTER_EXCLUDE));
+        include.chain(exclude)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        Self {
+            ring_buffer_bytes: 65536,
+            batch_bytes: 0,
+            batch_delay: Duration::from_millis(1000),
+            cgroups: Filter::default(),
+            uids: Filter::default(),
+            comms: Filter::default(),
+        }
+    }
+}
+
+impl ProbeConfig {
+    pub fn from_env() -> Self {
+        Self::from_vars(|name| env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let defaults = Self::default();
+        let list = |name: &str| -> Vec<String> {
+            var(name)
+                .unwrap_or_default()
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_string)
+                .collect()
+        };
+        let uids = |name: &str| -> Vec<u32> {
+            list(name)
+                .into_iter()
+                .filter_map(|uid| match uid.parse() {
+                    Ok(uid) => Some(uid),
+                    Err(_) => {
+                        warn!("{}: ignoring '{}', not a uid", name, uid);
+                        None
+                    }
+                })
+                .collect()
+        };
+        Self {
+            ring_buffer_bytes: var("RING_BUFFER_BYTES")
+                .and_then(|bytes| bytes.trim().parse().ok())
+                .unwrap_or(defaults.ring_buffer_bytes),
+            batch_bytes: var("RING_BUFFER_BATCH_BYTES")
+                .and_then(|bytes| bytes.trim().parse().ok())
+                .unwrap_or(defaults.batch_bytes),
+            batch_delay: var("RING_BUFFER_BATCH_DELAY_MS")
+                .and_then(|ms| ms.trim().parse().ok())
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.batch_delay),
+            cgroups: Filter {
+                include: list("FILTER_INCLUDE_CGROUPS"),
+                exclude: list("FILTER_EXCLUDE_CGROUPS"),
+            },
+            uids: Filter {
+                include: uids("FILTER_INCLUDE_UIDS"),
+                exclude: uids("FILTER_EXCLUDE_UIDS"),
+            },
+            comms: Filter {
+                include: list("FILTER_INCLUDE_COMMS"),
+                exclude: list("FILTER_EXCLUDE_COMMS"),
+            },
+        }
+    }
+
+    /// The probe's `FILTERS` global for these lists, with cgroup paths resolved in
+    /// `hierarchy`.
+    pub fn filter_config(&self, hierarchy: Hierarchy) -> FilterConfig {
+        FilterConfig {
+            cgroups_included: !self.cgroups.include.is_empty() as u32,
+            uids_included: !self.uids.include.is_empty() as u32,
+            comms_included: !self.comms.include.is_empty() as u32,
+            memcg_hierarchy: (hierarchy == Hierarchy::MemoryV1) as u32,
+        }
+    }
+
+    /// The probe's `WAKEUP_BYTES` global for a ring buffer of `ring_buffer_bytes`: how much
+    /// data must be waiting before a kill wakes userspace, or 0 to wake on every kill.
+    pub fn wakeup_bytes(&self, ring_buffer_bytes: u32) -> u64 {
+        u64::from(self.batch_bytes.min(ring_buffer_bytes / 2))
+    }
+
+    /// How long userspace may wait on a batch before reading the ring buffer regardless;
+    /// `None` when not batching, as every kill then wakes it.
+    pub fn wakeup_deadline(&self) -> Option<Duration> {
+        (self.batch_bytes > 0).then_some(self.batch_delay)
+    }
+}
+
+/// The nearest ring buffer size the kernel accepts to `requested`: a power of two, and at
+/// least one page.
+pub fn ring_buffer_size(requested: u32, page_size: u32) -> u32 {
+    requested
+        .max(page_size)
+        .checked_next_power_of_two()
+        .unwrap_or(1 << 31)
+}
+
+/// The `COMM_FILTER` trie key matching every comm that starts with `prefix`: its length in
+/// bits and the prefix zero-padded to a comm. Longer prefixes are cut to the 15 bytes a
+/// comm holds.
+pub fn comm_key(prefix: &str) -> (u32, [u8; COMM_LEN]) {
+    let bytes = prefix.as_bytes();
+    let len = bytes.len().min(COMM_LEN - 1);
+    let mut data = [0u8; COMM_LEN];
+    data[..len].copy_from_slice(&bytes[..len]);
+    (len as u32 * 8, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn config(vars: &[(&str, &str)]) -> ProbeConfig {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        ProbeConfig::from_vars(|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn defaults_to_a_64k_ring_buffer_and_no_filters() {
+        let config = config(&[]);
+        assert_eq!(config, ProbeConfig::default());
+        assert_eq!(config.ring_buffer_bytes, 65536);
+        assert_eq!(config.filter_config(Hierarchy::Unified), FilterConfig::NONE);
+    }
+
+    #[test]
+    fn parses_comma_separated_lists() {
+        let config = config(&[
+            ("RING_BUFFER_BYTES", "262144"),
+            (
+                "FILTER_EXCLUDE_CGROUPS",
+                "/system.slice, /kubepods.slice/batch.slice,",
+            ),
+            ("FILTER_INCLUDE_UIDS", "1000,nobody,0"),
+            ("FILTER_EXCLUDE_COMMS", "kworker"),
+        ]);
+        assert_eq!(config.ring_buffer_bytes, 262144);
+        assert_eq!(
+            config.cgroups.exclude,
+            vec!["/system.slice", "/kubepods.slice/batch.slice"]
+        );
+        assert_eq!(config.uids.include, vec![1000, 0]);
+        assert_eq!(config.comms.exclude, vec!["kworker"]);
+
+        let filters = config.filter_config(Hierarchy::MemoryV1);
+        assert_eq!(filters.cgroups_included, 0);
+        assert_eq!(filters.uids_included, 1);
+        assert_eq!(filters.comms_included, 0);
+        assert_eq!(filters.memcg_hierarchy, 1);
+    }
+
+    #[test]
+    fn batches_wakeups_only_when_asked() {
+        let unbatched = config(&[]);
+        assert_eq!(unbatched.wakeup_bytes(65536), 0);
+        assert_eq!(unbatched.wakeup_deadline(), None);
+
+        let batched = config(&[
+            ("RING_BUFFER_BATCH_BYTES", "8192"),
+            ("RING_BUFFER_BATCH_DELAY_MS", "250"),
+        ]);
+        assert_eq!(batched.wakeup_bytes(65536), 8192);
+        assert_eq!(batched.wakeup_bytes(8192), 4096);
+        assert_eq!(batched.wakeup_deadline(), Some(Duration::from_millis(250)));
+    }
+
+    #[test]
+    fn lists_entries_with_their_actions() {
+        let filter = Filter {
+            include: vec![1000],
+            exclude: vec![0],
+        };
+        let entries: Vec<_> = filter.entries().collect();
+        assert_eq!(entries, vec![(&1000, FILTER_INCLUDE), (&0, FILTER_EXCLUDE)]);
+    }
+
+    #[test]
+    fn rounds_the_ring_buffer_to_a_power_of_two_pages() {
+        assert_eq!(ring_buffer_size(4096, 4096), 4096);
+        assert_eq!(ring_buffer_size(100_000, 4096), 131072);
+        assert_eq!(ring_buffer_size(1024, 16384), 16384);
+        assert_eq!(ring_buffer_size(u32::MAX, 4096), 1 << 31);
+    }
+
+    #[test]
+    fn keys_a_comm_prefix_by_its_bits() {
+        assert_eq!(comm_key("java"), (32, *b"java\0\0\0\0\0\0\0\0\0\0\0\0"));
+        let (bits, data) = comm_key("a-very-long-process-name");
+        assert_eq!(bits, 120);
+        assert_eq!(&data, b"a-very-long-pro\0");
+    }
+}
@@ -0,0 +1,101 @@
+//! Reap tracking: following each OOM victim from `oom:mark_victim` until its memory is
+//! released.
+//!
+//! A kill only helps once the victim's memory is actually freed — by the OOM reaper
+//! unmapping it, or by the victim exiting. A victim stuck in the kernel (uninterruptible
+//! sleep, a held `mmap_lock`) keeps its memory while the node stays under pressure, which
+//! is how one OOM turns into a cascade. The watch loop hands each step to
+//! [`process_event`], which records how long it took and warns about victims that held on
+//! for longer than [`SLOW_REAP`].
+
+use std::time::Duration;
+
+use log::{info, warn};
+use oom_watcher_common::{
+    ReapEvent, REAP_EXITED, REAP_FINISHED, REAP_SKIPPED, REAP_STARTED, REAP_WOKEN,
+};
+
+use crate::metrics::MetricsRecorder;
+
+/// A victim still holding its memory this long after the kill is worth a warning.
+const SLOW_REAP: Duration = Duration::from_secs(1);
+
+/// Record one step of a victim's exit against `node`, and log it.
+pub fn process_event<R: MetricsRecorder>(event: &ReapEvent, node: &str, recorder: &R) {
+    recorder.record_reap(node, event);
+    log_event(event);
+}
+
+/// The `stage` metric label of a `REAP_*` stage.
+pub fn stage_str(stage: u32) -> &'static str {
+    match stage {
+        REAP_WOKEN => "woken",
+        REAP_STARTED => "started",
+        REAP_FINISHED => "reaped",
+        REAP_SKIPPED => "skipped",
+        REAP_EXITED => "exited",
+        _ => "unknown",
+    }
+}
+
+fn log_event(event: &ReapEvent) {
+    let elapsed = Duration::from_nanos(event.elapsed_ns);
+    let released = matches!(event.stage, REAP_FINISHED | REAP_EXITED);
+    match event.stage {
+        REAP_SKIPPED => warn!(
+            "OOM reaper skipped victim PID {} after {:?}; its memory is held until it exits",
+            event.pid, elapsed
+        ),
+        _ if released && elapsed >= SLOW_REAP => warn!(
+            "OOM victim PID {} held its memory for {:?} before being {}",
+            event.pid,
+            elapsed,
+            stage_str(event.stage)
+        ),
+        _ => info!(
+            "OOM victim PID {} {} after {:?}",
+            event.pid,
+            stage_str(event.stage),
+            elapsed
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::SpyRecorder;
+
+    fn reap(stage: u32) -> ReapEvent {
+        ReapEvent {
+            pid: 1234,
+            stage,
+            elapsed_ns: 5_000_000,
+        }
+    }
+
+    #[test]
+    fn records_every_stage_against_the_node() {
+        let spy = SpyRecorder::default();
+
+        for stage in [REAP_WOKEN, REAP_FINISHED, REAP_EXITED] {
+            process_event(&reap(stage), "node-1", &spy);
+        }
+
+        assert_eq!(
+            *spy.reaps.borrow(),
+            vec![
+                ("node-1".to_string(), 1234, REAP_WOKEN),
+                ("node-1".to_string(), 1234, REAP_FINISHED),
+                ("node-1".to_string(), 1234, REAP_EXITED),
+            ]
+        );
+    }
+
+    #[test]
+    fn names_each_stage() {
+        assert_eq!(stage_str(REAP_SKIPPED), "skipped");
+        assert_eq!(stage_str(REAP_FINISHED), "reaped");
+        assert_eq!(stage_str(99), "unknown");
+    }
+}
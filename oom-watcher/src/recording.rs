@@ -0,0 +1,558 @@
+//! Recordings of what an OOM event source yields, and their replay.
+//!
+//! A recording is JSON lines: a [`Header`], then one [`Entry`] per event as the watch loop
+//! received it — a kill with the trigger stack and command its source had for it and the
+//! containers it was resolved to, a reap step, a score change, or events the source lost —
+//! stamped with the wall clock. [`RecordingSource`] tees any source to one as it runs;
+//! [`ReplaySource`] plays one back through the same seam, at its original pace or faster,
+//! and its [`ReplayResolver`] answers resolution as it was answered then, so an incident
+//! can be rerun against a local build, off the cluster it happened on. A kill missing fields a later build added replays with them
+//! unknown rather than zero, as [`OomKillEvent::unknown`] has them.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Lines, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::{error, info};
+use oom_watcher_common::{
+    ContainerIdentity, OomKillEvent, ProbeEvent, ProcessCommand, ReapEvent, ScoreAdjEvent,
+    TriggerStack,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    resolve::{ContainerResolver, ProcessRef, ResolutionOutcome},
+    watch::OomEventSource,
+};
+
+/// Version of the recording format, bumped when an older build would misread it.
+pub const RECORDING_VERSION: u32 = 1;
+
+/// The first line of a recording.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Header {
+    pub version: u32,
+    /// The node it was recorded on, when in-cluster: what replayed resolutions are for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node: Option<String>,
+}
+
+/// One line after the header.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Entry {
+    /// When it was received, in ms since the Unix epoch.
+    pub unix_ms: u64,
+    #[serde(flatten)]
+    pub event: Recorded,
+}
+
+/// What was received.
+// Moved once, from the line it was parsed from to the event it replays as; see ProbeEvent.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Recorded {
+    Kill {
+        event: OomKillEvent,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        stack: Option<TriggerStack>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        command: Option<ProcessCommand>,
+        /// The victim's container, as resolved.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        victim: Option<ContainerIdentity>,
+        /// The trigger's container, as resolved.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        trigger: Option<ContainerIdentity>,
+        /// The path of the memcg whose limit was hit, as resolved.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        oom_memcg: Option<String>,
+    },
+    Reap {
+        event: ReapEvent,
+    },
+    ScoreAdj {
+        event: ScoreAdjEvent,
+    },
+    /// Events the source lost, as counted when the watch loop asked after the one before.
+    Lost {
+        count: u64,
+    },
+}
+
+/// Tees the events of `S` to a recording as the watch loop takes them, or passes them
+/// through when not recording. A kill's stack and command are taken from `S` with it, as
+/// the loop asks for them straight after; the kill is written once the loop has told what
+/// it resolved to.
+pub struct RecordingSource<S> {
+    inner: S,
+    out: Option<BufWriter<File>>,
+    stack: Option<TriggerStack>,
+    command: Option<ProcessCommand>,
+    /// The kill taken last, until it is resolved.
+    kill: Option<Recorded>,
+}
+
+impl<S> RecordingSource<S> {
+    /// Record to `path`, if set, replacing what is there. `node` is the node resolution is
+    /// scoped to, if in-cluster.
+    pub fn new(inner: S, path: Option<&Path>, node: Option<&str>) -> io::Result<Self> {
+        let out = match path {
+            Some(path) => {
+                let mut out = BufWriter::new(File::create(path)?);
+                write_line(
+                    &mut out,
+                    &Header {
+                        version: RECORDING_VERSION,
+                        node: node.map(str::to_string),
+                    },
+                )?;
+                info!("Recording OOM events to {}", path.display());
+                Some(out)
+            }
+            None => None,
+        };
+        Ok(Self {
+            inner,
+            out,
+            stack: None,
+            command: None,
+            kill: None,
+        })
+    }
+
+    /// Write the kill taken last, resolved or not.
+    fn record_kill(&mut self) {
+        if let Some(kill) = self.kill.take() {
+            self.record(kill);
+        }
+    }
+
+    fn record(&mut self, event: Recorded) {
+        let Some(out) = self.out.as_mut() else {
+            return;
+        };
+        let entry = Entry {
+            unix_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |now| now.as_millis() as u64),
+            event,
+        };
+        if let Err(e) = write_line(out, &entry) {
+            // A full disk stops the recording, not the watcher.
+            error!("Failed to write the recording, stopping it: {}", e);
+            self.out = None;
+        }
+    }
+}
+
+impl<S> Drop for RecordingSource<S> {
+    fn drop(&mut self) {
+        self.record_kill();
+    }
+}
+
+impl<S: OomEventSource> OomEventSource for RecordingSource<S> {
+    async fn next(&mut self) -> Option<ProbeEvent> {
+        self.record_kill();
+        let event = self.inner.next().await?;
+        if self.out.is_none() {
+            return Some(event);
+        }
+        let recorded = match &event {
+            ProbeEvent::Kill(kill) => {
+                self.stack = self.inner.trigger_stack(kill).await;
+                self.command = self.inner.victim_command(kill);
+                self.kill = Some(Recorded::Kill {
+                    event: *kill,
+                    stack: self.stack.clone(),
+                    command: self.command.clone(),
+                    victim: None,
+                    trigger: None,
+                    oom_memcg: None,
+                });
+                return Some(event);
+            }
+            ProbeEvent::Reap(reap) => Recorded::Reap { event: *reap },
+            ProbeEvent::ScoreAdj(change) => Recorded::ScoreAdj { event: *change },
+        };
+        self.record(recorded);
+        Some(event)
+    }
+
+    async fn trigger_stack(&mut self, event: &OomKillEvent) -> Option<TriggerStack> {
+        match self.out {
+            Some(_) => self.stack.take(),
+            None => self.inner.trigger_stack(event).await,
+        }
+    }
+
+    fn victim_command(&mut self, event: &OomKillEvent) -> Option<ProcessCommand> {
+        match self.out {
+            Some(_) => self.command.take(),
+            None => self.inner.victim_command(event),
+        }
+    }
+
+    fn lost_events(&mut self) -> u64 {
+        let count = self.inner.lost_events();
+        if count > 0 {
+            self.record(Recorded::Lost { count });
+        }
+        count
+    }
+
+    fn skipped_records(&mut self) -> Vec<(&'static str, u64)> {
+        self.inner.skipped_records()
+    }
+
+    fn kills_seen(&mut self) -> Vec<(&'static str, u64)> {
+        self.inner.kills_seen()
+    }
+
+    fn resolved(
+        &mut self,
+        event: &OomKillEvent,
+        victim: Option<&ContainerIdentity>,
+        trigger: Option<&ContainerIdentity>,
+        oom_memcg: Option<&str>,
+    ) {
+        if let Some(Recorded::Kill {
+            victim: recorded_victim,
+            trigger: recorded_trigger,
+            oom_memcg: recorded_memcg,
+            ..
+        }) = &mut self.kill
+        {
+            *recorded_victim = victim.cloned();
+            *recorded_trigger = trigger.cloned();
+            *recorded_memcg = oom_memcg.map(str::to_string);
+        }
+        self.record_kill();
+        self.inner.resolved(event, victim, trigger, oom_memcg);
+    }
+}
+
+fn write_line<T: Serialize>(out: &mut BufWriter<File>, line: &T) -> io::Result<()> {
+    serde_json::to_writer(&mut *out, line)?;
+    out.write_all(b"\n")?;
+    // Each line as it comes, so a recording survives the watcher being killed.
+    out.flush()
+}
+
+/// Plays a recording back as an [`OomEventSource`], ending with it.
+pub struct ReplaySource {
+    lines: Lines<BufReader<File>>,
+    /// The node it was recorded on, if in-cluster.
+    node: Option<String>,
+    resolutions: Arc<Mutex<Resolutions>>,
+    /// How many times faster than recorded; 0 replays without pause.
+    speed: f64,
+    /// When the entry before was received.
+    last_ms: Option<u64>,
+    stack: Option<TriggerStack>,
+    command: Option<ProcessCommand>,
+    lost: u64,
+}
+
+impl ReplaySource {
+    pub fn open(path: &Path, speed: f64) -> anyhow::Result<Self> {
+        let mut lines = BufReader::new(File::open(path)?).lines();
+        let header: Header = match lines.next() {
+            Some(line) => serde_json::from_str(&line?)?,
+            None => anyhow::bail!("{} is empty", path.display()),
+        };
+        if header.version != RECORDING_VERSION {
+            anyhow::bail!(
+                "{} is a version {} recording; this build reads version {}",
+                path.display(),
+                header.version,
+                RECORDING_VERSION
+            );
+        }
+        info!("Replaying OOM events from {} at {}x", path.display(), speed);
+        Ok(Self {
+            lines,
+            node: header.node,
+            resolutions: Arc::default(),
+            speed,
+            last_ms: None,
+            stack: None,
+            command: None,
+            lost: 0,
+        })
+    }
+
+    /// Resolution as recorded, for the kills replayed from here on; `None` for a recording
+    /// made off-cluster, which resolved nothing.
+    pub fn resolver(&self) -> Option<ReplayResolver> {
+        Some(ReplayResolver {
+            node: self.node.clone()?,
+            resolutions: self.resolutions.clone(),
+        })
+    }
+
+    /// The next entry, or `None` at the end of the recording or a line it cannot read.
+    fn entry(&mut self) -> Option<Entry> {
+        loop {
+            let line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(e) => {
+                    error!("Failed to read the recording: {}", e);
+                    return None;
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            return match serde_json::from_str(&line) {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    error!("Failed to parse recording entry '{}': {}", line, e);
+                    None
+                }
+            };
+        }
+    }
+}
+
+impl OomEventSource for ReplaySource {
+    async fn next(&mut self) -> Option<ProbeEvent> {
+        loop {
+            let entry = self.entry()?;
+            if let Some(pause) = pause(self.last_ms, entry.unix_ms, self.speed) {
+                tokio::time::sleep(pause).await;
+            }
+            self.last_ms = Some(entry.unix_ms);
+            match entry.event {
+                Recorded::Kill {
+                    event,
+                    stack,
+                    command,
+                    victim,
+                    trigger,
+                    oom_memcg,
+                } => {
+                    self.stack = stack;
+                    self.command = command;
+                    *lock(&self.resolutions) = Resolutions {
+                        processes: [
+                            (ProcessRef::victim(&event).pid, victim),
+                            (ProcessRef::trigger(&event).pid, trigger),
+                        ]
+                        .into_iter()
+                        .filter_map(|(pid, identity)| Some((pid, identity?)))
+                        .collect(),
+                        oom_memcg: oom_memcg.map(|path| (event.oom_memcg_id, path)),
+                    };
+                    return Some(ProbeEvent::Kill(event));
+                }
+                Recorded::Reap { event } => return Some(ProbeEvent::Reap(event)),
+                Recorded::ScoreAdj { event } => return Some(ProbeEvent::ScoreAdj(event)),
+                Recorded::Lost { count } => self.lost += count,
+            }
+        }
+    }
+
+    async fn trigger_stack(&mut self, _event: &OomKillEvent) -> Option<TriggerStack> {
+        self.stack.take()
+    }
+
+    fn victim_command(&mut self, _event: &OomKillEvent) -> Option<ProcessCommand> {
+        self.command.take()
+    }
+
+    fn lost_events(&mut self) -> u64 {
+        std::mem::take(&mut self.lost)
+    }
+}
+
+/// What the kill being replayed was resolved to when recorded.
+#[derive(Default)]
+struct Resolutions {
+    /// Its victim's and trigger's containers, by pid.
+    processes: Vec<(u32, ContainerIdentity)>,
+    /// The breached memcg's id and path.
+    oom_memcg: Option<(u64, String)>,
+}
+
+fn lock(resolutions: &Mutex<Resolutions>) -> std::sync::MutexGuard<'_, Resolutions> {
+    resolutions.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The replay adapter for the Resolution seam: the kill being replayed resolves as it did
+/// when recorded, on the node it was recorded on. What was not found then is not found
+/// now, and neither is anything but a kill's victim and trigger.
+#[derive(Clone)]
+pub struct ReplayResolver {
+    node: String,
+    resolutions: Arc<Mutex<Resolutions>>,
+}
+
+impl ContainerResolver for ReplayResolver {
+    fn node_name(&self) -> &str {
+        &self.node
+    }
+
+    async fn cgroup_path(&self, cgroup_id: u64) -> Option<String> {
+        match &lock(&self.resolutions).oom_memcg {
+            Some((id, path)) if *id == cgroup_id => Some(path.clone()),
+            _ => None,
+        }
+    }
+
+    async fn resolve(&self, process: &ProcessRef) -> ResolutionOutcome {
+        let resolutions = lock(&self.resolutions);
+        match resolutions
+            .processes
+            .iter()
+            .find(|(pid, _)| *pid == process.pid)
+        {
+            Some((_, identity)) => ResolutionOutcome::Found(identity.clone()),
+            None => ResolutionOutcome::NotFound,
+        }
+    }
+}
+
+/// How long to wait before replaying an entry received at `at_ms`, the one before it at
+/// `last_ms`, `speed` times faster than recorded. `None` for the first entry, or at speed 0.
+fn pause(last_ms: Option<u64>, at_ms: u64, speed: f64) -> Option<Duration> {
+    if speed <= 0.0 {
+        return None;
+    }
+    let gap = at_ms.saturating_sub(last_ms?);
+    Some(Duration::from_secs_f64(gap as f64 / 1000.0 / speed))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use oom_watcher_common::{
+        StackFrame, CONSTRAINT_UNKNOWN, FIELD_ANON_RSS, REAP_FINISHED, STACK_UNKNOWN,
+    };
+
+    use super::*;
+    use crate::resolve::identity;
+
+    /// Yields its events, losing one before the reap.
+    struct Source(VecDeque<ProbeEvent>);
+
+    impl OomEventSource for Source {
+        async fn next(&mut self) -> Option<ProbeEvent> {
+            self.0.pop_front()
+        }
+
+        async fn trigger_stack(&mut self, _event: &OomKillEvent) -> Option<TriggerStack> {
+            Some(TriggerStack {
+                kernel: vec![StackFrame {
+                    address: 0xffff_0000,
+                    symbol: Some("out_of_memory".to_string()),
+                    offset: 0x10,
+                    module: None,
+                }],
+                user: Vec::new(),
+            })
+        }
+
+        fn lost_events(&mut self) -> u64 {
+            matches!(self.0.front(), Some(ProbeEvent::Reap(_))) as u64
+        }
+    }
+
+    #[tokio::test]
+    async fn replays_what_it_recorded() {
+        let path = std::env::temp_dir().join(format!("oom-watcher-{}.jsonl", std::process::id()));
+        let kill = OomKillEvent {
+            victim_pid: 1234,
+            victim_comm: *b"java\0\0\0\0\0\0\0\0\0\0\0\0",
+            anon_rss: 520000,
+            trigger_pid: 99,
+            oom_memcg_id: 42,
+            ..OomKillEvent::default()
+        };
+        let reap = ReapEvent {
+            pid: 1234,
+            stage: REAP_FINISHED,
+            elapsed_ns: 5_000_000,
+        };
+        let events = VecDeque::from([ProbeEvent::Kill(kill), ProbeEvent::Reap(reap)]);
+
+        let mut recording =
+            RecordingSource::new(Source(events), Some(&path), Some("node-1")).unwrap();
+        while let Some(event) = recording.next().await {
+            recording.lost_events();
+            if let ProbeEvent::Kill(kill) = &event {
+                assert!(recording.trigger_stack(kill).await.is_some());
+                recording.resolved(kill, Some(&identity()), None, Some("/kubepods/pod1"));
+            }
+        }
+        drop(recording);
+
+        let mut replay = ReplaySource::open(&path, 0.0).unwrap();
+        let resolver = replay.resolver().unwrap();
+        assert_eq!(resolver.node_name(), "node-1");
+        let Some(ProbeEvent::Kill(replayed)) = replay.next().await else {
+            panic!("no kill replayed");
+        };
+        assert_eq!(
+            resolver
+                .resolve(&ProcessRef::victim(&replayed))
+                .await
+                .identity(),
+            Some(identity())
+        );
+        assert!(matches!(
+            resolver.resolve(&ProcessRef::trigger(&replayed)).await,
+            ResolutionOutcome::NotFound
+        ));
+        assert_eq!(
+            resolver.cgroup_path(42).await.as_deref(),
+            Some("/kubepods/pod1")
+        );
+        assert_eq!(
+            (replayed.victim_pid, replayed.victim_comm, replayed.anon_rss),
+            (1234, kill.victim_comm, 520000)
+        );
+        let stack = replay.trigger_stack(&replayed).await.unwrap();
+        assert_eq!(stack.kernel[0].symbol.as_deref(), Some("out_of_memory"));
+        assert_eq!(replay.victim_command(&replayed), None);
+
+        assert!(
+            matches!(replay.next().await, Some(ProbeEvent::Reap(r)) if r.stage == REAP_FINISHED)
+        );
+        assert_eq!(replay.lost_events(), 1);
+        assert!(replay.next().await.is_none());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reads_events_missing_newer_fields() {
+        let entry: Entry =
+            serde_json::from_str(r#"{"unix_ms":1,"kind":"kill","event":{"victim_pid":7}}"#)
+                .unwrap();
+        let Recorded::Kill { event, stack, .. } = entry.event else {
+            panic!("not a kill");
+        };
+        assert_eq!(event.victim_pid, 7);
+        assert!(!event.has(FIELD_ANON_RSS));
+        assert_eq!(event.constraint, CONSTRAINT_UNKNOWN);
+        assert_eq!(event.kernel_stack_id, STACK_UNKNOWN);
+        assert!(stack.is_none());
+    }
+
+    #[test]
+    fn scales_the_recorded_gaps() {
+        assert_eq!(pause(None, 5_000, 1.0), None);
+        assert_eq!(pause(Some(1_000), 3_000, 1.0), Some(Duration::from_secs(2)));
+        assert_eq!(
+            pause(Some(1_000), 3_000, 4.0),
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(pause(Some(1_000), 3_000, 0.0), None);
+    }
+}
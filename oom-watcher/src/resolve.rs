@@ -1,40 +1,147 @@
-//! The Resolution seam: turning a killed PID into a container identity.
+//! The Resolution seam: turning a killed process into a container identity.
 //!
-//! `Resolution` is the I/O act of mapping a PID to the Kubernetes container that
-//! owned it. [`ContainerResolver`] is the seam it lives behind, and
-//! [`ResolutionOutcome`] is what crosses that seam — preserving the
-//! not-found-vs-error distinction the enrichment collapse would otherwise discard.
+//! `Resolution` is the I/O act of mapping a [`ProcessRef`] — a PID plus the cgroup ids
+//! the probe captured for it — to the Kubernetes container that owned it.
+//! [`ContainerResolver`] is the seam it lives behind, and [`ResolutionOutcome`] is what
+//! crosses that seam — preserving the not-found-vs-error distinction the enrichment
+//! collapse would otherwise discard.
 
-use oom_watcher_common::ContainerIdentity;
+use std::{fmt, sync::Arc};
 
-/// The three outcomes of resolving a PID to a container identity.
+use log::debug;
+use oom_watcher_common::{ContainerIdentity, MemcgPressure, OomKillEvent, ScoreAdjEvent};
+
+/// The process to resolve: its PID and the cgroup ids the probe read from its
+/// `task_struct` at kill time. The ids outlive the process, so resolution can still
+/// succeed after the victim is reaped and `/proc/<pid>` is gone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProcessRef {
+    pub pid: u32,
+    /// cgroup v2 id on the unified hierarchy, if the probe captured one.
+    pub cgroup_id: Option<u64>,
+    /// cgroup id on the memory controller's hierarchy (v1), if the probe captured one.
+    pub memcg_id: Option<u64>,
+}
+
+impl ProcessRef {
+    /// The task an OOM kill event says was killed. A zero id from the probe means "not
+    /// captured".
+    pub fn victim(event: &OomKillEvent) -> Self {
+        Self {
+            pid: event.victim_pid,
+            cgroup_id: captured(event.victim_cgroup_id),
+            memcg_id: captured(event.victim_memcg_id),
+        }
+    }
+
+    /// The task whose allocation invoked the OOM killer.
+    pub fn trigger(event: &OomKillEvent) -> Self {
+        Self {
+            pid: event.trigger_pid,
+            cgroup_id: captured(event.trigger_cgroup_id),
+            memcg_id: captured(event.trigger_memcg_id),
+        }
+    }
+
+    /// The task whose `oom_score_adj` was changed.
+    pub fn score_adj_target(event: &ScoreAdjEvent) -> Self {
+        Self {
+            pid: event.pid,
+            cgroup_id: captured(event.cgroup_id),
+            memcg_id: captured(event.memcg_id),
+        }
+    }
+
+    /// The last task the probe saw in a cgroup under memory pressure. Any task in the
+    /// cgroup resolves to its container; the ids do even once that task is gone.
+    pub fn under_pressure(pressure: &MemcgPressure) -> Self {
+        Self {
+            pid: pressure.last_pid,
+            cgroup_id: captured(pressure.cgroup_id),
+            memcg_id: captured(pressure.memcg_id),
+        }
+    }
+
+    /// Whether two refs are known to sit in the same cgroup, and so resolve to the same
+    /// container. Unknown ids never match.
+    pub fn same_cgroup(&self, other: &Self) -> bool {
+        let same = |a: Option<u64>, b: Option<u64>| a.is_some() && a == b;
+        same(self.cgroup_id, other.cgroup_id) || same(self.memcg_id, other.memcg_id)
+    }
+}
+
+fn captured(id: u64) -> Option<u64> {
+    (id != 0).then_some(id)
+}
+
+/// The outcomes of resolving a process to a container identity.
 ///
-/// Keeping `NotFound` and `Failed` distinct past the seam lets metrics count them
-/// separately; [`identity`](Self::identity) is where both collapse to "no identity"
-/// for enrichment.
+/// Keeping `NotFound`, `Failed` and `RuntimeMismatch` distinct past the seam lets metrics
+/// count them separately; [`identity`](Self::identity) is where all three collapse to "no
+/// identity" for enrichment.
 #[derive(Debug)]
 pub enum ResolutionOutcome {
     /// A pod on this node owns the killed process.
     Found(ContainerIdentity),
-    /// We looked but no pod matched — the process is not in a container, or it was
-    /// already reaped before we could read its cgroup.
+    /// We looked but no pod matched — the process is not in a container, or the probe
+    /// captured no cgroup and it was already reaped before we could read `/proc`.
     NotFound,
-    /// The lookup itself failed: proc read, regex, or Kubernetes API error.
+    /// The lookup itself failed: the pod cache never synced with the Kubernetes API.
     Failed(anyhow::Error),
+    /// A pod's status carries the container id, but under another runtime than the
+    /// cgroup's: not attributed, since the match cannot be trusted.
+    RuntimeMismatch(RuntimeMismatch),
+}
+
+/// A container id seen under one runtime on its cgroup path and another in its pod's
+/// status, such as a containerd id on a CRI-O node.
+#[derive(Debug)]
+pub struct RuntimeMismatch {
+    pub container_id: String,
+    /// The runtime the cgroup path names.
+    pub cgroup: &'static str,
+    /// The `<runtime>://` scheme of the status id.
+    pub status: String,
+}
+
+impl fmt::Display for RuntimeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "container {} runs on {} by its cgroup but {} by its pod's status",
+            self.container_id, self.cgroup, self.status
+        )
+    }
 }
 
+impl std::error::Error for RuntimeMismatch {}
+
 impl ResolutionOutcome {
     /// Collapse to the shape [`enrich`](crate::enrich::enrich) consumes: an identity
-    /// iff resolution found one. Both failure modes become "no identity".
+    /// iff resolution found one. Every failure mode becomes "no identity".
     pub fn identity(self) -> Option<ContainerIdentity> {
         match self {
             Self::Found(identity) => Some(identity),
-            Self::NotFound | Self::Failed(_) => None,
+            Self::NotFound | Self::Failed(_) | Self::RuntimeMismatch(_) => None,
+        }
+    }
+
+    /// [`identity`](Self::identity) for a lookup whose failures are only logged, at debug,
+    /// against `what` was being resolved — such as "trigger PID 42".
+    pub fn found_or_log(self, what: impl fmt::Display) -> Option<ContainerIdentity> {
+        match self {
+            Self::Found(identity) => return Some(identity),
+            Self::NotFound => debug!("Could not find Kubernetes info for {}", what),
+            Self::Failed(e) => debug!("Error getting Kubernetes info for {}: {}", what, e),
+            Self::RuntimeMismatch(mismatch) => {
+                debug!("Not attributing {} to a container: {}", what, mismatch)
+            }
         }
+        None
     }
 }
 
-/// The seam for Resolution: turn a killed PID into a [`ResolutionOutcome`].
+/// The seam for Resolution: turn a killed process into a [`ResolutionOutcome`].
 ///
 /// `KubernetesClient` is the in-cluster adapter; tests use a fake. The watch loop
 /// holds an `Option<impl ContainerResolver>` — `Some` iff in-cluster — which is the
@@ -46,10 +153,30 @@ pub trait ContainerResolver {
     /// The node this resolver is scoped to — known because we are in-cluster.
     fn node_name(&self) -> &str;
 
-    /// Resolve a PID to its container identity. Never surfaces an error directly;
-    /// failures are carried as [`ResolutionOutcome::Failed`] so callers handle all
-    /// three outcomes through one match.
-    async fn resolve(&self, pid: u32) -> ResolutionOutcome;
+    /// The cgroupfs path of a bare cgroup id, such as the memcg an OOM kill breached —
+    /// the input to [`scope::classify`](crate::scope::classify).
+    async fn cgroup_path(&self, cgroup_id: u64) -> Option<String>;
+
+    /// Resolve a process to its container identity. Never surfaces an error directly;
+    /// failures are carried as [`ResolutionOutcome::Failed`] or
+    /// [`ResolutionOutcome::RuntimeMismatch`] so callers handle every outcome through one
+    /// match.
+    async fn resolve(&self, process: &ProcessRef) -> ResolutionOutcome;
+}
+
+/// One resolver shared by several loops (the watch loop and the pressure loop).
+impl<T: ContainerResolver> ContainerResolver for Arc<T> {
+    fn node_name(&self) -> &str {
+        (**self).node_name()
+    }
+
+    async fn cgroup_path(&self, cgroup_id: u64) -> Option<String> {
+        (**self).cgroup_path(cgroup_id).await
+    }
+
+    async fn resolve(&self, process: &ProcessRef) -> ResolutionOutcome {
+        (**self).resolve(process).await
+    }
 }
 
 /// A second adapter for the Resolution seam — proving the seam is real, and the harness
@@ -59,6 +186,8 @@ pub(crate) enum Behavior {
     Found(ContainerIdentity),
     NotFound,
     Fail,
+    /// `Found` for the listed PIDs, `NotFound` for any other.
+    PerPid(Vec<(u32, ContainerIdentity)>),
 }
 
 #[cfg(test)]
@@ -73,28 +202,45 @@ impl ContainerResolver for FakeResolver {
         &self.node
     }
 
-    async fn resolve(&self, _pid: u32) -> ResolutionOutcome {
+    async fn cgroup_path(&self, _cgroup_id: u64) -> Option<String> {
+        None
+    }
+
+    async fn resolve(&self, process: &ProcessRef) -> ResolutionOutcome {
         match &self.behavior {
             Behavior::Found(id) => ResolutionOutcome::Found(id.clone()),
             Behavior::NotFound => ResolutionOutcome::NotFound,
             Behavior::Fail => ResolutionOutcome::Failed(anyhow::anyhow!("api down")),
+            Behavior::PerPid(ids) => ids
+                .iter()
+                .find(|(pid, _)| *pid == process.pid)
+                .map_or(ResolutionOutcome::NotFound, |(_, id)| {
+                    ResolutionOutcome::Found(id.clone())
+                }),
         }
     }
 }
 
+/// The identity tests resolve to: the `api` container of a Deployment's pod. Override
+/// what a test needs with `..identity()`.
+#[cfg(test)]
+pub(crate) fn identity() -> ContainerIdentity {
+    ContainerIdentity {
+        namespace: "prod".into(),
+        pod_name: "api-7d9".into(),
+        container_name: "api".into(),
+        container_id: "abc123".into(),
+        runtime: "containerd".into(),
+        workload_kind: "Deployment".into(),
+        workload_name: "api".into(),
+        confidence: oom_watcher_common::Confidence::Container,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    fn identity() -> ContainerIdentity {
-        ContainerIdentity {
-            namespace: "prod".into(),
-            pod_name: "api-7d9".into(),
-            container_name: "api".into(),
-            container_id: "abc123".into(),
-        }
-    }
-
     #[test]
     fn identity_is_some_only_when_found() {
         assert_eq!(
@@ -108,6 +254,59 @@ mod tests {
         );
     }
 
+    fn process() -> ProcessRef {
+        ProcessRef {
+            pid: 1234,
+            cgroup_id: None,
+            memcg_id: None,
+        }
+    }
+
+    #[test]
+    fn splits_victim_and_trigger_and_treats_zero_ids_as_unknown() {
+        let event = OomKillEvent {
+            victim_pid: 1234,
+            victim_cgroup_id: 4242,
+            trigger_pid: 77,
+            trigger_memcg_id: 99,
+            ..OomKillEvent::default()
+        };
+        assert_eq!(
+            ProcessRef::victim(&event),
+            ProcessRef {
+                pid: 1234,
+                cgroup_id: Some(4242),
+                memcg_id: None,
+            }
+        );
+        assert_eq!(
+            ProcessRef::trigger(&event),
+            ProcessRef {
+                pid: 77,
+                cgroup_id: None,
+                memcg_id: Some(99),
+            }
+        );
+    }
+
+    #[test]
+    fn unknown_cgroups_are_never_the_same() {
+        let a = ProcessRef {
+            pid: 1,
+            cgroup_id: Some(10),
+            memcg_id: None,
+        };
+        let b = ProcessRef { pid: 2, ..a };
+        let unknown = ProcessRef {
+            pid: 3,
+            cgroup_id: None,
+            memcg_id: None,
+        };
+        assert!(a.same_cgroup(&b));
+        assert!(!unknown.same_cgroup(&unknown));
+        assert!(!a.same_cgroup(&unknown));
+    }
+
     #[tokio::test]
     async fn fake_resolver_yields_each_outcome() {
         let found = FakeResolver {
@@ -116,7 +315,7 @@ mod tests {
         };
         assert_eq!(found.node_name(), "node-1");
         assert!(matches!(
-            found.resolve(1234).await,
+            found.resolve(&process()).await,
             ResolutionOutcome::Found(_)
         ));
 
@@ -125,7 +324,7 @@ mod tests {
             behavior: Behavior::NotFound,
         };
         assert!(matches!(
-            missing.resolve(1234).await,
+            missing.resolve(&process()).await,
             ResolutionOutcome::NotFound
         ));
 
@@ -134,7 +333,7 @@ mod tests {
             behavior: Behavior::Fail,
         };
         assert!(matches!(
-            broken.resolve(1234).await,
+            broken.resolve(&process()).await,
             ResolutionOutcome::Failed(_)
         ));
     }
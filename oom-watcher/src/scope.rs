@@ -0,0 +1,107 @@
+//! Scope: whose memory limit an OOM kill enforced.
+//!
+//! The kernel tells us the kill's constraint and which memcg (if any) hit its limit; what
+//! alert routing needs is the level that limit sits at. A memcg OOM in the victim's own
+//! memcg is a container limit; in a `pod…` cgroup above it, a pod limit; anywhere else
+//! (a QoS class, `kubepods`, a system slice), some other cgroup. An unconstrained OOM is
+//! the node itself running out.
+
+use oom_watcher_common::{
+    OomKillEvent, OomScope, CONSTRAINT_CPUSET, CONSTRAINT_MEMCG, CONSTRAINT_MEMORY_POLICY,
+    CONSTRAINT_NONE,
+};
+
+use crate::cgroup_path;
+
+/// Classify `event`. `oom_memcg_path` is the cgroupfs path of the memcg that hit its
+/// limit, when it could be resolved; without it a memcg OOM outside the victim's own
+/// memcg can only be placed as [`OomScope::Cgroup`].
+pub fn classify(event: &OomKillEvent, oom_memcg_path: Option<&str>) -> OomScope {
+    match event.constraint {
+        CONSTRAINT_NONE => OomScope::Global,
+        CONSTRAINT_CPUSET => OomScope::Cpuset,
+        CONSTRAINT_MEMORY_POLICY => OomScope::MemoryPolicy,
+        CONSTRAINT_MEMCG => {
+            if event.oom_memcg_id != 0 && event.oom_memcg_id == event.victim_memcg_id {
+                OomScope::Container
+            } else if oom_memcg_path.is_some_and(is_pod_cgroup) {
+                OomScope::Pod
+            } else {
+                OomScope::Cgroup
+            }
+        }
+        _ => OomScope::Unknown,
+    }
+}
+
+/// Whether a cgroup path is a pod-level cgroup: one [`cgroup_path::parse`] finds a pod
+/// in, but no container below it.
+fn is_pod_cgroup(path: &str) -> bool {
+    cgroup_path::parse(path)
+        .is_some_and(|cgroup| cgroup.pod_uid.is_some() && cgroup.container_id.is_none())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memcg_oom(oom_memcg_id: u64) -> OomKillEvent {
+        OomKillEvent {
+            constraint: CONSTRAINT_MEMCG,
+            victim_memcg_id: 30,
+            oom_memcg_id,
+            ..OomKillEvent::default()
+        }
+    }
+
+    #[test]
+    fn the_victims_own_memcg_is_a_container_limit() {
+        assert_eq!(classify(&memcg_oom(30), None), OomScope::Container);
+    }
+
+    #[test]
+    fn a_pod_cgroup_above_the_victim_is_a_pod_limit() {
+        let cgroupfs = "/kubepods/burstable/pod8f3c1a2e-5b7d-4c9e-a1f0-123456789abc";
+        let systemd = "/kubepods.slice/kubepods-burstable.slice/\
+                       kubepods-burstable-pod8f3c1a2e_5b7d_4c9e_a1f0_123456789abc.slice";
+        assert_eq!(classify(&memcg_oom(20), Some(cgroupfs)), OomScope::Pod);
+        assert_eq!(classify(&memcg_oom(20), Some(systemd)), OomScope::Pod);
+    }
+
+    #[test]
+    fn any_other_memcg_is_a_cgroup_limit() {
+        assert_eq!(
+            classify(&memcg_oom(10), Some("/kubepods/burstable")),
+            OomScope::Cgroup
+        );
+        assert_eq!(
+            classify(&memcg_oom(10), Some("/kubepods.slice")),
+            OomScope::Cgroup
+        );
+        assert_eq!(classify(&memcg_oom(10), None), OomScope::Cgroup);
+        // An uncaptured memcg never matches an uncaptured victim memcg.
+        let unknown = OomKillEvent {
+            victim_memcg_id: 0,
+            ..memcg_oom(0)
+        };
+        assert_eq!(classify(&unknown, None), OomScope::Cgroup);
+    }
+
+    #[test]
+    fn other_constraints_map_directly() {
+        let with = |constraint| OomKillEvent {
+            constraint,
+            ..OomKillEvent::default()
+        };
+        assert_eq!(classify(&with(CONSTRAINT_NONE), None), OomScope::Global);
+        assert_eq!(classify(&with(CONSTRAINT_CPUSET), None), OomScope::Cpuset);
+        assert_eq!(
+            classify(&with(CONSTRAINT_MEMORY_POLICY), None),
+            OomScope::MemoryPolicy
+        );
+        assert_eq!(
+            classify(&with(oom_watcher_common::CONSTRAINT_UNKNOWN), None),
+            OomScope::Unknown
+        );
+    }
+}
@@ -0,0 +1,113 @@
+//! Score-adj auditing: tracking writes to `oom_score_adj`.
+//!
+//! `oom_score_adj` decides which process the OOM killer picks. Entrypoint scripts and
+//! runtimes rewrite it, and a container that lowered its own score (or had another's
+//! raised) skews every later kill on the node without anything showing it. The watch loop
+//! hands each change from the `oom:oom_score_adj_update` probe to [`process_event`], which
+//! resolves it to a container through the loop's own [`ContainerResolver`] and records it.
+
+use log::info;
+use oom_watcher_common::{ContainerIdentity, ScoreAdjEvent};
+
+use crate::{
+    metrics::MetricsRecorder,
+    resolve::{ContainerResolver, ProcessRef},
+    watch::comm_str,
+};
+
+/// Resolve and record one change, and log it. Without a resolver (standalone) it is
+/// recorded with unknown labels.
+pub async fn process_event<R: MetricsRecorder>(
+    event: &ScoreAdjEvent,
+    resolver: Option<&impl ContainerResolver>,
+    recorder: &R,
+) {
+    let (node, identity) = match resolver {
+        Some(client) => (client.node_name(), resolve(client, event).await),
+        None => ("unknown", None),
+    };
+    recorder.record_score_adj(node, identity.as_ref(), event);
+    log_event(event, identity.as_ref());
+}
+
+/// Resolve the task whose score changed. Failures are only logged: an unresolved change
+/// is still recorded, and `oom_resolution_failures_total` is a rate over kills.
+async fn resolve(
+    client: &impl ContainerResolver,
+    event: &ScoreAdjEvent,
+) -> Option<ContainerIdentity> {
+    let target = ProcessRef::score_adj_target(event);
+    client
+        .resolve(&target)
+        .await
+        .found_or_log(format_args!("oom_score_adj change of PID {}", target.pid))
+}
+
+fn log_event(event: &ScoreAdjEvent, identity: Option<&ContainerIdentity>) {
+    let container = identity.map_or_else(
+        || "unknown container".to_string(),
+        |i| format!("{}/{}/{}", i.namespace, i.pod_name, i.container_name),
+    );
+    info!(
+        "oom_score_adj of {} (PID: {}) in {} set to {} by {} (PID: {})",
+        comm_str(&event.comm),
+        event.pid,
+        container,
+        event.oom_score_adj,
+        comm_str(&event.writer_comm),
+        event.writer_pid
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        metrics::SpyRecorder,
+        resolve::{identity, Behavior, FakeResolver},
+    };
+
+    fn change(pid: u32, oom_score_adj: i16) -> ScoreAdjEvent {
+        ScoreAdjEvent {
+            pid,
+            comm: *b"entrypoint.sh\0\0\0",
+            oom_score_adj,
+            writer_pid: pid,
+            ..ScoreAdjEvent::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn resolves_changes_to_their_container() {
+        let spy = SpyRecorder::default();
+        let resolver = FakeResolver {
+            node: "node-1".into(),
+            behavior: Behavior::PerPid(vec![(10, identity())]),
+        };
+
+        for event in [change(10, -999), change(20, 500)] {
+            process_event(&event, Some(&resolver), &spy).await;
+        }
+
+        assert_eq!(
+            *spy.score_adjs.borrow(),
+            vec![
+                ("node-1".to_string(), Some("api".to_string()), -999),
+                // Unresolved changes are still recorded.
+                ("node-1".to_string(), None, 500),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn standalone_records_unknown_labels() {
+        let spy = SpyRecorder::default();
+
+        process_event(&change(10, 1000), None::<&FakeResolver>, &spy).await;
+
+        assert_eq!(
+            *spy.score_adjs.borrow(),
+            vec![("unknown".to_string(), None, 1000)]
+        );
+    }
+}
@@ -1,49 +1,196 @@
 //! Adapters for the OOM event source seam.
 //!
 //! `RingBufSource` is the in-cluster adapter: it owns the entire eBPF lifecycle — bumping
-//! the memlock rlimit, loading the probe, attaching it to `oom:mark_victim`, and draining
-//! the ring buffer — and performs the single `unsafe` decode of raw bytes into an
-//! `OomKillEvent`. It is the only place `aya` is referenced, which is why `aya`/`aya-log`/
-//! `libc` are optional deps gated on the `ebpf` feature. `ParkSource` is the no-op adapter
-//! for builds without that feature.
+//! the memlock rlimit, patching the kernel layout and the `ProbeConfig` (ring buffer
+//! size, victim filters) into the probe, loading it, attaching its kill probe by the
+//! first `AttachMode` this kernel supports (plus the kprobes on `oom_kill_process` and
+//! `__oom_kill_process` that capture the OOM context and the victim's cgroups), and
+//! draining the ring buffer whenever epoll reports it readable — and hands each record to
+//! [`wire`](crate::wire) to decode: a kill (the event and the victim's command), a reap
+//! step or an `oom_score_adj` change, all on the one ring buffer. It also reads each
+//! trigger's captured stacks out of the `STACKS` map for symbolizing, and accounts for
+//! kill events the probe dropped (`LOST_EVENTS` and sequence gaps) and records it could
+//! not decode. Its `PressureMapSource` sibling polls the memcg pressure counters. This is
+//! the only place `aya` is referenced, which is why `aya`/`aya-log` are optional deps
+//! gated on the `ebpf` feature. `ParkSource` is the no-op adapter for when neither the
+//! probe nor the kernel log fallback (`KmsgSource`) can be had, and `SelectedSource` is
+//! whichever source `OOM_EVENT_SOURCE` picked and `main` could bring up: the probe, the
+//! kernel log, cgroup events, a replay, several of those merged, or parked.
 
 #[cfg(feature = "ebpf")]
 mod ebpf_source {
-    use std::time::Duration;
+    use std::{collections::BTreeMap, time::Duration};
 
     use anyhow::{anyhow, Result};
     use aya::{
         include_bytes_aligned,
-        maps::{MapData, RingBuf},
-        programs::TracePoint,
-        Ebpf,
+        maps::{
+            lpm_trie::{Key, LpmTrie},
+            HashMap, MapData, PerCpuArray, PerCpuHashMap, PerCpuValues, RingBuf, StackTraceMap,
+        },
+        programs::{BtfTracePoint, KProbe, TracePoint},
+        Btf, Ebpf, EbpfLoader, Pod,
     };
     use aya_log::EbpfLogger;
-    use log::{error, info, warn};
-    use oom_watcher_common::OomKillEvent;
+    use log::{debug, error, info, warn};
+    use oom_watcher_common::{
+        FilterConfig, KernelLayout, MemcgPressure, OomKillEvent, ProbeEvent, ProcessCommand,
+        TriggerStack, VictimCommand, OFFSET_UNKNOWN, STACK_UNKNOWN,
+    };
+    use tokio::{
+        io::unix::AsyncFd,
+        time::{interval, timeout, Interval, MissedTickBehavior},
+    };
+
+    use crate::{
+        cgroup::CgroupIndex,
+        command, layout,
+        loss::LossTracker,
+        pressure::PressureSource,
+        probe::{self, ProbeConfig},
+        stack::{self, KernelSymbols},
+        tracefs,
+        watch::OomEventSource,
+        wire::{self, Record},
+    };
 
-    use crate::watch::OomEventSource;
+    /// [`KernelLayout`] as an aya global. `oom-watcher-common` stays free of aya, so the
+    /// `Pod` marker lives on this local wrapper instead.
+    #[repr(transparent)]
+    #[derive(Clone, Copy)]
+    struct LayoutGlobal(KernelLayout);
+
+    // SAFETY: KernelLayout is #[repr(C)] and all u32s — no padding, every bit pattern valid.
+    unsafe impl Pod for LayoutGlobal {}
+
+    /// [`FilterConfig`] as an aya global, for the same reason as [`LayoutGlobal`].
+    #[repr(transparent)]
+    #[derive(Clone, Copy)]
+    struct FiltersGlobal(FilterConfig);
+
+    // SAFETY: FilterConfig is #[repr(C)] and all u32s — no padding, every bit pattern valid.
+    unsafe impl Pod for FiltersGlobal {}
+
+    /// [`MemcgPressure`] as an aya map value, for the same reason as [`LayoutGlobal`].
+    #[repr(transparent)]
+    #[derive(Clone, Copy)]
+    struct PressureValue(MemcgPressure);
+
+    // SAFETY: MemcgPressure is #[repr(C)], eight u64s then two u32s — no padding, every bit
+    // pattern valid.
+    unsafe impl Pod for PressureValue {}
 
     /// The in-cluster adapter for [`OomEventSource`]. Holds the loaded eBPF program (so the
     /// tracepoint stays attached for the source's lifetime) and owns the ring buffer.
     pub struct RingBufSource {
-        // Keeps the program attached; never read directly.
-        _bpf: Ebpf,
-        ring_buf: RingBuf<MapData>,
+        // Keeps the programs attached; only read to hand out the other ring buffers.
+        bpf: Ebpf,
+        attach_mode: AttachMode,
+        attach_target: &'static str,
+        ring_buf: AsyncFd<RingBuf<MapData>>,
+        // Set when the probe batches wakeups: the longest a kill may sit unread.
+        wakeup_deadline: Option<Duration>,
+        stacks: StackTraceMap<MapData>,
+        // Read on the first kill with a kernel stack; a node may never see one.
+        kernel_symbols: Option<KernelSymbols>,
+        lost_events: PerCpuArray<MapData, u64>,
+        loss: LossTracker,
+        // The command that came with the last kill.
+        command: Option<VictimCommand>,
+        // Records skipped since last asked, by reason.
+        skipped: BTreeMap<&'static str, u64>,
     }
 
+    /// The pressure loop's adapter for [`PressureSource`]: polls the `MEMCG_PRESSURE`
+    /// per-CPU map, taken from a [`RingBufSource`], once per interval.
+    pub struct PressureMapSource {
+        map: PerCpuHashMap<MapData, u64, PressureValue>,
+        interval: Interval,
+    }
+
+    /// How the probe sees each kill, in order of preference. Kernels differ in which they
+    /// offer: `oom:mark_victim` is missing from some builds, and passes the victim's task
+    /// only on recent ones.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum AttachMode {
+        /// `tp_btf/mark_victim`: the tracepoint, reading the victim's task_struct it is
+        /// handed.
+        BtfTracePoint,
+        /// The classic `oom:mark_victim` tracepoint, reading its record.
+        TracePoint,
+        /// A kprobe on the kernel function that kills the victim, reading its task_struct.
+        KProbe,
+    }
+
+    impl AttachMode {
+        pub fn as_str(self) -> &'static str {
+            match self {
+                AttachMode::BtfTracePoint => "tp_btf",
+                AttachMode::TracePoint => "tracepoint",
+                AttachMode::KProbe => "kprobe",
+            }
+        }
+    }
+
+    /// The kill probes `RingBufSource` tries, first to attach wins: (mode, program,
+    /// target). `__oom_kill_process` sees every task of a group kill; `oom_kill_process`,
+    /// for kernels that inline it away, only the chosen one.
+    const KILL_PROBES: [(AttachMode, &str, &str); 4] = [
+        (AttachMode::BtfTracePoint, "mark_victim_btf", "mark_victim"),
+        (AttachMode::TracePoint, "mark_victim", "oom:mark_victim"),
+        (AttachMode::KProbe, "victim_kill", "__oom_kill_process"),
+        (AttachMode::KProbe, "chosen_kill", "oom_kill_process"),
+    ];
+
     impl RingBufSource {
-        /// Bring up the probe end to end: bump the memlock rlimit, load the eBPF object,
-        /// attach to `oom:mark_victim`, and take ownership of the `EVENTS` ring buffer.
-        pub fn new() -> Result<Self> {
+        /// Bring up the probe end to end: bump the memlock rlimit, resolve the kernel
+        /// layout, load the eBPF object configured by `config`, fill its filter maps,
+        /// attach the first kill probe this kernel supports, and take ownership of the
+        /// `EVENTS` ring buffer and `STACKS` map.
+        pub fn new(config: &ProbeConfig) -> Result<Self> {
             bump_memlock_rlimit();
 
+            // Without BTF the probe still reports kills; it just cannot walk the victim's
+            // task_struct, so resolution falls back to reading /proc.
+            let mut layout = layout::load().unwrap_or_else(|e| {
+                warn!("failed to resolve kernel layout from BTF: {:#}", e);
+                warn!("victim cgroups will not be captured in-kernel");
+                KernelLayout::UNKNOWN
+            });
+            layout.page_size = page_size();
+            tracefs::mark_victim(&mut layout);
+            tracefs::reap(&mut layout);
+            tracefs::score_adj(&mut layout);
+            tracefs::reclaim_end(&mut layout);
+            let global = LayoutGlobal(layout);
+            let cgroups = CgroupIndex::detect();
+            let filters = FiltersGlobal(config.filter_config(cgroups.hierarchy()));
+            let page = match layout.page_size {
+                OFFSET_UNKNOWN => 4096,
+                page_size => page_size,
+            };
+            let ring_buffer_bytes = probe::ring_buffer_size(config.ring_buffer_bytes, page);
+            info!("Kill ring buffer: {} bytes", ring_buffer_bytes);
+            let wakeup_bytes = config.wakeup_bytes(ring_buffer_bytes);
+            if wakeup_bytes > 0 {
+                info!(
+                    "Batching kill wakeups: {} bytes or {:?}",
+                    wakeup_bytes, config.batch_delay
+                );
+            }
+            let mut loader = EbpfLoader::new();
+            loader
+                .override_global("LAYOUT", &global, true)
+                .override_global("FILTERS", &filters, true)
+                .override_global("WAKEUP_BYTES", &wakeup_bytes, true)
+                .map_max_entries("EVENTS", ring_buffer_bytes);
+
             #[cfg(debug_assertions)]
-            let mut bpf = Ebpf::load(include_bytes_aligned!(
+            let mut bpf = loader.load(include_bytes_aligned!(
                 "../../target/ebpf-subbuild/bpfel-unknown-none/release/oom-watcher-ebpf"
             ))?;
             #[cfg(not(debug_assertions))]
-            let mut bpf = Ebpf::load(include_bytes_aligned!(concat!(
+            let mut bpf = loader.load(include_bytes_aligned!(concat!(
                 env!("OUT_DIR"),
                 "/oom-watcher-ebpf-object"
             )))?;
@@ -53,54 +200,485 @@ mod ebpf_source {
                 warn!("failed to initialize eBPF logger: {}", e);
             }
 
-            let program: &mut TracePoint = bpf
-                .program_mut("mark_victim")
-                .ok_or_else(|| anyhow!("Could not find eBPF program 'mark_victim'"))?
-                .try_into()?;
+            fill_filters(&mut bpf, config, &cgroups)?;
+            let (attach_mode, attach_target) = attach_kill_probe(&mut bpf, &layout)?;
 
-            info!("Loading eBPF program (tracepoint 'oom:mark_victim')...");
-            program.load()?;
+            // Best effort: these kernel functions are static and may be inlined away on
+            // some builds. Without them events carry unknown victim cgroups / OOM context.
+            // Only the classic tracepoint needs the victim's cgroups handed over.
+            if attach_mode == AttachMode::TracePoint {
+                attach_kprobe(
+                    &mut bpf,
+                    "victim_task",
+                    "__oom_kill_process",
+                    "victim cgroups",
+                )?;
+            }
+            attach_kprobe(&mut bpf, "oom_context", "oom_kill_process", "OOM context")?;
+            attach_kprobe(
+                &mut bpf,
+                "oom_context_exit",
+                "oom_kill_process",
+                "OOM context",
+            )?;
 
-            info!("Attaching to tracepoint oom:mark_victim...");
-            if let Err(e) = program.attach("oom", "mark_victim") {
-                error!("Failed to attach to tracepoint oom:mark_victim: {}", e);
-                error!("This might mean:");
-                error!("  1. The tracepoint 'oom:mark_victim' isn't available on this kernel");
-                error!("  2. Insufficient permissions (try running as root)");
-                return Err(e.into());
+            // Best effort: without them, kills are still reported, just not followed.
+            for (category, name) in [
+                ("oom", "wake_reaper"),
+                ("oom", "start_task_reaping"),
+                ("oom", "finish_task_reaping"),
+                ("oom", "skip_task_reaping"),
+            ] {
+                attach_tracepoint(&mut bpf, name, category, name)?;
             }
-            info!("Successfully attached to tracepoint oom:mark_victim");
+            attach_tracepoint(&mut bpf, "victim_exit", "sched", "sched_process_exit")?;
+            attach_tracepoint(&mut bpf, "score_adj_update", "oom", "oom_score_adj_update")?;
+            attach_tracepoint(
+                &mut bpf,
+                "memcg_reclaim_begin",
+                "vmscan",
+                "mm_vmscan_memcg_reclaim_begin",
+            )?;
+            attach_tracepoint(
+                &mut bpf,
+                "memcg_reclaim_end",
+                "vmscan",
+                "mm_vmscan_memcg_reclaim_end",
+            )?;
+            // try_charge_memcg was try_charge before 5.14; either may be inlined.
+            attach_kretprobe(
+                &mut bpf,
+                "charge_failed",
+                &["try_charge_memcg", "try_charge"],
+                "memcg charge failures",
+            )?;
+            attach_kprobe(
+                &mut bpf,
+                "over_high",
+                "mem_cgroup_handle_over_high",
+                "memory.high throttling",
+            )?;
+            attach_kprobe(
+                &mut bpf,
+                "over_high_exit",
+                "mem_cgroup_handle_over_high",
+                "memory.high throttling",
+            )?;
 
             let map = bpf
                 .take_map("EVENTS")
                 .ok_or_else(|| anyhow!("Could not find eBPF map 'EVENTS'"))?;
-            let ring_buf = RingBuf::try_from(map)?;
+            let ring_buf = AsyncFd::new(RingBuf::try_from(map)?)?;
+            let map = bpf
+                .take_map("STACKS")
+                .ok_or_else(|| anyhow!("Could not find eBPF map 'STACKS'"))?;
+            let stacks = StackTraceMap::try_from(map)?;
+            let map = bpf
+                .take_map("LOST_EVENTS")
+                .ok_or_else(|| anyhow!("Could not find eBPF map 'LOST_EVENTS'"))?;
+            let lost_events = PerCpuArray::try_from(map)?;
 
             Ok(Self {
-                _bpf: bpf,
+                bpf,
+                attach_mode,
+                attach_target,
                 ring_buf,
+                wakeup_deadline: config.wakeup_deadline(),
+                stacks,
+                kernel_symbols: None,
+                lost_events,
+                loss: LossTracker::default(),
+                command: None,
+                skipped: BTreeMap::new(),
+            })
+        }
+
+        /// The kill probe that attached: its mode and the tracepoint or function it is on.
+        pub fn attach_mode(&self) -> (AttachMode, &'static str) {
+            (self.attach_mode, self.attach_target)
+        }
+
+        /// Take the memcg pressure counters, for the pressure loop, polled every `period`.
+        /// Callable once.
+        pub fn pressure_source(&mut self, period: Duration) -> Result<PressureMapSource> {
+            let map = self
+                .bpf
+                .take_map("MEMCG_PRESSURE")
+                .ok_or_else(|| anyhow!("Could not find eBPF map 'MEMCG_PRESSURE'"))?;
+            let mut interval = interval(period);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            Ok(PressureMapSource {
+                map: PerCpuHashMap::try_from(map)?,
+                interval,
             })
         }
+
+        /// The instruction pointers of stack `id`, innermost first; empty if it was not
+        /// captured or has since been overwritten by another.
+        fn stack_addresses(&self, id: i32) -> Vec<u64> {
+            if id == STACK_UNKNOWN {
+                return Vec::new();
+            }
+            match self.stacks.get(&(id as u32), 0) {
+                Ok(trace) => trace.frames().iter().map(|frame| frame.ip).collect(),
+                Err(e) => {
+                    debug!("Failed to read stack {}: {}", id, e);
+                    Vec::new()
+                }
+            }
+        }
     }
 
     impl OomEventSource for RingBufSource {
-        async fn next(&mut self) -> Option<OomKillEvent> {
-            // Drain everything currently available, then poll. A real probe never ends, so
-            // this never returns None; the watch loop is stopped by aborting its task.
-            loop {
-                while let Some(item) = self.ring_buf.next() {
-                    let data: &[u8] = &item;
-                    if data.len() >= core::mem::size_of::<OomKillEvent>() {
-                        // The eBPF side writes exactly one #[repr(C)] OomKillEvent per entry.
-                        let ptr = data.as_ptr() as *const OomKillEvent;
-                        let event = unsafe { ptr.read_unaligned() };
-                        return Some(event);
+        async fn next(&mut self) -> Option<ProbeEvent> {
+            let record =
+                next_record(&mut self.ring_buf, self.wakeup_deadline, &mut self.skipped).await;
+            let event = match record {
+                Record::Kill(record) => record,
+                Record::Reap(event) => return Some(ProbeEvent::Reap(event)),
+                Record::ScoreAdj(event) => return Some(ProbeEvent::ScoreAdj(event)),
+            };
+            self.command = Some(event.command);
+            let event = event.event;
+            let missing = self.loss.observe(event.cpu, event.sequence);
+            if missing > 0 {
+                debug!(
+                    "{} kill events from CPU {} lost before sequence {}",
+                    missing, event.cpu, event.sequence
+                );
+            }
+            Some(ProbeEvent::Kill(event))
+        }
+
+        fn lost_events(&mut self) -> u64 {
+            match self.lost_events.get(&0, 0) {
+                Ok(per_cpu) => self.loss.set_dropped(per_cpu.iter().sum()),
+                Err(e) => debug!("Failed to read LOST_EVENTS: {}", e),
+            }
+            self.loss.take_lost()
+        }
+
+        fn skipped_records(&mut self) -> Vec<(&'static str, u64)> {
+            std::mem::take(&mut self.skipped).into_iter().collect()
+        }
+
+        async fn trigger_stack(&mut self, event: &OomKillEvent) -> Option<TriggerStack> {
+            let kernel = self.stack_addresses(event.kernel_stack_id);
+            let user = self.stack_addresses(event.user_stack_id);
+            if kernel.is_empty() && user.is_empty() {
+                return None;
+            }
+            let kernel_symbols = self.kernel_symbols.get_or_insert_with(KernelSymbols::load);
+            let kernel = kernel
+                .into_iter()
+                .map(|ip| kernel_symbols.frame(ip))
+                .collect();
+            // Parsing the trigger's binaries can take a while; not on the loop's thread.
+            let tgid = event.trigger_tgid;
+            let user = tokio::task::spawn_blocking(move || stack::user_frames(tgid, &user))
+                .await
+                .unwrap_or_else(|e| {
+                    warn!("Failed to symbolize the user stack of PID {}: {}", tgid, e);
+                    Vec::new()
+                });
+            Some(TriggerStack { kernel, user })
+        }
+
+        fn victim_command(&mut self, event: &OomKillEvent) -> Option<ProcessCommand> {
+            let mut decoded = self.command.take().and_then(|c| command::decode(&c));
+            // argv the probe could not reach may still be in /proc, if the victim is not
+            // yet reaped.
+            if decoded.as_ref().is_none_or(|c| c.args.is_empty()) {
+                let args = command::proc_args(event.victim_pid);
+                if !args.is_empty() {
+                    decoded.get_or_insert_with(ProcessCommand::default).args = args;
+                }
+            }
+            decoded
+        }
+    }
+
+    impl PressureSource for PressureMapSource {
+        async fn next(&mut self) -> Option<Vec<MemcgPressure>> {
+            self.interval.tick().await;
+            let snapshot = self
+                .map
+                .iter()
+                .filter_map(|entry| match entry {
+                    Ok((_, per_cpu)) => Some(sum_cpus(&per_cpu)),
+                    Err(e) => {
+                        warn!("Failed to read memcg pressure entry: {}", e);
+                        None
                     }
-                    warn!("Received short event: {} bytes", data.len());
+                })
+                .collect();
+            Some(snapshot)
+        }
+    }
+
+    /// One cgroup's counters across all CPUs. CPUs that never saw the cgroup hold zeros;
+    /// the ids and last task come from any CPU that did.
+    fn sum_cpus(per_cpu: &PerCpuValues<PressureValue>) -> MemcgPressure {
+        per_cpu.iter().fold(MemcgPressure::default(), |sum, cpu| {
+            let cpu = &cpu.0;
+            let seen = cpu.cgroup_id != 0 || cpu.memcg_id != 0;
+            MemcgPressure {
+                cgroup_id: sum.cgroup_id.max(cpu.cgroup_id),
+                memcg_id: sum.memcg_id.max(cpu.memcg_id),
+                reclaims: sum.reclaims + cpu.reclaims,
+                reclaim_ns: sum.reclaim_ns + cpu.reclaim_ns,
+                reclaimed_pages: sum.reclaimed_pages + cpu.reclaimed_pages,
+                charge_failures: sum.charge_failures + cpu.charge_failures,
+                high_throttles: sum.high_throttles + cpu.high_throttles,
+                high_throttle_ns: sum.high_throttle_ns + cpu.high_throttle_ns,
+                last_pid: if seen { cpu.last_pid } else { sum.last_pid },
+                last_tgid: if seen { cpu.last_tgid } else { sum.last_tgid },
+            }
+        })
+    }
+
+    /// The next record from `ring_buf` that decodes, counting those that do not in
+    /// `skipped`. Drains everything currently available, then sleeps until epoll reports
+    /// the ring buffer readable — or, when the probe batches wakeups, until `deadline`
+    /// passes, as a record short of a batch wakes nobody. A real probe never ends, so this
+    /// never returns; the loop reading it is stopped by aborting its task.
+    async fn next_record(
+        ring_buf: &mut AsyncFd<RingBuf<MapData>>,
+        deadline: Option<Duration>,
+        skipped: &mut BTreeMap<&'static str, u64>,
+    ) -> Record {
+        loop {
+            if let Some(record) = read_record(ring_buf.get_mut(), skipped) {
+                return record;
+            }
+            let readable = ring_buf.readable_mut();
+            let ready = match deadline {
+                Some(deadline) => match timeout(deadline, readable).await {
+                    Ok(ready) => ready,
+                    // Read whatever the batch holds so far.
+                    Err(_) => continue,
+                },
+                None => readable.await,
+            };
+            match ready {
+                // Cleared before the next drain, so a record landing after it raises
+                // readiness anew rather than being slept through.
+                Ok(mut guard) => guard.clear_ready(),
+                Err(e) => {
+                    warn!("Failed to wait on ring buffer: {}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+            }
+        }
+    }
+
+    /// The first record waiting in `ring_buf` that decodes.
+    fn read_record(
+        ring_buf: &mut RingBuf<MapData>,
+        skipped: &mut BTreeMap<&'static str, u64>,
+    ) -> Option<Record> {
+        while let Some(item) = ring_buf.next() {
+            match wire::decode(&item) {
+                Ok(record) => return Some(record),
+                Err(skip) => {
+                    debug!("Skipped a ring buffer record: {:?}", skip);
+                    *skipped.entry(skip.as_str()).or_default() += 1;
+                }
+            }
+        }
+        None
+    }
+
+    /// Write `config`'s filter lists into the probe's maps, cgroup paths resolved to ids
+    /// through `cgroups`. A path that does not exist is skipped with a warning; a list too
+    /// long for its map fails startup.
+    fn fill_filters(bpf: &mut Ebpf, config: &ProbeConfig, cgroups: &CgroupIndex) -> Result<()> {
+        let mut map: HashMap<_, u64, u8> = HashMap::try_from(
+            bpf.map_mut("CGROUP_FILTER")
+                .ok_or_else(|| anyhow!("Could not find eBPF map 'CGROUP_FILTER'"))?,
+        )?;
+        for (path, action) in config.cgroups.entries() {
+            match cgroups.id(path) {
+                Ok(id) => map.insert(id, action, 0)?,
+                Err(e) => warn!("Not filtering on cgroup {}: {}", path, e),
+            }
+        }
+
+        let mut map: HashMap<_, u32, u8> = HashMap::try_from(
+            bpf.map_mut("UID_FILTER")
+                .ok_or_else(|| anyhow!("Could not find eBPF map 'UID_FILTER'"))?,
+        )?;
+        for (uid, action) in config.uids.entries() {
+            map.insert(uid, action, 0)?;
+        }
+
+        let mut map: LpmTrie<_, [u8; 16], u8> = LpmTrie::try_from(
+            bpf.map_mut("COMM_FILTER")
+                .ok_or_else(|| anyhow!("Could not find eBPF map 'COMM_FILTER'"))?,
+        )?;
+        for (prefix, action) in config.comms.entries() {
+            let (bits, data) = probe::comm_key(prefix);
+            map.insert(&Key::new(bits, data), action, 0)?;
+        }
+
+        if !(config.cgroups.is_empty() && config.uids.is_empty() && config.comms.is_empty()) {
+            info!(
+                "Filtering kills in-kernel: cgroups {:?}, uids {:?}, comms {:?}",
+                config.cgroups, config.uids, config.comms
+            );
+        }
+        Ok(())
+    }
+
+    /// Attach the first of [`KILL_PROBES`] this kernel supports. Without any, the watcher
+    /// would see no kills at all, so that is the one attach failure that fails startup.
+    fn attach_kill_probe(
+        bpf: &mut Ebpf,
+        layout: &KernelLayout,
+    ) -> Result<(AttachMode, &'static str)> {
+        for (mode, program, target) in KILL_PROBES {
+            if let Some(reason) = unsupported(mode, program, layout) {
+                info!("Skipping {} on {}: {}", program, target, reason);
+                continue;
+            }
+            match attach_kill(bpf, mode, program, target) {
+                Ok(()) => {
+                    info!(
+                        "Attached {} to {} ({} mode)",
+                        program,
+                        target,
+                        mode.as_str()
+                    );
+                    return Ok((mode, target));
                 }
-                tokio::time::sleep(Duration::from_millis(100)).await;
+                Err(e) => warn!("Failed to attach {} to {}: {:#}", program, target, e),
             }
         }
+        error!("No OOM kill probe could be attached. This might mean:");
+        error!("  1. The kernel has neither oom:mark_victim nor a probeable OOM kill function");
+        error!("  2. Insufficient permissions (try running as root)");
+        Err(anyhow!("failed to attach any OOM kill probe"))
+    }
+
+    /// Why `program` cannot work on this kernel, judging by what its layout lacks, if so.
+    fn unsupported(mode: AttachMode, program: &str, layout: &KernelLayout) -> Option<&'static str> {
+        match mode {
+            // uid arrived with the task argument: older kernels pass only the pid.
+            AttachMode::BtfTracePoint if layout.mark_victim_uid == OFFSET_UNKNOWN => {
+                Some("mark_victim does not pass the victim's task on this kernel")
+            }
+            AttachMode::KProbe if layout.task_struct_pid == OFFSET_UNKNOWN => {
+                Some("reading the victim's task_struct needs kernel BTF")
+            }
+            AttachMode::KProbe
+                if program == "chosen_kill" && layout.oom_control_chosen == OFFSET_UNKNOWN =>
+            {
+                Some("oom_control.chosen is not in the kernel BTF")
+            }
+            _ => None,
+        }
+    }
+
+    /// Load kill probe `program` as `mode` and attach it to `target`.
+    fn attach_kill(bpf: &mut Ebpf, mode: AttachMode, program: &str, target: &str) -> Result<()> {
+        let program = bpf
+            .program_mut(program)
+            .ok_or_else(|| anyhow!("Could not find eBPF program '{}'", program))?;
+        match mode {
+            AttachMode::BtfTracePoint => {
+                let tp_btf: &mut BtfTracePoint = program.try_into()?;
+                tp_btf.load(target, &Btf::from_sys_fs()?)?;
+                tp_btf.attach()?;
+            }
+            AttachMode::TracePoint => {
+                let tracepoint: &mut TracePoint = program.try_into()?;
+                let (category, name) = target
+                    .split_once(':')
+                    .ok_or_else(|| anyhow!("tracepoint '{}' has no category", target))?;
+                tracepoint.load()?;
+                tracepoint.attach(category, name)?;
+            }
+            AttachMode::KProbe => {
+                let kprobe: &mut KProbe = program.try_into()?;
+                kprobe.load()?;
+                kprobe.attach(target, 0)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Load `program` and attach it to kernel function `function`. Only a missing or
+    /// unloadable program is an error; a failed attach is logged as losing `captures`.
+    fn attach_kprobe(bpf: &mut Ebpf, program: &str, function: &str, captures: &str) -> Result<()> {
+        let kprobe: &mut KProbe = bpf
+            .program_mut(program)
+            .ok_or_else(|| anyhow!("Could not find eBPF program '{}'", program))?
+            .try_into()?;
+        kprobe.load()?;
+        match kprobe.attach(function, 0) {
+            Ok(_) => info!("Attached {} to {}", program, function),
+            Err(e) => warn!(
+                "Failed to attach {} to {}: {}; {} will not be captured",
+                program, function, e, captures
+            ),
+        }
+        Ok(())
+    }
+
+    /// The page size the kernel's page counters count in.
+    fn page_size() -> u32 {
+        let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        u32::try_from(size).unwrap_or_else(|_| {
+            warn!("sysconf(_SC_PAGESIZE) failed; memcg usage will not be captured");
+            OFFSET_UNKNOWN
+        })
+    }
+
+    /// Load `program` and attach it to the first of `functions` the kernel has, best
+    /// effort like [`attach_kprobe`]. For functions renamed across kernel versions.
+    fn attach_kretprobe(
+        bpf: &mut Ebpf,
+        program: &str,
+        functions: &[&str],
+        captures: &str,
+    ) -> Result<()> {
+        let kprobe: &mut KProbe = bpf
+            .program_mut(program)
+            .ok_or_else(|| anyhow!("Could not find eBPF program '{}'", program))?
+            .try_into()?;
+        kprobe.load()?;
+        for function in functions {
+            match kprobe.attach(function, 0) {
+                Ok(_) => {
+                    info!("Attached {} to {}", program, function);
+                    return Ok(());
+                }
+                Err(e) => debug!("Failed to attach {} to {}: {}", program, function, e),
+            }
+        }
+        warn!(
+            "Failed to attach {} to any of {:?}; {} will not be captured",
+            program, functions, captures
+        );
+        Ok(())
+    }
+
+    /// Load `program` and attach it to tracepoint `category:name`, best effort like
+    /// [`attach_kprobe`].
+    fn attach_tracepoint(bpf: &mut Ebpf, program: &str, category: &str, name: &str) -> Result<()> {
+        let tracepoint: &mut TracePoint = bpf
+            .program_mut(program)
+            .ok_or_else(|| anyhow!("Could not find eBPF program '{}'", program))?
+            .try_into()?;
+        tracepoint.load()?;
+        match tracepoint.attach(category, name) {
+            Ok(_) => info!("Attached {} to {}:{}", program, category, name),
+            Err(e) => warn!(
+                "Failed to attach {} to {}:{}: {}; victims will not be followed through it",
+                program, category, name, e
+            ),
+        }
+        Ok(())
     }
 
     fn bump_memlock_rlimit() {
@@ -120,26 +698,383 @@ mod ebpf_source {
 #[cfg(feature = "ebpf")]
 pub use ebpf_source::RingBufSource;
 
-#[cfg(not(feature = "ebpf"))]
 mod park_source {
     use std::time::Duration;
 
-    use oom_watcher_common::OomKillEvent;
+    use oom_watcher_common::{MemcgPressure, ProbeEvent};
 
-    use crate::watch::OomEventSource;
+    use crate::{pressure::PressureSource, watch::OomEventSource};
 
-    /// A source that never yields, for builds without the `ebpf` feature: the binary still
-    /// starts (and serves metrics) but has no probe to read.
+    /// A source that never yields, for when there is nothing to read: the binary still
+    /// starts (and serves metrics) but sees no kills, or no memcg pressure without the
+    /// probe.
     pub struct ParkSource;
 
     impl OomEventSource for ParkSource {
-        async fn next(&mut self) -> Option<OomKillEvent> {
-            loop {
-                tokio::time::sleep(Duration::from_secs(3600)).await;
-            }
+        async fn next(&mut self) -> Option<ProbeEvent> {
+            park().await
+        }
+    }
+
+    impl PressureSource for ParkSource {
+        async fn next(&mut self) -> Option<Vec<MemcgPressure>> {
+            park().await
+        }
+    }
+
+    async fn park<T>() -> T {
+        loop {
+            tokio::time::sleep(Duration::from_secs(3600)).await;
         }
     }
 }
 
-#[cfg(not(feature = "ebpf"))]
 pub use park_source::ParkSource;
+
+mod selected_source {
+    use std::{env, path::PathBuf, time::Duration};
+
+    use anyhow::{bail, Context};
+    use log::warn;
+    use oom_watcher_common::{OomKillEvent, ProbeEvent, ProcessCommand, TriggerStack};
+
+    use super::ParkSource;
+    #[cfg(feature = "ebpf")]
+    use super::RingBufSource;
+    #[cfg(target_os = "linux")]
+    use crate::cgroup_events::CgroupEventsSource;
+    #[cfg(feature = "ebpf")]
+    use crate::probe::ProbeConfig;
+    use crate::{
+        kmsg::KmsgSource,
+        merged::MergedSource,
+        recording::{ReplayResolver, ReplaySource},
+        watch::OomEventSource,
+    };
+
+    /// `OOM_EVENT_SOURCE`: where kills are read from.
+    #[derive(Clone, Debug, PartialEq)]
+    pub enum SourceMode {
+        /// `auto` (the default): the best this host allows — the probe, then `/dev/kmsg`,
+        /// then the cgroup counters.
+        Auto,
+        /// `ebpf`: the probe, or fail.
+        Ebpf,
+        /// `kmsg`: the kernel log, without trying the probe.
+        Kmsg,
+        /// `cgroup`: the cgroup OOM counters alone, which need no privileges beyond
+        /// reading cgroupfs.
+        Cgroup,
+        /// `replay`: the recording at `REPLAY_FILE`, `REPLAY_SPEED` times faster than it
+        /// was recorded (default 1; 0 for no pauses).
+        Replay { path: PathBuf, speed: f64 },
+        /// A comma-separated list of `ebpf`, `kmsg` and `cgroup`: all of them at once, the
+        /// copies of a kill that arrive within `MERGE_WINDOW_MS` (default 1000) of each
+        /// other folded into one.
+        Merged {
+            modes: Vec<SourceMode>,
+            window: Duration,
+        },
+    }
+
+    impl SourceMode {
+        pub fn from_env() -> Self {
+            Self::from_vars(|name| env::var(name).ok())
+        }
+
+        fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+            let source = var("OOM_EVENT_SOURCE").unwrap_or_default();
+            if source.contains(',') {
+                let modes = source
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|name| !name.is_empty())
+                    .filter_map(|name| match name {
+                        "ebpf" => Some(Self::Ebpf),
+                        "kmsg" => Some(Self::Kmsg),
+                        "cgroup" => Some(Self::Cgroup),
+                        _ => {
+                            warn!(
+                                "OOM_EVENT_SOURCE: ignoring '{}', not a mergeable source",
+                                name
+                            );
+                            None
+                        }
+                    })
+                    .collect();
+                let window = var("MERGE_WINDOW_MS")
+                    .and_then(|ms| ms.trim().parse().ok())
+                    .map_or(Duration::from_secs(1), Duration::from_millis);
+                return Self::Merged { modes, window };
+            }
+            match source.trim() {
+                "" | "auto" => Self::Auto,
+                "ebpf" => Self::Ebpf,
+                "kmsg" => Self::Kmsg,
+                "cgroup" => Self::Cgroup,
+                "replay" => Self::Replay {
+                    path: var("REPLAY_FILE").unwrap_or_default().into(),
+                    speed: var("REPLAY_SPEED")
+                        .and_then(|speed| speed.trim().parse().ok())
+                        .unwrap_or(1.0),
+                },
+                other => {
+                    warn!("OOM_EVENT_SOURCE: ignoring '{}', not a source", other);
+                    Self::Auto
+                }
+            }
+        }
+
+        /// The `source` label of what a backend of a merged source saw.
+        fn name(&self) -> &'static str {
+            match self {
+                Self::Auto => "auto",
+                Self::Ebpf => "ebpf",
+                Self::Kmsg => "kmsg",
+                Self::Cgroup => "cgroup",
+                Self::Replay { .. } => "replay",
+                Self::Merged { .. } => "merged",
+            }
+        }
+    }
+
+    /// The kill source the watch loop runs on: the eBPF probe, the kernel log, the cgroup
+    /// counters, a recording, several of them merged, or nothing. Dispatches to it
+    /// statically, like every source.
+    // Built once and never moved after, so the probe is not boxed.
+    #[allow(clippy::large_enum_variant)]
+    pub enum SelectedSource {
+        #[cfg(feature = "ebpf")]
+        Probe(RingBufSource),
+        Kmsg(KmsgSource),
+        #[cfg(target_os = "linux")]
+        Cgroup(CgroupEventsSource),
+        Replay(ReplaySource),
+        Merged(MergedSource<SelectedSource>),
+        Park(ParkSource),
+    }
+
+    impl SelectedSource {
+        /// Bring up the source `mode` names. Only `auto` falls back, ending at parking if
+        /// nothing can be read; a named source that cannot be had fails startup.
+        pub fn open(mode: SourceMode) -> anyhow::Result<Self> {
+            match mode {
+                SourceMode::Auto => Ok(Self::auto()),
+                #[cfg(feature = "ebpf")]
+                SourceMode::Ebpf => Ok(Self::Probe(RingBufSource::new(&ProbeConfig::from_env())?)),
+                #[cfg(not(feature = "ebpf"))]
+                SourceMode::Ebpf => bail!("OOM_EVENT_SOURCE=ebpf, but built without the probe"),
+                SourceMode::Kmsg => Ok(Self::Kmsg(
+                    KmsgSource::open().context("Failed to open /dev/kmsg")?,
+                )),
+                #[cfg(target_os = "linux")]
+                SourceMode::Cgroup => Ok(Self::Cgroup(
+                    CgroupEventsSource::open().context("Failed to watch cgroup OOM counters")?,
+                )),
+                #[cfg(not(target_os = "linux"))]
+                SourceMode::Cgroup => bail!("OOM_EVENT_SOURCE=cgroup needs Linux"),
+                SourceMode::Replay { path, speed } => Ok(Self::Replay(
+                    ReplaySource::open(&path, speed)
+                        .with_context(|| format!("Failed to open recording {}", path.display()))?,
+                )),
+                SourceMode::Merged { modes, window } => {
+                    // Belt and braces: a backend that cannot be had leaves the others.
+                    let mut backends = Vec::new();
+                    for mode in modes {
+                        let name = mode.name();
+                        match Self::open(mode) {
+                            Ok(source) => backends.push((name, source)),
+                            Err(e) => warn!("Not merging in {}: {:#}", name, e),
+                        }
+                    }
+                    if backends.is_empty() {
+                        bail!("none of the sources OOM_EVENT_SOURCE lists could be brought up");
+                    }
+                    Ok(Self::Merged(MergedSource::new(backends, window)))
+                }
+            }
+        }
+
+        /// The probe, falling back to `/dev/kmsg` if it does not load (or this build has
+        /// none), then to the cgroup counters, and to parking if none can be read.
+        fn auto() -> Self {
+            #[cfg(feature = "ebpf")]
+            match RingBufSource::new(&ProbeConfig::from_env()) {
+                Ok(probe) => return Self::Probe(probe),
+                Err(e) => warn!(
+                    "Failed to bring up the eBPF probe: {:#}; falling back to the kernel log",
+                    e
+                ),
+            }
+            match KmsgSource::open() {
+                Ok(kmsg) => return Self::Kmsg(kmsg),
+                Err(e) => warn!(
+                    "Failed to open /dev/kmsg: {}; falling back to cgroup OOM counters",
+                    e
+                ),
+            }
+            #[cfg(target_os = "linux")]
+            match CgroupEventsSource::open() {
+                Ok(cgroup) => return Self::Cgroup(cgroup),
+                Err(e) => warn!("Failed to watch cgroup OOM counters: {}", e),
+            }
+            warn!("No OOM kill source could be brought up; no kills will be seen");
+            Self::Park(ParkSource)
+        }
+
+        /// How kills are seen, for `oom_probe_attach_info`: the probe's attach mode and
+        /// target, `kmsg` on `/dev/kmsg`, or `cgroup` on the counters file — one of each
+        /// for a merged source. None when they are not seen at all, or only replayed.
+        pub fn attach_modes(&mut self) -> Vec<(&'static str, &'static str)> {
+            match self {
+                #[cfg(feature = "ebpf")]
+                Self::Probe(probe) => {
+                    let (mode, target) = probe.attach_mode();
+                    vec![(mode.as_str(), target)]
+                }
+                Self::Kmsg(_) => vec![("kmsg", "/dev/kmsg")],
+                #[cfg(target_os = "linux")]
+                Self::Cgroup(cgroup) => vec![("cgroup", cgroup.target())],
+                Self::Merged(merged) => merged
+                    .backends_mut()
+                    .flat_map(|source| source.attach_modes())
+                    .collect(),
+                Self::Replay(_) | Self::Park(_) => Vec::new(),
+            }
+        }
+
+        /// Resolution as the recording being replayed answered it, if it was made in-cluster.
+        pub fn replay_resolver(&self) -> Option<ReplayResolver> {
+            match self {
+                Self::Replay(replay) => replay.resolver(),
+                _ => None,
+            }
+        }
+
+        /// The probe, on its own or merged, which also feeds the pressure loop.
+        #[cfg(feature = "ebpf")]
+        pub fn probe_mut(&mut self) -> Option<&mut RingBufSource> {
+            match self {
+                Self::Probe(probe) => Some(probe),
+                Self::Merged(merged) => merged.backends_mut().find_map(Self::probe_mut),
+                _ => None,
+            }
+        }
+    }
+
+    impl OomEventSource for SelectedSource {
+        async fn next(&mut self) -> Option<ProbeEvent> {
+            match self {
+                #[cfg(feature = "ebpf")]
+                Self::Probe(source) => source.next().await,
+                Self::Kmsg(source) => source.next().await,
+                #[cfg(target_os = "linux")]
+                Self::Cgroup(source) => source.next().await,
+                Self::Replay(source) => source.next().await,
+                // Boxed: the merged source's future holds its backends' futures.
+                Self::Merged(source) => Box::pin(source.next()).await,
+                Self::Park(source) => source.next().await,
+            }
+        }
+
+        async fn trigger_stack(&mut self, event: &OomKillEvent) -> Option<TriggerStack> {
+            match self {
+                #[cfg(feature = "ebpf")]
+                Self::Probe(source) => source.trigger_stack(event).await,
+                Self::Kmsg(source) => source.trigger_stack(event).await,
+                #[cfg(target_os = "linux")]
+                Self::Cgroup(source) => source.trigger_stack(event).await,
+                Self::Replay(source) => source.trigger_stack(event).await,
+                Self::Merged(source) => source.trigger_stack(event).await,
+                Self::Park(source) => source.trigger_stack(event).await,
+            }
+        }
+
+        fn victim_command(&mut self, event: &OomKillEvent) -> Option<ProcessCommand> {
+            match self {
+                #[cfg(feature = "ebpf")]
+                Self::Probe(source) => source.victim_command(event),
+                Self::Kmsg(source) => source.victim_command(event),
+                #[cfg(target_os = "linux")]
+                Self::Cgroup(source) => source.victim_command(event),
+                Self::Replay(source) => source.victim_command(event),
+                Self::Merged(source) => source.victim_command(event),
+                Self::Park(source) => source.victim_command(event),
+            }
+        }
+
+        fn lost_events(&mut self) -> u64 {
+            match self {
+                #[cfg(feature = "ebpf")]
+                Self::Probe(source) => source.lost_events(),
+                Self::Kmsg(source) => source.lost_events(),
+                #[cfg(target_os = "linux")]
+                Self::Cgroup(source) => source.lost_events(),
+                Self::Replay(source) => source.lost_events(),
+                Self::Merged(source) => source.lost_events(),
+                Self::Park(source) => source.lost_events(),
+            }
+        }
+
+        fn skipped_records(&mut self) -> Vec<(&'static str, u64)> {
+            match self {
+                #[cfg(feature = "ebpf")]
+                Self::Probe(source) => source.skipped_records(),
+                Self::Kmsg(source) => source.skipped_records(),
+                #[cfg(target_os = "linux")]
+                Self::Cgroup(source) => source.skipped_records(),
+                Self::Replay(source) => source.skipped_records(),
+                Self::Merged(source) => source.skipped_records(),
+                Self::Park(source) => source.skipped_records(),
+            }
+        }
+
+        fn kills_seen(&mut self) -> Vec<(&'static str, u64)> {
+            match self {
+                Self::Merged(source) => source.kills_seen(),
+                _ => Vec::new(),
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn mode(vars: &[(&str, &str)]) -> SourceMode {
+            SourceMode::from_vars(|name| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            })
+        }
+
+        #[test]
+        fn reads_the_source_mode() {
+            assert_eq!(mode(&[]), SourceMode::Auto);
+            assert_eq!(mode(&[("OOM_EVENT_SOURCE", "cgroup")]), SourceMode::Cgroup);
+            assert_eq!(mode(&[("OOM_EVENT_SOURCE", " kmsg")]), SourceMode::Kmsg);
+            assert_eq!(mode(&[("OOM_EVENT_SOURCE", "bpf")]), SourceMode::Auto);
+            assert_eq!(
+                mode(&[
+                    ("OOM_EVENT_SOURCE", "replay"),
+                    ("REPLAY_FILE", "/tmp/incident.jsonl"),
+                    ("REPLAY_SPEED", "10"),
+                ]),
+                SourceMode::Replay {
+                    path: "/tmp/incident.jsonl".into(),
+                    speed: 10.0
+                }
+            );
+            assert_eq!(
+                mode(&[("OOM_EVENT_SOURCE", "ebpf, cgroup,replay")]),
+                SourceMode::Merged {
+                    modes: vec![SourceMode::Ebpf, SourceMode::Cgroup],
+                    window: Duration::from_secs(1)
+                }
+            );
+        }
+    }
+}
+
+pub use selected_source::{SelectedSource, SourceMode};
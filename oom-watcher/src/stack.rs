@@ -0,0 +1,377 @@
+//! Symbolizing the stacks the probe captures of the allocation that triggered an OOM kill.
+//!
+//! Kernel frames resolve against `/proc/kallsyms` ([`KernelSymbols`]). User frames resolve
+//! against the trigger's own mappings ([`user_frames`]): `/proc/<pid>/maps` gives the file
+//! and offset an address falls in, and that file's ELF symbol table — or its separate
+//! debuginfo, found by build id — gives the function. Files are read through
+//! `/proc/<pid>/root`, so a container's binaries resolve from the host. Whatever cannot be
+//! resolved stays a bare address; a stack is a diagnostic, never a reason to drop a kill.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, warn};
+use object::{Object, ObjectSegment, ObjectSymbol, SymbolKind};
+use oom_watcher_common::StackFrame;
+
+const KALLSYMS: &str = "/proc/kallsyms";
+
+/// The kernel's function symbols, sorted by address.
+pub struct KernelSymbols {
+    symbols: Vec<KernelSymbol>,
+}
+
+struct KernelSymbol {
+    address: u64,
+    name: String,
+    module: Option<String>,
+}
+
+impl KernelSymbols {
+    /// Read `/proc/kallsyms`. Without `CAP_SYSLOG` the kernel reports every address as
+    /// zero, which leaves nothing to resolve against; frames then stay bare addresses.
+    pub fn load() -> Self {
+        match fs::read_to_string(KALLSYMS) {
+            Ok(text) => {
+                let symbols = Self::parse(&text);
+                if symbols.symbols.is_empty() {
+                    warn!(
+                        "{} lists no addresses; kernel stacks will not be symbolized",
+                        KALLSYMS
+                    );
+                }
+                symbols
+            }
+            Err(e) => {
+                warn!("Failed to read {}: {}", KALLSYMS, e);
+                Self {
+                    symbols: Vec::new(),
+                }
+            }
+        }
+    }
+
+    /// Parse the text of `/proc/kallsyms`, keeping function symbols only.
+    fn parse(text: &str) -> Self {
+        let mut symbols: Vec<KernelSymbol> = text
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                let address = u64::from_str_radix(fields.next()?, 16).ok()?;
+                let kind = fields.next()?;
+                let name = fields.next()?;
+                if address == 0 || !matches!(kind, "t" | "T" | "w" | "W") {
+                    return None;
+                }
+                let module = fields
+                    .next()
+                    .map(|m| m.trim_start_matches('[').trim_end_matches(']').to_string());
+                Some(KernelSymbol {
+                    address,
+                    name: name.to_string(),
+                    module,
+                })
+            })
+            .collect();
+        symbols.sort_by_key(|s| s.address);
+        Self { symbols }
+    }
+
+    /// The frame for kernel `address`: the nearest function at or below it.
+    pub fn frame(&self, address: u64) -> StackFrame {
+        let index = self.symbols.partition_point(|s| s.address <= address);
+        match index.checked_sub(1).map(|i| &self.symbols[i]) {
+            Some(symbol) => StackFrame {
+                address,
+                symbol: Some(symbol.name.clone()),
+                offset: address - symbol.address,
+                module: symbol.module.clone(),
+            },
+            None => bare(address, None),
+        }
+    }
+}
+
+/// Symbolize `addresses`, a user stack of process `pid`, against the files it has mapped.
+/// The process has to still be alive; if it is gone every frame stays a bare address.
+/// Blocks reading and parsing whole files, so is run on the blocking pool.
+pub fn user_frames(pid: u32, addresses: &[u64]) -> Vec<StackFrame> {
+    let proc = PathBuf::from(format!("/proc/{}", pid));
+    let mappings = match fs::read_to_string(proc.join("maps")) {
+        Ok(text) => parse_maps(&text),
+        Err(e) => {
+            debug!("Failed to read mappings of PID {}: {}", pid, e);
+            Vec::new()
+        }
+    };
+
+    // A stack usually walks a handful of files many times over; parse each once.
+    let mut files: HashMap<&str, Option<ElfSymbols>> = HashMap::new();
+    addresses
+        .iter()
+        .map(|&address| {
+            let mapping = match mappings
+                .iter()
+                .find(|m| (m.start..m.end).contains(&address))
+            {
+                Some(mapping) => mapping,
+                None => return bare(address, None),
+            };
+            let module = Some(module_name(&mapping.path));
+            if !mapping.path.starts_with('/') {
+                // [vdso], [heap], an anonymous JIT region: nothing to read symbols from.
+                return bare(address, module);
+            }
+            let elf = files
+                .entry(&mapping.path)
+                .or_insert_with(|| ElfSymbols::load(&proc.join("root"), &mapping.path));
+            let file_offset = address - mapping.start + mapping.offset;
+            match elf.as_ref().and_then(|elf| elf.lookup(file_offset)) {
+                Some((name, offset)) => StackFrame {
+                    address,
+                    symbol: Some(name.to_string()),
+                    offset,
+                    module,
+                },
+                None => bare(address, module),
+            }
+        })
+        .collect()
+}
+
+fn bare(address: u64, module: Option<String>) -> StackFrame {
+    StackFrame {
+        address,
+        symbol: None,
+        offset: 0,
+        module,
+    }
+}
+
+fn module_name(path: &str) -> String {
+    path.rsplit('/').next().unwrap_or(path).to_string()
+}
+
+/// One line of `/proc/<pid>/maps`.
+#[derive(Debug, PartialEq, Eq)]
+struct Mapping {
+    start: u64,
+    end: u64,
+    offset: u64, // Offset into the file of `start`
+    path: String,
+}
+
+fn parse_maps(text: &str) -> Vec<Mapping> {
+    text.lines()
+        .filter_map(|line| {
+            // start-end perms offset dev inode [path], the path padded and possibly spaced.
+            let mut fields = line.splitn(6, ' ');
+            let (start, end) = fields.next()?.split_once('-')?;
+            let _perms = fields.next()?;
+            let offset = fields.next()?;
+            let _dev = fields.next()?;
+            let _inode = fields.next()?;
+            let path = fields.next().unwrap_or("").trim();
+            Some(Mapping {
+                start: u64::from_str_radix(start, 16).ok()?,
+                end: u64::from_str_radix(end, 16).ok()?,
+                offset: u64::from_str_radix(offset, 16).ok()?,
+                path: path.trim_end_matches(" (deleted)").to_string(),
+            })
+        })
+        .collect()
+}
+
+/// The function symbols of one ELF file, and the load segments that place its file
+/// offsets at the addresses those symbols are given in.
+struct ElfSymbols {
+    segments: Vec<Segment>,
+    symbols: Vec<ElfSymbol>,
+}
+
+struct Segment {
+    file_offset: u64,
+    size: u64,
+    address: u64,
+}
+
+struct ElfSymbol {
+    address: u64,
+    size: u64,
+    name: String,
+}
+
+impl ElfSymbols {
+    /// Read `path`, as the process sees it, from under its `root`. A stripped file falls
+    /// back to its debuginfo under `/usr/lib/debug/.build-id`, if the image ships one.
+    fn load(root: &Path, path: &str) -> Option<Self> {
+        let data = fs::read(root.join(path.trim_start_matches('/')))
+            .map_err(|e| debug!("Failed to read {}: {}", path, e))
+            .ok()?;
+        let file = object::File::parse(&*data)
+            .map_err(|e| debug!("Failed to parse {} as ELF: {}", path, e))
+            .ok()?;
+
+        let segments = file
+            .segments()
+            .map(|segment| {
+                let (file_offset, size) = segment.file_range();
+                Segment {
+                    file_offset,
+                    size,
+                    address: segment.address(),
+                }
+            })
+            .collect();
+        let mut symbols = function_symbols(&file);
+        if symbols.is_empty() {
+            if let Ok(Some(build_id)) = file.build_id() {
+                symbols = debuginfo_symbols(root, build_id);
+            }
+        }
+        Some(Self { segments, symbols })
+    }
+
+    /// The function containing `file_offset`, and the offset into it.
+    fn lookup(&self, file_offset: u64) -> Option<(&str, u64)> {
+        let segment = self
+            .segments
+            .iter()
+            .find(|s| (s.file_offset..s.file_offset + s.size).contains(&file_offset))?;
+        let address = file_offset - segment.file_offset + segment.address;
+        let index = self.symbols.partition_point(|s| s.address <= address);
+        let symbol = &self.symbols[index.checked_sub(1)?];
+        // A sized symbol must cover the address; an unsized one is the nearest below.
+        if symbol.size != 0 && address >= symbol.address + symbol.size {
+            return None;
+        }
+        Some((&symbol.name, address - symbol.address))
+    }
+}
+
+/// The file's function symbols, from `.symtab` and `.dynsym`, sorted by address.
+fn function_symbols(file: &object::File) -> Vec<ElfSymbol> {
+    let mut symbols: Vec<ElfSymbol> = file
+        .symbols()
+        .chain(file.dynamic_symbols())
+        .filter(|s| s.kind() == SymbolKind::Text && s.address() != 0)
+        .filter_map(|s| {
+            Some(ElfSymbol {
+                address: s.address(),
+                size: s.size(),
+                name: s.name().ok()?.to_string(),
+            })
+        })
+        .collect();
+    symbols.sort_by_key(|s| s.address);
+    symbols.dedup_by_key(|s| s.address);
+    symbols
+}
+
+fn debuginfo_symbols(root: &Path, build_id: &[u8]) -> Vec<ElfSymbol> {
+    let hex: String = build_id.iter().map(|b| format!("{:02x}", b)).collect();
+    if hex.len() < 3 {
+        return Vec::new();
+    }
+    let path = root.join(format!(
+        "usr/lib/debug/.build-id/{}/{}.debug",
+        &hex[..2],
+        &hex[2..]
+    ));
+    let data = match fs::read(&path) {
+        Ok(data) => data,
+        Err(_) => return Vec::new(),
+    };
+    match object::File::parse(&*data) {
+        Ok(file) => function_symbols(&file),
+        Err(e) => {
+            debug!("Failed to parse {}: {}", path.display(), e);
+            Vec::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KALLSYMS: &str = "\
+ffffffff81000000 T _stext
+ffffffff81234500 T handle_mm_fault
+ffffffff81234a00 t __handle_mm_fault
+ffffffff81300000 D some_data
+ffffffffc0a01000 t nvme_queue_rq\t[nvme]
+";
+
+    #[test]
+    fn resolves_kernel_addresses_to_the_nearest_function() {
+        let symbols = KernelSymbols::parse(KALLSYMS);
+
+        let frame = symbols.frame(0xffffffff81234a1c);
+        assert_eq!(frame.symbol.as_deref(), Some("__handle_mm_fault"));
+        assert_eq!(frame.offset, 0x1c);
+        assert_eq!(frame.to_string(), "__handle_mm_fault+0x1c");
+        // Data symbols are not functions; the address stays in the one before.
+        assert_eq!(
+            symbols.frame(0xffffffff81300010).symbol.as_deref(),
+            Some("__handle_mm_fault")
+        );
+        assert_eq!(
+            symbols.frame(0xffffffffc0a01008).to_string(),
+            "nvme_queue_rq+0x8 [nvme]"
+        );
+        assert_eq!(symbols.frame(0x1000).to_string(), "0x1000");
+    }
+
+    #[test]
+    fn restricted_kallsyms_resolves_nothing() {
+        let symbols = KernelSymbols::parse("0000000000000000 T _stext\n0000000000000000 T foo\n");
+        assert_eq!(symbols.frame(0xffffffff81000000).symbol, None);
+    }
+
+    #[test]
+    fn parses_mappings_with_and_without_files() {
+        let maps = "\
+55d0c0a00000-55d0c0a21000 r-xp 00001000 08:01 1234                       /usr/bin/my app
+7ffd1e3f0000-7ffd1e3f2000 r-xp 00000000 00:00 0                          [vdso]
+7f0000000000-7f0000001000 rw-p 00000000 00:00 0
+7f1c2a000000-7f1c2a021000 r-xp 00028000 08:01 99                         /lib/libc.so.6 (deleted)
+";
+        let mappings = parse_maps(maps);
+
+        assert_eq!(
+            mappings[0],
+            Mapping {
+                start: 0x55d0c0a00000,
+                end: 0x55d0c0a21000,
+                offset: 0x1000,
+                path: "/usr/bin/my app".into(),
+            }
+        );
+        assert_eq!(mappings[1].path, "[vdso]");
+        assert_eq!(mappings[2].path, "");
+        assert_eq!(mappings[3].path, "/lib/libc.so.6");
+    }
+
+    #[inline(never)]
+    fn marker_function() -> u64 {
+        std::hint::black_box(42)
+    }
+
+    #[test]
+    fn symbolizes_a_function_of_this_process() {
+        let address = marker_function as *const () as u64 + 1;
+
+        let frames = user_frames(std::process::id(), &[address, 8]);
+
+        let symbol = frames[0].symbol.as_deref().unwrap_or_default();
+        assert!(symbol.contains("marker_function"), "got {}", frames[0]);
+        assert_eq!(frames[0].offset, 1);
+        // An unmapped address stays bare.
+        assert_eq!(frames[1].symbol, None);
+        assert_eq!(frames[1].module, None);
+    }
+}
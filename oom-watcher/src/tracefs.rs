@@ -0,0 +1,391 @@
+//! Tracepoint layout: where each field of a tracepoint's record lives, from its tracefs
+//! format file.
+//!
+//! A tracepoint's record is a kernel-internal struct that grows fields between versions —
+//! `oom:mark_victim` long carried only the victim's pid — so the probe cannot bake in its
+//! offsets. [`mark_victim`] parses `events/oom/mark_victim/format` at load time and
+//! fills the `mark_victim_*` offsets of the [`KernelLayout`] the loader patches into the
+//! probe; [`reap`], [`score_adj`] and [`reclaim_end`] do the same for the tracepoints a
+//! victim's reaping, `oom_score_adj` writes and memcg reclaim are seen through. A field that is missing, or not the size the probe reads, stays
+//! [`OFFSET_UNKNOWN`] and the probe reports it as unknown, or skips the event.
+
+use std::{collections::HashMap, fs};
+
+use anyhow::{Context, Result};
+use log::warn;
+use oom_watcher_common::{KernelLayout, OFFSET_UNKNOWN};
+
+/// Where tracefs is mounted: its own mount on current kernels, under debugfs on old ones.
+const TRACEFS_ROOTS: [&str; 2] = ["/sys/kernel/tracing", "/sys/kernel/debug/tracing"];
+
+/// The OOM reaper's tracepoints, in category `oom`.
+const REAPER_TRACEPOINTS: [&str; 4] = [
+    "wake_reaper",
+    "start_task_reaping",
+    "finish_task_reaping",
+    "skip_task_reaping",
+];
+
+/// `pid` has led the record, straight after the 8-byte common header, since the
+/// tracepoint was added. Used only when the format file cannot be read.
+const MARK_VICTIM_PID_FALLBACK: u32 = 8;
+
+/// One `field:` line of a format file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Field {
+    pub offset: u32,
+    pub size: u32,
+    /// A `__data_loc` field: the record holds a (length << 16 | offset) locator of the
+    /// data, which follows the fixed fields.
+    pub data_loc: bool,
+}
+
+/// The fields of one tracepoint's record, by name.
+pub struct Format {
+    fields: HashMap<String, Field>,
+}
+
+impl Format {
+    /// Read the format file of tracepoint `category:name` from the first tracefs mount
+    /// that has it.
+    pub fn load(category: &str, name: &str) -> Result<Self> {
+        let mut last_error = None;
+        for root in TRACEFS_ROOTS {
+            let path = format!("{}/events/{}/{}/format", root, category, name);
+            match fs::read_to_string(&path).with_context(|| format!("reading {}", path)) {
+                Ok(text) => return Ok(Self::parse(&text)),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.expect("TRACEFS_ROOTS is not empty"))
+    }
+
+    /// Parse the text of a format file. Lines that are not fields are skipped.
+    pub fn parse(text: &str) -> Self {
+        let fields = text
+            .lines()
+            .filter_map(|line| {
+                // field:unsigned long total_vm;	offset:16;	size:8;	signed:0;
+                let mut parts = line.trim().split(';').map(str::trim);
+                let declaration = parts.next()?.strip_prefix("field:")?;
+                let mut offset = None;
+                let mut size = None;
+                for part in parts {
+                    if let Some(value) = part.strip_prefix("offset:") {
+                        offset = value.parse().ok();
+                    } else if let Some(value) = part.strip_prefix("size:") {
+                        size = value.parse().ok();
+                    }
+                }
+                // The name is the declaration's last word, less any array suffix.
+                let name = declaration.rsplit(' ').next()?;
+                let name = name.split('[').next().unwrap_or(name);
+                let field = Field {
+                    offset: offset?,
+                    size: size?,
+                    data_loc: declaration.starts_with("__data_loc"),
+                };
+                Some((name.to_string(), field))
+            })
+            .collect();
+        Self { fields }
+    }
+
+    pub fn field(&self, name: &str) -> Option<&Field> {
+        self.fields.get(name)
+    }
+
+    /// The offset of `name` if it is a plain field of `size` bytes, else
+    /// [`OFFSET_UNKNOWN`].
+    fn offset(&self, name: &str, size: u32) -> u32 {
+        match self.field(name) {
+            Some(field) if !field.data_loc && field.size == size => field.offset,
+            _ => OFFSET_UNKNOWN,
+        }
+    }
+
+    /// The offset of `name` if it is a `__data_loc` field, else [`OFFSET_UNKNOWN`].
+    fn data_loc_offset(&self, name: &str) -> u32 {
+        match self.field(name) {
+            Some(field) if field.data_loc && field.size == 4 => field.offset,
+            _ => OFFSET_UNKNOWN,
+        }
+    }
+}
+
+/// Fill `layout`'s `mark_victim_*` offsets from the running kernel's `oom:mark_victim`
+/// format. If it cannot be read, only the pid — whose place has never moved — is known.
+pub fn mark_victim(layout: &mut KernelLayout) {
+    match Format::load("oom", "mark_victim") {
+        Ok(format) => apply_mark_victim(layout, &format),
+        Err(e) => {
+            warn!("failed to read the oom:mark_victim format: {:#}", e);
+            warn!("victim memory, uid and oom_score_adj will be reported as unknown");
+            layout.mark_victim_pid = MARK_VICTIM_PID_FALLBACK;
+        }
+    }
+}
+
+/// Fill `layout`'s `mark_victim_*` offsets from `format`, checking each field has the size
+/// the probe reads it as.
+pub fn apply_mark_victim(layout: &mut KernelLayout, format: &Format) {
+    let long = size_of::<std::ffi::c_ulong>() as u32;
+    layout.mark_victim_pid = format.offset("pid", 4);
+    layout.mark_victim_comm = format.data_loc_offset("comm");
+    layout.mark_victim_total_vm = format.offset("total_vm", long);
+    layout.mark_victim_anon_rss = format.offset("anon_rss", long);
+    layout.mark_victim_file_rss = format.offset("file_rss", long);
+    layout.mark_victim_shmem_rss = format.offset("shmem_rss", long);
+    layout.mark_victim_uid = format.offset("uid", 4);
+    layout.mark_victim_pgtables = format.offset("pgtables", long);
+    layout.mark_victim_oom_score_adj = format.offset("oom_score_adj", 2);
+}
+
+/// Fill `layout`'s offsets for following a victim's reaping, from the running kernel's
+/// OOM reaper and `sched:sched_process_exit` formats. A tracepoint whose format cannot be
+/// read is not followed.
+pub fn reap(layout: &mut KernelLayout) {
+    let load = |category: &str, name: &str| {
+        Format::load(category, name)
+            .inspect_err(|e| warn!("failed to read the {}:{} format: {:#}", category, name, e))
+            .ok()
+    };
+    let reaper: Vec<Format> = REAPER_TRACEPOINTS
+        .iter()
+        .filter_map(|name| load("oom", name))
+        .collect();
+    let exit = load("sched", "sched_process_exit");
+    apply_reap(layout, &reaper, exit.as_ref());
+    if layout.reaper_pid == OFFSET_UNKNOWN || layout.process_exit_pid == OFFSET_UNKNOWN {
+        warn!("OOM victims will not be followed until reaped");
+    }
+}
+
+/// Fill `layout`'s `score_adj_*` offsets from the running kernel's
+/// `oom:oom_score_adj_update` format. If it cannot be read, score changes are not seen.
+pub fn score_adj(layout: &mut KernelLayout) {
+    match Format::load("oom", "oom_score_adj_update") {
+        Ok(format) => apply_score_adj(layout, &format),
+        Err(e) => {
+            warn!(
+                "failed to read the oom:oom_score_adj_update format: {:#}",
+                e
+            );
+            warn!("oom_score_adj changes will not be seen");
+        }
+    }
+}
+
+/// Fill `layout`'s `score_adj_*` offsets from `format`.
+pub fn apply_score_adj(layout: &mut KernelLayout, format: &Format) {
+    layout.score_adj_pid = format.offset("pid", 4);
+    layout.score_adj_comm = format.offset("comm", 16);
+    layout.score_adj_oom_score_adj = format.offset("oom_score_adj", 2);
+}
+
+/// Fill `layout`'s `reclaim_end_*` offset from the running kernel's
+/// `vmscan:mm_vmscan_memcg_reclaim_end` format. If it cannot be read, reclaims are still
+/// counted, but not the pages they reclaimed.
+pub fn reclaim_end(layout: &mut KernelLayout) {
+    match Format::load("vmscan", "mm_vmscan_memcg_reclaim_end") {
+        Ok(format) => apply_reclaim_end(layout, &format),
+        Err(e) => {
+            warn!(
+                "failed to read the vmscan:mm_vmscan_memcg_reclaim_end format: {:#}",
+                e
+            );
+            warn!("pages reclaimed from memcgs will not be counted");
+        }
+    }
+}
+
+/// Fill `layout`'s `reclaim_end_*` offset from `format`.
+pub fn apply_reclaim_end(layout: &mut KernelLayout, format: &Format) {
+    let long = size_of::<std::ffi::c_ulong>() as u32;
+    layout.reclaim_end_nr_reclaimed = format.offset("nr_reclaimed", long);
+}
+
+/// Fill `layout`'s reap offsets from the OOM reaper tracepoints' formats, which must agree
+/// on where their `pid` is, and `sched:sched_process_exit`'s.
+pub fn apply_reap(layout: &mut KernelLayout, reaper: &[Format], exit: Option<&Format>) {
+    let mut pids = reaper.iter().map(|format| format.offset("pid", 4));
+    layout.reaper_pid = match pids.next() {
+        Some(pid) if pids.all(|other| other == pid) => pid,
+        _ => OFFSET_UNKNOWN,
+    };
+    layout.process_exit_pid = exit.map_or(OFFSET_UNKNOWN, |format| format.offset("pid", 4));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `oom:mark_victim` on a current kernel.
+    const CURRENT: &str = "\
+name: mark_victim
+ID: 520
+format:
+\tfield:unsigned short common_type;\toffset:0;\tsize:2;\tsigned:0;
+\tfield:unsigned char common_flags;\toffset:2;\tsize:1;\tsigned:0;
+\tfield:unsigned char common_preempt_count;\toffset:3;\tsize:1;\tsigned:0;
+\tfield:int common_pid;\toffset:4;\tsize:4;\tsigned:1;
+
+\tfield:int pid;\toffset:8;\tsize:4;\tsigned:1;
+\tfield:__data_loc char[] comm;\toffset:12;\tsize:4;\tsigned:0;
+\tfield:unsigned long total_vm;\toffset:16;\tsize:8;\tsigned:0;
+\tfield:unsigned long anon_rss;\toffset:24;\tsize:8;\tsigned:0;
+\tfield:unsigned long file_rss;\toffset:32;\tsize:8;\tsigned:0;
+\tfield:unsigned long shmem_rss;\toffset:40;\tsize:8;\tsigned:0;
+\tfield:uid_t uid;\toffset:48;\tsize:4;\tsigned:0;
+\tfield:unsigned long pgtables;\toffset:56;\tsize:8;\tsigned:0;
+\tfield:short oom_score_adj;\toffset:64;\tsize:2;\tsigned:1;
+
+print fmt: \"pid=%d comm=%s\", REC->pid, __get_str(comm)
+";
+
+    /// `oom:mark_victim` on an older kernel: the pid alone.
+    const PID_ONLY: &str = "\
+name: mark_victim
+ID: 412
+format:
+\tfield:unsigned short common_type;\toffset:0;\tsize:2;\tsigned:0;
+\tfield:unsigned char common_flags;\toffset:2;\tsize:1;\tsigned:0;
+\tfield:unsigned char common_preempt_count;\toffset:3;\tsize:1;\tsigned:0;
+\tfield:int common_pid;\toffset:4;\tsize:4;\tsigned:1;
+
+\tfield:int pid;\toffset:8;\tsize:4;\tsigned:1;
+
+print fmt: \"pid=%d\", REC->pid
+";
+
+    /// An OOM reaper tracepoint, `pid` at `offset`.
+    fn reaper(offset: u32) -> Format {
+        Format::parse(&format!(
+            "name: wake_reaper\nformat:\n\tfield:int pid;\toffset:{};\tsize:4;\tsigned:1;\n",
+            offset
+        ))
+    }
+
+    /// `sched:sched_process_exit`.
+    const PROCESS_EXIT: &str = "\
+name: sched_process_exit
+ID: 310
+format:
+\tfield:unsigned short common_type;\toffset:0;\tsize:2;\tsigned:0;
+\tfield:unsigned char common_flags;\toffset:2;\tsize:1;\tsigned:0;
+\tfield:unsigned char common_preempt_count;\toffset:3;\tsize:1;\tsigned:0;
+\tfield:int common_pid;\toffset:4;\tsize:4;\tsigned:1;
+
+\tfield:char comm[16];\toffset:8;\tsize:16;\tsigned:0;
+\tfield:pid_t pid;\toffset:24;\tsize:4;\tsigned:1;
+\tfield:int prio;\toffset:28;\tsize:4;\tsigned:1;
+
+print fmt: \"comm=%s pid=%d prio=%d\", REC->comm, REC->pid, REC->prio
+";
+
+    #[test]
+    fn parses_fields_with_their_offsets_and_sizes() {
+        let format = Format::parse(CURRENT);
+        assert_eq!(
+            format.field("comm"),
+            Some(&Field {
+                offset: 12,
+                size: 4,
+                data_loc: true,
+            })
+        );
+        assert_eq!(format.field("oom_score_adj").map(|f| f.offset), Some(64));
+        assert_eq!(format.field("common_pid").map(|f| f.size), Some(4));
+        assert_eq!(format.field("name"), None);
+    }
+
+    #[test]
+    fn resolves_every_field_of_a_current_kernel() {
+        let mut layout = KernelLayout::UNKNOWN;
+        apply_mark_victim(&mut layout, &Format::parse(CURRENT));
+        assert_eq!(layout.mark_victim_pid, 8);
+        assert_eq!(layout.mark_victim_comm, 12);
+        assert_eq!(layout.mark_victim_total_vm, 16);
+        assert_eq!(layout.mark_victim_shmem_rss, 40);
+        assert_eq!(layout.mark_victim_uid, 48);
+        assert_eq!(layout.mark_victim_pgtables, 56);
+        assert_eq!(layout.mark_victim_oom_score_adj, 64);
+    }
+
+    #[test]
+    fn leaves_fields_an_older_kernel_lacks_unknown() {
+        let mut layout = KernelLayout::UNKNOWN;
+        apply_mark_victim(&mut layout, &Format::parse(PID_ONLY));
+        assert_eq!(layout.mark_victim_pid, 8);
+        assert_eq!(layout.mark_victim_comm, OFFSET_UNKNOWN);
+        assert_eq!(layout.mark_victim_anon_rss, OFFSET_UNKNOWN);
+        assert_eq!(layout.mark_victim_oom_score_adj, OFFSET_UNKNOWN);
+    }
+
+    #[test]
+    fn resolves_the_reap_tracepoints_pids() {
+        let mut layout = KernelLayout::UNKNOWN;
+        let exit = Format::parse(PROCESS_EXIT);
+        apply_reap(&mut layout, &[reaper(8), reaper(8)], Some(&exit));
+        assert_eq!(layout.reaper_pid, 8);
+        assert_eq!(layout.process_exit_pid, 24);
+
+        // Reaper tracepoints that disagree are not trusted, nor are missing formats.
+        apply_reap(&mut layout, &[reaper(8), reaper(12)], None);
+        assert_eq!(layout.reaper_pid, OFFSET_UNKNOWN);
+        assert_eq!(layout.process_exit_pid, OFFSET_UNKNOWN);
+        apply_reap(&mut layout, &[], Some(&exit));
+        assert_eq!(layout.reaper_pid, OFFSET_UNKNOWN);
+    }
+
+    #[test]
+    fn resolves_the_score_adj_update_fields() {
+        let format = Format::parse(
+            "\
+name: oom_score_adj_update
+format:
+\tfield:unsigned short common_type;\toffset:0;\tsize:2;\tsigned:0;
+\tfield:pid_t pid;\toffset:8;\tsize:4;\tsigned:1;
+\tfield:char comm[16];\toffset:12;\tsize:16;\tsigned:0;
+\tfield:short oom_score_adj;\toffset:28;\tsize:2;\tsigned:1;
+",
+        );
+        let mut layout = KernelLayout::UNKNOWN;
+        apply_score_adj(&mut layout, &format);
+        assert_eq!(
+            (
+                layout.score_adj_pid,
+                layout.score_adj_comm,
+                layout.score_adj_oom_score_adj
+            ),
+            (8, 12, 28)
+        );
+    }
+
+    #[test]
+    fn resolves_the_pages_a_reclaim_reclaimed() {
+        let format = Format::parse(
+            "\
+name: mm_vmscan_memcg_reclaim_end
+format:
+\tfield:unsigned short common_type;\toffset:0;\tsize:2;\tsigned:0;
+\tfield:unsigned long nr_reclaimed;\toffset:8;\tsize:8;\tsigned:0;
+",
+        );
+        let mut layout = KernelLayout::UNKNOWN;
+        apply_reclaim_end(&mut layout, &format);
+        assert_eq!(layout.reclaim_end_nr_reclaimed, 8);
+    }
+
+    #[test]
+    fn rejects_a_field_of_an_unexpected_size() {
+        let format = Format::parse(&CURRENT.replace(
+            "short oom_score_adj;\toffset:64;\tsize:2",
+            "int oom_score_adj;\toffset:64;\tsize:4",
+        ));
+        let mut layout = KernelLayout::UNKNOWN;
+        apply_mark_victim(&mut layout, &format);
+        assert_eq!(layout.mark_victim_oom_score_adj, OFFSET_UNKNOWN);
+        assert_eq!(layout.mark_victim_uid, 48);
+    }
+}
@@ -1,30 +1,85 @@
 //! The watch loop: the per-event pipeline that turns OOM kill events into recorded,
 //! enriched OOM events. See CONTEXT.md ("Watch loop").
 //!
-//! [`run`] owns the whole loop, pulling from an [`OomEventSource`], resolving through a
-//! [`ContainerResolver`], and reporting to a [`MetricsRecorder`]. It is generic over all
-//! three seams plus an injected clock, so the entire pipeline is the test surface: a
-//! finite source drives it to completion with no kernel and no Kubernetes.
+//! [`run`] owns the whole loop, pulling every [`ProbeEvent`] from an [`OomEventSource`] —
+//! kills, and the reap steps and `oom_score_adj` changes it hands to [`reaper`] and
+//! [`score_adj`] — resolving through a [`ContainerResolver`], and reporting to a
+//! [`MetricsRecorder`]. It is generic over all three seams plus an injected clock, so the
+//! entire pipeline is the test surface: a finite source drives it to completion with no
+//! kernel and no Kubernetes.
 
 use log::{info, warn};
-use oom_watcher_common::{EnrichedOomEvent, OomKillEvent};
+use oom_watcher_common::{
+    ContainerIdentity, EnrichedOomEvent, OomKillEvent, ProbeEvent, ProcessCommand, TriggerStack,
+    CONSTRAINT_CPUSET, CONSTRAINT_MEMCG, CONSTRAINT_MEMORY_POLICY, CONSTRAINT_NONE, FIELD_ANON_RSS,
+    FIELD_FILE_RSS, FIELD_HIWATER_RSS, FIELD_NS_PID, FIELD_OOM_SCORE_ADJ, FIELD_PARENT,
+    FIELD_PGTABLES, FIELD_SHMEM_RSS, FIELD_START_TIME, FIELD_SWAP, FIELD_THREADS, FIELD_TOTAL_VM,
+    FIELD_UID, FIELD_VICTIM_COMM, MEMCG_NO_LIMIT,
+};
 
 use crate::{
-    enrich::enrich,
+    enrich::{enrich, KillDetails},
     metrics::MetricsRecorder,
-    resolve::{ContainerResolver, ResolutionOutcome},
+    reaper,
+    resolve::{ContainerResolver, ProcessRef, ResolutionOutcome},
+    scope, score_adj,
 };
 
-/// The seam for where OOM kill events reach userspace. `next` yields whole, decoded
-/// events; `None` means the stream has ended. A real source never ends, so in production
-/// the loop runs until the task is aborted; a finite test source ends and the loop returns.
+/// The seam for where probe events reach userspace. `next` yields whole, decoded events
+/// of every kind; `None` means the stream has ended. A real source never ends, so in
+/// production the loop runs until the task is aborted; a finite test source ends and the
+/// loop returns.
 // Static dispatch only — the loop is generic over a concrete source, never `dyn`.
 #[allow(async_fn_in_trait)]
 pub trait OomEventSource {
-    async fn next(&mut self) -> Option<OomKillEvent>;
+    async fn next(&mut self) -> Option<ProbeEvent>;
+
+    /// The symbolized stacks of the allocation that triggered `event`, if the source
+    /// captured them. Asked straight after `next` yields the event, while the trigger is
+    /// most likely still alive to have its mappings read.
+    async fn trigger_stack(&mut self, _event: &OomKillEvent) -> Option<TriggerStack> {
+        None
+    }
+
+    /// The executable and arguments of `event`'s victim, if the source captured them.
+    /// Asked straight after `next` yields the event.
+    fn victim_command(&mut self, _event: &OomKillEvent) -> Option<ProcessCommand> {
+        None
+    }
+
+    /// How many events the source knows it lost — dropped before they could be delivered
+    /// — since it was last asked. Asked after each delivered event.
+    fn lost_events(&mut self) -> u64 {
+        0
+    }
+
+    /// How many records the source read but could not decode — of a wire version or kind
+    /// it does not know, or cut short — by reason, since it was last asked. Asked after
+    /// each delivered event.
+    fn skipped_records(&mut self) -> Vec<(&'static str, u64)> {
+        Vec::new()
+    }
+
+    /// Which backends saw the kill just yielded, for a source that merges several: each
+    /// with a count, as if since last asked. Asked after each delivered event.
+    fn kills_seen(&mut self) -> Vec<(&'static str, u64)> {
+        Vec::new()
+    }
+
+    /// What the kill just yielded was resolved to — its victim's and trigger's containers,
+    /// and the breached memcg's path — for a source that records it. Told once the loop
+    /// has resolved them, before it asks for the next event.
+    fn resolved(
+        &mut self,
+        _event: &OomKillEvent,
+        _victim: Option<&ContainerIdentity>,
+        _trigger: Option<&ContainerIdentity>,
+        _oom_memcg: Option<&str>,
+    ) {
+    }
 }
 
-/// Run the watch loop: drain `source`, processing each OOM kill event, until it ends.
+/// Run the watch loop: drain `source`, processing each event, until it ends.
 pub async fn run<S, R, C>(
     mut source: S,
     resolver: Option<impl ContainerResolver>,
@@ -35,51 +90,169 @@ pub async fn run<S, R, C>(
     R: MetricsRecorder,
     C: Fn() -> u64,
 {
-    while let Some(raw_event) = source.next().await {
-        process_event(&raw_event, resolver.as_ref(), recorder, now()).await;
+    while let Some(event) = source.next().await {
+        let node = resolver.as_ref().map_or("unknown", |r| r.node_name());
+        let lost = source.lost_events();
+        if lost > 0 {
+            warn!(
+                "{} OOM kill events were lost before this one; kill counts are short by as many",
+                lost
+            );
+            recorder.record_lost_events(node, lost);
+        }
+        for (reason, count) in source.skipped_records() {
+            warn!(
+                "Skipped {} probe records that could not be decoded ({})",
+                count, reason
+            );
+            recorder.record_skipped_records(node, reason, count);
+        }
+        for (backend, count) in source.kills_seen() {
+            recorder.record_kills_seen(node, backend, count);
+        }
+        let raw_event = match event {
+            ProbeEvent::Kill(raw_event) => raw_event,
+            ProbeEvent::Reap(event) => {
+                reaper::process_event(&event, node, recorder);
+                continue;
+            }
+            ProbeEvent::ScoreAdj(event) => {
+                score_adj::process_event(&event, resolver.as_ref(), recorder).await;
+                continue;
+            }
+        };
+        process_event(&raw_event, &mut source, resolver.as_ref(), recorder, now()).await;
     }
 }
 
-/// Process a single OOM kill event: run resolution (recording the outcome), collapse to an
-/// identity, enrich, then record the enriched event. The node name is known iff a resolver
-/// exists — the single source of the enrichment iff-rule.
+/// Process a single OOM kill event: run resolution for the victim (recording the outcome)
+/// and the trigger, collapse to identities and tell the source them, enrich with the
+/// trigger's stack and the victim's command, then record the enriched event. The node name
+/// is known iff a resolver exists — the single source of the enrichment iff-rule.
 async fn process_event<R: MetricsRecorder>(
     raw_event: &OomKillEvent,
+    source: &mut impl OomEventSource,
     resolver: Option<&impl ContainerResolver>,
     recorder: &R,
     timestamp: u64,
 ) {
-    let (node_name, identity) = match resolver {
+    let stack = source.trigger_stack(raw_event).await;
+    let command = source.victim_command(raw_event);
+    let victim = ProcessRef::victim(raw_event);
+    let (node_name, identity, trigger, oom_memcg_path) = match resolver {
         Some(client) => {
-            let outcome = client.resolve(raw_event.pid).await;
+            let outcome = client.resolve(&victim).await;
             recorder.record_resolution_outcome(client.node_name(), &outcome);
             match &outcome {
                 ResolutionOutcome::NotFound => {
-                    warn!("Could not find Kubernetes info for PID {}", raw_event.pid)
+                    warn!("Could not find Kubernetes info for PID {}", victim.pid)
                 }
                 ResolutionOutcome::Failed(e) => warn!(
                     "Error getting Kubernetes info for PID {}: {}",
-                    raw_event.pid, e
+                    victim.pid, e
+                ),
+                ResolutionOutcome::RuntimeMismatch(mismatch) => warn!(
+                    "Not attributing PID {} to a container: {}",
+                    victim.pid, mismatch
                 ),
                 ResolutionOutcome::Found(_) => {}
             }
-            (Some(client.node_name().to_string()), outcome.identity())
+            let identity = outcome.identity();
+            let trigger = resolve_trigger(
+                client,
+                &victim,
+                &ProcessRef::trigger(raw_event),
+                identity.as_ref(),
+            )
+            .await;
+            let oom_memcg_path = match raw_event.oom_memcg_id {
+                0 => None,
+                id => client.cgroup_path(id).await,
+            };
+            (
+                Some(client.node_name().to_string()),
+                identity,
+                trigger,
+                oom_memcg_path,
+            )
         }
-        None => (None, None),
+        None => (None, None, None, None),
     };
-
-    let enriched = enrich(*raw_event, node_name.as_deref(), identity, timestamp);
+    source.resolved(
+        raw_event,
+        identity.as_ref(),
+        trigger.as_ref(),
+        oom_memcg_path.as_deref(),
+    );
+    let scope = scope::classify(raw_event, oom_memcg_path.as_deref());
+
+    let enriched = enrich(
+        *raw_event,
+        node_name.as_deref(),
+        identity,
+        scope,
+        KillDetails {
+            trigger,
+            trigger_stack: stack,
+            victim_command: command,
+        },
+        timestamp,
+    );
     recorder.record_oom_event(&enriched);
-    log_event(raw_event, &enriched);
+    log_event(raw_event, &enriched, oom_memcg_path.as_deref());
 }
 
-fn log_event(raw_event: &OomKillEvent, enriched: &EnrichedOomEvent) {
-    let comm_str = std::str::from_utf8(&raw_event.comm)
-        .unwrap_or("?")
-        .trim_end_matches('\0');
+/// Resolve the container of the task whose allocation triggered the kill. A trigger in the
+/// victim's own cgroup (often the victim itself) reuses the victim's identity rather than
+/// resolving twice. Only the victim's outcome is recorded — `oom_resolution_failures_total`
+/// is a rate over kills — so a trigger that fails to resolve is just logged.
+async fn resolve_trigger(
+    client: &impl ContainerResolver,
+    victim: &ProcessRef,
+    trigger: &ProcessRef,
+    victim_identity: Option<&ContainerIdentity>,
+) -> Option<ContainerIdentity> {
+    if trigger.pid == victim.pid || trigger.same_cgroup(victim) {
+        return victim_identity.cloned();
+    }
+    client
+        .resolve(trigger)
+        .await
+        .found_or_log(format_args!("trigger PID {}", trigger.pid))
+}
 
+fn log_event(raw_event: &OomKillEvent, enriched: &EnrichedOomEvent, oom_memcg: Option<&str>) {
     info!("🚨 OOM EVENT DETECTED:");
-    info!("   Process: {} (PID: {})", comm_str, raw_event.pid);
+    let victim_comm = if raw_event.has(FIELD_VICTIM_COMM) {
+        comm_str(&raw_event.victim_comm)
+    } else {
+        "unknown"
+    };
+    info!("   Killed: {} (PID: {})", victim_comm, raw_event.victim_pid);
+    if let Some(ref command) = enriched.victim_command {
+        info!(
+            "   Command: {}{} (exe: {})",
+            command.args.join(" "),
+            if command.truncated { " …" } else { "" },
+            command.exe.as_deref().unwrap_or("unknown")
+        );
+    }
+    info!(
+        "   Process: parent={} ns-pid={} threads={} age={}",
+        field_str(
+            raw_event,
+            FIELD_PARENT,
+            format_args!(
+                "{} (PID: {})",
+                comm_str(&raw_event.victim_parent_comm),
+                raw_event.victim_ppid
+            ),
+            ""
+        ),
+        field_str(raw_event, FIELD_NS_PID, raw_event.victim_ns_pid, ""),
+        field_str(raw_event, FIELD_THREADS, raw_event.threads, ""),
+        field_str(raw_event, FIELD_START_TIME, age_str(raw_event.age_ns), "")
+    );
     if let Some(ref ns) = enriched.namespace {
         info!(
             "   Kubernetes: {}/{}/{}",
@@ -88,83 +261,245 @@ fn log_event(raw_event: &OomKillEvent, enriched: &EnrichedOomEvent) {
             enriched.container_name.as_deref().unwrap_or("unknown")
         );
     }
+    if let (Some(kind), Some(name)) = (&enriched.workload_kind, &enriched.workload_name) {
+        info!("   Workload: {}/{}", kind, name);
+    }
+    info!(
+        "   Triggered by: {} (PID: {}, TGID: {})",
+        comm_str(&raw_event.trigger_comm),
+        raw_event.trigger_pid,
+        raw_event.trigger_tgid
+    );
+    if let Some(ref trigger) = enriched.trigger {
+        info!(
+            "   Trigger Kubernetes: {}/{}/{}",
+            trigger.namespace, trigger.pod_name, trigger.container_name
+        );
+    }
+    if let Some(ref stack) = enriched.trigger_stack {
+        info!("   Allocation stack:");
+        for frame in &stack.kernel {
+            info!("     [k] {}", frame);
+        }
+        for frame in &stack.user {
+            info!("     [u] {}", frame);
+        }
+    }
+    info!(
+        "   Scope: {} (constraint={} gfp_mask={:#x} order={} memcg={})",
+        enriched.scope.as_str(),
+        constraint_str(raw_event.constraint),
+        raw_event.gfp_mask,
+        raw_event.order,
+        oom_memcg.unwrap_or(if raw_event.oom_memcg_id == 0 {
+            "none"
+        } else {
+            "unresolved"
+        })
+    );
     info!(
-        "   Memory: total-vm={}kB anon-rss={}kB file-rss={}kB shmem-rss={}kB",
-        raw_event.total_vm, raw_event.anon_rss, raw_event.file_rss, raw_event.shmem_rss
+        "   Memory: total-vm={} anon-rss={} file-rss={} shmem-rss={} peak-rss={} swap={}",
+        field_str(raw_event, FIELD_TOTAL_VM, raw_event.total_vm, "kB"),
+        field_str(raw_event, FIELD_ANON_RSS, raw_event.anon_rss, "kB"),
+        field_str(raw_event, FIELD_FILE_RSS, raw_event.file_rss, "kB"),
+        field_str(raw_event, FIELD_SHMEM_RSS, raw_event.shmem_rss, "kB"),
+        field_str(raw_event, FIELD_HIWATER_RSS, raw_event.hiwater_rss, "kB"),
+        field_str(raw_event, FIELD_SWAP, raw_event.swap, "kB")
     );
+    if raw_event.memcg_limit != 0 || raw_event.memcg_usage != 0 {
+        let limit = match raw_event.memcg_limit {
+            MEMCG_NO_LIMIT => "max".to_string(),
+            limit => format!("{}kB", limit / 1024),
+        };
+        info!(
+            "   Memcg: usage={}kB limit={} swap={}kB peak={}kB failcnt={}",
+            raw_event.memcg_usage / 1024,
+            limit,
+            raw_event.memcg_swap / 1024,
+            raw_event.memcg_peak / 1024,
+            raw_event.memcg_failcnt
+        );
+    }
     info!(
-        "   User: UID={} pgtables={}kB oom_score_adj={}",
-        raw_event.uid, raw_event.pgtables, raw_event.oom_score_adj
+        "   User: UID={} pgtables={} oom_score_adj={}",
+        field_str(raw_event, FIELD_UID, raw_event.uid, ""),
+        field_str(raw_event, FIELD_PGTABLES, raw_event.pgtables, "kB"),
+        field_str(raw_event, FIELD_OOM_SCORE_ADJ, raw_event.oom_score_adj, "")
     );
 }
 
+/// A victim field for the log, or `unknown` if this kernel's tracepoint lacks it.
+fn field_str(
+    event: &OomKillEvent,
+    field: u32,
+    value: impl std::fmt::Display,
+    unit: &str,
+) -> String {
+    if event.has(field) {
+        format!("{}{}", value, unit)
+    } else {
+        "unknown".to_string()
+    }
+}
+
+/// A process age for the log: seconds under a minute, then whole units down to the
+/// second (or the minute, past a day).
+fn age_str(ns: u64) -> String {
+    let secs = ns / 1_000_000_000;
+    let (days, hours, mins) = (secs / 86_400, secs / 3_600 % 24, secs / 60 % 60);
+    match (days, hours, mins) {
+        (0, 0, 0) => format!("{:.1}s", ns as f64 / 1e9),
+        (0, 0, _) => format!("{}m{}s", mins, secs % 60),
+        (0, _, _) => format!("{}h{}m{}s", hours, mins, secs % 60),
+        _ => format!("{}d{}h{}m", days, hours, mins),
+    }
+}
+
+fn constraint_str(constraint: u32) -> &'static str {
+    match constraint {
+        CONSTRAINT_NONE => "none",
+        CONSTRAINT_CPUSET => "cpuset",
+        CONSTRAINT_MEMORY_POLICY => "memory_policy",
+        CONSTRAINT_MEMCG => "memcg",
+        _ => "unknown",
+    }
+}
+
+/// A kernel `comm` as text, without its NUL padding.
+pub(crate) fn comm_str(comm: &[u8; 16]) -> &str {
+    std::str::from_utf8(comm)
+        .unwrap_or("?")
+        .trim_end_matches('\0')
+}
+
 #[cfg(test)]
 mod tests {
-    use std::{cell::RefCell, collections::VecDeque};
+    use std::{cell::RefCell, collections::VecDeque, rc::Rc, time::Duration};
 
-    use oom_watcher_common::ContainerIdentity;
+    use oom_watcher_common::{ReapEvent, ScoreAdjEvent, REAP_FINISHED};
 
     use super::*;
-    use crate::resolve::{Behavior, FakeResolver};
+    use crate::{
+        merged::MergedSource,
+        metrics::SpyRecorder,
+        resolve::{identity, Behavior, FakeResolver},
+    };
 
     /// In-memory event source — the second adapter for [`OomEventSource`], so the seam is
     /// real and the loop is drivable in tests.
-    struct VecSource(VecDeque<OomKillEvent>);
+    struct VecSource(VecDeque<ProbeEvent>);
 
     impl OomEventSource for VecSource {
-        async fn next(&mut self) -> Option<OomKillEvent> {
+        async fn next(&mut self) -> Option<ProbeEvent> {
             self.0.pop_front()
         }
     }
 
     fn source(events: impl IntoIterator<Item = OomKillEvent>) -> VecSource {
-        VecSource(events.into_iter().collect())
+        VecSource(events.into_iter().map(ProbeEvent::Kill).collect())
+    }
+
+    /// A source that captured the same allocation stack for every event it yields.
+    struct StackSource(VecSource, TriggerStack);
+
+    impl OomEventSource for StackSource {
+        async fn next(&mut self) -> Option<ProbeEvent> {
+            self.0.next().await
+        }
+
+        async fn trigger_stack(&mut self, _event: &OomKillEvent) -> Option<TriggerStack> {
+            Some(self.1.clone())
+        }
     }
 
-    /// Recording spy — the second adapter for [`MetricsRecorder`]. Captures every call so
-    /// tests assert what the loop reported, with no Prometheus involved.
-    #[derive(Default)]
-    struct SpyRecorder {
-        outcomes: RefCell<Vec<(String, &'static str)>>,
-        events: RefCell<Vec<EnrichedOomEvent>>,
+    /// A source that captured the same victim command for every event it yields.
+    struct CommandSource(VecSource, ProcessCommand);
+
+    impl OomEventSource for CommandSource {
+        async fn next(&mut self) -> Option<ProbeEvent> {
+            self.0.next().await
+        }
+
+        fn victim_command(&mut self, _event: &OomKillEvent) -> Option<ProcessCommand> {
+            Some(self.1.clone())
+        }
     }
 
-    impl MetricsRecorder for SpyRecorder {
-        fn record_resolution_outcome(&self, node: &str, outcome: &ResolutionOutcome) {
-            let reason = match outcome {
-                ResolutionOutcome::Found(_) => "found",
-                ResolutionOutcome::NotFound => "not_found",
-                ResolutionOutcome::Failed(_) => "error",
-            };
-            self.outcomes.borrow_mut().push((node.to_string(), reason));
+    /// A source that lost `.1[i]` events just before yielding its `i`th.
+    struct LossySource(VecSource, VecDeque<u64>);
+
+    impl OomEventSource for LossySource {
+        async fn next(&mut self) -> Option<ProbeEvent> {
+            self.0.next().await
+        }
+
+        fn lost_events(&mut self) -> u64 {
+            self.1.pop_front().unwrap_or(0)
+        }
+    }
+
+    /// A source that skipped `.1[i]` undecodable records just before yielding its `i`th.
+    struct SkippingSource(VecSource, VecDeque<Vec<(&'static str, u64)>>);
+
+    impl OomEventSource for SkippingSource {
+        async fn next(&mut self) -> Option<ProbeEvent> {
+            self.0.next().await
+        }
+
+        fn skipped_records(&mut self) -> Vec<(&'static str, u64)> {
+            self.1.pop_front().unwrap_or_default()
+        }
+    }
+
+    type Told = Rc<RefCell<Vec<(u32, Option<ContainerIdentity>, Option<ContainerIdentity>)>>>;
+
+    /// A source that keeps what the loop told it each kill resolved to.
+    struct TellingSource(VecSource, Told);
+
+    impl OomEventSource for TellingSource {
+        async fn next(&mut self) -> Option<ProbeEvent> {
+            self.0.next().await
         }
 
-        fn record_oom_event(&self, event: &EnrichedOomEvent) {
-            self.events.borrow_mut().push(event.clone());
+        fn resolved(
+            &mut self,
+            event: &OomKillEvent,
+            victim: Option<&ContainerIdentity>,
+            trigger: Option<&ContainerIdentity>,
+            _oom_memcg: Option<&str>,
+        ) {
+            self.1
+                .borrow_mut()
+                .push((event.victim_pid, victim.cloned(), trigger.cloned()));
         }
     }
 
     fn raw(pid: u32) -> OomKillEvent {
         OomKillEvent {
-            pid,
-            tgid: pid,
-            comm: *b"target\0\0\0\0\0\0\0\0\0\0",
+            victim_pid: pid,
+            victim_tgid: pid,
+            victim_comm: *b"target\0\0\0\0\0\0\0\0\0\0",
             total_vm: 100,
             anon_rss: 50,
             file_rss: 10,
             shmem_rss: 5,
             uid: 1000,
             pgtables: 2,
-            oom_score_adj: 0,
+            trigger_pid: pid,
+            trigger_tgid: pid,
+            trigger_comm: *b"target\0\0\0\0\0\0\0\0\0\0",
+            ..OomKillEvent::default()
         }
     }
 
-    fn identity() -> ContainerIdentity {
-        ContainerIdentity {
-            namespace: "prod".into(),
-            pod_name: "api-7d9".into(),
-            container_name: "api".into(),
-            container_id: "abc123".into(),
+    /// An event where `sidecar` allocated and `victim` was killed, in different cgroups.
+    fn cross_container(victim: u32, sidecar: u32) -> OomKillEvent {
+        OomKillEvent {
+            victim_cgroup_id: 10,
+            trigger_pid: sidecar,
+            trigger_tgid: sidecar,
+            trigger_cgroup_id: 20,
+            ..raw(victim)
         }
     }
 
@@ -190,7 +525,7 @@ mod tests {
         assert_eq!(e.namespace.as_deref(), Some("prod"));
         assert_eq!(e.pod_name.as_deref(), Some("api-7d9"));
         assert_eq!(e.container_name.as_deref(), Some("api"));
-        assert_eq!(e.raw_event.pid, 1234);
+        assert_eq!(e.raw_event.victim_pid, 1234);
         assert_eq!(e.timestamp, CLOCK);
         // The loop forwards every outcome; the adapter decides to ignore Found.
         assert_eq!(
@@ -248,6 +583,139 @@ mod tests {
         assert!(spy.outcomes.borrow().is_empty());
     }
 
+    #[tokio::test]
+    async fn a_trigger_in_the_victims_cgroup_reuses_its_identity() {
+        let spy = SpyRecorder::default();
+        let resolver = Some(FakeResolver {
+            node: "node-1".into(),
+            behavior: Behavior::Found(identity()),
+        });
+        let event = OomKillEvent {
+            victim_cgroup_id: 10,
+            trigger_pid: 99,
+            trigger_cgroup_id: 10,
+            ..raw(1)
+        };
+
+        run(source([event]), resolver, &spy, clock).await;
+
+        assert_eq!(spy.events.borrow()[0].trigger, Some(identity()));
+    }
+
+    #[tokio::test]
+    async fn resolves_a_trigger_in_another_container_separately() {
+        let spy = SpyRecorder::default();
+        let sidecar = ContainerIdentity {
+            container_name: "envoy".into(),
+            container_id: "def456".into(),
+            ..identity()
+        };
+        let resolver = Some(FakeResolver {
+            node: "node-1".into(),
+            behavior: Behavior::PerPid(vec![(1, identity()), (2, sidecar.clone())]),
+        });
+
+        run(source([cross_container(1, 2)]), resolver, &spy, clock).await;
+
+        let events = spy.events.borrow();
+        assert_eq!(events[0].container_name.as_deref(), Some("api"));
+        assert_eq!(events[0].trigger, Some(sidecar));
+        // Only the victim's resolution counts towards the failure rate.
+        assert_eq!(
+            *spy.outcomes.borrow(),
+            vec![("node-1".to_string(), "found")]
+        );
+    }
+
+    #[tokio::test]
+    async fn tells_the_source_what_each_kill_resolved_to() {
+        let spy = SpyRecorder::default();
+        let sidecar = ContainerIdentity {
+            container_name: "envoy".into(),
+            ..identity()
+        };
+        let resolver = Some(FakeResolver {
+            node: "node-1".into(),
+            behavior: Behavior::PerPid(vec![(1, identity()), (2, sidecar.clone())]),
+        });
+        let told = Told::default();
+        let telling = TellingSource(source([cross_container(1, 2), raw(3)]), told.clone());
+
+        run(telling, resolver, &spy, clock).await;
+
+        assert_eq!(
+            *told.borrow(),
+            vec![(1, Some(identity()), Some(sidecar)), (3, None, None)]
+        );
+    }
+
+    #[tokio::test]
+    async fn an_unresolved_trigger_is_not_recorded_as_a_failure() {
+        let spy = SpyRecorder::default();
+        let resolver = Some(FakeResolver {
+            node: "node-1".into(),
+            behavior: Behavior::PerPid(vec![(1, identity())]),
+        });
+
+        run(source([cross_container(1, 2)]), resolver, &spy, clock).await;
+
+        assert_eq!(spy.events.borrow()[0].trigger, None);
+        assert_eq!(
+            *spy.outcomes.borrow(),
+            vec![("node-1".to_string(), "found")]
+        );
+    }
+
+    #[tokio::test]
+    async fn carries_the_sources_trigger_stack() {
+        let spy = SpyRecorder::default();
+        let stack = TriggerStack {
+            kernel: vec![oom_watcher_common::StackFrame {
+                address: 0xffffffff81234a1c,
+                symbol: Some("__handle_mm_fault".into()),
+                offset: 0x1c,
+                module: None,
+            }],
+            user: Vec::new(),
+        };
+
+        run(
+            StackSource(source([raw(1)]), stack.clone()),
+            None::<FakeResolver>,
+            &spy,
+            clock,
+        )
+        .await;
+        run(source([raw(2)]), None::<FakeResolver>, &spy, clock).await;
+
+        let events = spy.events.borrow();
+        assert_eq!(events[0].trigger_stack, Some(stack));
+        assert_eq!(events[1].trigger_stack, None);
+    }
+
+    #[tokio::test]
+    async fn carries_the_sources_victim_command() {
+        let spy = SpyRecorder::default();
+        let command = ProcessCommand {
+            exe: Some("/usr/bin/java".into()),
+            args: vec!["java".into(), "-jar".into(), "app.jar".into()],
+            truncated: false,
+        };
+
+        run(
+            CommandSource(source([raw(1)]), command.clone()),
+            None::<FakeResolver>,
+            &spy,
+            clock,
+        )
+        .await;
+        run(source([raw(2)]), None::<FakeResolver>, &spy, clock).await;
+
+        let events = spy.events.borrow();
+        assert_eq!(events[0].victim_command, Some(command));
+        assert_eq!(events[1].victim_command, None);
+    }
+
     #[tokio::test]
     async fn drains_every_event_in_order() {
         let spy = SpyRecorder::default();
@@ -262,8 +730,113 @@ mod tests {
             .events
             .borrow()
             .iter()
-            .map(|e| e.raw_event.pid)
+            .map(|e| e.raw_event.victim_pid)
             .collect();
         assert_eq!(pids, vec![1, 2, 3]);
     }
+
+    #[tokio::test]
+    async fn records_events_the_source_lost() {
+        let spy = SpyRecorder::default();
+        let resolver = Some(FakeResolver {
+            node: "node-1".into(),
+            behavior: Behavior::NotFound,
+        });
+        let lossy = LossySource(source([raw(1), raw(2)]), [0, 3].into());
+
+        run(lossy, resolver, &spy, clock).await;
+
+        assert_eq!(*spy.lost.borrow(), vec![("node-1".to_string(), 3)]);
+        assert_eq!(spy.events.borrow().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn records_records_the_source_skipped() {
+        let spy = SpyRecorder::default();
+        let resolver = Some(FakeResolver {
+            node: "node-1".into(),
+            behavior: Behavior::NotFound,
+        });
+        let skipping = SkippingSource(
+            source([raw(1), raw(2)]),
+            [vec![], vec![("kind", 2), ("version", 1)]].into(),
+        );
+
+        run(skipping, resolver, &spy, clock).await;
+
+        assert_eq!(
+            *spy.skipped.borrow(),
+            vec![
+                ("node-1".to_string(), "kind".to_string(), 2),
+                ("node-1".to_string(), "version".to_string(), 1),
+            ]
+        );
+        assert_eq!(spy.events.borrow().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn records_which_backends_saw_each_kill() {
+        let spy = SpyRecorder::default();
+        let resolver = Some(FakeResolver {
+            node: "node-1".into(),
+            behavior: Behavior::NotFound,
+        });
+        let merged = MergedSource::new(
+            vec![
+                ("ebpf", source([raw(1), raw(2)])),
+                ("kmsg", source([raw(1)])),
+            ],
+            Duration::from_secs(60),
+        );
+
+        run(merged, resolver, &spy, clock).await;
+
+        assert_eq!(spy.events.borrow().len(), 2);
+        let seen = |source: &str| ("node-1".to_string(), source.to_string(), 1);
+        assert_eq!(
+            *spy.seen.borrow(),
+            vec![seen("ebpf"), seen("kmsg"), seen("ebpf")]
+        );
+    }
+
+    #[tokio::test]
+    async fn hands_reaps_and_score_changes_to_their_modules() {
+        let spy = SpyRecorder::default();
+        let reap = ReapEvent {
+            pid: 1,
+            stage: REAP_FINISHED,
+            elapsed_ns: 5_000_000,
+        };
+        let change = ScoreAdjEvent {
+            pid: 2,
+            oom_score_adj: -999,
+            ..ScoreAdjEvent::default()
+        };
+        let events = [
+            ProbeEvent::ScoreAdj(change),
+            ProbeEvent::Kill(raw(1)),
+            ProbeEvent::Reap(reap),
+        ];
+
+        run(VecSource(events.into()), None::<FakeResolver>, &spy, clock).await;
+
+        // Standalone, so each is recorded against an unknown node.
+        assert_eq!(
+            *spy.score_adjs.borrow(),
+            vec![("unknown".to_string(), None, -999)]
+        );
+        assert_eq!(
+            *spy.reaps.borrow(),
+            vec![("unknown".to_string(), 1, REAP_FINISHED)]
+        );
+        assert_eq!(spy.events.borrow().len(), 1);
+    }
+
+    #[test]
+    fn formats_process_ages() {
+        assert_eq!(age_str(1_500_000_000), "1.5s");
+        assert_eq!(age_str(61_000_000_000), "1m1s");
+        assert_eq!(age_str(3_725_000_000_000), "1h2m5s");
+        assert_eq!(age_str(90_061_000_000_000), "1d1h1m");
+    }
 }
@@ -0,0 +1,152 @@
+//! Decoding of the probe's ring buffer records: a [`WireHeader`], then a body of the kind
+//! it names.
+//!
+//! This is where raw bytes become typed events, so a record is only ever read as the type
+//! its header names — and only when the header is one this build understands. Anything else
+//! is skipped with a [`Skip`] reason rather than mis-decoded: a probe and userspace from
+//! different builds, or a kind this one predates, cost records, never corrupt ones.
+
+use oom_watcher_common::{
+    OomKillRecord, ReapEvent, ScoreAdjEvent, WireHeader, KIND_OOM_KILL, KIND_REAP, KIND_SCORE_ADJ,
+    WIRE_VERSION,
+};
+
+/// One record's body, decoded.
+// Moved once, straight into a ProbeEvent, so kills are not boxed; see there.
+#[allow(clippy::large_enum_variant)]
+pub enum Record {
+    Kill(OomKillRecord),
+    Reap(ReapEvent),
+    ScoreAdj(ScoreAdjEvent),
+}
+
+/// Why a record was skipped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Skip {
+    /// Written by a probe of another [`WIRE_VERSION`].
+    Version(u16),
+    /// Of a kind this build does not know.
+    Kind(u16),
+    /// Shorter than its header, or than the body its kind names.
+    Length(usize),
+}
+
+impl Skip {
+    /// The `reason` metric label.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Version(_) => "version",
+            Self::Kind(_) => "kind",
+            Self::Length(_) => "length",
+        }
+    }
+}
+
+/// Decode one ring buffer entry. A body longer than its kind needs is accepted, the rest
+/// ignored, so fields can be appended without a version bump.
+pub fn decode(data: &[u8]) -> Result<Record, Skip> {
+    let header: WireHeader = read(data).ok_or(Skip::Length(data.len()))?;
+    if header.version != WIRE_VERSION {
+        return Err(Skip::Version(header.version));
+    }
+    let body = &data[size_of::<WireHeader>()..];
+    let body = body.get(..header.len as usize).unwrap_or(body);
+    let body_of = || Skip::Length(body.len());
+    match header.kind {
+        KIND_OOM_KILL => read(body).map(Record::Kill).ok_or_else(body_of),
+        KIND_REAP => read(body).map(Record::Reap).ok_or_else(body_of),
+        KIND_SCORE_ADJ => read(body).map(Record::ScoreAdj).ok_or_else(body_of),
+        kind => Err(Skip::Kind(kind)),
+    }
+}
+
+/// A `T` from the start of `data`, if it is long enough to hold one.
+///
+/// `T` must be one of the `#[repr(C)]` plain-data types the probe writes — integers and
+/// byte arrays, for which every bit pattern is valid.
+fn read<T: Copy>(data: &[u8]) -> Option<T> {
+    if data.len() < size_of::<T>() {
+        return None;
+    }
+    // SAFETY: length checked above; T is plain data, read without assuming alignment.
+    Some(unsafe { (data.as_ptr() as *const T).read_unaligned() })
+}
+
+#[cfg(test)]
+mod tests {
+    use oom_watcher_common::{WireRecord, REAP_FINISHED};
+
+    use super::*;
+
+    /// The bytes of `record`, as the probe lays it out on the ring buffer.
+    fn bytes<T: Copy>(record: &WireRecord<T>) -> Vec<u8> {
+        let ptr = record as *const WireRecord<T> as *const u8;
+        unsafe { std::slice::from_raw_parts(ptr, size_of::<WireRecord<T>>()) }.to_vec()
+    }
+
+    fn reap() -> WireRecord<ReapEvent> {
+        WireRecord {
+            header: WireHeader::of::<ReapEvent>(KIND_REAP),
+            body: ReapEvent {
+                pid: 1234,
+                stage: REAP_FINISHED,
+                elapsed_ns: 5_000_000,
+            },
+        }
+    }
+
+    #[test]
+    fn decodes_the_kind_the_header_names() {
+        let change = WireRecord {
+            header: WireHeader::of::<ScoreAdjEvent>(KIND_SCORE_ADJ),
+            body: ScoreAdjEvent {
+                pid: 10,
+                oom_score_adj: -999,
+                ..ScoreAdjEvent::default()
+            },
+        };
+
+        match decode(&bytes(&reap())) {
+            Ok(Record::Reap(event)) => assert_eq!((event.pid, event.stage), (1234, REAP_FINISHED)),
+            _ => panic!("not decoded as a reap"),
+        }
+        match decode(&bytes(&change)) {
+            Ok(Record::ScoreAdj(event)) => assert_eq!(event.oom_score_adj, -999),
+            _ => panic!("not decoded as a score change"),
+        }
+    }
+
+    #[test]
+    fn skips_other_versions_and_unknown_kinds() {
+        let mut record = reap();
+        record.header.version = WIRE_VERSION + 1;
+        assert_eq!(
+            decode(&bytes(&record)).err(),
+            Some(Skip::Version(WIRE_VERSION + 1))
+        );
+
+        let mut record = reap();
+        record.header.kind = 99;
+        assert_eq!(decode(&bytes(&record)).err(), Some(Skip::Kind(99)));
+    }
+
+    #[test]
+    fn skips_bodies_too_short_for_their_kind() {
+        let record = bytes(&reap());
+        assert_eq!(decode(&record[..4]).err(), Some(Skip::Length(4)));
+
+        // A score change's header on a reap's body, too short for the kind it names.
+        let mut short = reap();
+        short.header.kind = KIND_SCORE_ADJ;
+        assert_eq!(decode(&bytes(&short)).err(), Some(Skip::Length(16)));
+    }
+
+    #[test]
+    fn ignores_what_follows_a_longer_body() {
+        let mut record = bytes(&reap());
+        record.extend_from_slice(&[0xff; 8]);
+        record[4..8].copy_from_slice(&24u32.to_ne_bytes());
+
+        assert!(matches!(decode(&record), Ok(Record::Reap(_))));
+    }
+}
@@ -0,0 +1,265 @@
+//! Which workload a pod belongs to: the controller at the top of its `ownerReferences`.
+//!
+//! A pod names only its direct controller. A StatefulSet's or DaemonSet's pods name the
+//! workload itself, but a Deployment's name a ReplicaSet and a CronJob's a Job, so
+//! [`Workloads`] looks those intermediate owners up through an [`OwnerLookup`]. A
+//! controller found is cached, so an OOM storm across a Deployment's pods costs one lookup
+//! per ReplicaSet; one not found yet — the object is gone, or not adopted yet — is looked
+//! up again on the next kill. The cache keeps the most recently used owners only, since a
+//! CronJob makes a new Job every run.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use anyhow::Result;
+use k8s_openapi::{
+    api::{apps::v1::ReplicaSet, batch::v1::Job},
+    apimachinery::pkg::apis::meta::v1::ObjectMeta,
+};
+use kube::{Api, Client};
+use log::debug;
+
+/// The kinds that are owned by the workload rather than being it, when they have an owner.
+const INTERMEDIATE: [&str; 2] = ["ReplicaSet", "Job"];
+
+/// How many intermediate owners' controllers are cached before the least recently used is
+/// dropped.
+const CACHE_CAPACITY: usize = 1024;
+
+/// A controller, by kind and name, in the namespace of whatever it owns.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Owner {
+    pub kind: String,
+    pub name: String,
+}
+
+/// The controller among an object's `ownerReferences`, if any.
+pub fn controller(meta: &ObjectMeta) -> Option<Owner> {
+    meta.owner_references
+        .iter()
+        .flatten()
+        .find(|owner| owner.controller == Some(true))
+        .map(|owner| Owner {
+            kind: owner.kind.clone(),
+            name: owner.name.clone(),
+        })
+}
+
+/// The seam for reading an intermediate owner's own controller. `Client` is the
+/// in-cluster adapter; tests use a fake.
+// Static dispatch only, like the other seams.
+#[allow(async_fn_in_trait)]
+pub trait OwnerLookup {
+    /// The controller of the `kind` object `name` in `namespace`: `None` if it has none,
+    /// or no longer exists.
+    async fn controller(&self, namespace: &str, kind: &str, name: &str) -> Result<Option<Owner>>;
+}
+
+/// Reads only the object's metadata, which is all the walk needs.
+impl OwnerLookup for Client {
+    async fn controller(&self, namespace: &str, kind: &str, name: &str) -> Result<Option<Owner>> {
+        let meta = match kind {
+            "ReplicaSet" => Api::<ReplicaSet>::namespaced(self.clone(), namespace)
+                .get_metadata_opt(name)
+                .await?
+                .map(|object| object.metadata),
+            "Job" => Api::<Job>::namespaced(self.clone(), namespace)
+                .get_metadata_opt(name)
+                .await?
+                .map(|object| object.metadata),
+            _ => None,
+        };
+        Ok(meta.as_ref().and_then(controller))
+    }
+}
+
+/// Resolves a pod's direct owner to its workload, caching each intermediate owner's
+/// controller.
+pub struct Workloads<L> {
+    lookup: L,
+    owners: Mutex<Cache>,
+}
+
+type Key = (String, String, String);
+
+/// (namespace, kind, name) → that object's controller, with the tick it was last used at.
+struct Cache {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<Key, (Owner, u64)>,
+}
+
+impl Cache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            tick: 0,
+            entries: HashMap::new(),
+        }
+    }
+
+    fn get(&mut self, key: &Key) -> Option<Owner> {
+        self.tick += 1;
+        let (owner, used) = self.entries.get_mut(key)?;
+        *used = self.tick;
+        Some(owner.clone())
+    }
+
+    fn insert(&mut self, key: Key, owner: Owner) {
+        self.tick += 1;
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(&key) {
+            // A linear scan: eviction is rare, and the cache small.
+            let oldest = self.entries.iter().min_by_key(|(_, (_, used))| *used);
+            if let Some(oldest) = oldest.map(|(key, _)| key.clone()) {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(key, (owner, self.tick));
+    }
+}
+
+impl<L: OwnerLookup> Workloads<L> {
+    pub fn new(lookup: L) -> Self {
+        Self::with_capacity(lookup, CACHE_CAPACITY)
+    }
+
+    fn with_capacity(lookup: L, capacity: usize) -> Self {
+        Self {
+            lookup,
+            owners: Mutex::new(Cache::new(capacity)),
+        }
+    }
+
+    /// The workload a pod whose controller is `owner` belongs to: the owner's own
+    /// controller when it is a ReplicaSet or Job that has one, otherwise the owner itself.
+    /// A lookup that fails or finds no controller falls back to the owner, and is retried
+    /// next time.
+    pub async fn resolve(&self, namespace: &str, owner: Owner) -> Owner {
+        if !INTERMEDIATE.contains(&owner.kind.as_str()) {
+            return owner;
+        }
+        let key = (
+            namespace.to_string(),
+            owner.kind.clone(),
+            owner.name.clone(),
+        );
+        if let Some(cached) = self
+            .owners
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&key)
+        {
+            return cached;
+        }
+        match self
+            .lookup
+            .controller(namespace, &owner.kind, &owner.name)
+            .await
+        {
+            Ok(Some(parent)) => {
+                self.owners
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .insert(key, parent.clone());
+                parent
+            }
+            Ok(None) => owner,
+            Err(e) => {
+                debug!(
+                    "Could not look up the owner of {} {}/{}: {}",
+                    owner.kind, namespace, owner.name, e
+                );
+                owner
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use anyhow::anyhow;
+
+    use super::*;
+
+    fn owner(kind: &str, name: &str) -> Owner {
+        Owner {
+            kind: kind.to_string(),
+            name: name.to_string(),
+        }
+    }
+
+    /// Owns every ReplicaSet by a Deployment of the same name less its hash, except
+    /// `orphan-*` ones, which have no controller; fails for Jobs; counts its lookups.
+    #[derive(Default)]
+    struct FakeLookup {
+        calls: Cell<u32>,
+    }
+
+    impl OwnerLookup for FakeLookup {
+        async fn controller(&self, _: &str, kind: &str, name: &str) -> Result<Option<Owner>> {
+            self.calls.set(self.calls.get() + 1);
+            match kind {
+                "ReplicaSet" => Ok(name
+                    .rsplit_once('-')
+                    .filter(|(deployment, _)| *deployment != "orphan")
+                    .map(|(deployment, _)| owner("Deployment", deployment))),
+                _ => Err(anyhow!("api down")),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn walks_a_replica_set_up_to_its_deployment_once() {
+        let workloads = Workloads::new(FakeLookup::default());
+
+        for _ in 0..3 {
+            let workload = workloads
+                .resolve("shop", owner("ReplicaSet", "cart-7d9f"))
+                .await;
+            assert_eq!(workload, owner("Deployment", "cart"));
+        }
+        assert_eq!(workloads.lookup.calls.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn keeps_top_level_owners_and_falls_back_on_failed_lookups() {
+        let workloads = Workloads::new(FakeLookup::default());
+
+        let set = owner("StatefulSet", "db");
+        assert_eq!(workloads.resolve("shop", set.clone()).await, set);
+        assert_eq!(workloads.lookup.calls.get(), 0);
+
+        // Not cached: the next kill tries again.
+        let job = owner("Job", "report-2891");
+        assert_eq!(workloads.resolve("shop", job.clone()).await, job);
+        assert_eq!(workloads.resolve("shop", job.clone()).await, job);
+        assert_eq!(workloads.lookup.calls.get(), 2);
+    }
+
+    #[tokio::test]
+    async fn looks_again_for_an_owner_with_no_controller() {
+        let workloads = Workloads::new(FakeLookup::default());
+
+        let orphan = owner("ReplicaSet", "orphan-5c4b");
+        assert_eq!(workloads.resolve("shop", orphan.clone()).await, orphan);
+        assert_eq!(workloads.resolve("shop", orphan.clone()).await, orphan);
+        assert_eq!(workloads.lookup.calls.get(), 2);
+    }
+
+    #[tokio::test]
+    async fn drops_the_least_recently_used_owner_when_full() {
+        let workloads = Workloads::with_capacity(FakeLookup::default(), 2);
+
+        for name in ["a-1", "b-1", "a-1", "c-1"] {
+            workloads.resolve("shop", owner("ReplicaSet", name)).await;
+        }
+        assert_eq!(workloads.lookup.calls.get(), 3);
+
+        // b-1 went to make room for c-1; a-1, used since, stayed.
+        workloads.resolve("shop", owner("ReplicaSet", "a-1")).await;
+        assert_eq!(workloads.lookup.calls.get(), 3);
+        workloads.resolve("shop", owner("ReplicaSet", "b-1")).await;
+        assert_eq!(workloads.lookup.calls.get(), 4);
+    }
+}
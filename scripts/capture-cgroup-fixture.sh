@@ -0,0 +1,32 @@
+#!/bin/bash
+# Capture a container's /proc/<pid>/cgroup, as the node sees it, into the cgroup fixture
+# corpus. Run on the node itself, as root, with crictl pointed at the runtime:
+#
+#   kind:   docker exec -i kind-control-plane bash -s -- <fixture> <pod> [namespace] \
+#               < scripts/capture-cgroup-fixture.sh > oom-watcher/fixtures/cgroup/<fixture>.cgroup
+#   a VM:   ssh node sudo bash -s -- <fixture> <pod> [namespace] \
+#               < scripts/capture-cgroup-fixture.sh > oom-watcher/fixtures/cgroup/<fixture>.cgroup
+#
+# The pod's first container is captured. Reading from inside the container would show
+# only its own cgroup namespace (`0::/` on cgroup v2), which is why this runs on the node.
+
+set -euo pipefail
+
+if [ $# -lt 2 ]; then
+    echo "usage: $0 <fixture> <pod> [namespace]" >&2
+    exit 1
+fi
+fixture=$1
+pod=$2
+namespace=${3:-default}
+
+pod_id=$(crictl pods --name "^${pod}\$" --namespace "$namespace" -q | head -1)
+if [ -z "$pod_id" ]; then
+    echo "❌ No pod $namespace/$pod on this node" >&2
+    exit 1
+fi
+container_id=$(crictl ps --pod "$pod_id" -q | head -1)
+pid=$(crictl inspect --output go-template --template '{{.info.pid}}' "$container_id")
+
+echo "📸 $fixture: $namespace/$pod container ${container_id:0:12} (pid $pid)" >&2
+cat "/proc/$pid/cgroup"